@@ -0,0 +1,37 @@
+//! Build script, generating the C header for the `ffi` feature
+
+fn main() {
+    #[cfg(feature = "ffi")]
+    ffi_header();
+}
+
+/// Generate `cp2130.h` into `OUT_DIR`, refreshing the checked in `include/cp2130.h`
+/// only when `CP2130_UPDATE_HEADER` is set so builds never write to the source tree
+#[cfg(feature = "ffi")]
+fn ffi_header() {
+    use std::env;
+    use std::path::PathBuf;
+
+    println!("cargo:rerun-if-changed=src/ffi.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    println!("cargo:rerun-if-env-changed=CP2130_UPDATE_HEADER");
+
+    let dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR not set"));
+    let out = PathBuf::from(env::var("OUT_DIR").expect("OUT_DIR not set"));
+
+    let config_path = dir.join("cbindgen.toml");
+    let config = cbindgen::Config::from_file(&config_path)
+        .unwrap_or_else(|e| panic!("failed to load {}: {}", config_path.display(), e));
+
+    let bindings = cbindgen::Builder::new()
+        .with_config(config)
+        .with_src(dir.join("src/ffi.rs"))
+        .generate()
+        .unwrap_or_else(|e| panic!("failed to generate C header from src/ffi.rs: {}", e));
+
+    bindings.write_to_file(out.join("cp2130.h"));
+
+    if env::var_os("CP2130_UPDATE_HEADER").is_some() {
+        bindings.write_to_file(dir.join("include/cp2130.h"));
+    }
+}
@@ -8,9 +8,7 @@ use embedded_graphics::{
     prelude::*,
     text::Text,
 };
-use linux_embedded_hal::Delay;
 
-use embedded_hal_compat::ReverseCompat as _;
 use ssd1306::{prelude::*, Ssd1306};
 
 fn main() {
@@ -20,23 +18,23 @@ fn main() {
     // Create CP2130 connection
     let cp2130 = Cp2130::new(device, descriptor, UsbOptions::default()).unwrap();
 
-    let spi = cp2130.spi(1, SpiConfig::default()).unwrap();
+    let spi = cp2130.spi(1, SpiConfig::default(), None).unwrap();
 
     let dc = cp2130
         .gpio_out(0, GpioMode::PushPull, GpioLevel::Low)
         .unwrap();
 
-    let rst = cp2130
+    let mut rst = cp2130
         .gpio_out(1, GpioMode::PushPull, GpioLevel::Low)
         .unwrap();
 
-    let mut delay = Delay {};
+    let mut delay = Delay::new();
 
-    let interface = SPIInterfaceNoCS::new(spi.reverse(), dc.reverse());
+    let interface = SPIInterface::new(spi, dc);
     let mut disp = Ssd1306::new(interface, DisplaySize128x64, DisplayRotation::Rotate0)
         .into_buffered_graphics_mode();
 
-    disp.reset(&mut rst.reverse(), &mut delay).unwrap();
+    disp.reset(&mut rst, &mut delay).unwrap();
     disp.init().unwrap();
 
     let text_style = MonoTextStyle::new(&FONT_6X10, BinaryColor::On);
@@ -51,5 +49,8 @@ fn main() {
 
     disp.flush().unwrap();
 
-    loop {}
+    // Hold the device open so the display is not reset
+    loop {
+        std::thread::park();
+    }
 }
@@ -0,0 +1,29 @@
+//! Feed arbitrary OTP / PROM contents through the configuration parsers
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use driver_cp2130::otp::{decode_string, encode_string, PinConfig, PromImage, UsbConfig};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = UsbConfig::decode(data);
+
+    // Decoded pin configurations must re-encode to an equivalent configuration
+    if let Ok(config) = PinConfig::decode(data) {
+        let buff = config.encode().expect("decoded pin config must encode");
+        assert_eq!(PinConfig::decode(&buff).unwrap(), config);
+    }
+
+    if let Ok(image) = PromImage::from_bytes(data) {
+        assert_eq!(image.as_bytes(), data);
+        let _ = image.blocks().count();
+    }
+
+    // Strings decoded from arbitrary blocks must fit back into the same blocks
+    let s = decode_string(data);
+    let blocks = data.len().div_ceil(64).max(1);
+    if let Ok(buff) = encode_string(&s, blocks) {
+        assert_eq!(decode_string(&buff), s);
+    }
+});
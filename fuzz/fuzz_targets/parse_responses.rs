@@ -0,0 +1,53 @@
+//! Feed arbitrary device responses through the protocol parsers
+//!
+//! Parsers must reject short or invalid input without panicking, and values
+//! that parse must survive a round trip through the matching encoder.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use driver_cp2130::protocol::*;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(v) = GpioLevels::decode(data) {
+        assert_eq!(GpioLevels::decode(&v.encode()), Ok(v));
+    }
+
+    if let Ok(v) = decode_version(data) {
+        assert_eq!(decode_version(&encode_version(v)), Ok(v));
+    }
+
+    if let Ok(v) = EventCounter::decode(data) {
+        assert_eq!(EventCounter::decode(&v.encode()), Ok(v));
+    }
+
+    if let Ok(v) = ChipSelectEnables::decode(data) {
+        assert_eq!(ChipSelectEnables::decode(&v.encode()), Ok(v));
+    }
+
+    if let Ok(v) = SpiDelayWord::decode(data) {
+        assert_eq!(SpiDelayWord::decode(&v.encode()), Ok(v));
+    }
+
+    if let Ok(v) = TransferHeader::decode(data) {
+        assert_eq!(TransferHeader::decode(&v.encode()), Ok(v));
+    }
+
+    if let Ok(v) = GpioModeLevel::decode(data) {
+        assert_eq!(v.encode()[..], data[..GpioModeLevel::LEN]);
+    }
+
+    if let Ok(v) = GpioValues::decode(data) {
+        assert_eq!(GpioValues::decode(&v.encode()), Ok(v));
+    }
+
+    if let Ok(v) = ChipSelect::decode(data) {
+        assert_eq!(v.encode()[..], data[..ChipSelect::LEN]);
+    }
+
+    if let Some(w) = data.first() {
+        let v = SpiWord::decode(*w);
+        assert_eq!(SpiWord::decode(v.encode()), v);
+    }
+});
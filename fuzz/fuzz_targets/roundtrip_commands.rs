@@ -0,0 +1,103 @@
+//! Round trip arbitrary commands through the protocol encoders and parsers
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use embedded_hal::spi::{MODE_0, MODE_1, MODE_2, MODE_3};
+use libfuzzer_sys::fuzz_target;
+
+use driver_cp2130::protocol::*;
+
+#[derive(Debug, Arbitrary)]
+struct Input {
+    channel: u8,
+    clock: u8,
+    spi_mode: u8,
+    push_pull: bool,
+    delay_mask: u8,
+    delays: [u16; 3],
+    cs_mode: u8,
+    pin: u8,
+    gpio_mode: u8,
+    high: bool,
+    levels: u16,
+    mask: u16,
+    counter_mode: Option<u8>,
+    overflow: bool,
+    count: u16,
+    transfer: u8,
+    len: u32,
+}
+
+fuzz_target!(|i: Input| {
+    let word = SpiWord {
+        clock: SpiClock::ALL[i.clock as usize % SpiClock::ALL.len()],
+        spi_mode: [MODE_0, MODE_1, MODE_2, MODE_3][i.spi_mode as usize % 4],
+        cs_pin_mode: match i.push_pull {
+            true => GpioMode::PushPull,
+            false => GpioMode::OpenDrain,
+        },
+    };
+    assert_eq!(SpiWord::decode(word.encode()), word);
+
+    let delay = SpiDelayWord {
+        channel: i.channel,
+        mask: DelayMask::from_bits_truncate(i.delay_mask),
+        inter_byte: i.delays[0],
+        post_assert: i.delays[1],
+        pre_deassert: i.delays[2],
+    };
+    assert_eq!(SpiDelayWord::decode(&delay.encode()), Ok(delay));
+
+    let cs = ChipSelect {
+        channel: i.channel,
+        mode: CsMode::try_from(i.cs_mode % 3).unwrap(),
+    };
+    assert_eq!(ChipSelect::decode(&cs.encode()), Ok(cs));
+
+    let level = match i.high {
+        true => GpioLevel::High,
+        false => GpioLevel::Low,
+    };
+    let mode_level = GpioModeLevel {
+        pin: i.pin,
+        mode: [GpioMode::Input, GpioMode::OpenDrain, GpioMode::PushPull][i.gpio_mode as usize % 3],
+        level,
+    };
+    assert_eq!(GpioModeLevel::decode(&mode_level.encode()), Ok(mode_level));
+
+    let mut levels = GpioLevels::from_bits_truncate(i.levels);
+    let values = GpioValues {
+        levels,
+        mask: GpioLevels::from_bits_truncate(i.mask),
+    };
+    assert_eq!(GpioValues::decode(&values.encode()), Ok(values));
+
+    // Pin accessors only touch the addressed pin
+    let before = levels;
+    levels.set_pin(i.pin, level);
+    for (p, l) in levels.iter_pins() {
+        match p == i.pin {
+            true => assert_eq!(l, level),
+            false => assert_eq!(l == GpioLevel::High, before.pin(p)),
+        }
+    }
+
+    let counter = EventCounter {
+        mode: i.counter_mode.and_then(EventCounterMode::from_bits),
+        overflow: i.overflow,
+        count: i.count,
+    };
+    assert_eq!(EventCounter::decode(&counter.encode()), Ok(counter));
+
+    let header = TransferHeader {
+        command: [
+            TransferCommand::Read,
+            TransferCommand::Write,
+            TransferCommand::WriteRead,
+            TransferCommand::ReadWithRTR,
+        ][i.transfer as usize % 4],
+        len: i.len,
+    };
+    assert_eq!(TransferHeader::decode(&header.encode()), Ok(header));
+});
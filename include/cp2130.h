@@ -0,0 +1,147 @@
+/* CP2130 driver C API, generated by cbindgen from src/ffi.rs */
+
+#ifndef CP2130_H
+#define CP2130_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Status codes returned by the C API
+ */
+typedef enum Cp2130Status {
+  CP2130_STATUS_OK = 0,
+  /**
+   * Invalid argument (null pointer, pin, channel or configuration)
+   */
+  CP2130_STATUS_INVALID_ARGUMENT = -1,
+  /**
+   * No matching device found
+   */
+  CP2130_STATUS_NOT_FOUND = -2,
+  /**
+   * USB communication error
+   */
+  CP2130_STATUS_USB = -3,
+  /**
+   * USB operation timed out
+   */
+  CP2130_STATUS_TIMEOUT = -4,
+  /**
+   * Device disconnected
+   */
+  CP2130_STATUS_DISCONNECTED = -5,
+  /**
+   * Pin or channel already in use
+   */
+  CP2130_STATUS_BUSY = -6,
+  /**
+   * Other driver error
+   */
+  CP2130_STATUS_ERROR = -7,
+  /**
+   * Internal panic, the handle should be closed
+   */
+  CP2130_STATUS_PANIC = -8,
+} Cp2130Status;
+
+/**
+ * Opaque device handle
+ */
+typedef struct Cp2130Handle Cp2130Handle;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Open a CP2130, by serial number where `serial` is non-null, otherwise by index
+ *
+ * # Safety
+ * `serial` must be null or a valid nul-terminated string, and `handle` a valid pointer.
+ * The returned handle must be released with [`cp2130_close`].
+ */
+enum Cp2130Status cp2130_open(uint32_t index, const char *serial, struct Cp2130Handle **handle);
+
+/**
+ * Close a CP2130 handle, null handles are ignored
+ *
+ * # Safety
+ * `handle` must be null or a handle returned by [`cp2130_open`], and is invalid after this call.
+ */
+enum Cp2130Status cp2130_close(struct Cp2130Handle *handle);
+
+/**
+ * Fetch the CP2130 chip version
+ *
+ * # Safety
+ * `handle` must be a valid handle and `version` a valid pointer.
+ */
+enum Cp2130Status cp2130_version(struct Cp2130Handle *handle, uint16_t *version);
+
+/**
+ * Configure an SPI channel for subsequent transfers
+ *
+ * `clock_hz` must be a supported CP2130 clock rate and `mode` an SPI mode (0 to 3).
+ * Where `cs_pin` is non-negative the pin is driven as chip select for each transfer.
+ *
+ * # Safety
+ * `handle` must be a valid handle.
+ */
+enum Cp2130Status cp2130_spi_configure(struct Cp2130Handle *handle,
+                                       uint8_t channel,
+                                       uint32_t clock_hz,
+                                       uint8_t mode,
+                                       int cs_pin);
+
+/**
+ * Transfer `len` bytes on the configured SPI channel
+ *
+ * Where `write` is null zeros are written, where `read` is null data read is discarded.
+ *
+ * # Safety
+ * `handle` must be a valid handle, `write` and `read` must be null or valid for `len` bytes.
+ */
+enum Cp2130Status cp2130_spi_transfer(struct Cp2130Handle *handle,
+                                      const uint8_t *write,
+                                      uint8_t *read,
+                                      uintptr_t len);
+
+/**
+ * Set the mode and level of a GPIO pin
+ *
+ * `mode` is 0 for input, 1 for open-drain and 2 for push-pull, `level` is 0 for low
+ * or non-zero for high.
+ *
+ * # Safety
+ * `handle` must be a valid handle.
+ */
+enum Cp2130Status cp2130_gpio_set(struct Cp2130Handle *handle,
+                                  uint8_t pin,
+                                  uint8_t mode,
+                                  uint8_t level);
+
+/**
+ * Read the level of a GPIO pin, setting `level` to 0 for low or 1 for high
+ *
+ * # Safety
+ * `handle` must be a valid handle and `level` a valid pointer.
+ */
+enum Cp2130Status cp2130_gpio_get(struct Cp2130Handle *handle, uint8_t pin, uint8_t *level);
+
+/**
+ * Copy the most recent error message on this thread into `buff` (nul-terminated,
+ * truncated to fit), returning the full message length
+ *
+ * # Safety
+ * `buff` must be null or valid for `len` bytes.
+ */
+uintptr_t cp2130_last_error(char *buff, uintptr_t len);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* CP2130_H */
@@ -0,0 +1,150 @@
+//! CP2130 Driver Batched GPIO Operations
+//!
+//! [`Cp2130::gpio_batch`] records a sequence of GPIO operations and executes them under
+//! a single device lock, so multi-pin sequences (such as toggling data/command and chip
+//! select lines for a display) are not interleaved with operations from other threads.
+//! Consecutive level changes on distinct pins are combined into a single multi-pin
+//! command, as are consecutive reads.
+//!
+//! Copyright 2019 Ryan Kurte
+
+use rusb::UsbContext;
+
+use crate::device::{check_pin, Inner};
+use crate::{Cp2130, Error, GpioLevel, GpioLevels, GpioMode};
+
+/// Recorded GPIO operation
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum GpioOp {
+    Set(u8, GpioLevel),
+    SetModeLevel(u8, GpioMode, GpioLevel),
+    Get(u8),
+}
+
+/// Sequence of GPIO operations, see [`Cp2130::gpio_batch`]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GpioBatch {
+    ops: Vec<GpioOp>,
+}
+
+impl GpioBatch {
+    /// Set the level of an output pin
+    pub fn set(&mut self, pin: u8, level: GpioLevel) -> &mut Self {
+        self.ops.push(GpioOp::Set(pin, level));
+        self
+    }
+
+    /// Set an output pin high
+    pub fn set_high(&mut self, pin: u8) -> &mut Self {
+        self.set(pin, GpioLevel::High)
+    }
+
+    /// Set an output pin low
+    pub fn set_low(&mut self, pin: u8) -> &mut Self {
+        self.set(pin, GpioLevel::Low)
+    }
+
+    /// Set the mode and level of a pin
+    pub fn set_mode_level(&mut self, pin: u8, mode: GpioMode, level: GpioLevel) -> &mut Self {
+        self.ops.push(GpioOp::SetModeLevel(pin, mode, level));
+        self
+    }
+
+    /// Read the level of a pin, returned (in order with other reads) by
+    /// [`Cp2130::gpio_batch`]
+    pub fn get(&mut self, pin: u8) -> &mut Self {
+        self.ops.push(GpioOp::Get(pin));
+        self
+    }
+
+    /// Check whether the batch contains no operations
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Execute the batch, returning the levels read (`true` for high) in order
+    fn execute<T: UsbContext>(&self, inner: &mut Inner<T>) -> Result<Vec<bool>, Error> {
+        let mut reads = vec![];
+
+        // Pending multi-pin level changes
+        let mut levels = GpioLevels::empty();
+        let mut mask = GpioLevels::empty();
+
+        // Values from the most recent read, valid until the next change
+        let mut values = None;
+
+        for op in &self.ops {
+            // Flush pending changes prior to any other operation, or a repeated pin
+            let flush = match op {
+                GpioOp::Set(pin, _) => mask.pin(*pin),
+                _ => !mask.is_empty(),
+            };
+            if flush {
+                inner.set_gpio_values(levels, mask)?;
+                levels = GpioLevels::empty();
+                mask = GpioLevels::empty();
+            }
+
+            match *op {
+                GpioOp::Set(pin, level) => {
+                    levels.set_pin(pin, level);
+                    mask.set_pin(pin, GpioLevel::High);
+                    values = None;
+                }
+                GpioOp::SetModeLevel(pin, mode, level) => {
+                    inner.set_gpio_mode_level(pin, mode, level)?;
+                    values = None;
+                }
+                GpioOp::Get(pin) => {
+                    let v = match values {
+                        Some(v) => v,
+                        None => inner.get_gpio_values()?,
+                    };
+                    values = Some(v);
+                    reads.push(v.pin(pin));
+                }
+            }
+        }
+
+        if !mask.is_empty() {
+            inner.set_gpio_values(levels, mask)?;
+        }
+
+        Ok(reads)
+    }
+}
+
+impl<T: UsbContext> Cp2130<T> {
+    /// Record GPIO operations with `f` and execute them under a single device lock,
+    /// returning the levels read by [`GpioBatch::get`] in order
+    ///
+    /// Pins are validated before any operation is executed. As for
+    /// [`Cp2130::set_gpio_values`] level changes only apply to pins configured as outputs.
+    /// Operations are not rolled back on failure, those preceding the failed operation
+    /// remain applied.
+    ///
+    /// ```no_run
+    /// # use driver_cp2130::prelude::*;
+    /// # fn f(cp2130: &Cp2130) -> Result<(), Cp2130Error> {
+    /// // dc low, cs low, cs high, dc high
+    /// cp2130.gpio_batch(|b| {
+    ///     b.set_low(2).set_low(0).set_high(0).set_high(2);
+    /// })?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn gpio_batch(&self, f: impl FnOnce(&mut GpioBatch)) -> Result<Vec<bool>, Error> {
+        let mut batch = GpioBatch::default();
+        f(&mut batch);
+
+        for op in &batch.ops {
+            match op {
+                GpioOp::Set(pin, _) | GpioOp::SetModeLevel(pin, _, _) | GpioOp::Get(pin) => {
+                    check_pin(*pin)?
+                }
+            }
+        }
+
+        batch.execute(&mut self.inner.lock().unwrap())
+    }
+}
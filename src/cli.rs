@@ -3,25 +3,38 @@
 //!
 //! Copyright 2019 Ryan Kurte
 
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
 extern crate clap;
-use clap::Parser;
+use clap::{CommandFactory, Parser};
 
 #[macro_use]
 extern crate log;
 extern crate simplelog;
 use simplelog::{LevelFilter, TermLogger, TerminalMode};
 
+use driver_cp2130::device::{clock_out_divider, clock_out_frequency};
+use driver_cp2130::otp::PinFunction;
 use driver_cp2130::prelude::*;
+use driver_cp2130::provision::ProvisioningPlan;
+use driver_cp2130::remote::Server;
+#[cfg(target_os = "linux")]
+use driver_cp2130::spidev::{serve_cuse, SpidevBridge};
 
 extern crate embedded_hal;
 use embedded_hal::spi::*;
 
 extern crate hex;
-extern crate rand;
-use crate::rand::Rng;
+
+use serde::Serialize;
+use serde_json::json;
 
 #[derive(Debug, Parser)]
-#[clap(name = "cp2130-util")]
+#[clap(name = "cp2130-util", after_help = EXIT_CODES)]
 /// CP2130 Utility
 pub struct Options {
     #[clap(subcommand)]
@@ -33,21 +46,145 @@ pub struct Options {
     #[clap(flatten)]
     pub options: UsbOptions,
 
-    #[clap(long, default_value = "0")]
-    /// Device index (to select from multiple devices)
+    #[clap(long, default_value = "0", conflicts_with = "serial")]
+    /// Device index (to select from multiple devices, prefer `--serial` in scripts)
     pub index: usize,
 
+    #[clap(long, value_enum, default_value = "text")]
+    /// Output format, JSON is written to stdout with logs on stderr
+    pub format: Format,
+
+    #[clap(long, short, conflicts_with = "format")]
+    /// Print only command output (hex data or values) to stdout, errors to stderr
+    pub quiet: bool,
+
     #[clap(long = "log-level", default_value = "info")]
     /// Enable verbose logging
     pub level: LevelFilter,
+
+    #[cfg(feature = "nusb")]
+    #[clap(long)]
+    /// Connect using the nusb backend in place of libusb
+    pub nusb: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Format {
+    /// Human readable log output
+    Text,
+    /// Machine readable JSON, one object per line
+    Json,
+    /// Output values only (`--quiet`)
+    #[value(skip)]
+    Raw,
+}
+
+const EXIT_CODES: &str = "Exit codes:
+  1  Error
+  2  Device not found
+  3  USB error
+  4  Verification mismatch";
+
+/// Command failures, mapped to process exit codes
+#[derive(Debug)]
+enum Exit {
+    /// No matching device was found
+    NotFound,
+    /// Read back or verification did not match
+    Mismatch(String),
+    /// Provisioning spec could not be parsed
+    Spec(String),
+    Error(Cp2130Error),
+}
+
+impl Exit {
+    fn code(&self) -> i32 {
+        match self {
+            Exit::NotFound => 2,
+            Exit::Mismatch(_) => 4,
+            Exit::Spec(_) => 1,
+            Exit::Error(Cp2130Error::Usb(_) | Cp2130Error::ShortTransfer { .. }) => 3,
+            #[cfg(feature = "nusb")]
+            Exit::Error(
+                Cp2130Error::Nusb(_)
+                | Cp2130Error::NusbTransfer(_)
+                | Cp2130Error::NusbDescriptor(_),
+            ) => 3,
+            Exit::Error(_) => 1,
+        }
+    }
+}
+
+impl std::fmt::Display for Exit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Exit::NotFound => write!(f, "No matching device found"),
+            Exit::Mismatch(m) => write!(f, "{}", m),
+            Exit::Spec(m) => write!(f, "{}", m),
+            Exit::Error(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<Cp2130Error> for Exit {
+    fn from(e: Cp2130Error) -> Self {
+        Exit::Error(e)
+    }
+}
+
+impl From<std::io::Error> for Exit {
+    fn from(e: std::io::Error) -> Self {
+        Exit::Error(e.into())
+    }
 }
 
 #[derive(Debug, Parser)]
 pub enum Command {
+    /// List matching devices
+    List,
+    /// Generate shell completions
+    Completions {
+        #[clap(value_enum)]
+        /// Shell to generate completions for
+        shell: clap_complete::Shell,
+    },
     /// Fetch the chip version
     Version,
     /// Fetch chip info
     Info,
+    /// Check the device responds and report its configuration, exiting with an error
+    /// status on failure
+    Probe {
+        #[clap(long, default_value = "0")]
+        /// SPI channel to read back configuration for
+        channel: u8,
+    },
+    /// Serve the device to remote clients over TCP
+    Serve {
+        #[clap(long, default_value = "0.0.0.0:2130")]
+        /// Address to listen on
+        listen: String,
+    },
+    /// Expose an SPI channel as a Linux spidev device via CUSE (requires root)
+    #[cfg(target_os = "linux")]
+    Spidev {
+        #[clap(long, default_value = "spidev9.0")]
+        /// Device name, created as /dev/<name>
+        name: String,
+
+        #[clap(flatten)]
+        spi_opts: SpiOpts,
+    },
+    /// Reset the device
+    Reset {
+        #[clap(long)]
+        /// Wait for the device to re-enumerate and confirm it responds
+        wait: bool,
+
+        #[clap(long, default_value = "5000")]
+        /// Timeout for re-enumeration in milliseconds
+        timeout_ms: u64,
+    },
     /// Set a GPIO output
     SetOutput {
         #[clap(long, default_value = "6")]
@@ -72,26 +209,264 @@ pub enum Command {
         /// GPIO pin mode to set
         mode: Option<GpioMode>,
     },
+    /// Monitor the GPIO.4 event counter, printing counts and frequency
+    Counter {
+        #[clap(long, default_value = "rising")]
+        /// Counter mode (rising, falling, negative-pulse, positive-pulse)
+        mode: EventCounterMode,
+
+        #[clap(long, default_value = "1s", value_parser = parse_duration)]
+        /// Sampling window (eg. `1s`, `250ms`)
+        window: Duration,
+
+        #[clap(long)]
+        /// Number of windows to sample (runs until interrupted by default)
+        count: Option<usize>,
+    },
+    /// Configure the GPIO.5 clock output
+    Clkout {
+        #[clap(long, value_parser = parse_frequency, conflicts_with = "divider")]
+        /// Output frequency (eg. `1.5mhz`), rounded to the nearest divider
+        freq: Option<u32>,
+
+        #[clap(long)]
+        /// Clock divider (24 MHz / divider, 0 divides by 256)
+        divider: Option<u8>,
+    },
+    /// GPIO operations on multiple pins
+    #[clap(subcommand)]
+    Gpio(GpioCommand),
     /// Transfer (write-read) to an attached SPI device
     SpiTransfer {
-        #[clap(value_parser=parse_hex_str)]
-        /// Data to write (in hex)
-        data: Data,
+        #[clap(flatten)]
+        data: SpiData,
+
+        #[clap(long)]
+        /// File to write received data to
+        out_file: Option<PathBuf>,
 
         #[clap(flatten)]
         spi_opts: SpiOpts,
+
+        #[clap(flatten)]
+        repeat: Repeat,
     },
     /// Write to an attached SPI device
     SpiWrite {
-        #[clap(value_parser=parse_hex_str)]
-        /// Data to write (in hex)
-        data: Data,
+        #[clap(flatten)]
+        data: SpiData,
+
+        #[clap(flatten)]
+        spi_opts: SpiOpts,
+
+        #[clap(flatten)]
+        repeat: Repeat,
+    },
+    /// Read from an attached SPI device
+    SpiRead {
+        #[clap(long)]
+        /// Number of bytes to read
+        len: usize,
+
+        #[clap(long)]
+        /// File to write received data to
+        out_file: Option<PathBuf>,
 
         #[clap(flatten)]
         spi_opts: SpiOpts,
+
+        #[clap(flatten)]
+        repeat: Repeat,
     },
+    /// Read, write and erase SPI NOR flash devices
+    #[clap(subcommand)]
+    Flash(FlashCommand),
+    /// Read and write 25xx-series SPI EEPROMs
+    #[clap(subcommand)]
+    Eeprom(EepromCommand),
     /// Test interaction with the CP2130 device
-    Test(TestOpts),
+    Test(SelfTestConfig),
+    /// One-time-programmable (PROM) configuration
+    #[clap(subcommand)]
+    Otp(OtpCommand),
+    /// Provision the device PROM from a spec file, showing changes without `--commit`
+    Provision {
+        #[clap(long)]
+        /// Provisioning spec file (TOML)
+        spec: PathBuf,
+
+        #[clap(long)]
+        /// Permanently program the device (this cannot be undone)
+        commit: bool,
+    },
+}
+
+#[derive(Debug, Parser)]
+pub enum GpioCommand {
+    /// Read the levels of all GPIO pins
+    ReadAll,
+    /// Set the levels of multiple GPIO pins
+    Set {
+        #[clap(long, value_delimiter = ',', value_parser = parse_pin_level)]
+        /// Pin levels to set (eg. `0=high,3=low`)
+        pins: Vec<(u8, GpioLevel)>,
+
+        #[clap(long)]
+        /// Configure pins with the provided mode, otherwise pins must already be outputs
+        mode: Option<GpioMode>,
+    },
+}
+
+#[derive(Debug, Parser)]
+pub enum FlashCommand {
+    /// Identify the attached flash using the JEDEC ID and SFDP parameters
+    Identify {
+        #[clap(flatten)]
+        flash_opts: FlashOpts,
+    },
+    /// Read flash contents
+    Read {
+        #[clap(long, value_parser = parse_int)]
+        /// Start address
+        address: u32,
+
+        #[clap(long, value_parser = parse_int)]
+        /// Number of bytes to read
+        len: u32,
+
+        #[clap(long)]
+        /// File to write the flash contents to
+        out_file: Option<PathBuf>,
+
+        #[clap(flatten)]
+        flash_opts: FlashOpts,
+    },
+    /// Program an image into flash
+    Write {
+        #[clap(long, value_parser = parse_int, default_value = "0")]
+        /// Start address
+        address: u32,
+
+        #[clap(long)]
+        /// Image file to program, `-` for stdin
+        in_file: PathBuf,
+
+        #[clap(long)]
+        /// Erase sectors covering the image prior to programming
+        erase: bool,
+
+        #[clap(long)]
+        /// Read back and compare the image after programming
+        verify: bool,
+
+        #[clap(flatten)]
+        flash_opts: FlashOpts,
+    },
+    /// Erase flash sectors
+    Erase {
+        #[clap(long, value_parser = parse_int, required_unless_present = "chip")]
+        /// Start address (sector aligned)
+        address: Option<u32>,
+
+        #[clap(long, value_parser = parse_int, required_unless_present = "chip")]
+        /// Number of bytes to erase (sector aligned)
+        len: Option<u32>,
+
+        #[clap(long, conflicts_with_all = ["address", "len"])]
+        /// Erase the entire device
+        chip: bool,
+
+        #[clap(flatten)]
+        flash_opts: FlashOpts,
+    },
+    /// Compare flash contents with an image
+    Verify {
+        #[clap(long, value_parser = parse_int, default_value = "0")]
+        /// Start address
+        address: u32,
+
+        #[clap(long)]
+        /// Image file to compare, `-` for stdin
+        in_file: PathBuf,
+
+        #[clap(flatten)]
+        flash_opts: FlashOpts,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Parser)]
+pub struct FlashOpts {
+    #[clap(long)]
+    /// Flash page size in bytes (defaults to SFDP value or 256)
+    page_size: Option<usize>,
+
+    #[clap(long)]
+    /// Flash sector (erase) size in bytes (defaults to SFDP value or 4096)
+    sector_size: Option<usize>,
+
+    #[clap(flatten)]
+    spi_opts: SpiOpts,
+}
+
+#[derive(Debug, Parser)]
+pub enum EepromCommand {
+    /// Read EEPROM contents
+    Read {
+        #[clap(long, value_parser = parse_int, default_value = "0")]
+        /// Start address
+        address: u32,
+
+        #[clap(long, value_parser = parse_int)]
+        /// Number of bytes to read (defaults to the remainder of the device)
+        len: Option<u32>,
+
+        #[clap(long)]
+        /// File to write the EEPROM contents to
+        out_file: Option<PathBuf>,
+
+        #[clap(flatten)]
+        eeprom_opts: EepromOpts,
+    },
+    /// Write data to the EEPROM
+    Write {
+        #[clap(long, value_parser = parse_int, default_value = "0")]
+        /// Start address
+        address: u32,
+
+        #[clap(flatten)]
+        data: SpiData,
+
+        #[clap(long)]
+        /// Read back and compare data after writing
+        verify: bool,
+
+        #[clap(flatten)]
+        eeprom_opts: EepromOpts,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Parser)]
+pub struct EepromOpts {
+    #[clap(long, value_parser = parse_int, default_value = "32768")]
+    /// EEPROM capacity in bytes (eg. 32768 for a 25xx256)
+    size: u32,
+
+    #[clap(long, default_value = "64")]
+    /// EEPROM page size in bytes
+    page_size: usize,
+
+    #[clap(flatten)]
+    spi_opts: SpiOpts,
+}
+
+#[derive(Debug, Parser)]
+pub enum OtpCommand {
+    /// Read and display the PROM configuration
+    Dump {
+        #[clap(long)]
+        /// File to save the raw PROM image to
+        output: Option<PathBuf>,
+    },
 }
 
 #[derive(Clone, Debug, PartialEq, Parser)]
@@ -103,17 +478,124 @@ pub struct SpiOpts {
     #[clap(long, default_value = "0")]
     /// SPI CS gpio index
     cs_pin: u8,
+
+    #[clap(long, default_value = "3mhz", value_parser = parse_spi_clock)]
+    /// SPI clock (12mhz, 6mhz, 3mhz, 1.5mhz, 750khz, 375khz, 187.5khz, 93.75khz)
+    clock: SpiClock,
+
+    #[clap(long, default_value = "0", value_parser = clap::value_parser!(u8).range(0..=3))]
+    /// SPI mode (0..=3)
+    mode: u8,
+
+    #[clap(long, default_value = "disabled")]
+    /// Automatic chip select mode (disabled, enabled, exclusive)
+    cs_mode: CsMode,
+
+    #[clap(long, default_value = "0s", value_parser = parse_duration)]
+    /// Delay between bytes (10 us resolution)
+    inter_byte_delay: Duration,
+
+    #[clap(long, default_value = "0s", value_parser = parse_duration)]
+    /// Delay after CS assertion (10 us resolution)
+    post_assert_delay: Duration,
+
+    #[clap(long, default_value = "0s", value_parser = parse_duration)]
+    /// Delay prior to CS deassertion (10 us resolution)
+    pre_deassert_delay: Duration,
+
+    #[clap(long)]
+    /// Toggle chip select between each byte
+    cs_toggle: bool,
 }
 
-#[derive(Debug, Parser)]
-pub struct TestOpts {
-    #[clap(long, default_value = "0")]
-    /// Pin for GPIO write
-    write_pin: u8,
+impl SpiOpts {
+    /// Build the SPI configuration from the provided options
+    fn config(&self) -> Result<SpiConfig, Cp2130Error> {
+        let mode = match self.mode {
+            0 => MODE_0,
+            1 => MODE_1,
+            2 => MODE_2,
+            _ => MODE_3,
+        };
+
+        SpiConfig::builder()
+            .clock(self.clock)
+            .spi_mode(mode)
+            .cs_mode(self.cs_mode.clone())
+            .inter_byte_delay(self.inter_byte_delay)
+            .post_assert_delay(self.post_assert_delay)
+            .pre_deassert_delay(self.pre_deassert_delay)
+            .cs_toggle(self.cs_toggle)
+            .build()
+    }
+}
 
+/// Repetition of an operation at a fixed interval
+#[derive(Clone, Debug, PartialEq, Parser)]
+pub struct Repeat {
     #[clap(long, default_value = "1")]
-    /// Pin for GPIO read
-    read_pin: u8,
+    /// Number of times to run the operation (0 to run until interrupted)
+    repeat: usize,
+
+    #[clap(long, default_value = "0s", value_parser = parse_duration)]
+    /// Interval between the start of each operation (eg. `1s`, `100ms`)
+    interval: Duration,
+}
+
+impl Repeat {
+    /// Run an operation for the configured number of repeats
+    fn run(&self, mut f: impl FnMut() -> Result<(), Cp2130Error>) -> Result<(), Cp2130Error> {
+        let mut next = Instant::now();
+        let mut n = 0;
+
+        while self.repeat == 0 || n < self.repeat {
+            if n > 0 {
+                next += self.interval;
+                std::thread::sleep(next.saturating_duration_since(Instant::now()));
+            }
+
+            f()?;
+            n += 1;
+        }
+
+        Ok(())
+    }
+}
+
+/// SPI data to write, from the command line or a file
+#[derive(Clone, Debug, PartialEq, Parser)]
+pub struct SpiData {
+    #[clap(value_parser=parse_hex_str, required_unless_present = "in_file")]
+    /// Data to write (in hex)
+    data: Option<Data>,
+
+    #[clap(long, conflicts_with = "data")]
+    /// File to read data from, `-` for stdin
+    in_file: Option<PathBuf>,
+}
+
+impl SpiData {
+    /// Load data to write, reading from the input file where provided
+    fn load(&self) -> Result<Data, std::io::Error> {
+        match (&self.data, &self.in_file) {
+            (Some(d), _) => Ok(d.clone()),
+            (None, Some(p)) => read_input(p),
+            (None, None) => Ok(vec![]),
+        }
+    }
+}
+
+/// Read an input file, `-` reads from stdin
+fn read_input(path: &PathBuf) -> Result<Data, std::io::Error> {
+    use std::io::Read;
+
+    if path.as_os_str() != "-" {
+        return std::fs::read(path);
+    }
+
+    let mut d = vec![];
+    std::io::stdin().read_to_end(&mut d)?;
+    Ok(d)
 }
 
 type Data = Vec<u8>;
@@ -122,148 +604,855 @@ fn parse_hex_str(src: &str) -> Result<Vec<u8>, hex::FromHexError> {
     hex::decode(src)
 }
 
+fn parse_int(src: &str) -> Result<u32, std::num::ParseIntError> {
+    match src.strip_prefix("0x") {
+        Some(h) => u32::from_str_radix(h, 16),
+        None => src.parse(),
+    }
+}
+
+fn parse_duration(src: &str) -> Result<Duration, String> {
+    let split = src
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(src.len());
+    let (value, unit) = src.split_at(split);
+
+    let value: f64 = value
+        .parse()
+        .map_err(|_| format!("invalid duration '{}'", src))?;
+
+    let secs = match unit {
+        "s" | "" => value,
+        "ms" => value / 1e3,
+        "us" => value / 1e6,
+        _ => return Err(format!("unrecognised duration unit '{}'", unit)),
+    };
+
+    Ok(Duration::from_secs_f64(secs))
+}
+
+fn parse_frequency(src: &str) -> Result<u32, String> {
+    let s = src.to_lowercase();
+    let (value, scale) = match s.strip_suffix("mhz") {
+        Some(v) => (v, 1e6),
+        None => match s.strip_suffix("khz") {
+            Some(v) => (v, 1e3),
+            None => (s.strip_suffix("hz").unwrap_or(&s), 1.0),
+        },
+    };
+
+    let value: f64 = value
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid frequency '{}'", src))?;
+
+    Ok((value * scale).round() as u32)
+}
+
+fn parse_spi_clock(src: &str) -> Result<SpiClock, String> {
+    let hz = parse_frequency(src)?;
+
+    SpiClock::from_frequency_exact(hz as u64)
+        .map_err(|_| format!("unsupported SPI clock '{}'", src))
+}
+
+fn parse_pin_level(src: &str) -> Result<(u8, GpioLevel), String> {
+    let (pin, level) = src
+        .split_once('=')
+        .ok_or_else(|| "expected PIN=LEVEL".to_string())?;
+
+    let pin = pin.parse().map_err(|_| format!("invalid pin '{}'", pin))?;
+
+    Ok((pin, level.parse()?))
+}
+
+/// Write a value as a single line of JSON
+fn json<V: Serialize>(value: &V) {
+    println!("{}", serde_json::to_string(value).unwrap());
+}
+
+fn connect(opts: &Options) -> Result<Cp2130, Cp2130Error> {
+    #[cfg(feature = "nusb")]
+    if opts.nusb {
+        let device = Manager::nusb_device(opts.filter.clone(), opts.index)?;
+        return Cp2130::from_nusb(&device, opts.options.clone());
+    }
+
+    let (device, descriptor) = Manager::device(opts.filter.clone(), opts.index)?;
+    Cp2130::new(device, descriptor, opts.options.clone())
+}
+
 fn main() {
-    let opts = Options::parse();
+    let mut opts = Options::parse();
+    if opts.quiet {
+        opts.format = Format::Raw;
+        opts.level = LevelFilter::Error;
+    }
 
-    // Setup logging
-    TermLogger::init(
-        opts.level,
-        simplelog::Config::default(),
-        TerminalMode::Mixed,
-    )
-    .unwrap();
+    // Setup logging, keeping stdout clear for JSON and raw output
+    let mode = match opts.format {
+        Format::Text => TerminalMode::Mixed,
+        Format::Json | Format::Raw => TerminalMode::Stderr,
+    };
+    TermLogger::init(opts.level, simplelog::Config::default(), mode).unwrap();
 
-    // Find matching devices
-    let (device, descriptor) = Manager::device(opts.filter, opts.index).unwrap();
+    if let Err(e) = run(opts) {
+        error!("{}", e);
+        std::process::exit(e.code());
+    }
+}
 
-    // Create CP2130 connection
-    let mut cp2130 = Cp2130::new(device, descriptor, opts.options).unwrap();
+fn run(opts: Options) -> Result<(), Exit> {
+    let format = opts.format;
+
+    // Commands not requiring a connection
+    match opts.command {
+        Command::List => return Ok(list(&opts.filter, format)?),
+        Command::Completions { shell } => {
+            let mut cmd = Options::command();
+            let name = cmd.get_name().to_string();
+            clap_complete::generate(shell, &mut cmd, name, &mut std::io::stdout());
+            return Ok(());
+        }
+        _ => (),
+    }
+
+    // Find matching device and create CP2130 connection
+    let mut cp2130 = match (connect(&opts), &opts.filter.serial) {
+        (Err(Cp2130Error::InvalidIndex), Some(s)) => {
+            error!("No device found with serial '{}'", s);
+            return Err(Exit::NotFound);
+        }
+        (Err(Cp2130Error::InvalidIndex), None) => return Err(Exit::NotFound),
+        (res, _) => res?,
+    };
 
     debug!("Device connected");
 
     match opts.command {
+        Command::List | Command::Completions { .. } => unreachable!(),
         Command::Info => {
             let i = cp2130.info();
-            info!("Device info: {:?}", i);
+            match format {
+                Format::Text => info!("Device info: {}", i),
+                Format::Json => json(&i),
+                Format::Raw => println!("{}", i),
+            }
+        }
+        Command::Probe { channel } => probe(&cp2130, channel, format)?,
+        Command::Serve { listen } => {
+            let server = Server::bind(Arc::new(cp2130), listen)?;
+            info!("Listening on {}", server.local_addr()?);
+            server.run()?;
+            return Ok(());
+        }
+        #[cfg(target_os = "linux")]
+        Command::Spidev { name, spi_opts } => {
+            let mut bridge = SpidevBridge::new(
+                &cp2130,
+                spi_opts.channel,
+                Some(spi_opts.cs_pin),
+                spi_opts.config()?,
+            )?;
+            serve_cuse(&mut bridge, &name)?;
+            return Ok(());
+        }
+        Command::Reset { wait, timeout_ms } => {
+            reset(&cp2130, wait, Duration::from_millis(timeout_ms))?;
         }
         Command::Version => {
-            let v = cp2130.version().unwrap();
-            info!("Device version: {}", v);
+            let v = cp2130.version()?;
+            match format {
+                Format::Text => info!("Device version: {}", v),
+                Format::Json => json(&json!({ "version": v })),
+                Format::Raw => println!("{}", v),
+            }
         }
         Command::SetOutput { pin, mode, state } => {
-            cp2130.set_gpio_mode_level(pin, mode, state).unwrap()
+            cp2130.set_gpio_mode_level(pin, mode, state)?;
         }
         Command::ReadInput { pin, mode } => {
             if let Some(m) = mode {
-                cp2130.set_gpio_mode_level(pin, m, GpioLevel::Low).unwrap();
+                cp2130.set_gpio_mode_level(pin, m, GpioLevel::Low)?;
             }
-            let v = cp2130.get_gpio_level(pin).unwrap();
-            info!("Pin: {} value: {}", pin, v);
+            let v = cp2130.get_gpio_level(pin)?;
+            match format {
+                Format::Text => info!("Pin: {} value: {}", pin, v),
+                Format::Json => json(&json!({ "pin": pin, "value": v })),
+                Format::Raw => println!("{}", v),
+            }
+        }
+        Command::Counter {
+            mode,
+            window,
+            count,
+        } => {
+            counter(&cp2130, mode, window, count)?;
+        }
+        Command::Clkout { freq, divider } => {
+            clkout(&cp2130, freq, divider)?;
+        }
+        Command::Gpio(GpioCommand::ReadAll) => {
+            let levels = cp2130.get_gpio_values()?;
+            match format {
+                Format::Text => {
+                    for (pin, level) in levels.iter_pins() {
+                        info!("GPIO{:<2} {}", pin, level);
+                    }
+                }
+                Format::Json => {
+                    let pins: Vec<_> = levels
+                        .iter_pins()
+                        .map(|(pin, level)| json!({ "pin": pin, "level": level }))
+                        .collect();
+                    json(&json!({ "pins": pins }));
+                }
+                Format::Raw => println!("{:04x}", levels.bits()),
+            }
+        }
+        Command::Gpio(GpioCommand::Set { pins, mode }) => {
+            gpio_set(&cp2130, &pins, mode)?;
         }
-        Command::SpiTransfer { data, spi_opts } => {
+        Command::SpiTransfer {
+            data,
+            out_file,
+            spi_opts,
+            repeat,
+        } => {
+            let data = data.load()?;
             info!("Transmit: {}", hex::encode(&data));
 
-            let mut spi = cp2130
-                .spi(
-                    spi_opts.channel,
-                    SpiConfig::default(),
-                    Some(spi_opts.cs_pin),
-                )
-                .unwrap();
-
-            let mut buff = data.clone();
+            let mut spi =
+                cp2130.spi(spi_opts.channel, spi_opts.config()?, Some(spi_opts.cs_pin))?;
 
-            spi.transfer_in_place(&mut buff).unwrap();
+            let mut out = out_file.as_ref().map(open_output).transpose()?;
 
-            info!("Received: {}", hex::encode(buff));
+            repeat.run(|| {
+                let mut buff = data.clone();
+                spi.transfer_in_place(&mut buff)?;
+                spi_output(Some(&data), &buff, out.as_mut(), format)
+            })?;
         }
-        Command::SpiWrite { data, spi_opts } => {
+        Command::SpiWrite {
+            data,
+            spi_opts,
+            repeat,
+        } => {
+            let data = data.load()?;
             info!("Transmit: {}", hex::encode(&data));
 
-            let mut spi = cp2130
-                .spi(
-                    spi_opts.channel,
-                    SpiConfig::default(),
-                    Some(spi_opts.cs_pin),
+            let mut spi =
+                cp2130.spi(spi_opts.channel, spi_opts.config()?, Some(spi_opts.cs_pin))?;
+
+            repeat.run(|| {
+                spi.write(&data)?;
+                if format == Format::Json {
+                    json(&json!({ "transmit": hex::encode(&data) }));
+                }
+                Ok(())
+            })?;
+        }
+        Command::SpiRead {
+            len,
+            out_file,
+            spi_opts,
+            repeat,
+        } => {
+            let mut spi =
+                cp2130.spi(spi_opts.channel, spi_opts.config()?, Some(spi_opts.cs_pin))?;
+
+            let mut out = out_file.as_ref().map(open_output).transpose()?;
+
+            repeat.run(|| {
+                let mut buff = vec![0u8; len];
+                spi.read(&mut buff)?;
+                spi_output(None, &buff, out.as_mut(), format)
+            })?;
+        }
+        Command::Flash(cmd) => flash(&cp2130, cmd, format)?,
+        Command::Eeprom(cmd) => eeprom(&cp2130, cmd, format)?,
+        Command::Test(opts) => run_tests(&mut cp2130, &opts)?,
+        Command::Otp(OtpCommand::Dump { output }) => otp_dump(&cp2130, output)?,
+        Command::Provision { spec, commit } => provision(&cp2130, &spec, commit)?,
+    }
+
+    Ok(())
+}
+
+/// Open an output file, `-` writes to stdout
+fn open_output(path: &PathBuf) -> Result<Box<dyn Write>, std::io::Error> {
+    match path.as_os_str() == "-" {
+        true => Ok(Box::new(std::io::stdout())),
+        false => Ok(Box::new(File::create(path)?)),
+    }
+}
+
+/// Output received SPI data, appending to a file where provided
+fn spi_output(
+    transmit: Option<&[u8]>,
+    receive: &[u8],
+    out_file: Option<&mut Box<dyn Write>>,
+    format: Format,
+) -> Result<(), Cp2130Error> {
+    let saved = out_file.is_some();
+    if let Some(f) = out_file {
+        f.write_all(receive)?;
+        info!("Saved {} bytes", receive.len());
+    }
+
+    match format {
+        Format::Text if !saved => info!("Received: {}", hex::encode(receive)),
+        Format::Raw if !saved => println!("{}", hex::encode(receive)),
+        Format::Text | Format::Raw => (),
+        Format::Json => {
+            let mut v = json!({ "receive": hex::encode(receive) });
+            if let Some(t) = transmit {
+                v["transmit"] = hex::encode(t).into();
+            }
+            json(&v);
+        }
+    }
+
+    Ok(())
+}
+
+/// Flash operations are split into chunks to report progress
+const FLASH_CHUNK: usize = 64 * 1024;
+
+fn flash(cp2130: &Cp2130, cmd: FlashCommand, format: Format) -> Result<(), Exit> {
+    let opts = match &cmd {
+        FlashCommand::Identify { flash_opts }
+        | FlashCommand::Read { flash_opts, .. }
+        | FlashCommand::Write { flash_opts, .. }
+        | FlashCommand::Erase { flash_opts, .. }
+        | FlashCommand::Verify { flash_opts, .. } => flash_opts.clone(),
+    };
+
+    let spi = cp2130.spi(
+        opts.spi_opts.channel,
+        opts.spi_opts.config()?,
+        Some(opts.spi_opts.cs_pin),
+    )?;
+
+    let mut flash = SpiFlash::new(spi, FlashGeometry::default());
+
+    let id = flash.read_id()?;
+    if !id.is_valid() {
+        let m = format!("No flash detected (JEDEC ID: {})", id);
+        return Err(Exit::Mismatch(m));
+    }
+    info!("Flash JEDEC ID: {}", id);
+
+    // Default geometry from SFDP where supported, falling back to the JEDEC capacity
+    let sfdp = flash.read_sfdp()?;
+    let mut geometry = match &sfdp {
+        Some(s) => s.geometry(),
+        None => FlashGeometry {
+            capacity: id
+                .capacity_bytes()
+                .unwrap_or(FlashGeometry::default().capacity),
+            ..Default::default()
+        },
+    };
+    geometry.page_size = opts.page_size.unwrap_or(geometry.page_size);
+    geometry.sector_size = opts.sector_size.unwrap_or(geometry.sector_size);
+
+    debug!("Flash geometry: {:?}", geometry);
+    flash.set_geometry(geometry.clone())?;
+
+    match cmd {
+        FlashCommand::Identify { .. } => match format {
+            Format::Text => {
+                info!(
+                    "Manufacturer: {}",
+                    id.manufacturer_name().unwrap_or("Unknown")
+                );
+                info!("Capacity: {} bytes", geometry.capacity);
+                info!("Page size: {} bytes", geometry.page_size);
+                info!("Sector size: {} bytes", geometry.sector_size);
+
+                match &sfdp {
+                    Some(s) => {
+                        info!("SFDP revision: {}.{}", s.revision.0, s.revision.1);
+                        for e in &s.erase_types {
+                            info!("Erase: {} bytes (opcode 0x{:02x})", e.size, e.opcode);
+                        }
+                    }
+                    None => info!("SFDP not supported"),
+                }
+            }
+            Format::Raw => println!("{}", id),
+            Format::Json => json(&json!({
+                "jedec_id": id,
+                "manufacturer": id.manufacturer_name(),
+                "geometry": geometry,
+                "sfdp": sfdp,
+            })),
+        },
+        FlashCommand::Read {
+            address,
+            len,
+            out_file,
+            ..
+        } => {
+            let mut data = vec![0u8; len as usize];
+            for (i, c) in data.chunks_mut(FLASH_CHUNK).enumerate() {
+                let a = address + (i * FLASH_CHUNK) as u32;
+                debug!("Reading 0x{:06x} ({} bytes)", a, c.len());
+                flash.read(a, c)?;
+            }
+
+            let mut out = out_file.as_ref().map(open_output).transpose()?;
+            spi_output(None, &data, out.as_mut(), format)?;
+        }
+        FlashCommand::Write {
+            address,
+            in_file,
+            erase,
+            verify,
+            ..
+        } => {
+            let data = read_input(&in_file)?;
+
+            if erase {
+                let sector = geometry.sector_size as u32;
+                let start = address / sector * sector;
+                let end = (address + data.len() as u32).div_ceil(sector) * sector;
+
+                info!("Erasing 0x{:06x} to 0x{:06x}", start, end);
+                flash.erase(start, (end - start) as usize)?;
+            }
+
+            for (i, c) in data.chunks(FLASH_CHUNK).enumerate() {
+                let a = address + (i * FLASH_CHUNK) as u32;
+                debug!("Programming 0x{:06x} ({} bytes)", a, c.len());
+                flash.write(a, c)?;
+            }
+            info!("Programmed {} bytes at 0x{:06x}", data.len(), address);
+
+            if verify {
+                flash_verify(&mut flash, address, &data)?;
+            }
+        }
+        FlashCommand::Erase {
+            address, len, chip, ..
+        } => match chip {
+            true => {
+                info!("Erasing device");
+                flash.erase_chip()?;
+            }
+            false => {
+                let (address, len) = (address.unwrap_or(0), len.unwrap_or(0));
+                info!("Erasing 0x{:06x} to 0x{:06x}", address, address + len);
+                flash.erase(address, len as usize)?;
+            }
+        },
+        FlashCommand::Verify {
+            address, in_file, ..
+        } => {
+            let data = read_input(&in_file)?;
+            flash_verify(&mut flash, address, &data)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn flash_verify(flash: &mut SpiFlash<Spi>, address: u32, data: &[u8]) -> Result<(), Exit> {
+    for (i, c) in data.chunks(FLASH_CHUNK).enumerate() {
+        let a = address + (i * FLASH_CHUNK) as u32;
+        debug!("Verifying 0x{:06x} ({} bytes)", a, c.len());
+
+        if let Some(n) = flash.verify(a, c)? {
+            let m = format!("Verify failed at 0x{:06x}", a as usize + n);
+            return Err(Exit::Mismatch(m));
+        }
+    }
+
+    info!("Verified {} bytes at 0x{:06x}", data.len(), address);
+
+    Ok(())
+}
+
+fn eeprom(cp2130: &Cp2130, cmd: EepromCommand, format: Format) -> Result<(), Exit> {
+    let opts = match &cmd {
+        EepromCommand::Read { eeprom_opts, .. } | EepromCommand::Write { eeprom_opts, .. } => {
+            eeprom_opts.clone()
+        }
+    };
+
+    let spi = cp2130.spi(
+        opts.spi_opts.channel,
+        opts.spi_opts.config()?,
+        Some(opts.spi_opts.cs_pin),
+    )?;
+
+    let geometry = EepromGeometry::new(opts.size as usize, opts.page_size)?;
+    let mut eeprom = SpiEeprom::new(spi, geometry);
+
+    match cmd {
+        EepromCommand::Read {
+            address,
+            len,
+            out_file,
+            ..
+        } => {
+            let len = len.unwrap_or(opts.size.saturating_sub(address));
+            let mut data = vec![0u8; len as usize];
+            eeprom.read(address, &mut data)?;
+
+            let mut out = out_file.as_ref().map(open_output).transpose()?;
+            spi_output(None, &data, out.as_mut(), format)?;
+        }
+        EepromCommand::Write {
+            address,
+            data,
+            verify,
+            ..
+        } => {
+            let data = data.load()?;
+            eeprom.write(address, &data)?;
+            info!("Wrote {} bytes at 0x{:04x}", data.len(), address);
+
+            if verify {
+                if let Some(n) = eeprom.verify(address, &data)? {
+                    let m = format!("Verify failed at 0x{:04x}", address as usize + n);
+                    return Err(Exit::Mismatch(m));
+                }
+                info!("Verified {} bytes at 0x{:04x}", data.len(), address);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn provision(cp2130: &Cp2130, path: &PathBuf, commit: bool) -> Result<(), Exit> {
+    let spec = std::fs::read_to_string(path)?;
+    let spec: ProvisioningSpec = match toml::from_str(&spec) {
+        Ok(s) => s,
+        Err(e) => {
+            let m = format!("Parsing spec {}: {}", path.display(), e);
+            return Err(Exit::Spec(m));
+        }
+    };
+
+    let plan = cp2130.provision_plan(&spec)?;
+    if plan.is_empty() {
+        info!("Device matches spec, nothing to program");
+        return Ok(());
+    }
+
+    show_plan(&plan);
+
+    if !commit {
+        info!("Dry run, pass --commit to program the device");
+        return Ok(());
+    }
+
+    let report = cp2130.provision(&spec, OtpWrite::irreversible())?;
+
+    for r in &report.results {
+        match &r.detail {
+            None => info!("{:?} verified", r.item),
+            Some(d) => error!("{:?} mismatch: {}", r.item, d),
+        }
+    }
+
+    match report.passed() {
+        true => info!("Provisioning complete, reset the device to apply changes"),
+        false => {
+            let m = "Provisioning failed verification".to_string();
+            return Err(Exit::Mismatch(m));
+        }
+    }
+
+    Ok(())
+}
+
+fn show_plan(plan: &ProvisioningPlan) {
+    let usb = &plan.usb;
+    let changes = [
+        ("VID", usb.vid.map(|v| format!("{:04x}", v))),
+        ("PID", usb.pid.map(|v| format!("{:04x}", v))),
+        ("Max power", usb.max_power_ma.map(|v| format!("{} mA", v))),
+        ("Power mode", usb.power_mode.map(|v| format!("{:?}", v))),
+        ("Release", usb.release.map(|(a, b)| format!("{}.{}", a, b))),
+        (
+            "Transfer priority",
+            usb.transfer_priority.map(|v| format!("{:?}", v)),
+        ),
+        ("Manufacturer", plan.manufacturer.clone()),
+        ("Product", plan.product.clone()),
+        ("Serial", plan.serial.clone()),
+    ];
+
+    for (name, value) in changes {
+        if let Some(v) = value {
+            info!("{} -> {}", name, v);
+        }
+    }
+
+    if let Some(pins) = &plan.pins {
+        for (i, p) in pins.pins.iter().enumerate() {
+            info!("GPIO{} -> {:?}", i, p);
+        }
+        info!("Clock divider -> {}", pins.clock_divider);
+    }
+
+    if !plan.lock.is_empty() {
+        info!("Lock -> {:?}", plan.lock);
+    }
+}
+
+/// Run probe checks, returning the first failure
+fn probe(cp2130: &Cp2130, channel: u8, format: Format) -> Result<(), Exit> {
+    let checks = [
+        ("version", cp2130.version().map(|v| format!("{}", v))),
+        (
+            "gpio",
+            cp2130.get_gpio_values().map(|levels| {
+                let pins: Vec<_> = levels
+                    .iter_pins()
+                    .map(|(p, l)| format!("{}={}", p, l))
+                    .collect();
+                pins.join(" ")
+            }),
+        ),
+        (
+            "spi",
+            cp2130.read_spi_config(channel).map(|c| {
+                format!(
+                    "channel {} clock: {} Hz mode: {:?} cs: {:?} ({:?}) delays: {:?}",
+                    channel,
+                    c.clock.freq(),
+                    c.spi_mode,
+                    c.cs_mode,
+                    c.cs_pin_mode,
+                    c.delays
                 )
-                .unwrap();
+            }),
+        ),
+    ];
+
+    let passed = checks.iter().all(|(_, r)| r.is_ok());
+
+    match format {
+        Format::Text | Format::Raw => {
+            for (name, r) in &checks {
+                match r {
+                    Ok(v) => info!("{}: ok ({})", name, v),
+                    Err(e) => error!("{}: failed ({})", name, e),
+                }
+            }
 
-            spi.write(&data).unwrap();
+            match passed {
+                true => info!("Device {} healthy", cp2130.info().serial()),
+                false => error!("Device {} probe failed", cp2130.info().serial()),
+            }
         }
-        Command::Test(opts) => {
-            run_tests(&mut cp2130, &opts);
+        Format::Json => {
+            let results: Vec<_> = checks
+                .iter()
+                .map(|(name, r)| match r {
+                    Ok(v) => json!({ "check": name, "passed": true, "detail": v }),
+                    Err(e) => json!({ "check": name, "passed": false, "detail": e.to_string() }),
+                })
+                .collect();
+            json(&json!({ "passed": passed, "checks": results }));
         }
     }
+
+    match checks.into_iter().find_map(|(_, r)| r.err()) {
+        Some(e) => Err(e.into()),
+        None => Ok(()),
+    }
+}
+
+fn reset(cp2130: &Cp2130, wait: bool, timeout: Duration) -> Result<(), Cp2130Error> {
+    if !wait {
+        cp2130.reset()?;
+        info!("Device reset");
+        return Ok(());
+    }
+
+    cp2130.reset_and_reopen(timeout)?;
+
+    let v = cp2130.version()?;
+    info!("Device reset and reconnected (version: {})", v);
+
+    Ok(())
+}
+
+fn counter(
+    cp2130: &Cp2130,
+    mode: EventCounterMode,
+    window: Duration,
+    count: Option<usize>,
+) -> Result<(), Cp2130Error> {
+    cp2130.set_event_counter(mode, 0)?;
+
+    let mut last = 0u16;
+    let mut n = 0;
+
+    while count.map(|c| n < c).unwrap_or(true) {
+        std::thread::sleep(window);
+
+        let c = cp2130.event_counter()?;
+        let delta = c.count.wrapping_sub(last);
+        last = c.count;
+        n += 1;
+
+        info!(
+            "Count: {} (+{}) frequency: {:.2} Hz",
+            c.count,
+            delta,
+            delta as f64 / window.as_secs_f64()
+        );
+    }
+
+    Ok(())
 }
 
-fn run_tests(cp2130: &mut Cp2130, opts: &TestOpts) {
-    info!("Testing GPIO read/write");
+fn clkout(cp2130: &Cp2130, freq: Option<u32>, divider: Option<u8>) -> Result<(), Cp2130Error> {
+    let divider = match (freq, divider) {
+        (Some(f), _) => Some(clock_out_divider(f)?),
+        (_, d) => d,
+    };
+
+    if let Some(d) = divider {
+        cp2130.set_clock_divider(d)?;
+    }
 
-    cp2130
-        .set_gpio_mode_level(opts.read_pin, GpioMode::Input, GpioLevel::Low)
-        .unwrap();
+    let d = cp2130.clock_divider()?;
+    info!("Clock divider: {} ({} Hz)", d, clock_out_frequency(d));
 
-    cp2130
-        .set_gpio_mode_level(opts.write_pin, GpioMode::PushPull, GpioLevel::Low)
-        .unwrap();
-    let v = cp2130.get_gpio_level(opts.read_pin).unwrap();
-    if v != false {
-        error!("GPIO read error");
+    // Clock output is only available where GPIO.5 is configured for CLKOUT
+    if cp2130.pin_config()?.pins[5].function != PinFunction::ClockOut {
+        warn!("GPIO.5 is not configured for clock output in the PROM pin configuration");
     }
 
-    cp2130
-        .set_gpio_mode_level(opts.write_pin, GpioMode::PushPull, GpioLevel::High)
-        .unwrap();
-    let v = cp2130.get_gpio_level(opts.read_pin).unwrap();
-    if v != true {
-        error!("GPIO read error");
+    Ok(())
+}
+
+fn gpio_set(
+    cp2130: &Cp2130,
+    pins: &[(u8, GpioLevel)],
+    mode: Option<GpioMode>,
+) -> Result<(), Cp2130Error> {
+    // Configuring modes requires setting each pin
+    if let Some(m) = mode {
+        for (pin, level) in pins {
+            cp2130.set_gpio_mode_level(*pin, m, *level)?;
+        }
+        return Ok(());
     }
 
-    info!("GPIO read/write okay");
+    let (mut levels, mut mask) = (GpioLevels::empty(), GpioLevels::empty());
+    for (pin, level) in pins {
+        mask |= GpioLevels::mask(*pin).ok_or(Cp2130Error::InvalidPin(*pin))?;
+        levels.set_pin(*pin, *level);
+    }
+
+    cp2130.set_gpio_values(levels, mask)
+}
 
-    info!("Testing SPI write (short)");
+fn list(filter: &Filter, format: Format) -> Result<(), Cp2130Error> {
+    let devices = Manager::list_devices(filter.clone())?;
 
-    let mut rng = rand::thread_rng();
-    let data: Vec<u8> = (0..34).map(|_| rng.gen()).collect();
+    match format {
+        Format::Json => json(&devices),
+        Format::Raw => devices.iter().for_each(|d| println!("{}", d)),
+        Format::Text => (),
+    }
+    if format != Format::Text {
+        return Ok(());
+    }
 
-    cp2130.spi_write(&data).unwrap();
+    if devices.is_empty() {
+        info!("No matching devices found");
+    }
 
-    info!("SPI write (short) okay");
+    for (i, d) in devices.iter().enumerate() {
+        info!("{}: {}", i, d);
+    }
 
-    info!("Testing SPI write (long)");
+    Ok(())
+}
 
-    let mut rng = rand::thread_rng();
-    let data: Vec<u8> = (0..300).map(|_| rng.gen()).collect();
+fn otp_dump(cp2130: &Cp2130, output: Option<PathBuf>) -> Result<(), Cp2130Error> {
+    let locked = cp2130.locked_fields()?;
+    info!("Locked fields: {:?}", locked);
 
-    cp2130.spi_write(&data).unwrap();
+    let usb = cp2130.usb_config()?;
+    info!("VID:PID: {:04x}:{:04x}", usb.vid, usb.pid);
+    info!("Max power: {} mA", usb.max_power_ma);
+    info!("Power mode: {:?}", usb.power_mode);
+    if let Some(p) = cp2130.info().power() {
+        info!(
+            "Active power: {} mA ({})",
+            p.max_power_ma,
+            match p.self_powered {
+                true => "self powered",
+                false => "bus powered",
+            }
+        );
+        if !usb.matches_power(&p) {
+            warn!("Programmed power differs from the active descriptor (reset to apply)");
+        }
+    }
+    info!("Release: {}.{}", usb.release_major, usb.release_minor);
+    info!("Transfer priority: {:?}", usb.transfer_priority);
 
-    info!("SPI write (long) okay");
+    info!("Manufacturer: {}", cp2130.manufacturer_string()?);
+    info!("Product: {}", cp2130.product_string()?);
+    info!("Serial: {}", cp2130.serial_string()?);
 
-    info!("Testing SPI transfer (short)");
+    let pins = cp2130.pin_config()?;
+    for (i, p) in pins.pins.iter().enumerate() {
+        info!(
+            "GPIO{}: {:?} (suspend: {:?} {}, wakeup: {:?})",
+            i, p.function, p.suspend_mode, p.suspend_level, p.wakeup
+        );
+    }
+    info!("Clock divider: {}", pins.clock_divider);
 
-    let mut rng = rand::thread_rng();
-    let data: Vec<u8> = (0..34).map(|_| rng.gen()).collect();
-    let mut buff = vec![0u8; data.len()];
+    let (suspend, not_suspend) = pins.suspend_outputs();
+    info!(
+        "Suspend outputs: SUSPEND {}, !SUSPEND {}",
+        suspend, not_suspend
+    );
+    info!("Remote wakeup: {}", pins.remote_wakeup());
 
-    cp2130.spi_write_read(&data, &mut buff).unwrap();
+    let image = cp2130.read_prom()?;
+    for (i, b) in image.blocks().enumerate() {
+        info!("PROM {}: {}", i, hex::encode(b));
+    }
 
-    if &data != &buff {
-        error!("SPI transfer (short) error ({:?} vs. {:?})", data, buff);
+    if let Some(path) = output {
+        std::fs::write(&path, image.to_string())?;
+        info!("Saved PROM image to {}", path.display());
     }
 
-    info!("SPI transfer (short) okay");
+    Ok(())
+}
 
-    info!("Testing SPI transfer (long)");
+fn run_tests(cp2130: &mut Cp2130, opts: &SelfTestConfig) -> Result<(), Exit> {
+    info!("Running self tests");
 
-    let mut rng = rand::thread_rng();
-    let data: Vec<u8> = (0..300).map(|_| rng.gen()).collect();
-    let mut buff = vec![0u8; data.len()];
+    let report = cp2130.self_test(opts.clone());
 
-    cp2130.spi_write_read(&data, &mut buff).unwrap();
+    for r in &report.results {
+        match &r.detail {
+            None => info!("{:?} okay", r.check),
+            Some(d) => error!("{:?} error: {}", r.check, d),
+        }
+    }
 
-    if &data != &buff {
-        error!("SPI transfer (long) error ({:?} vs. {:?})", data, buff);
+    match report.passed() {
+        true => info!("Self tests passed"),
+        false => return Err(Exit::Mismatch("Self tests failed".to_string())),
     }
 
-    info!("SPI transfer (long) okay");
+    Ok(())
 }
@@ -0,0 +1,126 @@
+//! CP2130 Driver Debounced Inputs
+//!
+//! [`DebouncedInput`] samples an input at a fixed interval and only reports a level
+//! change once the new level has been seen for a number of consecutive samples,
+//! filtering contact bounce from mechanical buttons and relay feedback contacts.
+//!
+//! Copyright 2019 Ryan Kurte
+
+use std::time::{Duration, Instant};
+
+use embedded_hal::digital::{ErrorType, InputPin};
+
+use crate::Error;
+
+/// Input pin wrapper reporting level changes only once stable for N samples
+pub struct DebouncedInput<P> {
+    pin: P,
+    interval: Duration,
+    samples: usize,
+    /// Debounced level
+    level: bool,
+    /// Consecutive samples differing from the debounced level
+    count: usize,
+    /// Time of the most recent sample
+    last: Instant,
+}
+
+impl<P: InputPin<Error = Error>> DebouncedInput<P> {
+    /// Wrap an input, sampling every `interval` and requiring `samples` consecutive
+    /// matching samples for a change
+    ///
+    /// The initial level is read immediately without debouncing.
+    pub fn new(mut pin: P, interval: Duration, samples: usize) -> Result<Self, Error> {
+        if samples == 0 {
+            return Err(Error::InvalidConfig {
+                field: "samples",
+                reason: "at least one sample is required",
+            });
+        }
+
+        let level = pin.is_high()?;
+
+        Ok(Self {
+            pin,
+            interval,
+            samples,
+            level,
+            count: 0,
+            last: Instant::now(),
+        })
+    }
+
+    /// Fetch the debounced level (`true` for high) without sampling
+    pub fn level(&self) -> bool {
+        self.level
+    }
+
+    /// Take a sample if the sample interval has elapsed, returning the new level
+    /// where a debounced change occurred
+    pub fn update(&mut self) -> Result<Option<bool>, Error> {
+        if self.last.elapsed() < self.interval {
+            return Ok(None);
+        }
+
+        self.last = Instant::now();
+
+        if self.pin.is_high()? == self.level {
+            self.count = 0;
+            return Ok(None);
+        }
+
+        self.count += 1;
+        if self.count < self.samples {
+            return Ok(None);
+        }
+
+        self.count = 0;
+        self.level = !self.level;
+
+        Ok(Some(self.level))
+    }
+
+    /// Sample until a debounced change occurs, returning the new level or `None`
+    /// on timeout
+    pub fn wait_for_change(&mut self, timeout: Option<Duration>) -> Result<Option<bool>, Error> {
+        let start = Instant::now();
+
+        loop {
+            if let Some(level) = self.update()? {
+                return Ok(Some(level));
+            }
+
+            if let Some(t) = timeout {
+                if start.elapsed() >= t {
+                    return Ok(None);
+                }
+            }
+
+            std::thread::sleep(
+                (self.last + self.interval).saturating_duration_since(Instant::now()),
+            );
+        }
+    }
+
+    /// Release the underlying pin
+    pub fn release(self) -> P {
+        self.pin
+    }
+}
+
+impl<P> ErrorType for DebouncedInput<P> {
+    type Error = Error;
+}
+
+/// Reads take a sample where due and return the debounced level
+impl<P: InputPin<Error = Error>> InputPin for DebouncedInput<P> {
+    fn is_high(&mut self) -> Result<bool, Self::Error> {
+        self.update()?;
+        Ok(self.level)
+    }
+
+    fn is_low(&mut self) -> Result<bool, Self::Error> {
+        let v = self.is_high()?;
+        Ok(!v)
+    }
+}
@@ -0,0 +1,38 @@
+//! CP2130 Driver Delays
+//!
+//! [`Delay`] implements the embedded-hal [`DelayNs`] trait using host sleeps, for
+//! drivers (such as displays and radios) that require a delay provider alongside
+//! the CP2130 SPI and GPIO handles.
+//!
+//! Copyright 2019 Ryan Kurte
+
+use std::time::Duration;
+
+use embedded_hal::delay::DelayNs;
+
+/// Host sleep based delay provider
+///
+/// Delays are at least the requested duration, but may be extended by host scheduling.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Delay;
+
+impl Delay {
+    /// Create a new delay provider
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl DelayNs for Delay {
+    fn delay_ns(&mut self, ns: u32) {
+        std::thread::sleep(Duration::from_nanos(ns as u64));
+    }
+
+    fn delay_us(&mut self, us: u32) {
+        std::thread::sleep(Duration::from_micros(us as u64));
+    }
+
+    fn delay_ms(&mut self, ms: u32) {
+        std::thread::sleep(Duration::from_millis(ms as u64));
+    }
+}
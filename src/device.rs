@@ -3,102 +3,217 @@
 //!
 //! Copyright 2019 Ryan Kurte
 
+use std::io::Read;
+use std::ops::Range;
 use std::str::FromStr;
-use std::time::{Duration, SystemTime};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
 
-use bitflags::bitflags;
-use byteorder::{ByteOrder, BE, LE};
 use log::{debug, error, trace};
 
-use rusb::{
-    Context as UsbContext, Device as UsbDevice, DeviceDescriptor, DeviceHandle, Direction,
-    TransferType,
-};
+use rusb::{Device as UsbDevice, DeviceDescriptor, DeviceHandle, GlobalContext, UsbContext};
 
-use embedded_hal::spi::{Mode as SpiMode, Phase, Polarity, MODE_0};
+use embedded_hal::spi::{Mode as SpiMode, MODE_0};
 
-use crate::Error;
+use crate::manager::{PortPath, Speed};
+use crate::protocol;
+pub use crate::protocol::{
+    ChipSelect, ChipSelectEnables, Commands, CsMode, DelayMask, EventCounter, EventCounterMode,
+    GpioLevel, GpioLevels, GpioMode, GpioModeLevel, GpioValues, RequestType, SpiClock,
+    SpiDelayWord, SpiWord, TransferCommand, TransferHeader, GPIO_COUNT, MAX_TRANSFER_LEN, PID, VID,
+};
+use crate::stats::Stats;
+use crate::transport::{RusbTransport, Transport};
+use crate::worker::Worker;
+use crate::{wait_until, Error, DEFAULT_SPIN_THRESHOLD};
+
+/// Power attributes of the active USB configuration, as reported to the host
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UsbPower {
+    /// Self powered attribute (bmAttributes)
+    pub self_powered: bool,
+    /// Maximum bus current in mA (bMaxPower)
+    pub max_power_ma: u16,
+    /// Remote wakeup attribute (bmAttributes), set where the device may signal resume
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub remote_wakeup: bool,
+}
 
+/// Connected device information
+///
+/// USB location, speed, release and power are only available where provided by the transport.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Info {
     manufacturer: String,
     product: String,
     serial: String,
+    #[cfg_attr(feature = "serde", serde(default))]
+    release: Option<u16>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    bus: Option<u8>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    address: Option<u8>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    port: Option<PortPath>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    speed: Speed,
+    #[cfg_attr(feature = "serde", serde(default))]
+    power: Option<UsbPower>,
+}
+
+impl Info {
+    /// Create device information, for use with custom transports
+    pub fn new(manufacturer: String, product: String, serial: String) -> Self {
+        Self {
+            manufacturer,
+            product,
+            serial,
+            release: None,
+            bus: None,
+            address: None,
+            port: None,
+            speed: Speed::Unknown,
+            power: None,
+        }
+    }
+
+    /// Set the device release number (bcdDevice)
+    pub fn with_release(mut self, release: u16) -> Self {
+        self.release = Some(release);
+        self
+    }
+
+    /// Set the USB bus number, device address and port chain
+    pub fn with_location(mut self, bus: u8, address: u8, ports: Vec<u8>) -> Self {
+        self.bus = Some(bus);
+        self.address = Some(address);
+        self.port = Some(PortPath { bus, ports });
+        self
+    }
+
+    /// Set the USB connection speed
+    pub fn with_speed(mut self, speed: Speed) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    /// Set the power attributes of the active USB configuration
+    pub fn with_power(mut self, power: UsbPower) -> Self {
+        self.power = Some(power);
+        self
+    }
+
+    /// Fetch the device manufacturer string
+    pub fn manufacturer(&self) -> &str {
+        &self.manufacturer
+    }
+
+    /// Fetch the device product string
+    pub fn product(&self) -> &str {
+        &self.product
+    }
+
+    /// Fetch the device serial number
+    pub fn serial(&self) -> &str {
+        &self.serial
+    }
+
+    /// Fetch the device release number in BCD (bcdDevice, eg. `0x0100` for 1.00)
+    pub fn release(&self) -> Option<u16> {
+        self.release
+    }
+
+    /// Fetch the USB bus number
+    pub fn bus(&self) -> Option<u8> {
+        self.bus
+    }
+
+    /// Fetch the USB device address
+    pub fn address(&self) -> Option<u8> {
+        self.address
+    }
+
+    /// Fetch the USB port path
+    pub fn port(&self) -> Option<&PortPath> {
+        self.port.as_ref()
+    }
+
+    /// Fetch the USB connection speed
+    pub fn speed(&self) -> Speed {
+        self.speed
+    }
+
+    /// Fetch the power attributes of the active USB configuration
+    ///
+    /// These reflect the PROM configuration at enumeration, so differ from
+    /// [`Cp2130::usb_config`](crate::Cp2130::usb_config) until the device is reset
+    /// following programming.
+    pub fn power(&self) -> Option<UsbPower> {
+        self.power
+    }
+
+    /// Add USB location, speed, release and power for a libusb device
+    pub(crate) fn with_usb_device<T: UsbContext>(
+        self,
+        device: &UsbDevice<T>,
+        descriptor: &DeviceDescriptor,
+    ) -> Self {
+        let v = descriptor.device_version();
+        let release = (v.major() as u16) << 8 | (v.minor() as u16) << 4 | (v.sub_minor() as u16);
+
+        let info = self
+            .with_release(release)
+            .with_location(
+                device.bus_number(),
+                device.address(),
+                device.port_numbers().unwrap_or_default(),
+            )
+            .with_speed(device.speed().into());
+
+        match device.active_config_descriptor() {
+            Ok(c) => info.with_power(UsbPower {
+                self_powered: c.self_powered(),
+                max_power_ma: c.max_power(),
+                remote_wakeup: c.remote_wakeup(),
+            }),
+            Err(_) => info,
+        }
+    }
+}
+
+impl std::fmt::Display for Info {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.manufacturer, self.product)?;
+
+        if !self.serial.is_empty() {
+            write!(f, " (serial: {})", self.serial)?;
+        }
+        if let Some(r) = self.release {
+            write!(f, " release {:x}.{:02x}", r >> 8, r & 0xff)?;
+        }
+        if let (Some(bus), Some(address)) = (self.bus, self.address) {
+            write!(f, " bus {:03} address {:03}", bus, address)?;
+        }
+        if let Some(p) = &self.port {
+            write!(f, " port {}", p)?;
+        }
+        if self.speed != Speed::Unknown {
+            write!(f, " {} speed", self.speed)?;
+        }
+
+        Ok(())
+    }
 }
 
-/// CP2130 command enumeration
-#[derive(Debug, PartialEq, Clone, Copy)]
-pub enum Commands {
-    GetClockDivider = 0x46,
-    GetEventCounter = 0x44,
-    GetFullThreshold = 0x34,
-    GetGpioChipSelect = 0x24,
-    GetGpioModeAndLevel = 0x22,
-    GetGpioValues = 0x20,
-    GetRtrState = 0x36,
-    GetSpiWord = 0x30,
-    GetSpiDelay = 0x32,
-    GetReadOnlyVersion = 0x11,
-    ResetDevice = 0x10,
-    SetClockDivider = 0x47,
-    SetEventCOunter = 0x45,
-    SetFullThreshold = 0x35,
-    SetGpioChipSelect = 0x25,
-    SetGpioModeAndLevel = 0x23,
-    SetGpioValues = 0x21,
-    SetRtrStop = 0x37,
-    SetSpiWord = 0x31,
-    SetSpiDelay = 0x33,
-}
-
-/// Default CP2130 VID
-pub const VID: u16 = 0x10c4;
-
-/// Default CP2130 PID
-pub const PID: u16 = 0x87a0;
-
-bitflags!(
-    /// USB request type flags
-    pub struct RequestType: u8 {
-        const HOST_TO_DEVICE = 0b0000_0000;
-        const DEVICE_TO_HOST = 0b1000_0000;
-
-        const TYPE_STANDARD = 0b0000_0000;
-        const TYPE_CLASS =    0b0010_0000;
-        const TYPE_VENDOR =   0b0100_0000;
-
-        const RECIPIENT_DEVICE =    0b0000_0000;
-        const RECIPIENT_INTERFACE = 0b0000_0001;
-        const RECIPIENT_ENDPOINT =  0b0000_0010;
-        const RECIPIENT_OTHER =     0b0000_0011;
-    }
-);
-
-bitflags!(
-    /// Gpio PIN masks for multiple pin operations
-    /// The endianness of this varies depending on where it is used...
-    pub struct GpioLevels: u16 {
-        const GPIO_10 = (1 << 14);
-        const GPIO_9  = (1 << 13);
-        const GPIO_8  = (1 << 12);
-        const GPIO_7  = (1 << 11);
-        const GPIO_6  = (1 << 10);
-        const GPIO_5  = (1 << 8);
-
-        const GPIO_4  = (1 << 7);
-        const GPIO_3  = (1 << 6);
-        const GPIO_2  = (1 << 5);
-        const GPIO_1  = (1 << 4);
-        const GPIO_0  = (1 << 3);
-    }
-);
-
-/// GPIO mode enumeration
-#[derive(Debug, PartialEq, Clone, Copy)]
-pub enum GpioMode {
-    Input = 0x00,
-    OpenDrain = 0x01,
-    PushPull = 0x02,
+/// Check a GPIO pin index is valid
+pub(crate) fn check_pin(pin: u8) -> Result<(), Error> {
+    match pin < GPIO_COUNT {
+        true => Ok(()),
+        false => Err(Error::InvalidPin(pin)),
+    }
 }
 
 impl FromStr for GpioMode {
@@ -109,20 +224,110 @@ impl FromStr for GpioMode {
             "input" => Ok(Self::Input),
             "open-drain" => Ok(Self::OpenDrain),
             "push-pull" => Ok(Self::PushPull),
-            _ => Err(format!(
-                "Unrecognised GPIO mode, try 'input', 'open-drain', or 'push-pull'"
-            )),
+            _ => {
+                Err("Unrecognised GPIO mode, try 'input', 'open-drain', or 'push-pull'".to_string())
+            }
+        }
+    }
+}
+
+impl TryFrom<u8> for EventCounterMode {
+    type Error = Error;
+
+    fn try_from(v: u8) -> Result<Self, Self::Error> {
+        Self::from_bits(v).ok_or(Error::InvalidConfig {
+            field: "event_counter",
+            reason: "unrecognised event counter mode",
+        })
+    }
+}
+
+impl FromStr for EventCounterMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rising" => Ok(Self::RisingEdge),
+            "falling" => Ok(Self::FallingEdge),
+            "negative-pulse" => Ok(Self::NegativePulse),
+            "positive-pulse" => Ok(Self::PositivePulse),
+            _ => Err("Unrecognised event counter mode, try 'rising', 'falling', \
+                'negative-pulse' or 'positive-pulse'"
+                .to_string()),
+        }
+    }
+}
+
+/// GPIO input edge
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Edge {
+    Rising,
+    Falling,
+    Any,
+}
+
+impl Edge {
+    /// Event counter mode counting this edge, where supported
+    pub fn event_counter_mode(&self) -> Option<EventCounterMode> {
+        match self {
+            Self::Rising => Some(EventCounterMode::RisingEdge),
+            Self::Falling => Some(EventCounterMode::FallingEdge),
+            Self::Any => None,
+        }
+    }
+
+    /// Check whether a transition between levels matches this edge
+    pub fn matches(&self, from: bool, to: bool) -> bool {
+        match self {
+            Self::Rising => !from && to,
+            Self::Falling => from && !to,
+            Self::Any => from != to,
         }
     }
 }
 
-/// GPIO level enumeration
-#[derive(Debug, PartialEq, Clone, Copy)]
-pub enum GpioLevel {
-    Low = 0x00,
-    High = 0x01,
+/// Clock output (GPIO.5) source frequency
+pub const CLOCK_OUT_BASE_HZ: u32 = 24_000_000;
+
+/// Compute the clock output frequency for a divider (0 divides by 256)
+pub fn clock_out_frequency(divider: u8) -> u32 {
+    match divider {
+        0 => CLOCK_OUT_BASE_HZ / 256,
+        d => CLOCK_OUT_BASE_HZ / d as u32,
+    }
+}
+
+/// Compute the clock output divider closest to the requested frequency
+pub fn clock_out_divider(hz: u32) -> Result<u8, Error> {
+    if hz == 0 || hz > CLOCK_OUT_BASE_HZ {
+        return Err(Error::InvalidConfig {
+            field: "clock_out",
+            reason: "frequency must be between 93.75 kHz and 24 MHz",
+        });
+    }
+
+    let d = (CLOCK_OUT_BASE_HZ as f64 / hz as f64).round() as u32;
+
+    match d {
+        256.. => Ok(0),
+        d => Ok(d as u8),
+    }
 }
 
+/// GPIO pin usable as an event counter input
+pub const EVENT_COUNTER_PIN: u8 = 4;
+
+/// GPIO pin usable as the RTR (ready to read) input, gating ReadWithRTR transfers
+pub const RTR_PIN: u8 = 3;
+
+/// GPIO pin usable as the SUSPEND output (high while the USB bus is suspended)
+pub const SUSPEND_PIN: u8 = 9;
+
+/// GPIO pin usable as the !SUSPEND output (low while the USB bus is suspended)
+pub const NOT_SUSPEND_PIN: u8 = 10;
+
 impl FromStr for GpioLevel {
     type Err = String;
 
@@ -130,51 +335,48 @@ impl FromStr for GpioLevel {
         match s {
             "1" | "true" | "high" => Ok(Self::High),
             "0" | "false" | "low" => Ok(Self::Low),
-            _ => Err(format!("Unrecognised GPIO level, try 'high' or 'low'")),
+            _ => Err("Unrecognised GPIO level, try 'high' or 'low'".to_string()),
         }
     }
 }
 
-/// Transfer command enumeration
-#[derive(Debug, PartialEq, Clone)]
-pub enum TransferCommand {
-    Read = 0x00,
-    Write = 0x01,
-    WriteRead = 0x02,
-    ReadWithRTR = 0x04,
-}
-
 /// Inner struct contains CP2130 IO functions
 /// This is used to split SPI and GPIO components
-pub(crate) struct Inner {
-    _device: UsbDevice<UsbContext>,
-    handle: DeviceHandle<UsbContext>,
-    endpoints: Endpoints,
+pub(crate) struct Inner<T: UsbContext = GlobalContext> {
+    /// libusb device, where connected via libusb
+    _device: Option<UsbDevice<T>>,
+    /// Worker thread owning the transport
+    worker: Arc<Worker>,
+    /// SPI bus lock and scratch buffers, held for the duration of SPI transfers and transactions
+    spi_bus: Arc<Mutex<SpiScratch>>,
 
     pub(crate) gpio_allocated: [bool; 11],
-    spi_clock: SpiClock,
+    /// Incremented on each GPIO release to invalidate outstanding pin handles
+    pub(crate) gpio_generation: [u32; 11],
+    /// CS pin allocated to each SPI channel
+    pub(crate) spi_cs: [Option<u8>; 11],
+    /// Incremented on each SPI release to invalidate outstanding SPI handles
+    pub(crate) spi_generation: [u32; 11],
+    /// SPI timing applied to each channel, used to scale transfer timeouts
+    spi_timing: [SpiTiming; 11],
+    /// Most recently configured SPI channel, whose timing applies to transfers
+    /// issued without a channel
+    spi_channel: u8,
+    /// Options used to open the device, re-used on reconnection
+    options: UsbOptions,
+    /// Last applied GPIO mode and level for each pin, restored on reconnection
+    gpio_state: [Option<(GpioMode, GpioLevel)>; 11],
+    /// Last applied configuration for each SPI channel, restored on reconnection
+    spi_config: [Option<SpiConfig>; 11],
 }
 
-/// Device specific endpoints
-/// TODO: given it's one device this could all be hard-coded
-#[derive(Debug)]
-struct Endpoints {
-    _control: Endpoint,
-    read: Endpoint,
-    write: Endpoint,
-}
-
-/// Internal endpoint representations
-#[derive(Debug, PartialEq, Clone)]
-struct Endpoint {
-    config: u8,
-    iface: u8,
-    setting: u8,
-    address: u8,
-}
+/// Interval for polling re-enumeration following a reset
+const REOPEN_POLL_INTERVAL: Duration = Duration::from_millis(100);
 
 /// Options for creating a device instance
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 #[cfg_attr(feature = "clap", derive(clap::Parser))]
 pub struct UsbOptions {
     #[cfg_attr(feature = "clap", clap(long))]
@@ -184,6 +386,21 @@ pub struct UsbOptions {
     #[cfg_attr(feature = "clap", clap(long))]
     /// Attempt to claim interface
     pub claim_interface: bool,
+
+    #[cfg_attr(
+        feature = "clap",
+        clap(
+            long = "no-reset",
+            action = clap::ArgAction::SetFalse,
+            help = "Skip device reset on connection (preserves existing GPIO state)"
+        )
+    )]
+    /// Reset the device on connection (disable to preserve existing GPIO state)
+    pub reset_on_open: bool,
+
+    #[cfg_attr(feature = "clap", clap(long))]
+    /// Allow the host to suspend the device when idle, with remote wakeup enabled (linux only)
+    pub allow_suspend: bool,
 }
 
 impl Default for UsbOptions {
@@ -203,21 +420,25 @@ impl Default for UsbOptions {
             claim_interface: true,
             #[cfg(target_os = "macos")]
             claim_interface: true,
+
+            reset_on_open: true,
+            allow_suspend: false,
         }
     }
 }
 
-impl Inner {
+impl<T: UsbContext> Inner<T> {
     /// Create a new CP2130 instance from a libusb device and descriptor
     pub fn new(
-        device: UsbDevice<UsbContext>,
+        device: UsbDevice<T>,
         descriptor: DeviceDescriptor,
         opts: UsbOptions,
-    ) -> Result<(Self, Info), Error> {
-        let timeout = Duration::from_millis(200);
-
+    ) -> Result<(Self, Info), Error>
+    where
+        T: 'static,
+    {
         // Fetch device handle
-        let mut handle = match device.open() {
+        let handle = match device.open() {
             Ok(v) => v,
             Err(e) => {
                 error!("Opening device: {}", e);
@@ -225,162 +446,210 @@ impl Inner {
             }
         };
 
-        // Reset device
-        handle.reset()?;
+        Self::from_handle(handle, descriptor, opts)
+    }
 
-        // Fetch base configuration
-        let languages = handle.read_languages(timeout)?;
-        let active_config = handle.active_configuration()?;
+    /// Create a new CP2130 instance from an already opened libusb device handle
+    pub fn from_handle(
+        handle: DeviceHandle<T>,
+        descriptor: DeviceDescriptor,
+        opts: UsbOptions,
+    ) -> Result<(Self, Info), Error>
+    where
+        T: 'static,
+    {
+        let device = handle.device();
 
-        trace!("Active configuration: {}", active_config);
-        trace!("Languages: {:?}", languages);
+        let (transport, info) = RusbTransport::new(handle, descriptor, opts.clone())?;
 
-        // Check a language is available
-        if languages.len() == 0 {
-            return Err(Error::NoLanguages);
-        }
+        let mut inner = Self::with_transport(Box::new(transport), Some(device));
+        inner.options = opts;
 
-        // Fetch information
-        let language = languages[0];
-        let manufacturer = handle.read_manufacturer_string(language, &descriptor, timeout)?;
-        let product = handle.read_product_string(language, &descriptor, timeout)?;
-        let serial = handle.read_serial_number_string(language, &descriptor, timeout)?;
-        let info = Info {
-            manufacturer,
-            product,
-            serial,
-        };
+        Ok((inner, info))
+    }
 
-        // Check at least one configuration exists
-        if descriptor.num_configurations() != 1 {
-            error!("Unexpected number of configurations");
-            return Err(Error::Configurations);
+    /// Create a new CP2130 instance using the provided transport
+    pub(crate) fn with_transport(
+        transport: Box<dyn Transport>,
+        device: Option<UsbDevice<T>>,
+    ) -> Self {
+        Inner {
+            _device: device,
+            worker: Arc::new(Worker::spawn(transport)),
+            spi_bus: Arc::new(Mutex::new(SpiScratch::default())),
+            gpio_allocated: [false; 11],
+            gpio_generation: [0; 11],
+            spi_cs: [None; 11],
+            spi_generation: [0; 11],
+            spi_timing: Default::default(),
+            spi_channel: 0,
+            options: UsbOptions::default(),
+            gpio_state: [None; 11],
+            spi_config: Default::default(),
         }
+    }
+
+    /// Release any resources claimed by the transport on connection
+    pub(crate) fn close(&mut self) -> Result<(), Error> {
+        self.worker.close()
+    }
 
-        // Connect to endpoints
-        let config_desc = device.config_descriptor(0)?;
+    /// Reset and close the device ahead of re-opening it, returning the USB context and
+    /// descriptor used to search for the re-enumerated device
+    pub(crate) fn reset_for_reopen(&mut self) -> Result<(T, DeviceDescriptor), Error> {
+        let device = match &self._device {
+            Some(d) => d.clone(),
+            None => {
+                return Err(Error::Unsupported(
+                    "reset and reopen requires a libusb device",
+                ))
+            }
+        };
 
-        let (mut write, mut read) = (None, None);
+        let descriptor = device.device_descriptor()?;
+        let context = device.context().clone();
 
-        for interface in config_desc.interfaces() {
-            for interface_desc in interface.descriptors() {
-                for endpoint_desc in interface_desc.endpoint_descriptors() {
-                    // Create an endpoint container
-                    let e = Endpoint {
-                        config: config_desc.number(),
-                        iface: interface_desc.interface_number(),
-                        setting: interface_desc.setting_number(),
-                        address: endpoint_desc.address(),
-                    };
+        // The device may drop off the bus before acknowledging the reset
+        if let Err(e) = self.reset() {
+            debug!("Reset: {}", e);
+        }
+        if let Err(e) = self.close() {
+            debug!("Closing reset device: {}", e);
+        }
 
-                    trace!("Endpoint: {:?}", e);
+        Ok((context, descriptor))
+    }
 
-                    // Find the relevant endpoints
-                    match (endpoint_desc.transfer_type(), endpoint_desc.direction()) {
-                        (TransferType::Bulk, Direction::In) => read = Some(e),
-                        (TransferType::Bulk, Direction::Out) => write = Some(e),
-                        (_, _) => continue,
-                    }
-                }
+    /// Re-open a re-enumerated device and restore the last applied GPIO and SPI
+    /// configuration
+    pub(crate) fn reopen(
+        &mut self,
+        device: UsbDevice<T>,
+        descriptor: DeviceDescriptor,
+    ) -> Result<(), Error>
+    where
+        T: 'static,
+    {
+        // Swap in the new connection, retaining allocations so existing handles remain valid
+        let (mut inner, _info) = Inner::new(device, descriptor, self.options.clone())?;
+        std::mem::swap(&mut self._device, &mut inner._device);
+        std::mem::swap(&mut self.worker, &mut inner.worker);
+
+        // Restore configuration
+        for pin in 0..GPIO_COUNT {
+            if let Some((mode, level)) = self.gpio_state[pin as usize] {
+                self.set_gpio_mode_level(pin, mode, level)?;
             }
         }
 
-        // Configure endpoints
-        let control = Endpoint {
-            config: 1,
-            iface: 0,
-            setting: 0,
-            address: 0,
-        };
-        //control.configure(&mut handle)?;
-
-        // Detach kernel driver if required
-        // TODO: track this and re-enable kernel driver on closing?
-        if opts.detach_kernel_driver {
-            debug!("Checking for active kernel driver");
-            match handle.kernel_driver_active(control.iface)? {
-                true => {
-                    debug!("Detaching kernel driver");
-                    handle.detach_kernel_driver(control.iface)?;
-                }
-                false => {
-                    debug!("Kernel driver inactive");
-                }
+        for channel in 0..GPIO_COUNT {
+            if let Some(config) = self.spi_config[channel as usize].clone() {
+                self.spi_configure(channel, config)?;
             }
-        } else {
-            debug!("Skipping kernel driver attach check");
         }
 
-        // Claim interface
-        if opts.claim_interface {
-            debug!("Claiming device interface");
-            handle.claim_interface(control.iface)?;
-        } else {
-            debug!("Skipping claim device interface");
+        Ok(())
+    }
+}
+
+/// Wait for a device with the provided serial to enumerate, matching the vendor and
+/// product IDs of the original descriptor
+pub(crate) fn wait_for_serial<T: UsbContext>(
+    context: &T,
+    descriptor: &DeviceDescriptor,
+    serial: &str,
+    timeout: Duration,
+) -> Result<(UsbDevice<T>, DeviceDescriptor), Error> {
+    let start = Instant::now();
+
+    loop {
+        if start.elapsed() > timeout {
+            error!("Timeout waiting for device {} to re-enumerate", serial);
+            return Err(Error::Usb(rusb::Error::Timeout));
         }
 
-        // Map endpoints
-        let write = match write {
-            Some(c) => c,
-            None => {
-                error!("No write endpoint found");
-                return Err(Error::Endpoint);
+        std::thread::sleep(REOPEN_POLL_INTERVAL);
+
+        for d in context.devices()?.iter() {
+            let desc = match d.device_descriptor() {
+                Ok(desc) => desc,
+                Err(_) => continue,
+            };
+
+            if desc.vendor_id() != descriptor.vendor_id()
+                || desc.product_id() != descriptor.product_id()
+            {
+                continue;
             }
-        };
-        handle.set_active_configuration(write.config)?;
 
-        let read = match read {
-            Some(c) => c,
-            None => {
-                error!("No read endpoint found");
-                return Err(Error::Endpoint);
+            // The reset device may still be listed, but will fail to read until re-enumerated
+            match read_info(&d, &desc) {
+                Ok(i) if i.serial == serial => {
+                    debug!("Re-opening device {}", serial);
+                    return Ok((d, desc));
+                }
+                Ok(_) => (),
+                Err(e) => debug!("Reading device information: {}", e),
             }
-        };
-        handle.set_active_configuration(read.config)?;
+        }
+    }
+}
 
-        // Build endpoints
-        let endpoints = Endpoints {
-            _control: control,
-            write,
-            read,
-        };
-        Ok((
-            Inner {
-                _device: device,
-                handle,
-                endpoints,
-                gpio_allocated: [false; 11],
-                spi_clock: SpiClock::Clock12Mhz,
-            },
-            info,
-        ))
-    }
-}
-
-/// SPI clock configuration
-#[derive(Debug, PartialEq, Copy, Clone)]
-pub enum SpiClock {
-    Clock12Mhz,
-    Clock6MHz,
-    Clock3MHz,
-    Clock1_5MHz,
-    Clock750KHz,
-    Clock375MHz,
+/// Read device information strings for a libusb device, without claiming the interface
+///
+/// Missing strings are returned as empty.
+pub(crate) fn read_info<T: UsbContext>(
+    device: &UsbDevice<T>,
+    descriptor: &DeviceDescriptor,
+) -> Result<Info, Error> {
+    let timeout = USB_TIMEOUT;
+    let handle = device.open()?;
+
+    let language = match handle.read_languages(timeout)?.first() {
+        Some(l) => *l,
+        None => return Err(Error::NoLanguages),
+    };
+
+    let read_string = |index| match index {
+        Some(i) => handle.read_string_descriptor(language, i, timeout),
+        None => Ok(String::new()),
+    };
+
+    let info = Info::new(
+        read_string(descriptor.manufacturer_string_index())?,
+        read_string(descriptor.product_string_index())?,
+        read_string(descriptor.serial_number_string_index())?,
+    );
+
+    Ok(info.with_usb_device(device, descriptor))
+}
+
+impl<T: UsbContext> Drop for Inner<T> {
+    fn drop(&mut self) {
+        if let Err(e) = self.close() {
+            error!("Closing device: {}", e);
+        }
+    }
 }
 
+/// Number of packets per bulk transfer when segmenting SPI reads and writes
+const PIPELINE_PACKETS: usize = 16;
+
+/// USB control transfer timeout, also added as a margin to the expected bus time for
+/// SPI transfers
+pub(crate) const USB_TIMEOUT: Duration = Duration::from_millis(200);
+
 /// SPI operation delay added to transaction time to ensure we don't clobber previous SPI transactions
 pub const SPI_OP_DELAY_US: u64 = 100;
 
 impl SpiClock {
-    pub fn freq(&self) -> u64 {
-        match self {
-            SpiClock::Clock12Mhz => 12_000_000,
-            SpiClock::Clock6MHz => 6_000_000,
-            SpiClock::Clock3MHz => 3_000_000,
-            SpiClock::Clock1_5MHz => 1_500_000,
-            SpiClock::Clock750KHz => 750_000,
-            SpiClock::Clock375MHz => 375_000,
-        }
+    /// Fetch the clock rate exactly matching the provided frequency in Hz,
+    /// returning [`Error::InvalidBaud`] if the rate is not supported
+    pub fn from_frequency_exact(hz: u64) -> Result<SpiClock, Error> {
+        Self::ALL
+            .into_iter()
+            .find(|c| c.freq() == hz)
+            .ok_or(Error::InvalidBaud)
     }
 
     pub fn transfer_time(&self, len_bytes: u64) -> std::time::Duration {
@@ -389,57 +658,187 @@ impl SpiClock {
     }
 }
 
+impl From<SpiClock> for u64 {
+    fn from(c: SpiClock) -> Self {
+        c.freq()
+    }
+}
+
+impl std::convert::TryFrom<u64> for SpiClock {
+    type Error = Error;
+
+    fn try_from(v: u64) -> Result<Self, Self::Error> {
+        Self::from_frequency_exact(v)
+    }
+}
+
 impl std::convert::TryFrom<usize> for SpiClock {
     type Error = Error;
 
     fn try_from(v: usize) -> Result<Self, Self::Error> {
-        match v {
-            12_000_000 => Ok(SpiClock::Clock12Mhz),
-            6_000_000 => Ok(SpiClock::Clock6MHz),
-            3_000_000 => Ok(SpiClock::Clock3MHz),
-            1_500_000 => Ok(SpiClock::Clock1_5MHz),
-            750_000 => Ok(SpiClock::Clock750KHz),
-            375_000 => Ok(SpiClock::Clock375MHz),
-            _ => Err(Error::InvalidBaud),
-        }
+        Self::from_frequency_exact(v as u64)
     }
 }
 
-/// Chip select mode
-#[derive(Debug, PartialEq, Clone)]
-pub enum CsMode {
-    /// Auto chip select is disabled for the specified channel
-    Disabled = 0x00,
-    /// Auto chip select is enabled for the specified channel
-    Enabled = 0x01,
-    /// Auto chip select is enabled for the specified channel,
-    /// all other chip selects are disabled
-    Exclusive = 0x02,
+impl FromStr for CsMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "disabled" => Ok(Self::Disabled),
+            "enabled" => Ok(Self::Enabled),
+            "exclusive" => Ok(Self::Exclusive),
+            _ => Err("Unrecognised CS mode, try 'disabled', 'enabled', or 'exclusive'".to_string()),
+        }
+    }
 }
 
 pub const CPOL_TRAILING: u8 = 0 << 5;
 
-bitflags!(
-    /// Mask for delay configuration
-    pub struct DelayMask: u8 {
-        const CS_TOGGLE      = 1 << 3;
-        const PRE_DEASSERT   = 1 << 2;
-        const POST_ASSERT    = 1 << 1;
-        const INTER_BYE      = 1 << 0;
+/// SPI delay configuration
+///
+/// Delays are held in 10 us device units, use the [`Duration`] based methods
+/// to construct delays without needing to know the device encoding.
+///
+/// ```
+/// # use std::time::Duration;
+/// # use driver_cp2130::SpiDelays;
+/// let delays = SpiDelays::new()
+///     .post_assert(Duration::from_micros(50))?
+///     .pre_deassert(Duration::from_micros(20))?;
+/// # Ok::<(), driver_cp2130::Error>(())
+/// ```
+#[derive(Debug, PartialEq, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(into = "SpiDelaysRepr", try_from = "SpiDelaysRepr")
+)]
+pub struct SpiDelays {
+    mask: DelayMask,
+    pre_deassert: u16,
+    post_assert: u16,
+    inter_byte: u16,
+}
+
+/// Resolution of SPI delays
+pub const SPI_DELAY_UNIT: Duration = Duration::from_micros(10);
+
+/// Maximum SPI delay supported by the device
+pub const SPI_DELAY_MAX: Duration = Duration::from_micros(10 * u16::MAX as u64);
+
+/// Convert a delay to device units, rounding up to the delay resolution
+fn delay_units(delay: Duration, field: &'static str) -> Result<u16, Error> {
+    let units = delay.as_micros().div_ceil(SPI_DELAY_UNIT.as_micros());
+
+    u16::try_from(units).map_err(|_| Error::InvalidConfig {
+        field,
+        reason: "delay exceeds the hardware maximum of 655.35 ms",
+    })
+}
+
+impl SpiDelays {
+    /// Create an empty delay configuration (no delays enabled)
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the delay between bytes, zero disables the delay
+    pub fn inter_byte(mut self, delay: Duration) -> Result<Self, Error> {
+        self.inter_byte = delay_units(delay, "inter_byte")?;
+        self.mask.set(DelayMask::INTER_BYE, self.inter_byte != 0);
+        Ok(self)
+    }
+
+    /// Set the delay after CS assertion, zero disables the delay
+    pub fn post_assert(mut self, delay: Duration) -> Result<Self, Error> {
+        self.post_assert = delay_units(delay, "post_assert")?;
+        self.mask.set(DelayMask::POST_ASSERT, self.post_assert != 0);
+        Ok(self)
+    }
+
+    /// Set the delay prior to CS deassertion, zero disables the delay
+    pub fn pre_deassert(mut self, delay: Duration) -> Result<Self, Error> {
+        self.pre_deassert = delay_units(delay, "pre_deassert")?;
+        self.mask
+            .set(DelayMask::PRE_DEASSERT, self.pre_deassert != 0);
+        Ok(self)
+    }
+
+    /// Toggle chip select between each byte
+    pub fn cs_toggle(mut self, enabled: bool) -> Self {
+        self.mask.set(DelayMask::CS_TOGGLE, enabled);
+        self
+    }
+
+    /// Fetch the delay between bytes
+    pub fn inter_byte_delay(&self) -> Duration {
+        SPI_DELAY_UNIT * self.inter_byte as u32
+    }
+
+    /// Fetch the delay after CS assertion
+    pub fn post_assert_delay(&self) -> Duration {
+        SPI_DELAY_UNIT * self.post_assert as u32
+    }
+
+    /// Fetch the delay prior to CS deassertion
+    pub fn pre_deassert_delay(&self) -> Duration {
+        SPI_DELAY_UNIT * self.pre_deassert as u32
+    }
+
+    /// Fetch whether chip select is toggled between bytes
+    pub fn cs_toggle_enabled(&self) -> bool {
+        self.mask.contains(DelayMask::CS_TOGGLE)
+    }
+
+    /// Compute the total delay added to a transfer of the provided length
+    pub fn transfer_time(&self, len_bytes: u64) -> Duration {
+        self.inter_byte_delay() * len_bytes.saturating_sub(1) as u32
+            + self.post_assert_delay()
+            + self.pre_deassert_delay()
     }
-);
+}
 
+/// SPI clock and delays applied to the device, used to estimate transfer times
 #[derive(Debug, PartialEq, Clone)]
-pub struct SpiDelays {
-    mask: DelayMask,
-    pre_deassert: u8,
-    post_assert: u8,
-    inter_byte: u8,
+pub(crate) struct SpiTiming {
+    pub(crate) clock: SpiClock,
+    pub(crate) delays: SpiDelays,
+    /// Per-call USB timeout, replacing the scaled timeout where set
+    pub(crate) timeout: Option<Duration>,
+}
+
+impl Default for SpiTiming {
+    fn default() -> Self {
+        Self {
+            clock: SpiClock::Clock12Mhz,
+            delays: SpiDelays::default(),
+            timeout: None,
+        }
+    }
+}
+
+impl SpiTiming {
+    /// Expected bus time for a transfer of the provided length
+    pub(crate) fn transfer_time(&self, len_bytes: usize) -> Duration {
+        self.clock.transfer_time(len_bytes as u64) + self.delays.transfer_time(len_bytes as u64)
+    }
+
+    /// USB timeout for a transfer of the provided length, scaled with the expected bus time
+    pub(crate) fn timeout(&self, len_bytes: usize) -> Duration {
+        match self.timeout {
+            Some(t) => t,
+            None => self.transfer_time(len_bytes) + USB_TIMEOUT,
+        }
+    }
 }
 
-#[derive(PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct SpiConfig {
     pub clock: SpiClock,
+    #[cfg_attr(feature = "serde", serde(with = "spi_mode_serde"))]
     pub spi_mode: SpiMode,
     pub cs_mode: CsMode,
     pub cs_pin_mode: GpioMode,
@@ -453,107 +852,323 @@ impl Default for SpiConfig {
             spi_mode: MODE_0,
             cs_mode: CsMode::Disabled,
             cs_pin_mode: GpioMode::PushPull,
-            delays: SpiDelays {
-                mask: DelayMask::empty(),
-                pre_deassert: 0,
-                post_assert: 0,
-                inter_byte: 0,
-            },
+            delays: SpiDelays::default(),
         }
     }
 }
 
-impl Inner {
-    pub(crate) fn spi_configure(&mut self, channel: u8, config: SpiConfig) -> Result<(), Error> {
-        debug!(
-            "Setting SPI channel: {:?} clock: {:?} cs mode: {:?}",
-            channel, config.clock, config.cs_mode
-        );
+/// Serialised form of [`SpiDelays`], delays in microseconds
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+struct SpiDelaysRepr {
+    inter_byte_us: u64,
+    post_assert_us: u64,
+    pre_deassert_us: u64,
+    cs_toggle: bool,
+}
 
-        // Set SPI channel configuration
-        self.set_spi_word(channel, config.clock, config.spi_mode, config.cs_pin_mode)?;
+#[cfg(feature = "serde")]
+impl Default for SpiDelaysRepr {
+    fn default() -> Self {
+        SpiDelays::default().into()
+    }
+}
 
-        // Configure chip select
-        self.set_gpio_chip_select(channel, config.cs_mode)?;
+#[cfg(feature = "serde")]
+impl From<SpiDelays> for SpiDelaysRepr {
+    fn from(d: SpiDelays) -> Self {
+        Self {
+            inter_byte_us: d.inter_byte_delay().as_micros() as u64,
+            post_assert_us: d.post_assert_delay().as_micros() as u64,
+            pre_deassert_us: d.pre_deassert_delay().as_micros() as u64,
+            cs_toggle: d.cs_toggle_enabled(),
+        }
+    }
+}
 
-        // Configure delays
-        self.set_spi_delay(channel, config.delays)?;
+#[cfg(feature = "serde")]
+impl std::convert::TryFrom<SpiDelaysRepr> for SpiDelays {
+    type Error = Error;
 
-        Ok(())
+    fn try_from(r: SpiDelaysRepr) -> Result<Self, Self::Error> {
+        SpiDelays::new()
+            .inter_byte(Duration::from_micros(r.inter_byte_us))?
+            .post_assert(Duration::from_micros(r.post_assert_us))?
+            .pre_deassert(Duration::from_micros(r.pre_deassert_us))
+            .map(|d| d.cs_toggle(r.cs_toggle))
     }
+}
 
-    pub(crate) fn set_spi_word(
-        &mut self,
-        channel: u8,
-        clock: SpiClock,
-        spi_mode: SpiMode,
-        cs_pin_mode: GpioMode,
-    ) -> Result<(), Error> {
-        let mut flags = 0;
+/// (De)serialise SPI modes as the conventional mode number (0..=3)
+#[cfg(feature = "serde")]
+mod spi_mode_serde {
+    use embedded_hal::spi::{Mode, MODE_0, MODE_1, MODE_2, MODE_3};
+    use serde::{de::Error as _, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(mode: &Mode, s: S) -> Result<S::Ok, S::Error> {
+        let n = match *mode {
+            MODE_0 => 0,
+            MODE_1 => 1,
+            MODE_2 => 2,
+            _ => 3,
+        };
+        s.serialize_u8(n)
+    }
 
-        if let Phase::CaptureOnSecondTransition = spi_mode.phase {
-            flags |= 1 << 5;
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Mode, D::Error> {
+        match u8::deserialize(d)? {
+            0 => Ok(MODE_0),
+            1 => Ok(MODE_1),
+            2 => Ok(MODE_2),
+            3 => Ok(MODE_3),
+            n => Err(D::Error::custom(format!(
+                "invalid SPI mode {}, expected 0..=3",
+                n
+            ))),
         }
+    }
+}
 
-        if let Polarity::IdleHigh = spi_mode.polarity {
-            flags |= 1 << 4;
-        };
-
-        if let GpioMode::PushPull = cs_pin_mode {
-            flags |= 1 << 3
+impl SpiConfig {
+    /// Create a builder for SPI configurations, starting from the defaults
+    pub fn builder() -> SpiConfigBuilder {
+        SpiConfigBuilder {
+            config: SpiConfig::default(),
+            error: None,
         }
+    }
+}
 
-        flags |= (clock as u8) & 0b0111;
+/// Builder for [`SpiConfig`], configuration is validated on [`SpiConfigBuilder::build`]
+pub struct SpiConfigBuilder {
+    config: SpiConfig,
+    error: Option<Error>,
+}
 
-        debug!("Set SPI word: 0x{:02x?}", flags);
+impl SpiConfigBuilder {
+    /// Set the SPI clock rate
+    pub fn clock(mut self, clock: SpiClock) -> Self {
+        self.config.clock = clock;
+        self
+    }
 
-        let cmd = [channel, flags];
+    /// Set the SPI mode (clock polarity and phase)
+    pub fn spi_mode(mut self, spi_mode: SpiMode) -> Self {
+        self.config.spi_mode = spi_mode;
+        self
+    }
 
-        self.handle.write_control(
-            (RequestType::HOST_TO_DEVICE | RequestType::TYPE_VENDOR).bits(),
-            Commands::SetSpiWord as u8,
-            0,
-            0,
-            &cmd,
-            Duration::from_millis(200),
-        )?;
+    /// Set the automatic chip select mode
+    pub fn cs_mode(mut self, cs_mode: CsMode) -> Self {
+        self.config.cs_mode = cs_mode;
+        self
+    }
 
-        self.spi_clock = clock;
+    /// Set the CS pin drive mode (push-pull or open-drain)
+    pub fn cs_pin_mode(mut self, cs_pin_mode: GpioMode) -> Self {
+        self.config.cs_pin_mode = cs_pin_mode;
+        self
+    }
+
+    /// Set all SPI delays
+    pub fn delays(mut self, delays: SpiDelays) -> Self {
+        self.config.delays = delays;
+        self
+    }
+
+    /// Toggle chip select between each byte
+    pub fn cs_toggle(mut self, enabled: bool) -> Self {
+        self.config.delays.mask.set(DelayMask::CS_TOGGLE, enabled);
+        self
+    }
+
+    /// Set the delay between bytes, zero disables the delay
+    pub fn inter_byte_delay(self, delay: Duration) -> Self {
+        self.map_delays(|d| d.inter_byte(delay))
+    }
+
+    /// Set the delay after CS assertion, zero disables the delay
+    pub fn post_assert_delay(self, delay: Duration) -> Self {
+        self.map_delays(|d| d.post_assert(delay))
+    }
+
+    /// Set the delay prior to CS deassertion, zero disables the delay
+    pub fn pre_deassert_delay(self, delay: Duration) -> Self {
+        self.map_delays(|d| d.pre_deassert(delay))
+    }
+
+    /// Apply a fallible delay update, deferring errors to [`SpiConfigBuilder::build`]
+    fn map_delays(mut self, f: impl FnOnce(SpiDelays) -> Result<SpiDelays, Error>) -> Self {
+        if self.error.is_none() {
+            match f(self.config.delays.clone()) {
+                Ok(d) => self.config.delays = d,
+                Err(e) => self.error = Some(e),
+            }
+        }
+        self
+    }
+
+    /// Validate and build the SPI configuration
+    pub fn build(self) -> Result<SpiConfig, Error> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+
+        self.config.validate()?;
+        Ok(self.config)
+    }
+}
+
+impl SpiDelays {
+    /// Check delay values are consistent with the delay mask
+    pub fn validate(&self) -> Result<(), Error> {
+        let delays = [
+            (DelayMask::INTER_BYE, self.inter_byte, "inter_byte"),
+            (DelayMask::POST_ASSERT, self.post_assert, "post_assert"),
+            (DelayMask::PRE_DEASSERT, self.pre_deassert, "pre_deassert"),
+        ];
+
+        for (flag, value, field) in delays {
+            match (self.mask.contains(flag), value) {
+                (false, v) if v != 0 => {
+                    return Err(Error::InvalidConfig {
+                        field,
+                        reason: "delay is set but not enabled in the delay mask",
+                    })
+                }
+                (true, 0) => {
+                    return Err(Error::InvalidConfig {
+                        field,
+                        reason: "delay is enabled in the delay mask but is zero",
+                    })
+                }
+                _ => (),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl SpiConfig {
+    /// Check the configuration can be applied to the device
+    pub fn validate(&self) -> Result<(), Error> {
+        // CS pins can only be driven as push-pull or open-drain outputs
+        if self.cs_pin_mode == GpioMode::Input {
+            return Err(Error::InvalidConfig {
+                field: "cs_pin_mode",
+                reason: "CS pin mode must be push-pull or open-drain",
+            });
+        }
+
+        self.delays.validate()
+    }
+}
+
+/// Check an SPI channel index is valid
+pub(crate) fn check_channel(channel: u8) -> Result<(), Error> {
+    match channel < GPIO_COUNT {
+        true => Ok(()),
+        false => Err(Error::InvalidConfig {
+            field: "channel",
+            reason: "SPI channel must be in the range 0..=10",
+        }),
+    }
+}
+
+impl<T: UsbContext> Inner<T> {
+    pub(crate) fn spi_configure(&mut self, channel: u8, config: SpiConfig) -> Result<(), Error> {
+        // Check configuration prior to applying
+        check_channel(channel)?;
+        config.validate()?;
+
+        debug!(
+            "Setting SPI channel: {:?} clock: {:?} cs mode: {:?}",
+            channel, config.clock, config.cs_mode
+        );
+
+        // Set SPI channel configuration
+        self.set_spi_word(channel, config.clock, config.spi_mode, config.cs_pin_mode)?;
+
+        // Configure chip select
+        self.set_gpio_chip_select(channel, config.cs_mode.clone())?;
+
+        // Configure delays
+        self.set_spi_delay(channel, config.delays.clone())?;
+
+        self.spi_config[channel as usize] = Some(config);
+        self.spi_channel = channel;
+
+        Ok(())
+    }
+
+    pub(crate) fn set_spi_word(
+        &mut self,
+        channel: u8,
+        clock: SpiClock,
+        spi_mode: SpiMode,
+        cs_pin_mode: GpioMode,
+    ) -> Result<(), Error> {
+        let flags = SpiWord {
+            clock,
+            spi_mode,
+            cs_pin_mode,
+        }
+        .encode();
+
+        debug!("Set SPI word: 0x{:02x?}", flags);
+
+        let cmd = [channel, flags];
+
+        self.worker.control_out(
+            (RequestType::HOST_TO_DEVICE | RequestType::TYPE_VENDOR).bits(),
+            Commands::SetSpiWord as u8,
+            0,
+            0,
+            &cmd,
+            USB_TIMEOUT,
+        )?;
+
+        self.spi_timing[channel as usize].clock = clock;
 
         Ok(())
     }
 
     pub(crate) fn reset(&mut self) -> Result<(), Error> {
-        self.handle.write_control(
+        self.worker.control_out(
             (RequestType::HOST_TO_DEVICE | RequestType::TYPE_VENDOR).bits(),
             Commands::ResetDevice as u8,
             0,
             0,
             &[],
-            Duration::from_millis(200),
+            USB_TIMEOUT,
         )?;
 
         Ok(())
     }
 
     pub(crate) fn set_spi_delay(&mut self, channel: u8, delays: SpiDelays) -> Result<(), Error> {
-        let cmd = [
+        let cmd = SpiDelayWord {
             channel,
-            delays.mask.bits(),
-            delays.inter_byte,
-            delays.post_assert,
-            delays.pre_deassert,
-        ];
+            mask: delays.mask,
+            inter_byte: delays.inter_byte,
+            post_assert: delays.post_assert,
+            pre_deassert: delays.pre_deassert,
+        }
+        .encode();
 
-        self.handle.write_control(
+        self.worker.control_out(
             (RequestType::HOST_TO_DEVICE | RequestType::TYPE_VENDOR).bits(),
             Commands::SetSpiDelay as u8,
             0,
             0,
             &cmd,
-            Duration::from_millis(200),
+            USB_TIMEOUT,
         )?;
 
+        self.spi_timing[channel as usize].delays = delays;
+
         Ok(())
     }
 
@@ -562,172 +1177,177 @@ impl Inner {
         channel: u8,
         cs_mode: CsMode,
     ) -> Result<(), Error> {
-        let cmd = [channel, cs_mode as u8];
+        let cmd = ChipSelect {
+            channel,
+            mode: cs_mode,
+        }
+        .encode();
 
-        self.handle.write_control(
+        self.worker.control_out(
             (RequestType::HOST_TO_DEVICE | RequestType::TYPE_VENDOR).bits(),
             Commands::SetGpioChipSelect as u8,
             0,
             0,
             &cmd,
-            Duration::from_millis(200),
+            USB_TIMEOUT,
         )?;
 
         Ok(())
     }
 
-    /// Read from the SPI device
-    pub(crate) fn spi_read(&mut self, buff: &mut [u8]) -> Result<usize, Error> {
-        let mut cmd = [0u8; 8];
-        cmd[2] = TransferCommand::Read as u8;
-        LE::write_u32(&mut cmd[4..], buff.len() as u32);
-
-        trace!("SPI read (cmd: {:?})", cmd);
-
-        self.handle.write_bulk(
-            self.endpoints.write.address,
-            &cmd,
-            Duration::from_millis(200),
-        )?;
+    /// Read back the SPI configuration applied to a channel
+    ///
+    /// Chip select modes are reported as [`CsMode::Enabled`] or [`CsMode::Disabled`],
+    /// as exclusive mode cannot be distinguished from the device state.
+    pub(crate) fn read_spi_config(&mut self, channel: u8) -> Result<SpiConfig, Error> {
+        check_channel(channel)?;
+
+        // SPI control word, one byte per channel
+        let mut words = [0u8; GPIO_COUNT as usize];
+        self.control_in(Commands::GetSpiWord, 0, 0, &mut words)?;
+        let SpiWord {
+            clock,
+            spi_mode,
+            cs_pin_mode,
+        } = SpiWord::decode(words[channel as usize]);
+
+        // Delays
+        let mut buff = [0u8; SpiDelayWord::LEN];
+        self.control_in(Commands::GetSpiDelay, 0, channel as u16, &mut buff)?;
+        let word = SpiDelayWord::decode(&buff)?;
+        let delays = SpiDelays {
+            mask: word.mask,
+            inter_byte: word.inter_byte,
+            post_assert: word.post_assert,
+            pre_deassert: word.pre_deassert,
+        };
 
-        // TODO: loop for > 64-byte packets
-        let mut index = 0;
+        // Chip select enables, bit per channel
+        let mut buff = [0u8; ChipSelectEnables::LEN];
+        self.control_in(Commands::GetGpioChipSelect, 0, 0, &mut buff)?;
+        let cs_mode = match ChipSelectEnables::decode(&buff)?.channels & (1 << channel) {
+            0 => CsMode::Disabled,
+            _ => CsMode::Enabled,
+        };
 
-        while index < buff.len() {
-            let remainder = if buff.len() > index + 64 {
-                64
-            } else {
-                buff.len() - index
-            };
+        Ok(SpiConfig {
+            clock,
+            spi_mode,
+            cs_mode,
+            cs_pin_mode,
+            delays,
+        })
+    }
 
-            debug!("SPI read (i: {}, rem: {})", index, remainder);
+    /// Release a GPIO allocation, invalidating any outstanding pin handles
+    pub(crate) fn gpio_release(&mut self, pin: u8) {
+        let index = pin as usize;
 
-            let n = self.handle.read_bulk(
-                self.endpoints.read.address,
-                &mut buff[index..index + remainder],
-                Duration::from_millis(200),
-            )?;
+        self.gpio_allocated[index] = false;
+        self.gpio_generation[index] = self.gpio_generation[index].wrapping_add(1);
 
-            index += n;
+        // Forget any SPI channel using this pin for CS
+        for cs in self.spi_cs.iter_mut().filter(|cs| **cs == Some(pin)) {
+            *cs = None;
         }
-
-        trace!("SPI read done");
-
-        Ok(index)
     }
 
-    /// Write to the SPI device
-    pub(crate) fn spi_write(&mut self, buff: &[u8]) -> Result<(), Error> {
-        let mut cmd = vec![0u8; buff.len() + 8];
-
-        cmd[2] = TransferCommand::Write as u8;
-        LE::write_u32(&mut cmd[4..], buff.len() as u32);
-        (&mut cmd[8..]).copy_from_slice(buff);
-
-        let t = self.spi_clock.transfer_time(buff.len() as u64);
-        trace!("SPI write (cmd: {:?} time: {} us)", cmd, t.as_micros());
+    /// Release an SPI channel, disabling automatic CS and releasing the CS pin if allocated
+    pub(crate) fn spi_release(&mut self, channel: u8) -> Result<Option<u8>, Error> {
+        let index = channel as usize;
 
-        self.handle.write_bulk(
-            self.endpoints.write.address,
-            &cmd,
-            Duration::from_millis(200),
-        )?;
-
-        // Wait for operation to complete so we don't confuse the device
-        // IMPORTANT NOTE: THIS IS A LOAD BEARING DELAY
-        self.delay(t);
+        self.spi_generation[index] = self.spi_generation[index].wrapping_add(1);
 
-        //self.delay(Duration::from_millis(1));
+        self.set_gpio_chip_select(channel, CsMode::Disabled)?;
 
-        trace!("SPI write done");
+        let cs = self.spi_cs[index].take();
+        if let Some(pin) = cs {
+            self.gpio_release(pin);
+        }
 
-        Ok(())
+        Ok(cs)
     }
 
-    fn delay(&mut self, d: Duration) {
-        let n = SystemTime::now();
-        while n.elapsed().unwrap() < d {}
+    /// Fetch transfer statistics
+    pub(crate) fn stats(&self) -> Stats {
+        self.worker.stats()
     }
 
-    // Transfer (write-read) to and from the SPI device
-    pub(crate) fn spi_write_read(
-        &mut self,
-        buff_out: &[u8],
-        buff_in: &mut [u8],
-    ) -> Result<usize, Error> {
-        let mut cmd = vec![0u8; buff_out.len() + 8];
-
-        // TODO: split this into while loop so long packet writes work correctly
-        // At the moment the read buffer will probably be overwritten
-        cmd[2] = TransferCommand::WriteRead as u8;
-        LE::write_u32(&mut cmd[4..], buff_out.len() as u32);
-        (&mut cmd[8..]).copy_from_slice(buff_out);
-
-        let total_time = self.spi_clock.transfer_time(buff_out.len() as u64);
-        trace!(
-            "SPI transfer (cmd: {:?} time: {} us)",
-            cmd,
-            total_time.as_micros()
-        );
-
-        self.handle.write_bulk(
-            self.endpoints.write.address,
-            &cmd,
-            Duration::from_millis(200),
-        )?;
-
-        trace!("SPI transfer await resp");
-
-        let mut index = 0;
-
-        while index < buff_in.len() {
-            let remainder = if buff_in.len() > index + 64 {
-                64
-            } else {
-                buff_in.len() - index
-            };
-
-            let t = self.spi_clock.transfer_time(buff_out.len() as u64);
-
-            trace!(
-                "SPI read (len: {}, index: {}, rem: {}, time: {} us)",
-                buff_in.len(),
-                index,
-                remainder,
-                t.as_micros()
-            );
-
-            let n = self.handle.read_bulk(
-                self.endpoints.read.address,
-                &mut buff_in[index..index + remainder],
-                Duration::from_millis(200),
-            )?;
+    /// Reset transfer statistics, returning the previous values
+    pub(crate) fn reset_stats(&self) -> Stats {
+        self.worker.reset_stats()
+    }
 
-            index += n;
+    /// Fetch a handle for issuing SPI transfers without holding the device lock,
+    /// using the timing of the most recently configured channel
+    pub(crate) fn spi_transfers(&self) -> SpiTransfers {
+        self.spi_channel_transfers(self.spi_channel)
+    }
 
-            // Wait for operation to complete before we continue
-            self.delay(t);
+    /// Fetch a handle for issuing SPI transfers on `channel` without holding the device lock
+    pub(crate) fn spi_channel_transfers(&self, channel: u8) -> SpiTransfers {
+        SpiTransfers {
+            worker: self.worker.clone(),
+            bus: self.spi_bus.clone(),
+            timing: self.spi_timing[channel as usize].clone(),
         }
+    }
 
-        trace!("SPI transfer done");
+    /// Issue a vendor IN control request
+    pub(crate) fn control_in(
+        &self,
+        command: Commands,
+        value: u16,
+        index: u16,
+        buff: &mut [u8],
+    ) -> Result<usize, Error> {
+        self.worker.control_in(
+            (RequestType::DEVICE_TO_HOST | RequestType::TYPE_VENDOR).bits(),
+            command as u8,
+            value,
+            index,
+            buff,
+            USB_TIMEOUT,
+        )
+    }
 
-        Ok(index)
+    /// Issue a vendor OUT control request
+    pub(crate) fn control_out(
+        &self,
+        command: Commands,
+        value: u16,
+        index: u16,
+        buff: &[u8],
+    ) -> Result<usize, Error> {
+        self.worker.control_out(
+            (RequestType::HOST_TO_DEVICE | RequestType::TYPE_VENDOR).bits(),
+            command as u8,
+            value,
+            index,
+            buff,
+            USB_TIMEOUT,
+        )
     }
 
     /// Fetch the CP2130 chip version
     pub(crate) fn version(&mut self) -> Result<u16, Error> {
+        self.version_timeout(USB_TIMEOUT)
+    }
+
+    /// Fetch the CP2130 chip version, failing if no response is received within `timeout`
+    pub(crate) fn version_timeout(&mut self, timeout: Duration) -> Result<u16, Error> {
         let mut buff = [0u8; 2];
 
-        self.handle.read_control(
+        self.worker.control_in(
             (RequestType::DEVICE_TO_HOST | RequestType::TYPE_VENDOR).bits(),
             Commands::GetReadOnlyVersion as u8,
             0,
             0,
             &mut buff,
-            Duration::from_millis(200),
+            timeout,
         )?;
 
-        let version = LE::read_u16(&buff);
+        let version = protocol::decode_version(&buff)?;
 
         Ok(version)
     }
@@ -739,9 +1359,9 @@ impl Inner {
         mode: GpioMode,
         level: GpioLevel,
     ) -> Result<(), Error> {
-        assert!(pin <= 10);
+        check_pin(pin)?;
 
-        let cmd = [pin, mode as u8, level as u8];
+        let cmd = GpioModeLevel { pin, mode, level }.encode();
 
         trace!(
             "GPIO set pin: {} mode: {:?} level: {:?} (cmd: {:?})",
@@ -751,60 +1371,829 @@ impl Inner {
             cmd
         );
 
-        self.handle.write_control(
+        self.worker.control_out(
             (RequestType::HOST_TO_DEVICE | RequestType::TYPE_VENDOR).bits(),
             Commands::SetGpioModeAndLevel as u8,
             0,
             0,
             &cmd,
-            Duration::from_millis(200),
+            USB_TIMEOUT,
         )?;
 
+        self.gpio_state[pin as usize] = Some((mode, level));
+
         Ok(())
     }
 
+    /// Fetch the most recently applied mode and level for a pin
+    pub(crate) fn gpio_applied(&self, pin: u8) -> Option<(GpioMode, GpioLevel)> {
+        self.gpio_state.get(pin as usize).copied().flatten()
+    }
+
     /// Fetch the values for all GPIO pins
     pub(crate) fn get_gpio_values(&mut self) -> Result<GpioLevels, Error> {
+        self.get_gpio_values_timeout(USB_TIMEOUT)
+    }
+
+    /// Fetch the values for all GPIO pins, failing if no response is received within `timeout`
+    pub(crate) fn get_gpio_values_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<GpioLevels, Error> {
         let mut buff = [0u8; 2];
 
-        self.handle.read_control(
+        self.worker.control_in(
             (RequestType::DEVICE_TO_HOST | RequestType::TYPE_VENDOR).bits(),
             Commands::GetGpioValues as u8,
             0,
             0,
             &mut buff,
-            Duration::from_millis(200),
+            timeout,
         )?;
 
         // Inexplicably big endian here
-        let values = GpioLevels::from_bits_truncate(BE::read_u16(&buff));
+        let values = GpioLevels::decode(&buff)?;
 
         trace!("GPIO get pins (values: {:?})", values);
 
         Ok(values)
     }
 
+    /// Set the levels for multiple GPIO output pins, pins not in `mask` are unchanged
+    pub(crate) fn set_gpio_values(
+        &mut self,
+        levels: GpioLevels,
+        mask: GpioLevels,
+    ) -> Result<(), Error> {
+        let cmd = GpioValues { levels, mask }.encode();
+
+        trace!("GPIO set values (levels: {:?}, mask: {:?})", levels, mask);
+
+        self.control_out(Commands::SetGpioValues, 0, 0, &cmd)?;
+
+        // Track output levels for restoring configuration
+        for (pin, level) in levels.iter_pins().filter(|(p, _)| mask.pin(*p)) {
+            if let Some((mode, l)) = &mut self.gpio_state[pin as usize] {
+                if *mode != GpioMode::Input {
+                    *l = level;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Configure GPIO.4 as an event counter, starting from `count`
+    pub(crate) fn set_event_counter(
+        &mut self,
+        mode: EventCounterMode,
+        count: u16,
+    ) -> Result<(), Error> {
+        let cmd = EventCounter {
+            mode: Some(mode),
+            overflow: false,
+            count,
+        }
+        .encode();
+
+        debug!("Set event counter (mode: {:?}, count: {})", mode, count);
+
+        self.control_out(Commands::SetEventCOunter, 0, 0, &cmd)?;
+
+        Ok(())
+    }
+
+    /// Set the clock output (GPIO.5) divider
+    pub(crate) fn set_clock_divider(&mut self, divider: u8) -> Result<(), Error> {
+        debug!(
+            "Set clock divider: {} ({} Hz)",
+            divider,
+            clock_out_frequency(divider)
+        );
+
+        self.control_out(Commands::SetClockDivider, 0, 0, &[divider])?;
+
+        Ok(())
+    }
+
+    /// Fetch the clock output (GPIO.5) divider
+    pub(crate) fn clock_divider(&mut self) -> Result<u8, Error> {
+        let mut buff = [0u8; 1];
+
+        self.control_in(Commands::GetClockDivider, 0, 0, &mut buff)?;
+
+        Ok(buff[0])
+    }
+
+    /// Fetch the event counter state
+    pub(crate) fn event_counter(&mut self) -> Result<EventCounter, Error> {
+        let mut buff = [0u8; EventCounter::LEN];
+
+        self.control_in(Commands::GetEventCounter, 0, 0, &mut buff)?;
+
+        let counter = EventCounter::decode(&buff)?;
+
+        trace!("Event counter: {:?}", counter);
+
+        Ok(counter)
+    }
+
+    /// Check the RTR pin has not been allocated as a plain GPIO
+    pub(crate) fn check_rtr(&self) -> Result<(), Error> {
+        match self.gpio_allocated[RTR_PIN as usize] {
+            true => Err(Error::GpioInUse),
+            false => Ok(()),
+        }
+    }
+
     /// Fetch the value for a given GPIO pin
     pub(crate) fn get_gpio_level(&mut self, pin: u8) -> Result<bool, Error> {
-        assert!(pin <= 10);
-
-        let levels = self.get_gpio_values()?;
-
-        let v = match pin {
-            0 => levels.contains(GpioLevels::GPIO_0),
-            1 => levels.contains(GpioLevels::GPIO_1),
-            2 => levels.contains(GpioLevels::GPIO_2),
-            3 => levels.contains(GpioLevels::GPIO_3),
-            4 => levels.contains(GpioLevels::GPIO_4),
-            5 => levels.contains(GpioLevels::GPIO_5),
-            6 => levels.contains(GpioLevels::GPIO_6),
-            7 => levels.contains(GpioLevels::GPIO_7),
-            8 => levels.contains(GpioLevels::GPIO_8),
-            9 => levels.contains(GpioLevels::GPIO_9),
-            10 => levels.contains(GpioLevels::GPIO_10),
-            _ => panic!("invalid pin {}", pin),
-        };
+        self.get_gpio_level_timeout(pin, USB_TIMEOUT)
+    }
+
+    /// Fetch the value for a given GPIO pin, failing if no response is received within `timeout`
+    pub(crate) fn get_gpio_level_timeout(
+        &mut self,
+        pin: u8,
+        timeout: Duration,
+    ) -> Result<bool, Error> {
+        check_pin(pin)?;
+
+        let levels = self.get_gpio_values_timeout(timeout)?;
+
+        let v = levels.pin(pin);
 
         Ok(v)
     }
 }
+
+/// Handle for issuing SPI transfers via the worker without holding the device lock
+///
+/// Transfers are submitted to the worker as single jobs so they are not interleaved
+/// with other bulk operations, control transfers (GPIO etc.) may proceed concurrently.
+pub(crate) struct SpiTransfers {
+    worker: Arc<Worker>,
+    bus: Arc<Mutex<SpiScratch>>,
+    timing: SpiTiming,
+}
+
+/// Reusable command and response buffers, owned by the SPI bus lock
+#[derive(Debug, Default)]
+pub(crate) struct SpiScratch {
+    cmd: Vec<u8>,
+    resp: Vec<u8>,
+}
+
+impl SpiScratch {
+    /// Reset the command buffer with a transfer header for `len` bytes
+    fn header(&mut self, command: TransferCommand, len: usize) {
+        self.cmd.clear();
+        self.push_header(command, len);
+    }
+
+    /// Append a transfer header for `len` bytes, following any prior commands
+    fn push_header(&mut self, command: TransferCommand, len: usize) {
+        debug_assert!(len <= MAX_TRANSFER_LEN);
+
+        let header = TransferHeader {
+            command,
+            len: len as u32,
+        };
+
+        self.cmd.extend_from_slice(&header.encode());
+    }
+}
+
+/// Locked SPI bus, held to keep multi-transfer sequences together
+pub(crate) struct SpiBus<'a> {
+    transfers: &'a SpiTransfers,
+    scratch: MutexGuard<'a, SpiScratch>,
+}
+
+impl SpiTransfers {
+    /// Use `timeout` for each USB transfer in place of the timeout scaled with the bus time
+    pub(crate) fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timing.timeout = Some(timeout);
+        self
+    }
+
+    /// Acquire the SPI bus lock
+    pub(crate) fn lock(&self) -> SpiBus<'_> {
+        SpiBus {
+            transfers: self,
+            scratch: self.bus.lock().unwrap(),
+        }
+    }
+}
+
+impl SpiBus<'_> {
+    /// Run a job against the scratch buffers on the worker, returning them for reuse
+    fn run<R: Send + 'static>(
+        &mut self,
+        f: impl FnOnce(&dyn Transport, &SpiTiming, &mut SpiScratch) -> R + Send + 'static,
+    ) -> Result<R, Error> {
+        let mut scratch = std::mem::take(&mut *self.scratch);
+        let timing = self.transfers.timing.clone();
+
+        let (r, scratch) = self.transfers.worker.call(move |t| {
+            let r = f(t, &timing, &mut scratch);
+            (r, scratch)
+        })?;
+
+        *self.scratch = scratch;
+
+        Ok(r)
+    }
+
+    /// Update statistics with the result of a transfer
+    fn account<R>(&self, written: usize, read: usize, res: Result<R, Error>) -> Result<R, Error> {
+        let timing = &self.transfers.timing;
+
+        self.transfers.worker.record(|s| match &res {
+            Ok(_) => {
+                s.transfers += 1;
+                s.bytes_written += written as u64;
+                s.bytes_read += read as u64;
+                s.bus_time += timing.transfer_time(written.max(read));
+            }
+            Err(Error::Io(_)) => (),
+            Err(_) => s.usb_errors += 1,
+        });
+
+        res
+    }
+
+    /// Read from the SPI device, split into commands of at most [`MAX_TRANSFER_LEN`] bytes
+    pub(crate) fn read(&mut self, buff: &mut [u8]) -> Result<usize, Error> {
+        let mut index = 0;
+
+        for r in segments(buff.len()) {
+            let len = r.len();
+            let n = self
+                .read_segment(&mut buff[r])
+                .map_err(|e| resume(index, &buff[..index], e))?;
+            index += n;
+
+            if n < len {
+                break;
+            }
+        }
+
+        Ok(index)
+    }
+
+    /// Read from the SPI device with a single transfer command
+    fn read_segment(&mut self, buff: &mut [u8]) -> Result<usize, Error> {
+        let len = buff.len();
+        self.scratch.header(TransferCommand::Read, len);
+        self.scratch.resp.resize(len, 0);
+
+        let res = self.run(|t, timing, s| spi_read(t, timing, &s.cmd, &mut s.resp, None).0);
+        let n = self.account(0, len, res.and_then(|r| r))?;
+
+        buff[..n].copy_from_slice(&self.scratch.resp[..n]);
+
+        Ok(n)
+    }
+
+    /// Read from the SPI device once the RTR (ready to read) pin is asserted
+    ///
+    /// Timeouts are retried while `running` is set. Once cleared the outstanding command
+    /// is aborted and the timeout returned, wrapped in [`Error::Partial`] where some
+    /// data was read.
+    pub(crate) fn read_rtr(
+        &mut self,
+        buff: &mut [u8],
+        running: Arc<AtomicBool>,
+    ) -> Result<usize, Error> {
+        let mut index = 0;
+
+        for r in segments(buff.len()) {
+            let len = r.len();
+            let n = self
+                .read_rtr_segment(&mut buff[r], running.clone())
+                .map_err(|e| resume(index, &buff[..index], e))?;
+            index += n;
+
+            if n < len {
+                break;
+            }
+        }
+
+        Ok(index)
+    }
+
+    /// Read from the SPI device once RTR is asserted, with a single transfer command
+    fn read_rtr_segment(
+        &mut self,
+        buff: &mut [u8],
+        running: Arc<AtomicBool>,
+    ) -> Result<usize, Error> {
+        let len = buff.len();
+        self.scratch.header(TransferCommand::ReadWithRTR, len);
+        self.scratch.resp.resize(len, 0);
+
+        let res =
+            self.run(move |t, timing, s| spi_read(t, timing, &s.cmd, &mut s.resp, Some(&running)));
+
+        let res = res.map(|(r, retries)| {
+            self.transfers.worker.record(|s| s.retries += retries);
+            r
+        });
+        let n = self.account(0, len, res.and_then(|r| r))?;
+
+        buff[..n].copy_from_slice(&self.scratch.resp[..n]);
+
+        Ok(n)
+    }
+
+    /// Write to the SPI device, split into commands of at most [`MAX_TRANSFER_LEN`] bytes
+    pub(crate) fn write(&mut self, buff: &[u8]) -> Result<(), Error> {
+        for r in segments(buff.len()) {
+            let start = r.start;
+            self.write_segment(&buff[r])
+                .map_err(|e| resume(start, &[], e))?;
+        }
+
+        Ok(())
+    }
+
+    /// Write to the SPI device with a single transfer command
+    fn write_segment(&mut self, buff: &[u8]) -> Result<(), Error> {
+        self.scratch.header(TransferCommand::Write, buff.len());
+        self.scratch.cmd.extend_from_slice(buff);
+
+        let res = self.run(|t, timing, s| spi_write(t, timing, &s.cmd));
+        self.account(buff.len(), 0, res.and_then(|r| r))
+    }
+
+    /// Write `len` bytes from a reader to the SPI device as a single transfer,
+    /// without buffering the whole payload
+    ///
+    /// If the reader ends early the current command is completed with zeros and an error
+    /// returned, commands for the remainder of an oversized transfer are not issued.
+    pub(crate) fn write_stream(&mut self, reader: &mut dyn Read, len: usize) -> Result<(), Error> {
+        for r in segments(len) {
+            let start = r.start;
+            self.write_stream_segment(reader, r.len())
+                .map_err(|e| match e {
+                    Error::Io(_) => e,
+                    e => resume(start, &[], e),
+                })?;
+        }
+
+        Ok(())
+    }
+
+    /// Write `len` bytes from a reader with a single transfer command
+    fn write_stream_segment(&mut self, reader: &mut dyn Read, len: usize) -> Result<(), Error> {
+        let packet_size = self.transfers.worker.max_packet_size();
+        let chunk_len = packet_size * PIPELINE_PACKETS;
+
+        self.scratch.header(TransferCommand::Write, len);
+
+        let mut remaining = len;
+        let mut eof = None;
+
+        loop {
+            // Fill the command buffer up to a chunk, the first chunk includes the header
+            let start = self.scratch.cmd.len();
+            let n = (chunk_len - start).min(remaining);
+            self.scratch.cmd.resize(start + n, 0);
+
+            if eof.is_none() {
+                if let Err(e) = reader.read_exact(&mut self.scratch.cmd[start..]) {
+                    self.scratch.cmd[start..].fill(0);
+                    eof = Some(e);
+                }
+            }
+
+            remaining -= n;
+            let last = remaining == 0;
+
+            trace!("SPI write stream (chunk: {}, remaining: {})", n, remaining);
+
+            let res = self.run(move |t, timing, s| -> Result<(), Error> {
+                write_chunked(t, timing, &s.cmd)?;
+
+                // Wait for the final buffered packet to be shifted out
+                if last {
+                    let d = timing.transfer_time(len.min(packet_size));
+                    wait_until(Instant::now() + d, DEFAULT_SPIN_THRESHOLD);
+                }
+
+                Ok(())
+            });
+
+            if let Err(e) = res.and_then(|r| r) {
+                return self.account(0, 0, Err(e));
+            }
+
+            if last {
+                break;
+            }
+
+            self.scratch.cmd.clear();
+        }
+
+        let res = match eof {
+            Some(e) => Err(Error::Io(e)),
+            None => Ok(()),
+        };
+        self.account(len, 0, res)
+    }
+
+    /// Write to the bulk OUT endpoint without transfer command framing
+    pub(crate) fn bulk_out_raw(&mut self, buff: &[u8], timeout: Duration) -> Result<usize, Error> {
+        self.scratch.cmd.clear();
+        self.scratch.cmd.extend_from_slice(buff);
+
+        let res = self.run(move |t, _, s| t.bulk_out(&s.cmd, timeout));
+        self.account_raw(res.and_then(|r| r))
+    }
+
+    /// Read from the bulk IN endpoint without transfer command framing
+    pub(crate) fn bulk_in_raw(
+        &mut self,
+        buff: &mut [u8],
+        timeout: Duration,
+    ) -> Result<usize, Error> {
+        self.scratch.resp.resize(buff.len(), 0);
+
+        let res = self.run(move |t, _, s| t.bulk_in(&mut s.resp, timeout));
+        let n = self.account_raw(res.and_then(|r| r))?;
+
+        buff[..n].copy_from_slice(&self.scratch.resp[..n]);
+
+        Ok(n)
+    }
+
+    /// Fetch the bulk endpoint packet size
+    pub(crate) fn max_packet_size(&self) -> usize {
+        self.transfers.worker.max_packet_size()
+    }
+
+    /// Count failed raw transfers, which are not included in SPI statistics
+    fn account_raw<R>(&self, res: Result<R, Error>) -> Result<R, Error> {
+        if res.is_err() {
+            self.transfers.worker.record(|s| s.usb_errors += 1);
+        }
+        res
+    }
+
+    // Transfer (write-read) to and from the SPI device, split into commands of at most
+    // MAX_TRANSFER_LEN bytes
+    //
+    // The transfer runs for the longer of the two buffers, zeros are written beyond
+    // `buff_out` and the response beyond `buff_in` is discarded.
+    pub(crate) fn write_read(
+        &mut self,
+        buff_out: &[u8],
+        buff_in: &mut [u8],
+    ) -> Result<usize, Error> {
+        let len = buff_out.len().max(buff_in.len());
+        self.scratch.cmd.clear();
+
+        for r in segments(len) {
+            let out = r.start.min(buff_out.len())..r.end.min(buff_out.len());
+            let end = self.scratch.cmd.len() + TransferHeader::LEN + r.len();
+
+            self.scratch
+                .push_header(TransferCommand::WriteRead, r.len());
+            self.scratch.cmd.extend_from_slice(&buff_out[out]);
+            self.scratch.cmd.resize(end, 0);
+        }
+
+        self.transfer(len, buff_in)
+    }
+
+    /// Transfer to and from the SPI device, replacing the buffer contents with the response
+    pub(crate) fn write_read_in_place(&mut self, buff: &mut [u8]) -> Result<usize, Error> {
+        self.scratch.cmd.clear();
+
+        for r in segments(buff.len()) {
+            self.scratch
+                .push_header(TransferCommand::WriteRead, r.len());
+            self.scratch.cmd.extend_from_slice(&buff[r]);
+        }
+
+        self.transfer(buff.len(), buff)
+    }
+
+    /// Read from the SPI device using a transfer, clocking out zeros
+    pub(crate) fn write_zeros_read(&mut self, buff: &mut [u8]) -> Result<usize, Error> {
+        self.scratch.cmd.clear();
+
+        for r in segments(buff.len()) {
+            let end = self.scratch.cmd.len() + TransferHeader::LEN + r.len();
+
+            self.scratch
+                .push_header(TransferCommand::WriteRead, r.len());
+            self.scratch.cmd.resize(end, 0);
+        }
+
+        self.transfer(buff.len(), buff)
+    }
+
+    /// Run prepared write-read commands for `len` bytes, copying the response into `buff_in`
+    ///
+    /// The whole response is read so no data is left pending on the IN endpoint for
+    /// following transfers, bytes beyond the length of `buff_in` are discarded.
+    fn transfer(&mut self, len: usize, buff_in: &mut [u8]) -> Result<usize, Error> {
+        self.scratch.resp.resize(len, 0);
+
+        let res = self.run(|t, timing, s| spi_write_read(t, timing, &s.cmd, &mut s.resp));
+        let n = self
+            .account(len, len, res.and_then(|r| r))
+            .map_err(|e| match e {
+                // Report progress only for the response copied into `buff_in`
+                Error::Partial {
+                    completed,
+                    data,
+                    source,
+                } => {
+                    let n = completed.min(buff_in.len());
+                    partial(n, &data[..n], *source)
+                }
+                e => e,
+            })?;
+        let n = n.min(buff_in.len());
+
+        buff_in[..n].copy_from_slice(&self.scratch.resp[..n]);
+
+        Ok(n)
+    }
+}
+
+/// Read from the SPI device, `cmd` contains the read command header
+///
+/// Where `retry` is provided, timeouts are retried until it is cleared,
+/// returning the number of retries alongside the result.
+fn spi_read(
+    t: &dyn Transport,
+    timing: &SpiTiming,
+    cmd: &[u8],
+    buff: &mut [u8],
+    retry: Option<&AtomicBool>,
+) -> (Result<usize, Error>, u64) {
+    let mut retries = 0;
+    let res = spi_read_retry(t, timing, cmd, buff, retry, &mut retries);
+    (res, retries)
+}
+
+/// Read from the SPI device, counting retried timeouts
+fn spi_read_retry(
+    t: &dyn Transport,
+    timing: &SpiTiming,
+    cmd: &[u8],
+    buff: &mut [u8],
+    retry: Option<&AtomicBool>,
+    retries: &mut u64,
+) -> Result<usize, Error> {
+    trace!("SPI read (cmd: {:?})", cmd);
+
+    t.bulk_out(cmd, USB_TIMEOUT)?;
+
+    let packet_size = t.max_packet_size();
+    let mut index = 0;
+
+    while index < buff.len() {
+        let remainder = (buff.len() - index).min(packet_size);
+
+        debug!("SPI read (i: {}, rem: {})", index, remainder);
+
+        let n = match t.bulk_in(
+            &mut buff[index..index + remainder],
+            timing.timeout(remainder),
+        ) {
+            Ok(n) => n,
+            Err(e) if is_timeout(&e) && retry.is_some_and(|r| r.load(Ordering::Relaxed)) => {
+                *retries += 1;
+                continue;
+            }
+            Err(e) if is_timeout(&e) && retry.is_some() => {
+                // Stopped, cancel the gated read so it does not complete into later transfers
+                abort_rtr(t)?;
+                return Err(partial(index, &buff[..index], e));
+            }
+            Err(e) => return Err(partial(index, &buff[..index], e)),
+        };
+
+        index += n;
+    }
+
+    trace!("SPI read done");
+
+    Ok(index)
+}
+
+/// Timeout for draining data returned by an aborted ReadWithRTR command
+const RTR_DRAIN_TIMEOUT: Duration = Duration::from_millis(10);
+
+/// Abort an outstanding ReadWithRTR command
+///
+/// Setting RTR stop cancels the command, any data already returned is drained from the
+/// IN endpoint so it is not read by following transfers, then RTR stop is cleared so
+/// later gated reads may proceed.
+fn abort_rtr(t: &dyn Transport) -> Result<(), Error> {
+    let request_type = (RequestType::HOST_TO_DEVICE | RequestType::TYPE_VENDOR).bits();
+    let set_stop = |stop: bool| {
+        t.control_out(
+            request_type,
+            Commands::SetRtrStop as u8,
+            0,
+            0,
+            &[stop as u8],
+            USB_TIMEOUT,
+        )
+    };
+
+    debug!("Aborting RTR read");
+
+    set_stop(true)?;
+
+    let mut drain = vec![0u8; t.max_packet_size()];
+    loop {
+        match t.bulk_in(&mut drain, RTR_DRAIN_TIMEOUT) {
+            Ok(0) => break,
+            Ok(n) => trace!("Discarding {} bytes from aborted RTR read", n),
+            Err(e) if is_timeout(&e) => break,
+            Err(e) => return Err(e),
+        }
+    }
+
+    set_stop(false)?;
+
+    Ok(())
+}
+
+/// Write to the SPI device, `cmd` contains the write command header and payload
+///
+/// The device only acknowledges an OUT packet once it has room to buffer it, so
+/// completion of the bulk OUT means all but the final packet has been shifted out.
+fn spi_write(t: &dyn Transport, timing: &SpiTiming, cmd: &[u8]) -> Result<(), Error> {
+    let len = cmd.len() - TransferHeader::LEN;
+
+    trace!("SPI write (cmd: {:?})", cmd);
+
+    write_chunked(t, timing, cmd)?;
+
+    // Wait only for the final buffered packet to be shifted out so we don't confuse the device,
+    // the CP2130 reports no completion for writes (RTR only gates reads)
+    let d = timing.transfer_time(len.min(t.max_packet_size()));
+    trace!("SPI write accepted (remaining: {} us)", d.as_micros());
+
+    wait_until(Instant::now() + d, DEFAULT_SPIN_THRESHOLD);
+
+    trace!("SPI write done");
+
+    Ok(())
+}
+
+/// Write a command in packet-aligned chunks, checking progress after each chunk
+///
+/// The device accepts each chunk only as buffered data is shifted out, so
+/// timeouts are scaled per chunk rather than for the whole transfer.
+fn write_chunked(t: &dyn Transport, timing: &SpiTiming, cmd: &[u8]) -> Result<(), Error> {
+    let mut index: usize = 0;
+
+    for chunk in cmd.chunks(t.max_packet_size() * PIPELINE_PACKETS) {
+        let n = t
+            .bulk_out(chunk, timing.timeout(chunk.len()))
+            .map_err(|e| partial(index.saturating_sub(TransferHeader::LEN), &[], e))?;
+        index += n;
+
+        trace!("SPI write (index: {}, len: {})", index, cmd.len());
+
+        if n != chunk.len() {
+            return Err(Error::ShortTransfer {
+                expected: cmd.len(),
+                actual: index,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Split a transfer of `len` bytes into ranges issued as separate transfer commands,
+/// empty transfers are issued as a single command
+fn segments(len: usize) -> impl Iterator<Item = Range<usize>> {
+    (0..len.div_ceil(MAX_TRANSFER_LEN).max(1)).map(move |i| {
+        let start = i * MAX_TRANSFER_LEN;
+        start..(start + MAX_TRANSFER_LEN).min(len)
+    })
+}
+
+/// Offset a segment error by the progress of preceding segments
+fn resume(completed: usize, prior: &[u8], e: Error) -> Error {
+    match e {
+        Error::Partial {
+            completed: n,
+            data,
+            source,
+        } => Error::Partial {
+            completed: completed + n,
+            data: [prior, &data].concat(),
+            source,
+        },
+        e => partial(completed, prior, e),
+    }
+}
+
+/// Wrap an error with transfer progress where some data has been transferred
+fn partial(completed: usize, data: &[u8], e: Error) -> Error {
+    match completed {
+        0 => e,
+        _ => Error::Partial {
+            completed,
+            data: data.to_vec(),
+            source: Box::new(e),
+        },
+    }
+}
+
+/// Check whether an error is a USB transfer timeout
+fn is_timeout(e: &Error) -> bool {
+    match e {
+        Error::Usb(rusb::Error::Timeout) => true,
+        #[cfg(feature = "nusb")]
+        Error::NusbTransfer(::nusb::transfer::TransferError::Cancelled) => true,
+        _ => false,
+    }
+}
+
+// Transfer (write-read) to and from the SPI device, `cmd` contains one or more command
+// headers and payloads, split as by `segments`, and `buff_in` the combined response
+//
+// The bulk OUTs are issued from a scoped thread so that IN transfers are already
+// pending as the device begins returning data, with each IN covering multiple packets.
+// Following commands are written while the response to earlier commands is read, as the
+// device only accepts each OUT packet once it has room to buffer it.
+// Completion is detected by the returned byte counts, so no delay is required.
+fn spi_write_read(
+    t: &dyn Transport,
+    timing: &SpiTiming,
+    cmd: &[u8],
+    buff_in: &mut [u8],
+) -> Result<usize, Error> {
+    let len = buff_in.len();
+
+    trace!(
+        "SPI transfer (cmd: {:?} time: {} us)",
+        cmd,
+        timing.transfer_time(len).as_micros()
+    );
+
+    let packet_size = t.max_packet_size();
+
+    let index = std::thread::scope(|s| {
+        let write = s.spawn(|| {
+            let mut offset = 0;
+
+            for c in cmd.chunks(TransferHeader::LEN + MAX_TRANSFER_LEN) {
+                write_chunked(t, timing, c).map_err(|e| resume(offset, &[], e))?;
+                offset += c.len() - TransferHeader::LEN;
+            }
+
+            Ok::<_, Error>(())
+        });
+
+        trace!("SPI transfer await resp");
+
+        let mut index = 0;
+
+        while index < buff_in.len() {
+            let remainder = (buff_in.len() - index).min(packet_size * PIPELINE_PACKETS);
+
+            trace!(
+                "SPI read (len: {}, index: {}, rem: {})",
+                buff_in.len(),
+                index,
+                remainder,
+            );
+
+            let n = match t.bulk_in(
+                &mut buff_in[index..index + remainder],
+                timing.timeout(remainder),
+            ) {
+                Ok(n) => n,
+                Err(e) => {
+                    // Collect the write result so the OUT error is reported where relevant
+                    write.join().unwrap()?;
+                    return Err(partial(index, &buff_in[..index], e));
+                }
+            };
+
+            index += n;
+        }
+
+        write.join().unwrap()?;
+
+        Ok(index)
+    })?;
+
+    trace!("SPI transfer done");
+
+    Ok(index)
+}
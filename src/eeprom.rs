@@ -0,0 +1,208 @@
+//! CP2130 Driver SPI EEPROM
+//!
+//! Support for 25xx-series SPI EEPROMs, which unlike NOR flash need no erase but are
+//! written in small pages with write-in-progress polling after each page.
+//! Address width is derived from the device capacity, with the ninth address bit
+//! carried in the instruction for 512 byte devices (eg. 25xx040).
+//!
+//! Copyright 2019 Ryan Kurte
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use embedded_hal::spi::{Operation, SpiDevice};
+use log::{debug, trace};
+
+use crate::flash::STATUS_POLL_INTERVAL;
+use crate::Error;
+
+/// SPI EEPROM commands
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum EepromCommand {
+    Read = 0x03,
+    Write = 0x02,
+    WriteDisable = 0x04,
+    WriteEnable = 0x06,
+    ReadStatus = 0x05,
+    WriteStatus = 0x01,
+}
+
+/// Status register write-in-progress flag
+pub const STATUS_WIP: u8 = 1 << 0;
+
+/// Instruction bit carrying address bit 8 for 512 byte devices
+const A8_BIT: u8 = 1 << 3;
+
+/// EEPROM page and device sizes
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EepromGeometry {
+    /// Page write size in bytes
+    pub page_size: usize,
+    /// Device capacity in bytes
+    pub capacity: usize,
+}
+
+impl EepromGeometry {
+    /// Create a geometry for a device of the provided capacity and page size
+    pub fn new(capacity: usize, page_size: usize) -> Result<Self, Error> {
+        if page_size == 0 || capacity == 0 || capacity > 1 << 24 {
+            return Err(Error::InvalidConfig {
+                field: "eeprom",
+                reason: "capacity must be between 1 byte and 16 MiB with a non-zero page size",
+            });
+        }
+
+        Ok(Self {
+            page_size,
+            capacity,
+        })
+    }
+
+    /// Number of address bytes sent with each command
+    pub fn address_bytes(&self) -> usize {
+        match self.capacity {
+            0..=512 => 1,
+            513..=65536 => 2,
+            _ => 3,
+        }
+    }
+
+    /// Encode a command and address
+    fn command(&self, cmd: EepromCommand, address: u32) -> Vec<u8> {
+        let a = address.to_be_bytes();
+
+        match self.address_bytes() {
+            1 if address >= 256 => vec![cmd as u8 | A8_BIT, a[3]],
+            1 => vec![cmd as u8, a[3]],
+            2 => vec![cmd as u8, a[2], a[3]],
+            _ => vec![cmd as u8, a[1], a[2], a[3]],
+        }
+    }
+}
+
+/// SPI EEPROM device
+pub struct SpiEeprom<S> {
+    spi: S,
+    geometry: EepromGeometry,
+    write_timeout: Duration,
+}
+
+impl<S: SpiDevice<u8, Error = Error>> SpiEeprom<S> {
+    /// Create an EEPROM device using the provided SPI device and geometry
+    pub fn new(spi: S, geometry: EepromGeometry) -> Self {
+        Self {
+            spi,
+            geometry,
+            write_timeout: Duration::from_millis(20),
+        }
+    }
+
+    /// Override the default page write timeout
+    pub fn with_write_timeout(mut self, timeout: Duration) -> Self {
+        self.write_timeout = timeout;
+        self
+    }
+
+    /// Fetch the EEPROM geometry
+    pub fn geometry(&self) -> &EepromGeometry {
+        &self.geometry
+    }
+
+    /// Release the underlying SPI device
+    pub fn release(self) -> S {
+        self.spi
+    }
+
+    /// Read the status register
+    pub fn status(&mut self) -> Result<u8, Error> {
+        let mut buff = [0u8; 1];
+
+        self.spi.transaction(&mut [
+            Operation::Write(&[EepromCommand::ReadStatus as u8]),
+            Operation::Read(&mut buff),
+        ])?;
+
+        Ok(buff[0])
+    }
+
+    /// Poll the status register until the current write completes
+    pub fn wait_ready(&mut self) -> Result<(), Error> {
+        let start = Instant::now();
+
+        loop {
+            let status = self.status()?;
+            if status & STATUS_WIP == 0 {
+                return Ok(());
+            }
+
+            if start.elapsed() > self.write_timeout {
+                debug!("Timeout waiting for EEPROM (status: 0x{:02x})", status);
+                return Err(Error::Usb(rusb::Error::Timeout));
+            }
+
+            thread::sleep(STATUS_POLL_INTERVAL);
+        }
+    }
+
+    /// Read data starting at the provided address
+    pub fn read(&mut self, address: u32, buff: &mut [u8]) -> Result<(), Error> {
+        self.check_range(address, buff.len())?;
+
+        let cmd = self.geometry.command(EepromCommand::Read, address);
+
+        self.spi
+            .transaction(&mut [Operation::Write(&cmd), Operation::Read(buff)])
+    }
+
+    /// Write data starting at the provided address
+    ///
+    /// Writes are split on page boundaries (writes wrap within a page on the device),
+    /// polling for completion after each page.
+    pub fn write(&mut self, address: u32, data: &[u8]) -> Result<(), Error> {
+        self.check_range(address, data.len())?;
+
+        let page = self.geometry.page_size;
+        let mut offset = 0;
+
+        while offset < data.len() {
+            let a = address as usize + offset;
+            let n = (page - a % page).min(data.len() - offset);
+
+            trace!("Write page 0x{:04x} ({} bytes)", a, n);
+
+            self.spi.write(&[EepromCommand::WriteEnable as u8])?;
+
+            let cmd = self.geometry.command(EepromCommand::Write, a as u32);
+            self.spi.transaction(&mut [
+                Operation::Write(&cmd),
+                Operation::Write(&data[offset..offset + n]),
+            ])?;
+
+            self.wait_ready()?;
+
+            offset += n;
+        }
+
+        Ok(())
+    }
+
+    /// Compare EEPROM contents with the provided data, returning the offset of the
+    /// first mismatch
+    pub fn verify(&mut self, address: u32, data: &[u8]) -> Result<Option<usize>, Error> {
+        let mut buff = vec![0u8; data.len()];
+        self.read(address, &mut buff)?;
+
+        Ok(data.iter().zip(buff.iter()).position(|(a, b)| a != b))
+    }
+
+    fn check_range(&self, address: u32, len: usize) -> Result<(), Error> {
+        match address as usize + len <= self.geometry.capacity {
+            true => Ok(()),
+            false => Err(Error::InvalidConfig {
+                field: "address",
+                reason: "range exceeds EEPROM capacity",
+            }),
+        }
+    }
+}
@@ -0,0 +1,337 @@
+//! CP2130 Driver C API
+//!
+//! Flat C interface for using the driver from C / C++, enabled with the `ffi` feature.
+//! The header is generated into the build `OUT_DIR` when building with the feature,
+//! with a checked in copy at `include/cp2130.h`, and a shared library can be built with:
+//!
+//! ```text
+//! cargo rustc --release --lib --features ffi --crate-type cdylib
+//! ```
+//!
+//! After changing this module refresh the checked in header with:
+//!
+//! ```text
+//! CP2130_UPDATE_HEADER=1 cargo build --features ffi
+//! ```
+//!
+//! All functions return a [`Cp2130Status`], with a description of the most recent
+//! failure on the calling thread available from [`cp2130_last_error`].
+//!
+//! Copyright 2019 Ryan Kurte
+
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, CStr};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use embedded_hal::spi::SpiDevice;
+
+use crate::manager::{Filter, Manager};
+use crate::{
+    Cp2130, Device, Error, GpioAccess, GpioLevel, GpioMode, Spi, SpiClock, SpiConfig, UsbOptions,
+};
+
+/// Status codes returned by the C API
+#[repr(C)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Cp2130Status {
+    Ok = 0,
+    /// Invalid argument (null pointer, pin, channel or configuration)
+    InvalidArgument = -1,
+    /// No matching device found
+    NotFound = -2,
+    /// USB communication error
+    Usb = -3,
+    /// USB operation timed out
+    Timeout = -4,
+    /// Device disconnected
+    Disconnected = -5,
+    /// Pin or channel already in use
+    Busy = -6,
+    /// Other driver error
+    Error = -7,
+    /// Internal panic, the handle should be closed
+    Panic = -8,
+}
+
+impl From<&Error> for Cp2130Status {
+    fn from(e: &Error) -> Self {
+        match e {
+            Error::InvalidIndex => Self::NotFound,
+            Error::InvalidPin(_)
+            | Error::ForeignPin
+            | Error::InvalidBaud
+            | Error::InvalidConfig { .. } => Self::InvalidArgument,
+            Error::Usb(rusb::Error::Timeout) => Self::Timeout,
+            Error::Usb(rusb::Error::NoDevice) => Self::Disconnected,
+            Error::Usb(_) | Error::ShortTransfer { .. } => Self::Usb,
+            Error::GpioInUse => Self::Busy,
+            Error::Partial { source, .. } => Self::from(source.as_ref()),
+            _ => Self::Error,
+        }
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+/// Run an API call, recording failures and catching panics at the boundary
+fn ffi(f: impl FnOnce() -> Result<(), Error>) -> Cp2130Status {
+    let (status, message) = match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => return Cp2130Status::Ok,
+        Ok(Err(e)) => (Cp2130Status::from(&e), e.to_string()),
+        Err(_) => (Cp2130Status::Panic, "internal panic".to_string()),
+    };
+
+    LAST_ERROR.with(|l| *l.borrow_mut() = message);
+
+    status
+}
+
+/// Check a pointer argument is non-null
+fn non_null<T>(p: *const T) -> Result<(), Error> {
+    match p.is_null() {
+        false => Ok(()),
+        true => Err(Error::InvalidConfig {
+            field: "pointer",
+            reason: "null pointer argument",
+        }),
+    }
+}
+
+/// Opaque device handle
+pub struct Cp2130Handle {
+    cp2130: Cp2130,
+    spi: Option<Spi>,
+}
+
+/// Open a CP2130, by serial number where `serial` is non-null, otherwise by index
+///
+/// # Safety
+/// `serial` must be null or a valid nul-terminated string, and `handle` a valid pointer.
+/// The returned handle must be released with [`cp2130_close`].
+#[no_mangle]
+pub unsafe extern "C" fn cp2130_open(
+    index: u32,
+    serial: *const c_char,
+    handle: *mut *mut Cp2130Handle,
+) -> Cp2130Status {
+    ffi(|| {
+        non_null(handle)?;
+
+        let filter = match serial.is_null() {
+            true => Filter::default(),
+            false => {
+                let s = CStr::from_ptr(serial)
+                    .to_str()
+                    .map_err(|_| Error::InvalidConfig {
+                        field: "serial",
+                        reason: "serial must be valid UTF-8",
+                    })?;
+                Filter::with_serial(s)
+            }
+        };
+
+        let (device, descriptor) = Manager::device(filter, index as usize)?;
+        let cp2130 = Cp2130::new(device, descriptor, UsbOptions::default())?;
+
+        *handle = Box::into_raw(Box::new(Cp2130Handle { cp2130, spi: None }));
+
+        Ok(())
+    })
+}
+
+/// Close a CP2130 handle, null handles are ignored
+///
+/// # Safety
+/// `handle` must be null or a handle returned by [`cp2130_open`], and is invalid after this call.
+#[no_mangle]
+pub unsafe extern "C" fn cp2130_close(handle: *mut Cp2130Handle) -> Cp2130Status {
+    ffi(|| {
+        if handle.is_null() {
+            return Ok(());
+        }
+
+        let h = Box::from_raw(handle);
+        drop(h.spi);
+        h.cp2130.close()
+    })
+}
+
+/// Fetch the CP2130 chip version
+///
+/// # Safety
+/// `handle` must be a valid handle and `version` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn cp2130_version(
+    handle: *mut Cp2130Handle,
+    version: *mut u16,
+) -> Cp2130Status {
+    ffi(|| {
+        non_null(handle)?;
+        non_null(version)?;
+
+        *version = (*handle).cp2130.version()?;
+
+        Ok(())
+    })
+}
+
+/// Configure an SPI channel for subsequent transfers
+///
+/// `clock_hz` must be a supported CP2130 clock rate and `mode` an SPI mode (0 to 3).
+/// Where `cs_pin` is non-negative the pin is driven as chip select for each transfer.
+///
+/// # Safety
+/// `handle` must be a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn cp2130_spi_configure(
+    handle: *mut Cp2130Handle,
+    channel: u8,
+    clock_hz: u32,
+    mode: u8,
+    cs_pin: c_int,
+) -> Cp2130Status {
+    ffi(|| {
+        non_null(handle)?;
+        let h = &mut *handle;
+
+        let spi_mode = match mode {
+            0 => embedded_hal::spi::MODE_0,
+            1 => embedded_hal::spi::MODE_1,
+            2 => embedded_hal::spi::MODE_2,
+            3 => embedded_hal::spi::MODE_3,
+            _ => {
+                return Err(Error::InvalidConfig {
+                    field: "mode",
+                    reason: "SPI mode must be 0 to 3",
+                })
+            }
+        };
+
+        let config = SpiConfig::builder()
+            .clock(SpiClock::from_frequency_exact(clock_hz as u64)?)
+            .spi_mode(spi_mode)
+            .build()?;
+
+        let cs_pin = u8::try_from(cs_pin).ok();
+
+        // Release any existing channel (and CS pin) prior to reconfiguring
+        h.spi = None;
+        h.spi = Some(h.cp2130.spi(channel, config, cs_pin)?);
+
+        Ok(())
+    })
+}
+
+/// Transfer `len` bytes on the configured SPI channel
+///
+/// Where `write` is null zeros are written, where `read` is null data read is discarded.
+///
+/// # Safety
+/// `handle` must be a valid handle, `write` and `read` must be null or valid for `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn cp2130_spi_transfer(
+    handle: *mut Cp2130Handle,
+    write: *const u8,
+    read: *mut u8,
+    len: usize,
+) -> Cp2130Status {
+    ffi(|| {
+        non_null(handle)?;
+        let h = &mut *handle;
+
+        let spi = h.spi.as_mut().ok_or(Error::InvalidConfig {
+            field: "spi",
+            reason: "SPI channel not configured",
+        })?;
+
+        let out = match write.is_null() {
+            true => vec![0u8; len],
+            false => std::slice::from_raw_parts(write, len).to_vec(),
+        };
+
+        match read.is_null() {
+            true => spi.write(&out),
+            false => spi.transfer(std::slice::from_raw_parts_mut(read, len), &out),
+        }
+    })
+}
+
+/// Set the mode and level of a GPIO pin
+///
+/// `mode` is 0 for input, 1 for open-drain and 2 for push-pull, `level` is 0 for low
+/// or non-zero for high.
+///
+/// # Safety
+/// `handle` must be a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn cp2130_gpio_set(
+    handle: *mut Cp2130Handle,
+    pin: u8,
+    mode: u8,
+    level: u8,
+) -> Cp2130Status {
+    ffi(|| {
+        non_null(handle)?;
+
+        let mode = match mode {
+            0 => GpioMode::Input,
+            1 => GpioMode::OpenDrain,
+            2 => GpioMode::PushPull,
+            _ => {
+                return Err(Error::InvalidConfig {
+                    field: "mode",
+                    reason: "GPIO mode must be 0 (input), 1 (open-drain) or 2 (push-pull)",
+                })
+            }
+        };
+
+        let level = match level {
+            0 => GpioLevel::Low,
+            _ => GpioLevel::High,
+        };
+
+        (*handle).cp2130.set_gpio_mode_level(pin, mode, level)
+    })
+}
+
+/// Read the level of a GPIO pin, setting `level` to 0 for low or 1 for high
+///
+/// # Safety
+/// `handle` must be a valid handle and `level` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn cp2130_gpio_get(
+    handle: *mut Cp2130Handle,
+    pin: u8,
+    level: *mut u8,
+) -> Cp2130Status {
+    ffi(|| {
+        non_null(handle)?;
+        non_null(level)?;
+
+        *level = (*handle).cp2130.get_gpio_level(pin)? as u8;
+
+        Ok(())
+    })
+}
+
+/// Copy the most recent error message on this thread into `buff` (nul-terminated,
+/// truncated to fit), returning the full message length
+///
+/// # Safety
+/// `buff` must be null or valid for `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn cp2130_last_error(buff: *mut c_char, len: usize) -> usize {
+    LAST_ERROR.with(|l| {
+        let l = l.borrow();
+
+        if !buff.is_null() && len > 0 {
+            let n = l.len().min(len - 1);
+            std::ptr::copy_nonoverlapping(l.as_ptr() as *const c_char, buff, n);
+            *buff.add(n) = 0;
+        }
+
+        l.len()
+    })
+}
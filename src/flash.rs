@@ -0,0 +1,494 @@
+//! CP2130 Driver SPI NOR Flash
+//!
+//! Programming support for SPI NOR flash devices using the standard JEDEC command set
+//! (RDID, READ, page program, sector erase and status polling) with 24-bit addressing,
+//! generic over [`SpiDevice`] so it can be used with any CP2130 SPI channel.
+//!
+//! Device geometry can be discovered from the SFDP (JESD216) basic flash parameter table.
+//!
+//! Copyright 2019 Ryan Kurte
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use byteorder::{ByteOrder, LE};
+use embedded_hal::spi::{Operation, SpiDevice};
+use log::{debug, trace};
+
+use crate::Error;
+
+/// SPI NOR flash commands
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum FlashCommand {
+    WriteEnable = 0x06,
+    ReadStatus = 0x05,
+    Read = 0x03,
+    PageProgram = 0x02,
+    SectorErase = 0x20,
+    ChipErase = 0xC7,
+    ReadJedecId = 0x9F,
+    ReadSfdp = 0x5A,
+}
+
+/// Status register write-in-progress (busy) flag
+pub const STATUS_BUSY: u8 = 1 << 0;
+
+/// Status register write-enable-latch flag
+pub const STATUS_WEL: u8 = 1 << 1;
+
+/// Maximum address using 24-bit addressing
+pub const MAX_ADDRESS: u32 = 1 << 24;
+
+/// Interval between status reads while waiting for an operation to complete
+pub const STATUS_POLL_INTERVAL: Duration = Duration::from_micros(100);
+
+/// JEDEC device identifier
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct JedecId {
+    pub manufacturer: u8,
+    pub memory_type: u8,
+    pub capacity: u8,
+}
+
+impl JedecId {
+    /// Decode a JEDEC ID from an RDID response
+    pub fn decode(buff: &[u8; 3]) -> Self {
+        Self {
+            manufacturer: buff[0],
+            memory_type: buff[1],
+            capacity: buff[2],
+        }
+    }
+
+    /// Check whether the ID indicates a device is present (not all zeros or ones)
+    pub fn is_valid(&self) -> bool {
+        let b = [self.manufacturer, self.memory_type, self.capacity];
+        b != [0x00; 3] && b != [0xFF; 3]
+    }
+
+    /// Manufacturer name for common flash vendors
+    pub fn manufacturer_name(&self) -> Option<&'static str> {
+        let name = match self.manufacturer {
+            0x01 => "Infineon (Spansion)",
+            0x1F => "Adesto (Atmel)",
+            0x20 => "Micron (ST)",
+            0x9D => "ISSI",
+            0xBF => "Microchip (SST)",
+            0xC2 => "Macronix",
+            0xC8 => "GigaDevice",
+            0xEF => "Winbond",
+            _ => return None,
+        };
+        Some(name)
+    }
+
+    /// Device capacity in bytes, using the common `2^n` capacity encoding
+    pub fn capacity_bytes(&self) -> Option<usize> {
+        match self.capacity {
+            10..=31 => Some(1 << self.capacity),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for JedecId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:02x}{:02x}{:02x}",
+            self.manufacturer, self.memory_type, self.capacity
+        )
+    }
+}
+
+/// Flash page, sector and device sizes
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct FlashGeometry {
+    /// Page program size in bytes
+    pub page_size: usize,
+    /// Sector erase size in bytes
+    pub sector_size: usize,
+    /// Device capacity in bytes
+    pub capacity: usize,
+}
+
+impl Default for FlashGeometry {
+    fn default() -> Self {
+        Self {
+            page_size: 256,
+            sector_size: 4096,
+            capacity: MAX_ADDRESS as usize,
+        }
+    }
+}
+
+impl FlashGeometry {
+    /// Check the page and sector sizes are usable
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.page_size == 0 || self.sector_size == 0 {
+            return Err(Error::InvalidConfig {
+                field: "flash",
+                reason: "page and sector sizes must be non-zero",
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// SFDP header signature ("SFDP")
+pub const SFDP_SIGNATURE: u32 = 0x5044_4653;
+
+/// SFDP basic flash parameter table ID
+pub const SFDP_BFPT_ID: u16 = 0xFF00;
+
+/// Erase command supported by a flash device
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EraseType {
+    /// Erase size in bytes
+    pub size: usize,
+    pub opcode: u8,
+}
+
+/// Flash parameters from the SFDP basic flash parameter table
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Sfdp {
+    /// SFDP revision (major, minor)
+    pub revision: (u8, u8),
+    /// Device capacity in bytes
+    pub capacity: usize,
+    /// Page program size in bytes, where reported (JESD216A and later)
+    pub page_size: Option<usize>,
+    /// Supported erase commands, smallest first
+    pub erase_types: Vec<EraseType>,
+}
+
+impl Sfdp {
+    /// Decode the SFDP header, returning the revision and the basic flash parameter
+    /// table address and length (in bytes)
+    pub fn decode_header(buff: &[u8]) -> Option<((u8, u8), u32, usize)> {
+        if buff.len() < 16 || LE::read_u32(&buff[0..4]) != SFDP_SIGNATURE {
+            return None;
+        }
+
+        let revision = (buff[5], buff[4]);
+        let headers = buff[6] as usize + 1;
+
+        // Use the latest basic flash parameter table revision
+        buff[8..]
+            .chunks_exact(8)
+            .take(headers)
+            .filter(|h| u16::from_le_bytes([h[0], h[7]]) == SFDP_BFPT_ID)
+            .max_by_key(|h| (h[2], h[1]))
+            .map(|h| {
+                let addr = LE::read_u24(&h[4..7]);
+                (revision, addr, h[3] as usize * 4)
+            })
+    }
+
+    /// Decode the basic flash parameter table
+    pub fn decode(revision: (u8, u8), bfpt: &[u8]) -> Option<Self> {
+        if bfpt.len() < 9 * 4 {
+            return None;
+        }
+
+        let dword = |n: usize| LE::read_u32(&bfpt[(n - 1) * 4..n * 4]);
+
+        // Density in bits, either N+1 or 2^N where bit 31 is set
+        let density = dword(2);
+        let capacity = match density & (1 << 31) {
+            0 => (density as usize + 1) / 8,
+            _ => 1usize.checked_shl(density & 0x7FFF_FFFF)? / 8,
+        };
+
+        let mut erase_types: Vec<_> = [dword(8), dword(9)]
+            .iter()
+            .flat_map(|d| [*d as u16, (*d >> 16) as u16])
+            // Zero marks an unused entry, larger exponents than a 32-bit address space are invalid
+            .filter(|e| matches!(e & 0xFF, 1..=31))
+            .map(|e| EraseType {
+                size: 1 << (e & 0xFF),
+                opcode: (e >> 8) as u8,
+            })
+            .collect();
+        erase_types.sort_by_key(|e| e.size);
+
+        let page_size = match bfpt.len() >= 11 * 4 {
+            true => Some(1 << ((dword(11) >> 4) & 0x0F)),
+            false => None,
+        };
+
+        Some(Self {
+            revision,
+            capacity,
+            page_size,
+            erase_types,
+        })
+    }
+
+    /// Flash geometry using the standard 4 KiB sector erase where supported
+    pub fn geometry(&self) -> FlashGeometry {
+        let d = FlashGeometry::default();
+
+        let sector = self
+            .erase_types
+            .iter()
+            .find(|e| e.opcode == FlashCommand::SectorErase as u8)
+            .map(|e| e.size)
+            .unwrap_or(d.sector_size);
+
+        FlashGeometry {
+            page_size: self.page_size.unwrap_or(d.page_size),
+            sector_size: sector,
+            capacity: self.capacity,
+        }
+    }
+}
+
+/// Flash operation timeouts
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct FlashTimeouts {
+    pub page_program: Duration,
+    pub sector_erase: Duration,
+    pub chip_erase: Duration,
+}
+
+impl Default for FlashTimeouts {
+    fn default() -> Self {
+        Self {
+            page_program: Duration::from_millis(20),
+            sector_erase: Duration::from_millis(500),
+            chip_erase: Duration::from_secs(200),
+        }
+    }
+}
+
+/// SPI NOR flash device
+pub struct SpiFlash<S> {
+    spi: S,
+    geometry: FlashGeometry,
+    timeouts: FlashTimeouts,
+}
+
+impl<S: SpiDevice<u8, Error = Error>> SpiFlash<S> {
+    /// Create a flash device using the provided SPI device and geometry
+    pub fn new(spi: S, geometry: FlashGeometry) -> Self {
+        Self {
+            spi,
+            geometry,
+            timeouts: FlashTimeouts::default(),
+        }
+    }
+
+    /// Override default operation timeouts
+    pub fn with_timeouts(mut self, timeouts: FlashTimeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Fetch the flash geometry
+    pub fn geometry(&self) -> &FlashGeometry {
+        &self.geometry
+    }
+
+    /// Update the flash geometry, for example from [`Sfdp::geometry`]
+    pub fn set_geometry(&mut self, geometry: FlashGeometry) -> Result<(), Error> {
+        geometry.validate()?;
+        self.geometry = geometry;
+        Ok(())
+    }
+
+    /// Release the underlying SPI device
+    pub fn release(self) -> S {
+        self.spi
+    }
+
+    /// Read the JEDEC device ID
+    pub fn read_id(&mut self) -> Result<JedecId, Error> {
+        let mut buff = [0u8; 3];
+
+        self.spi.transaction(&mut [
+            Operation::Write(&[FlashCommand::ReadJedecId as u8]),
+            Operation::Read(&mut buff),
+        ])?;
+
+        Ok(JedecId::decode(&buff))
+    }
+
+    /// Read SFDP data starting at the provided address
+    pub fn read_sfdp_raw(&mut self, address: u32, buff: &mut [u8]) -> Result<(), Error> {
+        // SFDP reads are followed by a single dummy byte
+        let cmd = command(FlashCommand::ReadSfdp, address);
+
+        self.spi.transaction(&mut [
+            Operation::Write(&cmd),
+            Operation::Write(&[0]),
+            Operation::Read(buff),
+        ])
+    }
+
+    /// Read and decode the SFDP basic flash parameter table, returning `None` where
+    /// the device does not support SFDP
+    pub fn read_sfdp(&mut self) -> Result<Option<Sfdp>, Error> {
+        // Header and up to 31 parameter headers
+        let mut header = [0u8; 8 + 31 * 8];
+        self.read_sfdp_raw(0, &mut header[..16])?;
+
+        let headers = header[6] as usize + 1;
+        if headers > 1 && LE::read_u32(&header[0..4]) == SFDP_SIGNATURE {
+            self.read_sfdp_raw(0, &mut header[..8 + headers.min(31) * 8])?;
+        }
+
+        let (revision, addr, len) = match Sfdp::decode_header(&header) {
+            Some(h) => h,
+            None => {
+                debug!("No SFDP header found");
+                return Ok(None);
+            }
+        };
+
+        let mut bfpt = vec![0u8; len];
+        self.read_sfdp_raw(addr, &mut bfpt)?;
+
+        Ok(Sfdp::decode(revision, &bfpt))
+    }
+
+    /// Read the status register
+    pub fn status(&mut self) -> Result<u8, Error> {
+        let mut buff = [0u8; 1];
+
+        self.spi.transaction(&mut [
+            Operation::Write(&[FlashCommand::ReadStatus as u8]),
+            Operation::Read(&mut buff),
+        ])?;
+
+        Ok(buff[0])
+    }
+
+    /// Poll the status register until the device is no longer busy
+    pub fn wait_ready(&mut self, timeout: Duration) -> Result<(), Error> {
+        let start = Instant::now();
+
+        loop {
+            let status = self.status()?;
+            if status & STATUS_BUSY == 0 {
+                return Ok(());
+            }
+
+            if start.elapsed() > timeout {
+                debug!("Timeout waiting for flash (status: 0x{:02x})", status);
+                return Err(Error::Usb(rusb::Error::Timeout));
+            }
+
+            thread::sleep(STATUS_POLL_INTERVAL);
+        }
+    }
+
+    /// Read data starting at the provided address
+    pub fn read(&mut self, address: u32, buff: &mut [u8]) -> Result<(), Error> {
+        self.check_range(address, buff.len())?;
+
+        let cmd = command(FlashCommand::Read, address);
+
+        self.spi
+            .transaction(&mut [Operation::Write(&cmd), Operation::Read(buff)])
+    }
+
+    /// Program data starting at the provided address, the region must already be erased
+    ///
+    /// Writes are split on page boundaries, polling for completion after each page.
+    pub fn write(&mut self, address: u32, data: &[u8]) -> Result<(), Error> {
+        self.check_range(address, data.len())?;
+
+        let mut offset = 0;
+        while offset < data.len() {
+            let a = address as usize + offset;
+            let n =
+                (self.geometry.page_size - a % self.geometry.page_size).min(data.len() - offset);
+
+            trace!("Program page 0x{:06x} ({} bytes)", a, n);
+
+            self.write_enable()?;
+
+            let cmd = command(FlashCommand::PageProgram, a as u32);
+            self.spi.transaction(&mut [
+                Operation::Write(&cmd),
+                Operation::Write(&data[offset..offset + n]),
+            ])?;
+
+            self.wait_ready(self.timeouts.page_program)?;
+
+            offset += n;
+        }
+
+        Ok(())
+    }
+
+    /// Erase sectors covering the provided range, which must be sector aligned
+    pub fn erase(&mut self, address: u32, len: usize) -> Result<(), Error> {
+        self.check_range(address, len)?;
+
+        let sector = self.geometry.sector_size;
+        if !(address as usize).is_multiple_of(sector) || !len.is_multiple_of(sector) {
+            return Err(Error::InvalidConfig {
+                field: "erase",
+                reason: "address and length must be sector aligned",
+            });
+        }
+
+        for a in (address as usize..address as usize + len).step_by(sector) {
+            trace!("Erase sector 0x{:06x}", a);
+
+            self.write_enable()?;
+            self.spi
+                .write(&command(FlashCommand::SectorErase, a as u32))?;
+            self.wait_ready(self.timeouts.sector_erase)?;
+        }
+
+        Ok(())
+    }
+
+    /// Erase the entire device
+    pub fn erase_chip(&mut self) -> Result<(), Error> {
+        self.write_enable()?;
+        self.spi.write(&[FlashCommand::ChipErase as u8])?;
+        self.wait_ready(self.timeouts.chip_erase)
+    }
+
+    /// Compare flash contents with the provided data, returning the offset of the
+    /// first mismatch
+    pub fn verify(&mut self, address: u32, data: &[u8]) -> Result<Option<usize>, Error> {
+        let mut buff = vec![0u8; data.len()];
+        self.read(address, &mut buff)?;
+
+        Ok(data.iter().zip(buff.iter()).position(|(a, b)| a != b))
+    }
+
+    /// Set the write enable latch, required before each program or erase
+    fn write_enable(&mut self) -> Result<(), Error> {
+        self.spi.write(&[FlashCommand::WriteEnable as u8])
+    }
+
+    fn check_range(&self, address: u32, len: usize) -> Result<(), Error> {
+        self.geometry.validate()?;
+
+        match address as usize + len <= self.geometry.capacity.min(MAX_ADDRESS as usize) {
+            true => Ok(()),
+            false => Err(Error::InvalidConfig {
+                field: "address",
+                reason: "range exceeds flash capacity",
+            }),
+        }
+    }
+}
+
+/// Encode a command with a 24-bit address
+fn command(cmd: FlashCommand, address: u32) -> [u8; 4] {
+    let a = address.to_be_bytes();
+    [cmd as u8, a[1], a[2], a[3]]
+}
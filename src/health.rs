@@ -0,0 +1,200 @@
+//! CP2130 Driver Health Monitoring
+//!
+//! [`Cp2130::ping`] checks the device responds to a cheap request, and [`HealthMonitor`]
+//! pings periodically on a background thread so long-running applications detect a
+//! hung or disconnected bridge before their next transfer fails.
+//!
+//! Copyright 2019 Ryan Kurte
+
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use log::{debug, warn};
+use rusb::UsbContext;
+
+use crate::device::Inner;
+use crate::{Cp2130, Error};
+
+/// Health monitor configuration
+#[derive(Debug, Clone, PartialEq)]
+pub struct HealthConfig {
+    /// Interval between pings
+    pub interval: Duration,
+    /// Response timeout for each ping
+    pub timeout: Duration,
+    /// Consecutive failed pings before the device is reported unhealthy
+    pub failures: u32,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(1),
+            timeout: Duration::from_millis(200),
+            failures: 1,
+        }
+    }
+}
+
+/// State shared with the monitor thread
+struct Shared {
+    healthy: AtomicBool,
+    failures: AtomicU32,
+    latency: Mutex<Option<Duration>>,
+    error: Mutex<Option<Error>>,
+}
+
+/// Periodic device health check, stopped when dropped
+pub struct HealthMonitor {
+    tx: Option<Sender<()>>,
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl<T: UsbContext> Cp2130<T> {
+    /// Check the device is responding, returning the round trip time
+    ///
+    /// This reads the chip version, failing with a USB timeout where no response is
+    /// received within `timeout`. Pings wait for any operation in progress on other
+    /// threads to complete.
+    pub fn ping(&self, timeout: Duration) -> Result<Duration, Error> {
+        ping(&self.inner, timeout)
+    }
+}
+
+impl<T: UsbContext + 'static> Cp2130<T> {
+    /// Start a health monitor, calling `on_change` with the new state (`true` for
+    /// healthy) whenever the device stops or resumes responding
+    ///
+    /// The device is assumed healthy until the configured number of pings fail.
+    pub fn health_monitor(
+        &self,
+        config: HealthConfig,
+        on_change: impl FnMut(bool) + Send + 'static,
+    ) -> Result<HealthMonitor, Error> {
+        if config.failures == 0 {
+            return Err(Error::InvalidConfig {
+                field: "failures",
+                reason: "at least one failure is required",
+            });
+        }
+
+        let shared = Arc::new(Shared {
+            healthy: AtomicBool::new(true),
+            failures: AtomicU32::new(0),
+            latency: Mutex::new(None),
+            error: Mutex::new(None),
+        });
+        let (tx, rx) = mpsc::channel();
+
+        let inner = self.inner.clone();
+        let s = shared.clone();
+        let thread = std::thread::Builder::new()
+            .name("cp2130-health".to_string())
+            .spawn(move || monitor(&inner, config, on_change, rx, &s))
+            .expect("failed to spawn health monitor thread");
+
+        Ok(HealthMonitor {
+            tx: Some(tx),
+            shared,
+            thread: Some(thread),
+        })
+    }
+}
+
+fn ping<T: UsbContext>(inner: &Mutex<Inner<T>>, timeout: Duration) -> Result<Duration, Error> {
+    let mut inner = inner.lock().unwrap();
+
+    let start = Instant::now();
+    inner.version_timeout(timeout)?;
+
+    Ok(start.elapsed())
+}
+
+/// Monitor thread, pinging until the sender is dropped
+fn monitor<T: UsbContext>(
+    inner: &Mutex<Inner<T>>,
+    config: HealthConfig,
+    mut on_change: impl FnMut(bool),
+    rx: Receiver<()>,
+    shared: &Shared,
+) {
+    loop {
+        let start = Instant::now();
+
+        match ping(inner, config.timeout) {
+            Ok(latency) => {
+                *shared.latency.lock().unwrap() = Some(latency);
+                shared.failures.store(0, Ordering::Relaxed);
+
+                if !shared.healthy.swap(true, Ordering::SeqCst) {
+                    debug!("Device responding");
+                    on_change(true);
+                }
+            }
+            Err(e) => {
+                debug!("Ping failed: {}", e);
+                *shared.error.lock().unwrap() = Some(e);
+
+                let failures = shared.failures.fetch_add(1, Ordering::Relaxed) + 1;
+                if failures >= config.failures && shared.healthy.swap(false, Ordering::SeqCst) {
+                    warn!("Device not responding ({} failed pings)", failures);
+                    on_change(false);
+                }
+            }
+        }
+
+        let wait = (start + config.interval).saturating_duration_since(Instant::now());
+        match rx.recv_timeout(wait) {
+            Err(RecvTimeoutError::Timeout) => (),
+            _ => break,
+        }
+    }
+
+    debug!("Health monitor exiting");
+}
+
+impl HealthMonitor {
+    /// Check whether the device is currently considered healthy
+    pub fn is_healthy(&self) -> bool {
+        self.shared.healthy.load(Ordering::SeqCst)
+    }
+
+    /// Fetch the number of consecutive failed pings
+    pub fn failures(&self) -> u32 {
+        self.shared.failures.load(Ordering::Relaxed)
+    }
+
+    /// Fetch the round trip time of the most recent successful ping
+    pub fn latency(&self) -> Option<Duration> {
+        *self.shared.latency.lock().unwrap()
+    }
+
+    /// Take the error from the most recent failed ping
+    pub fn take_error(&self) -> Option<Error> {
+        self.shared.error.lock().unwrap().take()
+    }
+
+    /// Stop the monitor
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        // Dropping the sender wakes the thread
+        self.tx.take();
+
+        if let Some(t) = self.thread.take() {
+            let _ = t.join();
+        }
+    }
+}
+
+impl Drop for HealthMonitor {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
@@ -4,23 +4,77 @@
 //! Copyright 2019 Ryan Kurte
 
 use std::{
-    sync::{Arc, Mutex},
+    sync::{atomic::AtomicBool, Arc, Mutex, MutexGuard},
     time::{Duration, Instant},
 };
 
 pub use embedded_hal::spi::Mode as SpiMode;
-use rusb::{Context as UsbContext, Device as UsbDevice, DeviceDescriptor};
+use rusb::{Device as UsbDevice, DeviceDescriptor, DeviceHandle, GlobalContext, UsbContext};
 
+pub mod batch;
+pub mod debounce;
+pub mod delay;
 pub mod device;
+pub mod eeprom;
+pub mod flash;
+pub mod health;
 pub mod manager;
+pub mod onewire;
+pub mod otp;
+pub mod pins;
 pub mod prelude;
+pub mod protocol;
+pub mod provision;
+pub mod pwm;
+pub mod raw;
+pub mod remote;
+pub mod self_test;
+pub mod spidev;
+pub mod stats;
+pub mod stream;
+pub mod transport;
+mod worker;
 
+#[cfg(feature = "nusb")]
+pub use nusb;
+
+#[cfg(feature = "profile")]
+pub mod profile;
+
+#[cfg(feature = "async")]
+mod wait;
+
+#[cfg(feature = "mock")]
+pub mod mock;
+
+#[cfg(feature = "ffi")]
+pub mod ffi;
+
+#[cfg(feature = "python")]
+mod python;
+
+pub use crate::batch::GpioBatch;
+pub use crate::delay::Delay;
 use crate::device::*;
-pub use crate::device::{GpioLevel, GpioMode, SpiClock, SpiConfig, UsbOptions};
+pub use crate::device::{
+    CsMode, Edge, EventCounter, EventCounterMode, GpioLevel, GpioLevels, GpioMode, SpiClock,
+    SpiConfig, SpiConfigBuilder, SpiDelays, UsbOptions, UsbPower,
+};
+pub use crate::health::{HealthConfig, HealthMonitor};
+pub use crate::otp::{
+    OtpFields, OtpWrite, PinConfig, PinDefault, PinFunction, PowerMode, PromImage,
+    TransferPriority, UsbConfig, UsbConfigUpdate,
+};
+pub use crate::pwm::Pwm;
+pub use crate::self_test::{SelfTestConfig, SelfTestReport};
+pub use crate::stats::Stats;
+pub use crate::stream::{SpiStream, StreamConfig};
+pub use crate::transport::Transport;
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
-    //    Io(IoError),
+    #[error("IO error: {0}")]
+    Io(std::io::Error),
     #[error("USB error: {0}")]
     Usb(rusb::Error),
 
@@ -31,12 +85,65 @@ pub enum Error {
     Configurations,
     #[error("No matching endpoint found")]
     Endpoint,
+    #[error("Invalid GPIO pin index: {0}")]
+    InvalidPin(u8),
     #[error("GPIO pin already in use")]
     GpioInUse,
+    #[error("GPIO pin has been released")]
+    GpioReleased,
+    #[error("GPIO pin belongs to a different device")]
+    ForeignPin,
+    #[error("SPI channel has been released")]
+    SpiReleased,
     #[error("Invalid SPI index")]
     InvalidIndex,
     #[error("Invalid SPI baud rate")]
     InvalidBaud,
+    #[error("Invalid SPI configuration ({field}): {reason}")]
+    InvalidConfig {
+        field: &'static str,
+        reason: &'static str,
+    },
+    /// Transfer failed part way, `data` contains any bytes read prior to the failure
+    #[error("Transfer failed after {completed} bytes: {source}")]
+    Partial {
+        completed: usize,
+        data: Vec<u8>,
+        source: Box<Error>,
+    },
+    #[error("Short USB transfer ({actual} of {expected} bytes)")]
+    ShortTransfer { expected: usize, actual: usize },
+    #[error("Protocol error: {0}")]
+    Protocol(protocol::ProtocolError),
+    #[error("OTP fields are locked: {0:?}")]
+    Locked(otp::OtpFields),
+    #[error("Unsupported operation: {0}")]
+    Unsupported(&'static str),
+    #[error("SPI stream has stopped")]
+    StreamStopped,
+    #[error("PWM output has stopped")]
+    PwmStopped,
+    #[error("Device worker has stopped")]
+    WorkerStopped,
+    #[error("Transcript replay error: {0}")]
+    Replay(String),
+    #[error("Remote error: {0}")]
+    Remote(String),
+    #[cfg(feature = "nusb")]
+    #[error("nusb error: {0}")]
+    Nusb(nusb::Error),
+    #[cfg(feature = "nusb")]
+    #[error("nusb transfer error: {0}")]
+    NusbTransfer(nusb::transfer::TransferError),
+    #[cfg(feature = "nusb")]
+    #[error("nusb descriptor error: {0}")]
+    NusbDescriptor(nusb::GetDescriptorError),
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
 }
 
 impl From<rusb::Error> for Error {
@@ -45,26 +152,82 @@ impl From<rusb::Error> for Error {
     }
 }
 
+impl From<protocol::ProtocolError> for Error {
+    fn from(e: protocol::ProtocolError) -> Self {
+        Error::Protocol(e)
+    }
+}
+
+impl std::error::Error for protocol::ProtocolError {}
+
+#[cfg(feature = "nusb")]
+impl From<nusb::Error> for Error {
+    fn from(e: nusb::Error) -> Self {
+        Error::Nusb(e)
+    }
+}
+
+#[cfg(feature = "nusb")]
+impl From<nusb::transfer::TransferError> for Error {
+    fn from(e: nusb::transfer::TransferError) -> Self {
+        Error::NusbTransfer(e)
+    }
+}
+
+#[cfg(feature = "nusb")]
+impl From<nusb::GetDescriptorError> for Error {
+    fn from(e: nusb::GetDescriptorError) -> Self {
+        Error::NusbDescriptor(e)
+    }
+}
+
 /// CP2130 provides methods to interact with the device, as well as create new spi and gpio connectors.
-pub struct Cp2130 {
-    inner: Arc<Mutex<Inner>>,
+///
+/// This is generic over the libusb context used to open the device, defaulting to the
+/// libusb global context.
+///
+/// Cloning a `Cp2130` creates another handle to the same device, sharing the USB
+/// connection and SPI / GPIO allocations, so the device may be used from multiple
+/// threads. Operations from different handles are serialised by the device lock.
+pub struct Cp2130<T: UsbContext = GlobalContext> {
+    inner: Arc<Mutex<Inner<T>>>,
     info: Info,
 }
 
-/// Device trait provides methods directly on the CP2130
-pub trait Device {
+impl<T: UsbContext> Clone for Cp2130<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            info: self.info.clone(),
+        }
+    }
+}
+
+/// SPI access methods directly on the CP2130
+///
+/// Buffers longer than [`MAX_TRANSFER_LEN`](device::MAX_TRANSFER_LEN) are split across
+/// multiple transfer commands, hardware chip selects may be deasserted between commands
+/// so a GPIO chip select should be used where CS must be held for the whole buffer.
+pub trait SpiAccess {
     /// Read from the SPI device
     fn spi_read(&self, buff: &mut [u8]) -> Result<usize, Error>;
 
     /// Write to the SPI device
     fn spi_write(&self, buff: &[u8]) -> Result<(), Error>;
 
-    // Transfer (write-read) to and from the SPI device
-    fn spi_write_read(&self, buff_out: &[u8], buff_in: &mut [u8]) -> Result<usize, Error>;
+    /// Write `len` bytes from a reader to the SPI device as a single transfer,
+    /// without buffering the whole payload in memory
+    fn spi_write_stream(&self, reader: &mut dyn std::io::Read, len: usize) -> Result<(), Error>;
 
-    /// Fetch the CP2130 chip version
-    fn version(&self) -> Result<u16, Error>;
+    /// Transfer (write-read) to and from the SPI device
+    ///
+    /// The transfer runs for the longer of the two buffers, zeros are written beyond
+    /// `buff_out` and the response beyond `buff_in` is discarded.
+    fn spi_write_read(&self, buff_out: &[u8], buff_in: &mut [u8]) -> Result<usize, Error>;
+}
 
+/// GPIO access methods directly on the CP2130
+pub trait GpioAccess {
     /// Set the mode and level for a given GPIO pin
     fn set_gpio_mode_level(&self, pin: u8, mode: GpioMode, level: GpioLevel) -> Result<(), Error>;
 
@@ -75,13 +238,102 @@ pub trait Device {
     fn get_gpio_level(&self, pin: u8) -> Result<bool, Error>;
 }
 
-impl Cp2130 {
+/// Device trait provides methods directly on the CP2130
+///
+/// Consumers using only SPI or GPIO should prefer the narrower [`SpiAccess`] or
+/// [`GpioAccess`] bounds. This is dyn-compatible, so hardware and simulated devices
+/// may be selected at runtime and held as `Arc<dyn Device>`.
+pub trait Device: SpiAccess + GpioAccess + Send + Sync {
+    /// Fetch the CP2130 chip version
+    fn version(&self) -> Result<u16, Error>;
+}
+
+impl<D: SpiAccess + ?Sized> SpiAccess for &D {
+    fn spi_read(&self, buff: &mut [u8]) -> Result<usize, Error> {
+        (**self).spi_read(buff)
+    }
+
+    fn spi_write(&self, buff: &[u8]) -> Result<(), Error> {
+        (**self).spi_write(buff)
+    }
+
+    fn spi_write_stream(&self, reader: &mut dyn std::io::Read, len: usize) -> Result<(), Error> {
+        (**self).spi_write_stream(reader, len)
+    }
+
+    fn spi_write_read(&self, buff_out: &[u8], buff_in: &mut [u8]) -> Result<usize, Error> {
+        (**self).spi_write_read(buff_out, buff_in)
+    }
+}
+
+impl<D: GpioAccess + ?Sized> GpioAccess for &D {
+    fn set_gpio_mode_level(&self, pin: u8, mode: GpioMode, level: GpioLevel) -> Result<(), Error> {
+        (**self).set_gpio_mode_level(pin, mode, level)
+    }
+
+    fn get_gpio_values(&self) -> Result<GpioLevels, Error> {
+        (**self).get_gpio_values()
+    }
+
+    fn get_gpio_level(&self, pin: u8) -> Result<bool, Error> {
+        (**self).get_gpio_level(pin)
+    }
+}
+
+impl<D: Device + ?Sized> Device for &D {
+    fn version(&self) -> Result<u16, Error> {
+        (**self).version()
+    }
+}
+
+impl<D: SpiAccess + ?Sized> SpiAccess for Arc<D> {
+    fn spi_read(&self, buff: &mut [u8]) -> Result<usize, Error> {
+        (**self).spi_read(buff)
+    }
+
+    fn spi_write(&self, buff: &[u8]) -> Result<(), Error> {
+        (**self).spi_write(buff)
+    }
+
+    fn spi_write_stream(&self, reader: &mut dyn std::io::Read, len: usize) -> Result<(), Error> {
+        (**self).spi_write_stream(reader, len)
+    }
+
+    fn spi_write_read(&self, buff_out: &[u8], buff_in: &mut [u8]) -> Result<usize, Error> {
+        (**self).spi_write_read(buff_out, buff_in)
+    }
+}
+
+impl<D: GpioAccess + ?Sized> GpioAccess for Arc<D> {
+    fn set_gpio_mode_level(&self, pin: u8, mode: GpioMode, level: GpioLevel) -> Result<(), Error> {
+        (**self).set_gpio_mode_level(pin, mode, level)
+    }
+
+    fn get_gpio_values(&self) -> Result<GpioLevels, Error> {
+        (**self).get_gpio_values()
+    }
+
+    fn get_gpio_level(&self, pin: u8) -> Result<bool, Error> {
+        (**self).get_gpio_level(pin)
+    }
+}
+
+impl<D: Device + ?Sized> Device for Arc<D> {
+    fn version(&self) -> Result<u16, Error> {
+        (**self).version()
+    }
+}
+
+impl<T: UsbContext> Cp2130<T> {
     /// Create a new CP2130 instance from a libusb device and descriptor
     pub fn new(
-        device: UsbDevice<UsbContext>,
+        device: UsbDevice<T>,
         descriptor: DeviceDescriptor,
         options: UsbOptions,
-    ) -> Result<Self, Error> {
+    ) -> Result<Self, Error>
+    where
+        T: 'static,
+    {
         // Connect to device
         let (inner, info) = Inner::new(device, descriptor, options)?;
         let inner = Arc::new(Mutex::new(inner));
@@ -90,41 +342,299 @@ impl Cp2130 {
         Ok(Self { info, inner })
     }
 
+    /// Create a new CP2130 instance from an already opened libusb device handle
+    pub fn from_handle(handle: DeviceHandle<T>, options: UsbOptions) -> Result<Self, Error>
+    where
+        T: 'static,
+    {
+        let descriptor = handle.device().device_descriptor()?;
+
+        let (inner, info) = Inner::from_handle(handle, descriptor, options)?;
+        let inner = Arc::new(Mutex::new(inner));
+
+        Ok(Self { info, inner })
+    }
+
+    /// Create a new CP2130 instance from a file descriptor for an opened USB device,
+    /// as provided by the Android `UsbManager` API.
+    ///
+    /// On Android [`rusb::disable_device_discovery`] must be called prior to creating the context,
+    /// and `options.reset_on_open` should be disabled as the reset re-enumerates the device.
+    ///
+    /// # Safety
+    ///
+    /// `fd` must be a valid file descriptor for a USB device, and must remain open
+    /// for the lifetime of the returned object.
+    #[cfg(unix)]
+    pub unsafe fn from_fd(
+        context: &T,
+        fd: std::os::unix::io::RawFd,
+        options: UsbOptions,
+    ) -> Result<Self, Error>
+    where
+        T: 'static,
+    {
+        let handle = context.open_device_with_fd(fd)?;
+
+        Self::from_handle(handle, options)
+    }
+
     /// Fetch information for the connected device
     pub fn info(&self) -> Info {
         self.info.clone()
     }
 
+    /// Close the device, releasing the interface and re-attaching the kernel driver
+    /// if these were claimed / detached on connection.
+    ///
+    /// This is called automatically when all clones of the device and all SPI / GPIO
+    /// handles are dropped. An explicit close applies to all clones, outstanding
+    /// clones and handles should not be used after this.
+    pub fn close(self) -> Result<(), Error> {
+        self.inner.lock().unwrap().close()
+    }
+
+    /// Fetch transfer statistics for the device
+    pub fn stats(&self) -> Stats {
+        self.inner.lock().unwrap().stats()
+    }
+
+    /// Reset transfer statistics, returning the values prior to reset
+    pub fn reset_stats(&self) -> Stats {
+        self.inner.lock().unwrap().reset_stats()
+    }
+
     pub fn reset(&self) -> Result<(), Error> {
         self.inner.lock().unwrap().reset()
     }
 
+    /// Configure GPIO.4 as a hardware event counter, starting from `count`
+    pub fn set_event_counter(&self, mode: EventCounterMode, count: u16) -> Result<(), Error> {
+        self.inner.lock().unwrap().set_event_counter(mode, count)
+    }
+
+    /// Fetch the hardware event counter mode and count
+    pub fn event_counter(&self) -> Result<EventCounter, Error> {
+        self.inner.lock().unwrap().event_counter()
+    }
+
+    /// Set the clock output divider, GPIO.5 outputs 24 MHz / divider (0 divides by 256)
+    ///
+    /// GPIO.5 must be configured for clock output in the PROM pin configuration.
+    pub fn set_clock_divider(&self, divider: u8) -> Result<(), Error> {
+        self.inner.lock().unwrap().set_clock_divider(divider)
+    }
+
+    /// Fetch the clock output divider
+    pub fn clock_divider(&self) -> Result<u8, Error> {
+        self.inner.lock().unwrap().clock_divider()
+    }
+
+    /// Read back the SPI configuration applied to a channel
+    pub fn read_spi_config(&self, channel: u8) -> Result<SpiConfig, Error> {
+        self.inner.lock().unwrap().read_spi_config(channel)
+    }
+
+    /// Set the levels for multiple GPIO pins in a single operation
+    ///
+    /// Only pins set in `mask` and configured as outputs are modified.
+    pub fn set_gpio_values(&self, levels: GpioLevels, mask: GpioLevels) -> Result<(), Error> {
+        self.inner.lock().unwrap().set_gpio_values(levels, mask)
+    }
+
+    /// Read from the SPI device, using `timeout` for each USB transfer
+    ///
+    /// As for [`SpiAccess::spi_read`], with `timeout` in place of the default scaled
+    /// from the SPI clock and delays, for transfers known to be slow.
+    pub fn spi_read_with_timeout(
+        &self,
+        buff: &mut [u8],
+        timeout: Duration,
+    ) -> Result<usize, Error> {
+        let spi = self
+            .inner
+            .lock()
+            .unwrap()
+            .spi_transfers()
+            .with_timeout(timeout);
+        let mut bus = spi.lock();
+        bus.read(buff)
+    }
+
+    /// Write to the SPI device, using `timeout` for each USB transfer
+    pub fn spi_write_with_timeout(&self, buff: &[u8], timeout: Duration) -> Result<(), Error> {
+        let spi = self
+            .inner
+            .lock()
+            .unwrap()
+            .spi_transfers()
+            .with_timeout(timeout);
+        let mut bus = spi.lock();
+        bus.write(buff)
+    }
+
+    /// Transfer (write-read) to and from the SPI device, using `timeout` for each USB transfer
+    pub fn spi_write_read_with_timeout(
+        &self,
+        buff_out: &[u8],
+        buff_in: &mut [u8],
+        timeout: Duration,
+    ) -> Result<usize, Error> {
+        let spi = self
+            .inner
+            .lock()
+            .unwrap()
+            .spi_transfers()
+            .with_timeout(timeout);
+        let mut bus = spi.lock();
+        bus.write_read(buff_out, buff_in)
+    }
+
+    /// Read from the SPI device once the RTR (ready to read) pin is asserted
+    ///
+    /// GPIO.3 must be configured for RTR in the PROM pin configuration (see
+    /// [`Cp2130::program_rtr`]). Where RTR is not asserted within `timeout` the read is
+    /// aborted and the timeout returned, wrapped in [`Error::Partial`] where some data
+    /// was read. Fails with [`Error::GpioInUse`] while GPIO.3 is held as a plain GPIO.
+    pub fn spi_read_rtr(&self, buff: &mut [u8], timeout: Duration) -> Result<usize, Error> {
+        let spi = {
+            let inner = self.inner.lock().unwrap();
+            inner.check_rtr()?;
+            inner.spi_transfers().with_timeout(timeout)
+        };
+        let mut bus = spi.lock();
+        bus.read_rtr(buff, Arc::new(AtomicBool::new(false)))
+    }
+
+    /// Fetch the values for all GPIO pins, failing if no response is received within `timeout`
+    pub fn get_gpio_values_with_timeout(&self, timeout: Duration) -> Result<GpioLevels, Error> {
+        self.inner.lock().unwrap().get_gpio_values_timeout(timeout)
+    }
+
+    /// Fetch the value for a given GPIO pin, failing if no response is received within `timeout`
+    pub fn get_gpio_level_with_timeout(&self, pin: u8, timeout: Duration) -> Result<bool, Error> {
+        self.inner
+            .lock()
+            .unwrap()
+            .get_gpio_level_timeout(pin, timeout)
+    }
+
+    /// Reset the device and wait up to `timeout` for it to re-enumerate, then re-open it
+    /// (matching the serial number) and re-apply the last GPIO and SPI configuration.
+    ///
+    /// Existing SPI and GPIO handles remain valid, this is only supported for devices
+    /// connected via libusb.
+    pub fn reset_and_reopen(&self, timeout: Duration) -> Result<(), Error>
+    where
+        T: 'static,
+    {
+        let (context, descriptor) = self.inner.lock().unwrap().reset_for_reopen()?;
+
+        // Search without holding the device lock, other handles fail fast while closed
+        let (device, descriptor) =
+            device::wait_for_serial(&context, &descriptor, self.info.serial(), timeout)?;
+
+        self.inner.lock().unwrap().reopen(device, descriptor)
+    }
+
     /// Create an SPI connector with an optional CS pin
-    pub fn spi(&self, channel: u8, config: SpiConfig, cs_pin: Option<u8>) -> Result<Spi, Error> {
+    ///
+    /// The CS pin is allocated to the channel until released with [`Cp2130::spi_release`]
+    pub fn spi(&self, channel: u8, config: SpiConfig, cs_pin: Option<u8>) -> Result<Spi<T>, Error> {
+        check_channel(channel)?;
+
         let mut inner = self.inner.lock().unwrap();
+        self.spi_locked(&mut inner, channel, config, cs_pin)
+    }
+
+    /// Create an SPI connector with the device already locked
+    fn spi_locked(
+        &self,
+        inner: &mut Inner<T>,
+        channel: u8,
+        config: SpiConfig,
+        cs_pin: Option<u8>,
+    ) -> Result<Spi<T>, Error> {
+        let previous_cs = inner.spi_cs[channel as usize];
 
         // Configure CS pin if provided
         if let Some(cs) = cs_pin {
+            check_pin(cs)?;
+
+            if inner.gpio_allocated[cs as usize] && previous_cs != Some(cs) {
+                return Err(Error::GpioInUse);
+            }
+
             inner.set_gpio_mode_level(cs, GpioMode::PushPull, GpioLevel::High)?;
         }
 
         // Configure SPI
         inner.spi_configure(channel, config)?;
 
+        // Release any CS pin previously used by this channel
+        if let Some(prev) = previous_cs.filter(|p| Some(*p) != cs_pin) {
+            inner.gpio_release(prev);
+        }
+
+        if let Some(cs) = cs_pin {
+            inner.gpio_allocated[cs as usize] = true;
+            inner.spi_cs[channel as usize] = Some(cs);
+        }
+
         Ok(Spi {
             inner: self.inner.clone(),
-            _channel: channel,
+            channel,
+            generation: inner.spi_generation[channel as usize],
             cs: cs_pin,
+            spin_threshold: DEFAULT_SPIN_THRESHOLD,
         })
     }
 
+    /// Release an SPI channel, disabling automatic CS for the channel and releasing the CS pin.
+    ///
+    /// If `hi_z` is set the CS pin is returned to a high-impedance input.
+    /// Outstanding [`Spi`] handles for the channel will return [`Error::SpiReleased`].
+    pub fn spi_release(&self, channel: u8, hi_z: bool) -> Result<(), Error> {
+        check_channel(channel)?;
+
+        let mut inner = self.inner.lock().unwrap();
+
+        if let Some(cs) = inner.spi_release(channel)? {
+            if hi_z {
+                inner.set_gpio_mode_level(cs, GpioMode::Input, GpioLevel::Low)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Release a GPIO pin so the index can be re-allocated.
+    ///
+    /// If `hi_z` is set the pin is returned to a high-impedance input.
+    /// Outstanding pin handles for the index will return [`Error::GpioReleased`].
+    pub fn gpio_release(&self, index: u8, hi_z: bool) -> Result<(), Error> {
+        check_pin(index)?;
+
+        let mut inner = self.inner.lock().unwrap();
+
+        inner.gpio_release(index);
+
+        if hi_z {
+            inner.set_gpio_mode_level(index, GpioMode::Input, GpioLevel::Low)?;
+        }
+
+        Ok(())
+    }
+
     /// Create a GPIO OutputPin
     pub fn gpio_out(
         &self,
         index: u8,
         mode: GpioMode,
         level: GpioLevel,
-    ) -> Result<OutputPin, Error> {
+    ) -> Result<OutputPin<T>, Error> {
+        check_pin(index)?;
+
         let mut inner = self.inner.lock().unwrap();
 
         if inner.gpio_allocated[index as usize] {
@@ -135,14 +645,19 @@ impl Cp2130 {
         inner.gpio_allocated[index as usize] = true;
 
         Ok(OutputPin {
-            index,
+            pin: PinAllocation {
+                index,
+                generation: inner.gpio_generation[index as usize],
+                inner: self.inner.clone(),
+            },
             mode,
-            inner: self.inner.clone(),
         })
     }
 
     /// Create a GPIO InputPin
-    pub fn gpio_in(&self, index: u8) -> Result<InputPin, Error> {
+    pub fn gpio_in(&self, index: u8) -> Result<InputPin<T>, Error> {
+        check_pin(index)?;
+
         let mut inner = self.inner.lock().unwrap();
 
         if inner.gpio_allocated[index as usize] {
@@ -153,34 +668,84 @@ impl Cp2130 {
         inner.gpio_allocated[index as usize] = true;
 
         Ok(InputPin {
-            index,
-            inner: self.inner.clone(),
+            pin: PinAllocation {
+                index,
+                generation: inner.gpio_generation[index as usize],
+                inner: self.inner.clone(),
+            },
+            poll_interval: DEFAULT_POLL_INTERVAL,
         })
     }
 }
 
 /// Underlying device functions
-impl Device for Cp2130 {
+impl Cp2130 {
+    /// Create a new CP2130 instance using the provided transport
+    pub fn from_transport(transport: impl Transport + 'static, info: Info) -> Self {
+        let inner = Inner::with_transport(Box::new(transport), None);
+        let inner = Arc::new(Mutex::new(inner));
+
+        Self { info, inner }
+    }
+}
+
+#[cfg(feature = "nusb")]
+impl Cp2130 {
+    /// Create a new CP2130 instance from an nusb device info, using the nusb backend
+    pub fn from_nusb(device: &nusb::DeviceInfo, options: UsbOptions) -> Result<Self, Error> {
+        use nusb::MaybeFuture;
+
+        let (bus, address) = (
+            device.bus_id().parse().unwrap_or_default(),
+            device.device_address(),
+        );
+        let ports = device.port_chain().to_vec();
+
+        let mut cp2130 = Self::from_nusb_device(device.open().wait()?, options)?;
+        cp2130.info = cp2130.info.with_location(bus, address, ports);
+
+        Ok(cp2130)
+    }
+
+    /// Create a new CP2130 instance from an already opened nusb device
+    pub fn from_nusb_device(device: nusb::Device, options: UsbOptions) -> Result<Self, Error> {
+        let (transport, info) = transport::NusbTransport::new(device, options)?;
+
+        Ok(Self::from_transport(transport, info))
+    }
+}
+
+impl<T: UsbContext> SpiAccess for Cp2130<T> {
+    // SPI transfers are run by the worker without holding the device lock,
+    // so GPIO operations are not blocked by long transfers. Without a channel,
+    // timeouts are scaled with the timing of the most recently configured channel
+
     fn spi_read(&self, buff: &mut [u8]) -> Result<usize, Error> {
-        let mut inner = self.inner.lock().unwrap();
-        inner.spi_read(buff)
+        let spi = self.inner.lock().unwrap().spi_transfers();
+        let mut bus = spi.lock();
+        bus.read(buff)
     }
 
     fn spi_write(&self, buff: &[u8]) -> Result<(), Error> {
-        let mut inner = self.inner.lock().unwrap();
-        inner.spi_write(buff)
+        let spi = self.inner.lock().unwrap().spi_transfers();
+        let mut bus = spi.lock();
+        bus.write(buff)
     }
 
-    fn spi_write_read(&self, buff_out: &[u8], buff_in: &mut [u8]) -> Result<usize, Error> {
-        let mut inner = self.inner.lock().unwrap();
-        inner.spi_write_read(buff_out, buff_in)
+    fn spi_write_stream(&self, reader: &mut dyn std::io::Read, len: usize) -> Result<(), Error> {
+        let spi = self.inner.lock().unwrap().spi_transfers();
+        let mut bus = spi.lock();
+        bus.write_stream(reader, len)
     }
 
-    fn version(&self) -> Result<u16, Error> {
-        let mut inner = self.inner.lock().unwrap();
-        inner.version()
+    fn spi_write_read(&self, buff_out: &[u8], buff_in: &mut [u8]) -> Result<usize, Error> {
+        let spi = self.inner.lock().unwrap().spi_transfers();
+        let mut bus = spi.lock();
+        bus.write_read(buff_out, buff_in)
     }
+}
 
+impl<T: UsbContext> GpioAccess for Cp2130<T> {
     fn set_gpio_mode_level(&self, pin: u8, mode: GpioMode, level: GpioLevel) -> Result<(), Error> {
         let mut inner = self.inner.lock().unwrap();
         inner.set_gpio_mode_level(pin, mode, level)
@@ -197,69 +762,157 @@ impl Device for Cp2130 {
     }
 }
 
+impl<T: UsbContext> Device for Cp2130<T> {
+    fn version(&self) -> Result<u16, Error> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.version()
+    }
+}
+
 /// Spi object implements embedded-hal SPI traits for the CP2130
-pub struct Spi {
-    // TODO: use channel configuration
-    _channel: u8,
+pub struct Spi<T: UsbContext = GlobalContext> {
+    // SPI channel index
+    channel: u8,
+    // Channel generation at creation, used to detect release
+    generation: u32,
     // Handle for device singleton
-    inner: Arc<Mutex<Inner>>,
+    inner: Arc<Mutex<Inner<T>>>,
     // CS pin index
     cs: Option<u8>,
+    // Remaining delay below which transaction delays spin rather than sleep
+    spin_threshold: Duration,
+}
+
+/// Clones are additional handles to the same configured channel, transactions from
+/// each handle are serialised by the SPI bus lock. Releasing or reconfiguring the
+/// channel applies to all clones.
+impl<T: UsbContext> Clone for Spi<T> {
+    fn clone(&self) -> Self {
+        Self {
+            channel: self.channel,
+            generation: self.generation,
+            inner: self.inner.clone(),
+            cs: self.cs,
+            spin_threshold: self.spin_threshold,
+        }
+    }
+}
+
+impl<T: UsbContext> Spi<T> {
+    /// Fetch the spin threshold for transaction delays
+    pub fn spin_threshold(&self) -> Duration {
+        self.spin_threshold
+    }
+
+    /// Set the spin threshold for transaction delays
+    ///
+    /// [`SpiOp::DelayNs`] operations sleep until the remaining delay is below the
+    /// threshold, then spin for precision. Larger thresholds improve accuracy at the
+    /// cost of CPU time, a zero threshold always sleeps.
+    pub fn set_spin_threshold(&mut self, threshold: Duration) {
+        self.spin_threshold = threshold;
+    }
 }
 
 use embedded_hal::spi::Operation as SpiOp;
 
-impl embedded_hal::spi::SpiDevice<u8> for Spi {
+impl<T: UsbContext> embedded_hal::spi::SpiDevice<u8> for Spi<T> {
     fn transaction(&mut self, operations: &mut [SpiOp<'_, u8>]) -> Result<(), Self::Error> {
-        let mut i = self.inner.lock().unwrap();
+        // Hold the SPI bus for the whole transaction, the device lock is only
+        // taken for control operations so GPIO is not blocked by transfers
+        let spi = self
+            .inner
+            .lock()
+            .unwrap()
+            .spi_channel_transfers(self.channel);
+        let mut bus = spi.lock();
 
-        // Assert CS if available
-        if let Some(cs) = self.cs {
-            i.set_gpio_mode_level(cs, GpioMode::PushPull, GpioLevel::Low)?;
+        if self.inner.lock().unwrap().spi_generation[self.channel as usize] != self.generation {
+            return Err(Error::SpiReleased);
         }
 
+        // Assert CS if available, deasserted when the guard is dropped
+        let cs = match self.cs {
+            Some(pin) => Some(CsGuard::assert(&self.inner, pin)?),
+            None => None,
+        };
+
         for o in operations {
-            // Run operation and collect errors
-            let err = match o {
-                SpiOp::Write(w) => i.spi_write(w).err(),
-                SpiOp::Transfer(r, w) => i.spi_write_read(w, r).err(),
+            match o {
+                SpiOp::Write(w) => bus.write(w)?,
+                SpiOp::Transfer(r, w) => {
+                    bus.write_read(w, r)?;
+                }
                 SpiOp::TransferInPlace(b) => {
-                    let out = b.to_vec();
-                    i.spi_write_read(&out, b).err()
+                    bus.write_read_in_place(b)?;
                 }
                 SpiOp::Read(r) => {
-                    let out = vec![0u8; r.len()];
-                    i.spi_write_read(&out, r).err()
+                    bus.write_zeros_read(r)?;
                 }
                 SpiOp::DelayNs(ns) => {
-                    let now = Instant::now();
-                    while now.elapsed() < Duration::from_nanos(*ns as u64) {}
-                    None
-                }
-            };
-
-            // Check for errors
-            if let Some(e) = err {
-                // Deassert CS on failure
-                if let Some(cs) = self.cs {
-                    i.set_gpio_mode_level(cs, GpioMode::PushPull, GpioLevel::High)?;
+                    wait_until(
+                        Instant::now() + Duration::from_nanos(*ns as u64),
+                        self.spin_threshold,
+                    );
                 }
-
-                // Return error
-                return Err(e);
             }
         }
 
-        // Clear CS if enabled
-        if let Some(cs) = self.cs {
-            i.set_gpio_mode_level(cs, GpioMode::PushPull, GpioLevel::Low)?;
+        // Deassert CS, reporting any error
+        if let Some(cs) = cs {
+            cs.release()?;
         }
 
         Ok(())
     }
 }
 
-impl embedded_hal::spi::ErrorType for Spi {
+/// Chip select guard, deasserting CS on every exit path (including panics)
+struct CsGuard<'a, T: UsbContext> {
+    inner: &'a Mutex<Inner<T>>,
+    pin: Option<u8>,
+}
+
+impl<'a, T: UsbContext> CsGuard<'a, T> {
+    /// Assert (drive low) the CS pin
+    fn assert(inner: &'a Mutex<Inner<T>>, pin: u8) -> Result<Self, Error> {
+        inner
+            .lock()
+            .unwrap()
+            .set_gpio_mode_level(pin, GpioMode::PushPull, GpioLevel::Low)?;
+
+        Ok(Self {
+            inner,
+            pin: Some(pin),
+        })
+    }
+
+    /// Deassert (drive high) the CS pin
+    fn release(mut self) -> Result<(), Error> {
+        match self.pin.take() {
+            Some(pin) => Self::deassert(self.inner, pin),
+            None => Ok(()),
+        }
+    }
+
+    fn deassert(inner: &Mutex<Inner<T>>, pin: u8) -> Result<(), Error> {
+        // Recover the device if a panic poisoned the lock, CS must still be released
+        let mut i = inner.lock().unwrap_or_else(|e| e.into_inner());
+        i.set_gpio_mode_level(pin, GpioMode::PushPull, GpioLevel::High)
+    }
+}
+
+impl<T: UsbContext> Drop for CsGuard<'_, T> {
+    fn drop(&mut self) {
+        if let Some(pin) = self.pin.take() {
+            if let Err(e) = Self::deassert(self.inner, pin) {
+                log::error!("Failed to deassert CS pin {}: {}", pin, e);
+            }
+        }
+    }
+}
+
+impl<T: UsbContext> embedded_hal::spi::ErrorType for Spi<T> {
     type Error = Error;
 }
 
@@ -268,15 +921,152 @@ impl embedded_hal::spi::Error for Error {
         embedded_hal::spi::ErrorKind::Other
     }
 }
-/// InputPin object implements embedded-hal InputPin traits for the CP2130
-pub struct InputPin {
+
+/// Allocated GPIO pin, released on drop so the index can be reused
+struct PinAllocation<T: UsbContext> {
     index: u8,
-    inner: Arc<Mutex<Inner>>,
+    generation: u32,
+    inner: Arc<Mutex<Inner<T>>>,
+}
+
+impl<T: UsbContext> PinAllocation<T> {
+    /// Lock the device, checking the pin has not been released
+    fn lock(&self) -> Result<MutexGuard<'_, Inner<T>>, Error> {
+        lock_pin(&self.inner, self.index, self.generation)
+    }
 }
 
-impl embedded_hal::digital::InputPin for InputPin {
+/// Lock the device, checking the pin allocation with the provided generation has
+/// not been released
+fn lock_pin<T: UsbContext>(
+    inner: &Mutex<Inner<T>>,
+    index: u8,
+    generation: u32,
+) -> Result<MutexGuard<'_, Inner<T>>, Error> {
+    let inner = inner.lock().unwrap();
+
+    if inner.gpio_generation[index as usize] != generation {
+        return Err(Error::GpioReleased);
+    }
+
+    Ok(inner)
+}
+
+impl<T: UsbContext> Drop for PinAllocation<T> {
+    fn drop(&mut self) {
+        let mut inner = match self.inner.lock() {
+            Ok(i) => i,
+            Err(e) => e.into_inner(),
+        };
+
+        // Skip if the pin has already been explicitly released
+        if inner.gpio_generation[self.index as usize] == self.generation {
+            inner.gpio_release(self.index);
+        }
+    }
+}
+
+/// Default interval between GPIO reads when waiting on a pin level
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// InputPin object implements embedded-hal InputPin traits for the CP2130
+pub struct InputPin<T: UsbContext = GlobalContext> {
+    pin: PinAllocation<T>,
+    poll_interval: Duration,
+}
+
+impl<T: UsbContext> InputPin<T> {
+    /// Fetch the interval between GPIO reads used when waiting on the pin
+    pub fn poll_interval(&self) -> Duration {
+        self.poll_interval
+    }
+
+    /// Set the interval between GPIO reads used when waiting on the pin
+    ///
+    /// Each read is a USB control transfer, so shorter intervals reduce latency
+    /// at the cost of bus and CPU time.
+    pub fn set_poll_interval(&mut self, interval: Duration) {
+        self.poll_interval = interval;
+    }
+
+    /// Block until the provided edge occurs on the pin, or the timeout elapses
+    ///
+    /// On GPIO.4 rising and falling edges are detected with the hardware event counter,
+    /// so edges (and short pulses) between reads are not missed and the pin can be read
+    /// at the configured poll interval rather than in a tight loop. This resets the
+    /// event counter and returns GPIO.4 to a plain input once complete.
+    /// Other pins and [`Edge::Any`] poll the pin level at the configured interval.
+    ///
+    /// Timeouts are reported as [`rusb::Error::Timeout`].
+    pub fn wait_for_edge(&mut self, edge: Edge, timeout: Duration) -> Result<(), Error> {
+        let index = self.pin.index;
+
+        match edge.event_counter_mode() {
+            Some(mode) if index == EVENT_COUNTER_PIN => {
+                self.pin.lock()?.set_event_counter(mode, 0)?;
+
+                let res = self.poll_until(timeout, |inner, _| {
+                    let c = inner.event_counter()?;
+                    Ok(c.count > 0 || c.overflow)
+                });
+
+                let restore = self.pin.lock().and_then(|mut i| {
+                    i.set_gpio_mode_level(index, GpioMode::Input, GpioLevel::Low)
+                });
+
+                res.and(restore)
+            }
+            _ => {
+                let mut last = self.pin.lock()?.get_gpio_level(index)?;
+
+                self.poll_until(timeout, |inner, index| {
+                    let level = inner.get_gpio_level(index)?;
+                    let matched = edge.matches(last, level);
+                    last = level;
+                    Ok(matched)
+                })
+            }
+        }
+    }
+
+    /// Poll a condition at the configured interval until it holds or the timeout elapses
+    fn poll_until(
+        &mut self,
+        timeout: Duration,
+        mut f: impl FnMut(&mut Inner<T>, u8) -> Result<bool, Error>,
+    ) -> Result<(), Error> {
+        let start = Instant::now();
+
+        loop {
+            if f(&mut *self.pin.lock()?, self.pin.index)? {
+                return Ok(());
+            }
+
+            if start.elapsed() >= timeout {
+                return Err(Error::Usb(rusb::Error::Timeout));
+            }
+
+            std::thread::sleep(self.poll_interval);
+        }
+    }
+
+    /// Reconfigure the pin as an output with the provided mode and initial level,
+    /// retaining the pin allocation
+    pub fn into_output(self, mode: GpioMode, level: GpioLevel) -> Result<OutputPin<T>, Error> {
+        self.pin
+            .lock()?
+            .set_gpio_mode_level(self.pin.index, mode, level)?;
+
+        Ok(OutputPin {
+            pin: self.pin,
+            mode,
+        })
+    }
+}
+
+impl<T: UsbContext> embedded_hal::digital::InputPin for InputPin<T> {
     fn is_high(&mut self) -> Result<bool, Self::Error> {
-        self.inner.lock().unwrap().get_gpio_level(self.index)
+        self.pin.lock()?.get_gpio_level(self.pin.index)
     }
 
     fn is_low(&mut self) -> Result<bool, Self::Error> {
@@ -285,7 +1075,7 @@ impl embedded_hal::digital::InputPin for InputPin {
     }
 }
 
-impl embedded_hal::digital::ErrorType for InputPin {
+impl<T: UsbContext> embedded_hal::digital::ErrorType for InputPin<T> {
     type Error = Error;
 }
 
@@ -296,28 +1086,109 @@ impl embedded_hal::digital::Error for Error {
 }
 
 /// OutputPin object implements embedded-hal OutputPin traits for the CP2130
-pub struct OutputPin {
-    index: u8,
+pub struct OutputPin<T: UsbContext = GlobalContext> {
+    pin: PinAllocation<T>,
     mode: GpioMode,
-    inner: Arc<Mutex<Inner>>,
 }
 
-impl embedded_hal::digital::OutputPin for OutputPin {
-    fn set_high(&mut self) -> Result<(), Self::Error> {
-        self.inner
+impl<T: UsbContext> OutputPin<T> {
+    /// Reconfigure the pin as an input, retaining the pin allocation
+    pub fn into_input(self) -> Result<InputPin<T>, Error> {
+        self.pin
+            .lock()?
+            .set_gpio_mode_level(self.pin.index, GpioMode::Input, GpioLevel::Low)?;
+
+        Ok(InputPin {
+            pin: self.pin,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+        })
+    }
+
+    /// Drive the pin to `level` for `duration`, then restore the prior level
+    pub fn pulse(&mut self, level: GpioLevel, duration: Duration) -> Result<(), Error> {
+        self.pulse_pattern(&[(level, duration)])
+    }
+
+    /// Drive the pin through a sequence of levels and hold times, then restore the
+    /// prior level
+    ///
+    /// Hold times are measured on the host between issuing each level change and are
+    /// scheduled from the start of the pattern so they do not accumulate drift.
+    /// The edges seen on the pin are additionally offset by USB transfer jitter,
+    /// typically well under a millisecond.
+    pub fn pulse_pattern(&mut self, pattern: &[(GpioLevel, Duration)]) -> Result<(), Error> {
+        let index = self.pin.index;
+
+        let prior = match self.pin.lock()?.gpio_applied(index) {
+            Some((_, level)) => level,
+            None => GpioLevel::Low,
+        };
+
+        let mut deadline = Instant::now();
+
+        let res = pattern.iter().try_for_each(|(level, hold)| {
+            self.pin
+                .lock()?
+                .set_gpio_mode_level(index, self.mode, *level)?;
+
+            deadline += *hold;
+            wait_until(deadline, DEFAULT_SPIN_THRESHOLD);
+
+            Ok(())
+        });
+
+        // Restore the prior level even where the pattern failed part way
+        let restore = self
+            .pin
             .lock()
-            .unwrap()
-            .set_gpio_mode_level(self.index, self.mode, GpioLevel::High)
+            .and_then(|mut i| i.set_gpio_mode_level(index, self.mode, prior));
+
+        res.and(restore)
+    }
+}
+
+/// Default remaining delay below which waits spin rather than sleep
+pub const DEFAULT_SPIN_THRESHOLD: Duration = Duration::from_millis(1);
+
+/// Wait until the provided deadline, sleeping where possible then spinning for the
+/// final `spin` for precision
+pub(crate) fn wait_until(deadline: Instant, spin: Duration) {
+    let now = Instant::now();
+    if deadline > now + spin {
+        std::thread::sleep(deadline - now - spin);
+    }
+
+    while Instant::now() < deadline {
+        std::hint::spin_loop();
+    }
+}
+
+impl<T: UsbContext> embedded_hal::digital::OutputPin for OutputPin<T> {
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.pin
+            .lock()?
+            .set_gpio_mode_level(self.pin.index, self.mode, GpioLevel::High)
     }
 
     fn set_low(&mut self) -> Result<(), Self::Error> {
-        self.inner
-            .lock()
-            .unwrap()
-            .set_gpio_mode_level(self.index, self.mode, GpioLevel::Low)
+        self.pin
+            .lock()?
+            .set_gpio_mode_level(self.pin.index, self.mode, GpioLevel::Low)
     }
 }
 
-impl embedded_hal::digital::ErrorType for OutputPin {
+impl<T: UsbContext> embedded_hal::digital::ErrorType for OutputPin<T> {
     type Error = Error;
 }
+
+/// Outputs read back the pin level, for open-drain pins this includes other bus drivers
+impl<T: UsbContext> embedded_hal::digital::InputPin for OutputPin<T> {
+    fn is_high(&mut self) -> Result<bool, Self::Error> {
+        self.pin.lock()?.get_gpio_level(self.pin.index)
+    }
+
+    fn is_low(&mut self) -> Result<bool, Self::Error> {
+        let v = self.is_high()?;
+        Ok(!v)
+    }
+}
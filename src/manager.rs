@@ -4,35 +4,45 @@
 //! Copyright 2019 Ryan Kurte
 
 pub use rusb::{
-    Context as UsbContext, Device as UsbDevice, DeviceDescriptor, DeviceList, UsbContext as _,
+    Context, Device as UsbDevice, DeviceDescriptor, DeviceList, GlobalContext, UsbContext,
 };
 
+use std::collections::HashSet;
 #[cfg(feature = "clap")]
 use std::num::ParseIntError;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
 
 #[cfg(feature = "clap")]
 use clap::Parser;
 
 use log::{debug, error, trace};
+use rusb::{Hotplug, HotplugBuilder, Registration};
 
-use crate::device::{PID, VID};
-use crate::Error;
+use crate::device::{read_info, Info, PID, VID};
+use crate::{Cp2130, Error, UsbOptions};
 
-lazy_static::lazy_static! {
-    // LibUSB context created automagically
-    static ref CONTEXT: UsbContext = {
-        UsbContext::new().unwrap()
-    };
-}
-
-/// Manager object maintains libusb context and provides
+/// Manager object wraps a libusb context and provides
 /// methods for connecting to matching devices
-pub struct Manager {
-    //context: rusb::Context,
+///
+/// The associated functions (`Manager::devices()` etc.) use the libusb global context,
+/// use [`Manager::new`] to create a manager owning a new context, or
+/// [`Manager::with_context`] to search using an existing context.
+///
+/// An owned context may be configured (eg. [`Context::set_log_level`]) and
+/// is released when the manager and all devices opened from it are dropped.
+pub struct Manager<T: UsbContext = GlobalContext> {
+    context: T,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 #[cfg_attr(feature = "clap", derive(Parser))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct Filter {
     #[cfg_attr(feature = "clap", clap(long, default_value="10c4", value_parser=parse_hex))]
     /// Device Vendor ID (VID) in hex
@@ -41,6 +51,209 @@ pub struct Filter {
     #[cfg_attr(feature = "clap", clap(long, default_value="87a0", value_parser=parse_hex))]
     /// Device Product ID (PID) in hex
     pub pid: u16,
+
+    #[cfg_attr(feature = "clap", clap(long))]
+    /// Device serial number
+    pub serial: Option<String>,
+
+    #[cfg_attr(feature = "clap", clap(long))]
+    /// Device product string
+    pub product: Option<String>,
+
+    #[cfg_attr(feature = "clap", clap(long))]
+    /// USB bus number
+    pub bus: Option<u8>,
+
+    #[cfg_attr(feature = "clap", clap(long))]
+    /// USB device address
+    pub address: Option<u8>,
+
+    #[cfg_attr(feature = "clap", clap(long))]
+    /// USB port path (bus-port[.port...], eg. `1-2.3`)
+    pub port: Option<PortPath>,
+}
+
+/// Physical USB port path, a bus number and chain of hub ports
+///
+/// Formatted as `bus-port[.port...]` as used by Linux sysfs, eg. `1-2.3`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(into = "String", try_from = "String"))]
+pub struct PortPath {
+    pub bus: u8,
+    pub ports: Vec<u8>,
+}
+
+impl FromStr for PortPath {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidConfig {
+            field: "port",
+            reason: "port path must be of the form bus-port[.port...]",
+        };
+
+        let (bus, ports) = s.split_once('-').ok_or_else(invalid)?;
+
+        let bus = bus.parse().map_err(|_| invalid())?;
+        let ports = ports
+            .split('.')
+            .map(|p| p.parse().map_err(|_| invalid()))
+            .collect::<Result<Vec<u8>, _>>()?;
+
+        Ok(Self { bus, ports })
+    }
+}
+
+impl std::fmt::Display for PortPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-", self.bus)?;
+        for (i, p) in self.ports.iter().enumerate() {
+            if i > 0 {
+                write!(f, ".")?;
+            }
+            write!(f, "{}", p)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<PortPath> for String {
+    fn from(p: PortPath) -> Self {
+        p.to_string()
+    }
+}
+
+impl TryFrom<String> for PortPath {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// USB connection speed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum Speed {
+    #[default]
+    Unknown,
+    /// Low speed (1.5 Mbps)
+    Low,
+    /// Full speed (12 Mbps)
+    Full,
+    /// High speed (480 Mbps)
+    High,
+    /// Super speed (5 Gbps)
+    Super,
+    /// Super speed plus (10 Gbps)
+    SuperPlus,
+}
+
+impl From<rusb::Speed> for Speed {
+    fn from(s: rusb::Speed) -> Self {
+        match s {
+            rusb::Speed::Low => Speed::Low,
+            rusb::Speed::Full => Speed::Full,
+            rusb::Speed::High => Speed::High,
+            rusb::Speed::Super => Speed::Super,
+            rusb::Speed::SuperPlus => Speed::SuperPlus,
+            _ => Speed::Unknown,
+        }
+    }
+}
+
+#[cfg(feature = "nusb")]
+impl From<nusb::Speed> for Speed {
+    fn from(s: nusb::Speed) -> Self {
+        match s {
+            nusb::Speed::Low => Speed::Low,
+            nusb::Speed::Full => Speed::Full,
+            nusb::Speed::High => Speed::High,
+            nusb::Speed::Super => Speed::Super,
+            nusb::Speed::SuperPlus => Speed::SuperPlus,
+            _ => Speed::Unknown,
+        }
+    }
+}
+
+impl std::fmt::Display for Speed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Speed::Unknown => "unknown",
+            Speed::Low => "low",
+            Speed::Full => "full",
+            Speed::High => "high",
+            Speed::Super => "super",
+            Speed::SuperPlus => "super+",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// Summary of an attached device, for presenting device selection
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DeviceSummary {
+    pub vid: u16,
+    pub pid: u16,
+    pub bus: u8,
+    pub address: u8,
+    pub port: PortPath,
+    pub speed: Speed,
+    /// Serial number, `None` where the device could not be opened to read strings
+    pub serial: Option<String>,
+    /// Product string, `None` where the device could not be opened to read strings
+    pub product: Option<String>,
+}
+
+impl std::fmt::Display for DeviceSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:04x}:{:04x} bus {:03} address {:03} port {} ({} speed)",
+            self.vid, self.pid, self.bus, self.address, self.port, self.speed
+        )?;
+        if let Some(s) = &self.serial {
+            write!(f, " serial {}", s)?;
+        }
+        if let Some(p) = &self.product {
+            write!(f, " ({})", p)?;
+        }
+        Ok(())
+    }
+}
+
+/// Devices opened by [`Manager::open_all`]
+pub struct OpenAll<T: UsbContext = GlobalContext> {
+    /// Successfully opened devices, in enumeration order
+    pub devices: Vec<Cp2130<T>>,
+    /// Matching devices that could not be opened, identified by port
+    pub failures: Vec<(PortPath, Error)>,
+}
+
+/// Collect the results of opening devices, in order, keeping failures alongside
+/// the port of the device that could not be opened
+impl<T: UsbContext> FromIterator<(PortPath, Result<Cp2130<T>, Error>)> for OpenAll<T> {
+    fn from_iter<I: IntoIterator<Item = (PortPath, Result<Cp2130<T>, Error>)>>(iter: I) -> Self {
+        let mut open = OpenAll {
+            devices: vec![],
+            failures: vec![],
+        };
+
+        for (port, res) in iter {
+            match res {
+                Ok(d) => open.devices.push(d),
+                Err(e) => {
+                    error!("Opening device at port {}: {}", port, e);
+                    open.failures.push((port, e));
+                }
+            }
+        }
+
+        open
+    }
 }
 
 #[cfg(feature = "clap")]
@@ -50,17 +263,290 @@ fn parse_hex(src: &str) -> Result<u16, ParseIntError> {
 
 impl Default for Filter {
     fn default() -> Self {
-        Filter { vid: VID, pid: PID }
+        Filter {
+            vid: VID,
+            pid: PID,
+            serial: None,
+            product: None,
+            bus: None,
+            address: None,
+            port: None,
+        }
+    }
+}
+
+impl Filter {
+    /// Create a filter matching devices with the provided serial number
+    pub fn with_serial(serial: &str) -> Self {
+        Self {
+            serial: Some(serial.to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// Create a filter matching the device at the provided bus number and address
+    pub fn with_location(bus: u8, address: u8) -> Self {
+        Self {
+            bus: Some(bus),
+            address: Some(address),
+            ..Default::default()
+        }
+    }
+
+    /// Create a filter matching the device connected to the provided port
+    pub fn with_port(port: PortPath) -> Self {
+        Self {
+            port: Some(port),
+            ..Default::default()
+        }
+    }
+
+    /// Check whether a device matches the filter
+    ///
+    /// Serial and product strings are only checked where `info` is provided, as
+    /// reading these requires opening the device.
+    pub fn matches(
+        &self,
+        vid: u16,
+        pid: u16,
+        bus: u8,
+        address: u8,
+        ports: &[u8],
+        info: Option<&Info>,
+    ) -> bool {
+        vid == self.vid
+            && pid == self.pid
+            && self.matches_location(bus, address, ports)
+            && info.map(|i| self.matches_info(i)).unwrap_or(true)
+    }
+
+    /// Check whether a device location matches the filter
+    pub fn matches_location(&self, bus: u8, address: u8, ports: &[u8]) -> bool {
+        self.bus.map(|b| b == bus).unwrap_or(true)
+            && self.address.map(|a| a == address).unwrap_or(true)
+            && self
+                .port
+                .as_ref()
+                .map(|p| p.bus == bus && p.ports == ports)
+                .unwrap_or(true)
+    }
+
+    /// Check whether matching requires device strings (and thus opening the device)
+    pub fn requires_strings(&self) -> bool {
+        self.serial.is_some() || self.product.is_some()
+    }
+
+    /// Check whether device strings match the filter
+    pub fn matches_info(&self, info: &Info) -> bool {
+        let matches = |f: &Option<String>, v: &str| f.as_deref().map(|f| f == v).unwrap_or(true);
+
+        matches(&self.serial, info.serial()) && matches(&self.product, info.product())
+    }
+}
+
+/// Hotplug event for a device matching a [`Manager::watch`] filter
+#[derive(Debug)]
+pub enum HotplugEvent<T: UsbContext> {
+    /// Matching device connected
+    Arrived(UsbDevice<T>),
+    /// Matching device disconnected
+    Left(UsbDevice<T>),
+}
+
+/// Active hotplug watch, unregistered when dropped
+pub struct Watch<T: UsbContext> {
+    registration: Option<Registration<T>>,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    dispatch: Option<JoinHandle<()>>,
+}
+
+/// Adaptor forwarding libusb hotplug events for filtering outside the event handler
+struct HotplugForward<T: UsbContext>(Sender<HotplugEvent<T>>);
+
+impl<T: UsbContext> Hotplug<T> for HotplugForward<T> {
+    fn device_arrived(&mut self, device: UsbDevice<T>) {
+        let _ = self.0.send(HotplugEvent::Arrived(device));
+    }
+
+    fn device_left(&mut self, device: UsbDevice<T>) {
+        let _ = self.0.send(HotplugEvent::Left(device));
+    }
+}
+
+/// Check whether a device matches the filter, opening it to read strings where required
+fn matches_device<T: UsbContext>(
+    filter: &Filter,
+    device: &UsbDevice<T>,
+    descriptor: &DeviceDescriptor,
+) -> bool {
+    let ports = device.port_numbers().unwrap_or_default();
+    let (bus, address) = (device.bus_number(), device.address());
+
+    if !filter.matches(
+        descriptor.vendor_id(),
+        descriptor.product_id(),
+        bus,
+        address,
+        &ports,
+        None,
+    ) {
+        return false;
+    }
+
+    if !filter.requires_strings() {
+        return true;
+    }
+
+    match read_info(device, descriptor) {
+        Ok(info) => filter.matches_info(&info),
+        Err(e) => {
+            debug!("Skipping device, reading strings failed: {}", e);
+            false
+        }
     }
 }
 
 impl Manager {
     /// Fetch a libusb device list (for filtering and connecting to devices)
-    pub fn devices() -> Result<DeviceList<UsbContext>, Error> {
+    pub fn devices() -> Result<DeviceList<GlobalContext>, Error> {
+        Manager::with_context(GlobalContext::default()).list()
+    }
+
+    /// Fetch devices matching the provided filter
+    pub fn devices_filtered(
+        filter: Filter,
+    ) -> Result<Vec<(UsbDevice<GlobalContext>, DeviceDescriptor)>, Error> {
+        Manager::with_context(GlobalContext::default()).list_filtered(filter)
+    }
+
+    /// Fetch the device matching the provided filter at the specified index
+    pub fn device(
+        filter: Filter,
+        index: usize,
+    ) -> Result<(UsbDevice<GlobalContext>, DeviceDescriptor), Error> {
+        Manager::with_context(GlobalContext::default()).find(filter, index)
+    }
+
+    /// Fetch summaries of devices matching the provided filter
+    pub fn list_devices(filter: Filter) -> Result<Vec<DeviceSummary>, Error> {
+        Manager::with_context(GlobalContext::default()).list_summaries(filter)
+    }
+
+    /// Open all devices matching the provided filter
+    ///
+    /// Devices that fail to open are skipped and reported in [`OpenAll::failures`].
+    pub fn open_all(filter: Filter, options: UsbOptions) -> Result<OpenAll, Error> {
+        Manager::with_context(GlobalContext::default()).open_filtered(filter, options)
+    }
+
+    /// Fetch the device at the provided bus number and address
+    pub fn device_by_location(
+        bus: u8,
+        address: u8,
+    ) -> Result<(UsbDevice<GlobalContext>, DeviceDescriptor), Error> {
+        Self::device(Filter::with_location(bus, address), 0)
+    }
+
+    /// Fetch the device connected to the provided port path
+    pub fn device_by_port(
+        port: PortPath,
+    ) -> Result<(UsbDevice<GlobalContext>, DeviceDescriptor), Error> {
+        Self::device(Filter::with_port(port), 0)
+    }
+
+    /// Fetch the device with the provided serial number
+    ///
+    /// Serial numbers are stable across re-enumeration, so should be preferred over
+    /// device indices where multiple devices are attached.
+    pub fn device_by_serial(
+        serial: &str,
+    ) -> Result<(UsbDevice<GlobalContext>, DeviceDescriptor), Error> {
+        Self::device(Filter::with_serial(serial), 0)
+    }
+}
+
+impl Manager<Context> {
+    /// Create a manager owning a new libusb context
+    pub fn new() -> Result<Self, Error> {
+        let context = Context::new()?;
+
+        Ok(Self::with_context(context))
+    }
+
+    /// Fetch the underlying libusb context for configuration
+    pub fn context_mut(&mut self) -> &mut Context {
+        &mut self.context
+    }
+}
+
+#[cfg(feature = "nusb")]
+impl Manager {
+    /// Fetch nusb devices matching the provided filter
+    pub fn nusb_devices_filtered(filter: Filter) -> Result<Vec<nusb::DeviceInfo>, Error> {
+        use nusb::MaybeFuture;
+
+        debug!("Fetching available USB devices (nusb)");
+
+        let matches: Vec<_> = nusb::list_devices()
+            .wait()?
+            .inspect(|d| trace!("Device: {:?}", d))
+            .filter(|d| {
+                let info = Info::new(
+                    d.manufacturer_string().unwrap_or_default().to_string(),
+                    d.product_string().unwrap_or_default().to_string(),
+                    d.serial_number().unwrap_or_default().to_string(),
+                );
+                filter.matches(
+                    d.vendor_id(),
+                    d.product_id(),
+                    d.bus_id().parse().unwrap_or_default(),
+                    d.device_address(),
+                    d.port_chain(),
+                    Some(&info),
+                )
+            })
+            .collect();
+
+        debug!("Found {} matching devices", matches.len());
+
+        Ok(matches)
+    }
+
+    /// Fetch the nusb device matching the provided filter at the specified index
+    pub fn nusb_device(filter: Filter, index: usize) -> Result<nusb::DeviceInfo, Error> {
+        let mut matches = Self::nusb_devices_filtered(filter)?;
+
+        if index >= matches.len() {
+            error!(
+                "Device index ({}) exceeds number of discovered devices ({})",
+                index,
+                matches.len()
+            );
+            return Err(Error::InvalidIndex);
+        }
+
+        Ok(matches.remove(index))
+    }
+}
+
+impl<T: UsbContext> Manager<T> {
+    /// Create a manager using an existing libusb context
+    pub fn with_context(context: T) -> Self {
+        Self { context }
+    }
+
+    /// Fetch the underlying libusb context
+    pub fn context(&self) -> &T {
+        &self.context
+    }
+
+    /// Fetch a libusb device list from this manager's context
+    pub fn list(&self) -> Result<DeviceList<T>, Error> {
         debug!("Fetching available USB devices");
 
         // Attempt to fetch device list
-        let devices = match CONTEXT.devices() {
+        let devices = match self.context.devices() {
             Ok(v) => v,
             Err(e) => {
                 error!("Fetching devices: {}", e);
@@ -71,10 +557,15 @@ impl Manager {
         Ok(devices)
     }
 
-    pub fn devices_filtered(
+    /// Fetch devices from this manager's context matching the provided filter
+    ///
+    /// Where the filter includes serial or product strings, candidate devices are opened
+    /// to read these and devices that cannot be opened are skipped.
+    pub fn list_filtered(
+        &self,
         filter: Filter,
-    ) -> Result<Vec<(UsbDevice<UsbContext>, DeviceDescriptor)>, Error> {
-        let devices = Self::devices()?;
+    ) -> Result<Vec<(UsbDevice<T>, DeviceDescriptor)>, Error> {
+        let devices = self.list()?;
 
         let mut matches = vec![];
 
@@ -87,10 +578,11 @@ impl Manager {
 
             trace!("Device: {:?}", device_desc);
 
-            // Check for VID/PID match
-            if device_desc.vendor_id() == filter.vid && device_desc.product_id() == filter.pid {
-                matches.push((device, device_desc));
+            if !matches_device(&filter, &device, &device_desc) {
+                continue;
             }
+
+            matches.push((device, device_desc));
         }
 
         debug!("Found {} matching devices", matches.len());
@@ -98,15 +590,44 @@ impl Manager {
         Ok(matches)
     }
 
-    pub fn device(
+    /// Fetch summaries of devices from this manager's context matching the provided filter
+    ///
+    /// Devices are not claimed, serial numbers are read where the device can be opened.
+    pub fn list_summaries(&self, filter: Filter) -> Result<Vec<DeviceSummary>, Error> {
+        let matches = self.list_filtered(filter)?;
+
+        let summaries = matches
+            .iter()
+            .map(|(device, descriptor)| (device, descriptor, read_info(device, descriptor).ok()))
+            .map(|(device, descriptor, info)| DeviceSummary {
+                vid: descriptor.vendor_id(),
+                pid: descriptor.product_id(),
+                bus: device.bus_number(),
+                address: device.address(),
+                port: PortPath {
+                    bus: device.bus_number(),
+                    ports: device.port_numbers().unwrap_or_default(),
+                },
+                speed: device.speed().into(),
+                serial: info.as_ref().map(|i| i.serial().to_string()),
+                product: info.as_ref().map(|i| i.product().to_string()),
+            })
+            .collect();
+
+        Ok(summaries)
+    }
+
+    /// Fetch the device from this manager's context matching the provided filter at the specified index
+    pub fn find(
+        &self,
         filter: Filter,
         index: usize,
-    ) -> Result<(UsbDevice<UsbContext>, DeviceDescriptor), Error> {
+    ) -> Result<(UsbDevice<T>, DeviceDescriptor), Error> {
         // Find matching devices
-        let mut matches = Self::devices_filtered(filter)?;
+        let mut matches = self.list_filtered(filter)?;
 
         // Check index is valid
-        if matches.len() < index || matches.len() == 0 {
+        if index >= matches.len() {
             error!(
                 "Device index ({}) exceeds number of discovered devices ({})",
                 index,
@@ -119,3 +640,137 @@ impl Manager {
         Ok(matches.remove(index))
     }
 }
+
+impl<T: UsbContext + 'static> Manager<T> {
+    /// Open all devices from this manager's context matching the provided filter
+    ///
+    /// Devices that fail to open are skipped and reported in [`OpenAll::failures`].
+    pub fn open_filtered(&self, filter: Filter, options: UsbOptions) -> Result<OpenAll<T>, Error> {
+        let matches = self.list_filtered(filter)?;
+
+        let open: OpenAll<T> = matches
+            .into_iter()
+            .map(|(device, descriptor)| {
+                let port = PortPath {
+                    bus: device.bus_number(),
+                    ports: device.port_numbers().unwrap_or_default(),
+                };
+
+                (port, Cp2130::new(device, descriptor, options.clone()))
+            })
+            .collect();
+
+        debug!(
+            "Opened {} devices ({} failed)",
+            open.devices.len(),
+            open.failures.len()
+        );
+
+        Ok(open)
+    }
+
+    /// Watch for devices matching the filter arriving or leaving, with `callback`
+    /// called from a background thread.
+    ///
+    /// Devices already connected are reported as arrivals. Arrivals are matched against
+    /// the full filter (opening devices to read strings where required), and departures
+    /// are reported only for devices previously reported as arrived. Devices must not be
+    /// opened from within the callback, instead pass them to another thread to connect.
+    pub fn watch(
+        &self,
+        filter: Filter,
+        mut callback: impl FnMut(HotplugEvent<T>) + Send + 'static,
+    ) -> Result<Watch<T>, Error> {
+        if !rusb::has_hotplug() {
+            return Err(Error::Unsupported(
+                "hotplug is not supported on this platform",
+            ));
+        }
+
+        debug!(
+            "Registering hotplug watch for {:04x}:{:04x}",
+            filter.vid, filter.pid
+        );
+
+        let (tx, rx) = mpsc::channel();
+
+        let registration = HotplugBuilder::new()
+            .vendor_id(filter.vid)
+            .product_id(filter.pid)
+            .enumerate(true)
+            .register(self.context.clone(), Box::new(HotplugForward(tx)))?;
+
+        // Events are filtered from a separate thread as reading strings is not
+        // permitted within libusb hotplug callbacks, exiting once unregistered
+        let dispatch = std::thread::Builder::new()
+            .name("cp2130-hotplug-dispatch".to_string())
+            .spawn(move || {
+                let mut arrived = HashSet::new();
+
+                for event in rx {
+                    match event {
+                        HotplugEvent::Arrived(device) => {
+                            let descriptor = match device.device_descriptor() {
+                                Ok(d) => d,
+                                Err(_) => continue,
+                            };
+                            if matches_device(&filter, &device, &descriptor) {
+                                arrived.insert((device.bus_number(), device.address()));
+                                callback(HotplugEvent::Arrived(device));
+                            }
+                        }
+                        HotplugEvent::Left(device) => {
+                            if arrived.remove(&(device.bus_number(), device.address())) {
+                                callback(HotplugEvent::Left(device));
+                            }
+                        }
+                    }
+                }
+            })
+            .expect("failed to spawn hotplug dispatch thread");
+
+        // Hotplug callbacks are delivered while handling libusb events
+        let running = Arc::new(AtomicBool::new(true));
+        let (context, r) = (self.context.clone(), running.clone());
+
+        let thread = std::thread::Builder::new()
+            .name("cp2130-hotplug".to_string())
+            .spawn(move || {
+                while r.load(Ordering::SeqCst) {
+                    if let Err(e) = context.handle_events(Some(Duration::from_millis(100))) {
+                        error!("Handling USB events: {}", e);
+                        break;
+                    }
+                }
+            })
+            .expect("failed to spawn hotplug thread");
+
+        Ok(Watch {
+            registration: Some(registration),
+            running,
+            thread: Some(thread),
+            dispatch: Some(dispatch),
+        })
+    }
+}
+
+impl<T: UsbContext> Drop for Watch<T> {
+    fn drop(&mut self) {
+        // Unregister prior to stopping the event thread so pending events are delivered
+        drop(self.registration.take());
+
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(t) = self.thread.take() {
+            if t.join().is_err() {
+                error!("Hotplug thread panicked");
+            }
+        }
+
+        // Dispatch exits once the events forwarded prior to unregistering are handled
+        if let Some(t) = self.dispatch.take() {
+            if t.join().is_err() {
+                error!("Hotplug dispatch thread panicked");
+            }
+        }
+    }
+}
@@ -0,0 +1,557 @@
+//! CP2130 Driver Mock Device
+//!
+//! [`MockCp2130`] simulates a CP2130 behind the [`Transport`] interface so applications
+//! written against [`Device`] (or using [`Spi`](crate::Spi) / [`InputPin`](crate::InputPin) /
+//! [`OutputPin`](crate::OutputPin) handles) can be tested without hardware.
+//!
+//! Copyright 2019 Ryan Kurte
+
+use std::collections::VecDeque;
+use std::io::Write;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use byteorder::{ByteOrder, LE};
+use log::trace;
+
+use crate::device::{Info, GPIO_COUNT};
+use crate::otp::encode_string;
+use crate::protocol::{
+    self, ChipSelect, ChipSelectEnables, Commands, CsMode, EventCounter, GpioModeLevel, GpioValues,
+    SpiDelayWord, TransferCommand, TransferHeader, MEMORY_KEY,
+};
+use crate::transport::Recorder;
+use crate::{
+    Cp2130, Device, Error, GpioAccess, GpioLevel, GpioLevels, GpioMode, SpiAccess, Transport,
+};
+
+/// Simulated device state
+#[derive(Debug)]
+struct MockState {
+    version: u16,
+    modes: [GpioMode; GPIO_COUNT as usize],
+    levels: GpioLevels,
+    spi_responses: VecDeque<Vec<u8>>,
+    spi_writes: Vec<Vec<u8>>,
+    pending_read: VecDeque<u8>,
+    /// Payload bytes outstanding for a write split across bulk OUT transfers
+    pending_write: usize,
+    /// Simulated RTR input, ReadWithRTR data is held until asserted
+    rtr_ready: bool,
+    /// Data for an outstanding ReadWithRTR command, awaiting RTR
+    rtr_pending: VecDeque<u8>,
+    /// Simulated stalled bus, IN data is withheld while set
+    stalled: bool,
+    /// Injected results for upcoming USB operations, `None` succeeds
+    errors: VecDeque<Option<Error>>,
+    /// Programmed USB configuration (Get_USB_Config format)
+    usb_config: [u8; 9],
+    clock_divider: u8,
+    /// SPI control words and delays (Get_SPI_Delay format) per channel
+    spi_words: [u8; GPIO_COUNT as usize],
+    spi_delays: [[u8; 8]; GPIO_COUNT as usize],
+    /// Chip select enables, bit per channel
+    cs_enables: u16,
+    /// Event counter state
+    event_counter: EventCounter,
+    /// Lock byte, set bits are unlocked
+    lock: u16,
+    /// Programmed pin configuration (Get_Pin_Config format)
+    pin_config: [u8; 20],
+    /// Raw PROM contents, not mapped to the individual configuration fields
+    prom: Vec<u8>,
+    /// Programmed string blocks, in command order (manufacturer 1/2, product 1/2, serial)
+    strings: Vec<u8>,
+}
+
+/// Mock CP2130 device
+///
+/// This dereferences to a [`Cp2130`] connected to the simulated device, so SPI and GPIO
+/// handles are created in the same manner as for real hardware.
+pub struct MockCp2130 {
+    cp2130: Cp2130,
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockCp2130 {
+    /// Create a new mock device, with all pins as inputs pulled low
+    pub fn new() -> Self {
+        Self::with_transport(|t| t)
+    }
+
+    /// Create a new mock device, recording each USB exchange to `writer`
+    ///
+    /// Transcripts use the [`Recorder`] format, so sessions against the mock may be
+    /// compared with (or replayed in place of) sessions captured from hardware.
+    pub fn recorded<W: Write + Send + 'static>(writer: W) -> Self {
+        Self::with_transport(|t| Recorder::new(t, writer))
+    }
+
+    /// Create a new mock device, wrapping the simulated transport with `wrap`
+    fn with_transport<T: Transport + 'static>(wrap: impl FnOnce(MockTransport) -> T) -> Self {
+        let state = Arc::new(Mutex::new(MockState {
+            version: 0x0001,
+            modes: [GpioMode::Input; GPIO_COUNT as usize],
+            levels: GpioLevels::empty(),
+            spi_responses: VecDeque::new(),
+            spi_writes: vec![],
+            pending_read: VecDeque::new(),
+            pending_write: 0,
+            rtr_ready: true,
+            rtr_pending: VecDeque::new(),
+            stalled: false,
+            errors: VecDeque::new(),
+            usb_config: [0xc4, 0x10, 0xa0, 0x87, 0x32, 0x00, 0x01, 0x00, 0x01],
+            clock_divider: 0,
+            spi_words: [0u8; GPIO_COUNT as usize],
+            spi_delays: [[0u8; 8]; GPIO_COUNT as usize],
+            cs_enables: 0,
+            event_counter: EventCounter {
+                mode: None,
+                overflow: false,
+                count: 0,
+            },
+            lock: 0xffff,
+            pin_config: [0u8; 20],
+            prom: vec![0xff; 512],
+            strings: [
+                encode_string("Mock", 2).unwrap(),
+                encode_string("CP2130", 2).unwrap(),
+                encode_string("0", 1).unwrap(),
+            ]
+            .concat(),
+        }));
+
+        let transport = MockTransport {
+            state: state.clone(),
+        };
+        let info = Info::new("Mock".to_string(), "CP2130".to_string(), "0".to_string());
+
+        Self {
+            cp2130: Cp2130::from_transport(wrap(transport), info),
+            state,
+        }
+    }
+
+    /// Set the chip version reported by the device
+    pub fn set_version(&self, version: u16) {
+        self.state.lock().unwrap().version = version;
+    }
+
+    /// Simulate events on GPIO.4, counted where the event counter is enabled
+    pub fn push_events(&self, n: u16) {
+        let mut s = self.state.lock().unwrap();
+        let counter = &mut s.event_counter;
+        if counter.mode.is_some() {
+            let (count, overflow) = counter.count.overflowing_add(n);
+            counter.count = count;
+            counter.overflow |= overflow;
+        }
+    }
+
+    /// Set the externally driven level for a pin (only visible while the pin is an input)
+    pub fn set_input(&self, pin: u8, level: GpioLevel) {
+        let mut s = self.state.lock().unwrap();
+        if s.modes[pin as usize] == GpioMode::Input {
+            s.levels.set_pin(pin, level);
+        }
+    }
+
+    /// Fetch the current mode for a pin
+    pub fn gpio_mode(&self, pin: u8) -> GpioMode {
+        self.state.lock().unwrap().modes[pin as usize]
+    }
+
+    /// Fetch the current level for a pin
+    pub fn gpio_level(&self, pin: u8) -> GpioLevel {
+        match self.state.lock().unwrap().levels.pin(pin) {
+            true => GpioLevel::High,
+            false => GpioLevel::Low,
+        }
+    }
+
+    /// Queue data to be returned by the next SPI read or transfer
+    ///
+    /// Reads with no queued response return zeros.
+    pub fn push_spi_response(&self, data: &[u8]) {
+        self.state
+            .lock()
+            .unwrap()
+            .spi_responses
+            .push_back(data.to_vec());
+    }
+
+    /// Set whether the RTR input is asserted (default asserted)
+    ///
+    /// ReadWithRTR responses are held while RTR is deasserted, and released once asserted.
+    pub fn set_rtr_ready(&self, ready: bool) {
+        let mut s = self.state.lock().unwrap();
+        s.rtr_ready = ready;
+        if ready {
+            let pending = std::mem::take(&mut s.rtr_pending);
+            s.pending_read.extend(pending);
+        }
+    }
+
+    /// Set whether the SPI bus is stalled (default not stalled)
+    ///
+    /// Commands are accepted while stalled, with response data withheld until released.
+    pub fn set_stalled(&self, stalled: bool) {
+        self.state.lock().unwrap().stalled = stalled;
+    }
+
+    /// Take the data written by SPI writes and transfers since the last call
+    pub fn take_spi_writes(&self) -> Vec<Vec<u8>> {
+        std::mem::take(&mut self.state.lock().unwrap().spi_writes)
+    }
+
+    /// Fail the next USB operation with the provided error
+    pub fn inject_error(&self, err: Error) {
+        self.state.lock().unwrap().errors.push_back(Some(err));
+    }
+
+    /// Fail the USB operation following the next `ops` operations with the provided error
+    pub fn inject_error_after(&self, ops: usize, err: Error) {
+        let mut s = self.state.lock().unwrap();
+        s.errors.extend(std::iter::repeat_with(|| None).take(ops));
+        s.errors.push_back(Some(err));
+    }
+}
+
+impl Default for MockCp2130 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Deref for MockCp2130 {
+    type Target = Cp2130;
+
+    fn deref(&self) -> &Self::Target {
+        &self.cp2130
+    }
+}
+
+impl SpiAccess for MockCp2130 {
+    fn spi_read(&self, buff: &mut [u8]) -> Result<usize, Error> {
+        self.cp2130.spi_read(buff)
+    }
+
+    fn spi_write(&self, buff: &[u8]) -> Result<(), Error> {
+        self.cp2130.spi_write(buff)
+    }
+
+    fn spi_write_stream(&self, reader: &mut dyn std::io::Read, len: usize) -> Result<(), Error> {
+        self.cp2130.spi_write_stream(reader, len)
+    }
+
+    fn spi_write_read(&self, buff_out: &[u8], buff_in: &mut [u8]) -> Result<usize, Error> {
+        self.cp2130.spi_write_read(buff_out, buff_in)
+    }
+}
+
+impl GpioAccess for MockCp2130 {
+    fn set_gpio_mode_level(&self, pin: u8, mode: GpioMode, level: GpioLevel) -> Result<(), Error> {
+        self.cp2130.set_gpio_mode_level(pin, mode, level)
+    }
+
+    fn get_gpio_values(&self) -> Result<GpioLevels, Error> {
+        self.cp2130.get_gpio_values()
+    }
+
+    fn get_gpio_level(&self, pin: u8) -> Result<bool, Error> {
+        self.cp2130.get_gpio_level(pin)
+    }
+}
+
+impl Device for MockCp2130 {
+    fn version(&self) -> Result<u16, Error> {
+        self.cp2130.version()
+    }
+}
+
+/// Transport decoding CP2130 commands against the simulated state
+struct MockTransport {
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockState {
+    fn check_error(&mut self) -> Result<(), Error> {
+        match self.errors.pop_front() {
+            Some(Some(e)) => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// Offset of the string block for a get or set string command
+    fn string_block(request: u8) -> usize {
+        ((request - Commands::GetManufacturingString1 as u8) / 2) as usize * 64
+    }
+
+    /// Queue a response of `len` bytes for the next bulk read
+    fn queue_response(&mut self, len: usize) {
+        let mut data = self.spi_responses.pop_front().unwrap_or_default();
+        data.resize(len, 0);
+        self.pending_read.extend(data);
+    }
+}
+
+impl Transport for MockTransport {
+    fn control_in(
+        &self,
+        _request_type: u8,
+        request: u8,
+        _value: u16,
+        index: u16,
+        buff: &mut [u8],
+        _timeout: Duration,
+    ) -> Result<usize, Error> {
+        let mut s = self.state.lock().unwrap();
+        s.check_error()?;
+
+        trace!("Mock control in (request: 0x{:02x})", request);
+
+        match request {
+            r if r == Commands::GetReadOnlyVersion as u8 => {
+                buff.copy_from_slice(&protocol::encode_version(s.version))
+            }
+            r if r == Commands::GetGpioValues as u8 => buff.copy_from_slice(&s.levels.encode()),
+            r if (Commands::GetManufacturingString1 as u8..=Commands::SetSerialString as u8)
+                .contains(&r) =>
+            {
+                let offset = MockState::string_block(r);
+                buff.copy_from_slice(&s.strings[offset..offset + 64]);
+            }
+            r if r == Commands::GetPromConfig as u8 => {
+                let block = protocol::prom_block(index as usize).ok_or(Error::InvalidIndex)?;
+                buff.copy_from_slice(&s.prom[block]);
+            }
+            r if r == Commands::GetClockDivider as u8 => buff[0] = s.clock_divider,
+            r if r == Commands::GetSpiWord as u8 => buff.copy_from_slice(&s.spi_words),
+            r if r == Commands::GetSpiDelay as u8 => {
+                buff.copy_from_slice(&s.spi_delays[index as usize])
+            }
+            r if r == Commands::GetGpioChipSelect as u8 => {
+                let enables = ChipSelectEnables {
+                    channels: s.cs_enables,
+                    pins: s.cs_enables,
+                };
+                buff.copy_from_slice(&enables.encode());
+            }
+            r if r == Commands::GetEventCounter as u8 => {
+                buff.copy_from_slice(&s.event_counter.encode());
+            }
+            r if r == Commands::GetLockByte as u8 => LE::write_u16(buff, s.lock),
+            r if r == Commands::GetPinConfig as u8 => buff.copy_from_slice(&s.pin_config),
+            r if r == Commands::GetUsbConfig as u8 => {
+                let n = buff.len().min(s.usb_config.len());
+                buff[..n].copy_from_slice(&s.usb_config[..n]);
+            }
+            _ => buff.fill(0),
+        }
+
+        Ok(buff.len())
+    }
+
+    fn control_out(
+        &self,
+        _request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buff: &[u8],
+        _timeout: Duration,
+    ) -> Result<usize, Error> {
+        let mut s = self.state.lock().unwrap();
+        s.check_error()?;
+
+        trace!(
+            "Mock control out (request: 0x{:02x}, data: {:?})",
+            request,
+            buff
+        );
+
+        // PROM writes are ignored without the memory key
+        if request == Commands::SetUsbConfig as u8 && value == MEMORY_KEY {
+            let mask = buff[9];
+            let fields: [(u8, &[usize]); 6] = [
+                (1 << 0, &[0, 1]),
+                (1 << 1, &[2, 3]),
+                (1 << 2, &[4]),
+                (1 << 3, &[5]),
+                (1 << 4, &[6, 7]),
+                (1 << 7, &[8]),
+            ];
+            for (bit, bytes) in fields {
+                if mask & bit != 0 {
+                    for &i in bytes {
+                        s.usb_config[i] = buff[i];
+                    }
+                }
+            }
+        }
+
+        if (Commands::GetManufacturingString1 as u8..=Commands::SetSerialString as u8)
+            .contains(&request)
+            && value == MEMORY_KEY
+        {
+            let offset = MockState::string_block(request);
+            s.strings[offset..offset + 64].copy_from_slice(buff);
+        }
+
+        if request == Commands::SetPromConfig as u8 && value == MEMORY_KEY {
+            let block = protocol::prom_block(index as usize).ok_or(Error::InvalidIndex)?;
+            s.prom[block].copy_from_slice(buff);
+        }
+
+        if request == Commands::SetLockByte as u8 && value == MEMORY_KEY {
+            s.lock &= LE::read_u16(buff);
+        }
+
+        if request == Commands::SetPinConfig as u8 && value == MEMORY_KEY {
+            s.pin_config.copy_from_slice(buff);
+        }
+
+        if request == Commands::SetClockDivider as u8 {
+            s.clock_divider = buff[0];
+        }
+
+        if request == Commands::SetSpiWord as u8 {
+            s.spi_words[buff[0] as usize] = buff[1];
+        }
+
+        if request == Commands::SetSpiDelay as u8 {
+            let delay = SpiDelayWord::decode(buff)?;
+            s.spi_delays[delay.channel as usize] = delay.encode();
+        }
+
+        if request == Commands::SetGpioChipSelect as u8 {
+            let cs = ChipSelect::decode(buff)?;
+            let bit = 1 << cs.channel;
+            match cs.mode {
+                CsMode::Disabled => s.cs_enables &= !bit,
+                CsMode::Enabled => s.cs_enables |= bit,
+                CsMode::Exclusive => s.cs_enables = bit,
+            }
+        }
+
+        if request == Commands::SetEventCOunter as u8 {
+            s.event_counter = EventCounter::decode(buff)?;
+        }
+
+        if request == Commands::SetGpioValues as u8 {
+            let GpioValues { levels, mask } = GpioValues::decode(buff)?;
+
+            for (pin, level) in levels.iter_pins() {
+                if mask.pin(pin) && s.modes[pin as usize] != GpioMode::Input {
+                    s.levels.set_pin(pin, level);
+                }
+            }
+        }
+
+        // RTR stop aborts any outstanding ReadWithRTR command
+        if request == Commands::SetRtrStop as u8 && buff.first() == Some(&1) {
+            s.rtr_pending.clear();
+        }
+
+        if request == Commands::SetGpioModeAndLevel as u8 {
+            let GpioModeLevel { pin, mode, level } = GpioModeLevel::decode(buff)?;
+            s.modes[pin as usize] = mode;
+
+            // Reconfiguring GPIO.4 disables the event counter
+            if pin == 4 {
+                s.event_counter.mode = None;
+            }
+
+            if mode != GpioMode::Input {
+                s.levels.set_pin(pin, level);
+            }
+        }
+
+        Ok(buff.len())
+    }
+
+    fn bulk_in(&self, buff: &mut [u8], timeout: Duration) -> Result<usize, Error> {
+        let start = Instant::now();
+
+        // Wait for a concurrent bulk OUT to request data, timing out as with real hardware
+        let mut s = loop {
+            let mut s = self.state.lock().unwrap();
+            s.check_error()?;
+
+            if !s.stalled && !s.pending_read.is_empty() {
+                break s;
+            }
+            drop(s);
+
+            if start.elapsed() > timeout {
+                return Err(Error::Usb(rusb::Error::Timeout));
+            }
+            std::thread::sleep(Duration::from_micros(100));
+        };
+
+        let n = buff.len().min(s.pending_read.len());
+        for (b, v) in buff.iter_mut().zip(s.pending_read.drain(..n)) {
+            *b = v;
+        }
+
+        Ok(n)
+    }
+
+    fn bulk_out(&self, buff: &[u8], _timeout: Duration) -> Result<usize, Error> {
+        let mut s = self.state.lock().unwrap();
+        s.check_error()?;
+
+        // Continuation of a segmented write
+        if s.pending_write > 0 {
+            let n = buff.len().min(s.pending_write);
+            s.pending_write -= n;
+            if let Some(w) = s.spi_writes.last_mut() {
+                w.extend_from_slice(&buff[..n]);
+            }
+            return Ok(buff.len());
+        }
+
+        if buff.len() < TransferHeader::LEN {
+            return Ok(buff.len());
+        }
+
+        let header = TransferHeader::decode(buff)?;
+        let len = header.len as usize;
+        let data = &buff[TransferHeader::LEN..];
+
+        trace!(
+            "Mock bulk out (command: {:?}, len: {})",
+            header.command,
+            len
+        );
+
+        match header.command {
+            TransferCommand::Write => {
+                s.spi_writes.push(data.to_vec());
+                s.pending_write = len.saturating_sub(data.len());
+            }
+            TransferCommand::WriteRead => {
+                s.spi_writes.push(data.to_vec());
+                s.pending_write = len.saturating_sub(data.len());
+                s.queue_response(len);
+            }
+            TransferCommand::Read => s.queue_response(len),
+            TransferCommand::ReadWithRTR => {
+                s.queue_response(len);
+                if !s.rtr_ready {
+                    let at = s.pending_read.len() - len;
+                    let data = s.pending_read.split_off(at);
+                    s.rtr_pending.extend(data);
+                }
+            }
+        }
+
+        Ok(buff.len())
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
@@ -0,0 +1,291 @@
+//! CP2130 Driver 1-Wire Master
+//!
+//! Bit-banged 1-Wire master using an open-drain GPIO. Each bus operation is a USB
+//! control transfer so slot timing is best-effort, devices tolerant of stretched
+//! slots (or buses with a short cable and strong pull-up) are most likely to work.
+//!
+//! Byte operations, ROM commands and ROM search are provided by [`OneWireBus`] over
+//! the bit-level reset / read / write slots, so alternative bit drivers can be used.
+//!
+//! Copyright 2019 Ryan Kurte
+
+use std::time::{Duration, Instant};
+
+use embedded_hal::digital::{InputPin, OutputPin};
+use rusb::UsbContext;
+
+use crate::{
+    wait_until, Cp2130, Error, GpioLevel, GpioMode, OutputPin as Cp2130OutputPin,
+    DEFAULT_SPIN_THRESHOLD,
+};
+
+/// 1-Wire ROM commands
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RomCommand {
+    SearchRom = 0xF0,
+    ReadRom = 0x33,
+    MatchRom = 0x55,
+    SkipRom = 0xCC,
+}
+
+/// 1-Wire device ROM ID (family code, 48-bit serial and CRC)
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RomId(pub [u8; 8]);
+
+impl RomId {
+    /// Device family code
+    pub fn family(&self) -> u8 {
+        self.0[0]
+    }
+
+    /// Check the ROM CRC
+    pub fn crc_valid(&self) -> bool {
+        crc8(&self.0[..7]) == self.0[7]
+    }
+
+    fn bit(&self, n: usize) -> bool {
+        self.0[n / 8] & (1 << (n % 8)) != 0
+    }
+
+    fn set_bit(&mut self, n: usize, v: bool) {
+        match v {
+            true => self.0[n / 8] |= 1 << (n % 8),
+            false => self.0[n / 8] &= !(1 << (n % 8)),
+        }
+    }
+}
+
+impl std::fmt::Display for RomId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for b in self.0 {
+            write!(f, "{:02x}", b)?;
+        }
+        Ok(())
+    }
+}
+
+/// Compute the 1-Wire (Maxim) CRC8
+pub fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+
+    for b in data {
+        let mut b = *b;
+        for _ in 0..8 {
+            let mix = (crc ^ b) & 0x01;
+            crc >>= 1;
+            if mix != 0 {
+                crc ^= 0x8C;
+            }
+            b >>= 1;
+        }
+    }
+
+    crc
+}
+
+/// 1-Wire bus operations, byte and ROM operations are built on the bit-level slots
+pub trait OneWireBus {
+    /// Issue a reset pulse, returning whether any device responded with a presence pulse
+    fn reset(&mut self) -> Result<bool, Error>;
+
+    /// Write a single bit
+    fn write_bit(&mut self, bit: bool) -> Result<(), Error>;
+
+    /// Read a single bit
+    fn read_bit(&mut self) -> Result<bool, Error>;
+
+    /// Write a byte, LSB first
+    fn write_byte(&mut self, byte: u8) -> Result<(), Error> {
+        for i in 0..8 {
+            self.write_bit(byte & (1 << i) != 0)?;
+        }
+        Ok(())
+    }
+
+    /// Read a byte, LSB first
+    fn read_byte(&mut self) -> Result<u8, Error> {
+        let mut byte = 0;
+        for i in 0..8 {
+            if self.read_bit()? {
+                byte |= 1 << i;
+            }
+        }
+        Ok(byte)
+    }
+
+    /// Write multiple bytes
+    fn write_bytes(&mut self, data: &[u8]) -> Result<(), Error> {
+        data.iter().try_for_each(|b| self.write_byte(*b))
+    }
+
+    /// Read multiple bytes
+    fn read_bytes(&mut self, buff: &mut [u8]) -> Result<(), Error> {
+        for b in buff.iter_mut() {
+            *b = self.read_byte()?;
+        }
+        Ok(())
+    }
+
+    /// Reset the bus and address a single device, or all devices where `rom` is `None`
+    fn select(&mut self, rom: Option<&RomId>) -> Result<(), Error> {
+        if !self.reset()? {
+            return Err(Error::Unsupported("no 1-Wire presence pulse"));
+        }
+
+        match rom {
+            Some(r) => {
+                self.write_byte(RomCommand::MatchRom as u8)?;
+                self.write_bytes(&r.0)
+            }
+            None => self.write_byte(RomCommand::SkipRom as u8),
+        }
+    }
+
+    /// Read the ROM ID of the only device on the bus
+    fn read_rom(&mut self) -> Result<Option<RomId>, Error> {
+        if !self.reset()? {
+            return Ok(None);
+        }
+
+        self.write_byte(RomCommand::ReadRom as u8)?;
+
+        let mut rom = RomId([0u8; 8]);
+        self.read_bytes(&mut rom.0)?;
+
+        Ok(Some(rom))
+    }
+
+    /// Search the bus, returning the ROM IDs of all attached devices
+    ///
+    /// ROMs failing the CRC check are skipped.
+    fn search(&mut self) -> Result<Vec<RomId>, Error> {
+        let mut roms = vec![];
+        let mut rom = RomId([0u8; 8]);
+        let mut last_discrepancy = None;
+
+        loop {
+            if !self.reset()? {
+                break;
+            }
+
+            self.write_byte(RomCommand::SearchRom as u8)?;
+
+            let mut discrepancy = None;
+
+            for n in 0..64 {
+                let (bit, complement) = (self.read_bit()?, self.read_bit()?);
+
+                let dir = match (bit, complement) {
+                    // No devices participating
+                    (true, true) => return Ok(roms),
+                    // All remaining devices share this bit
+                    (b, c) if b != c => b,
+                    // Discrepancy, take the zero branch unless previously explored
+                    _ => {
+                        let dir = match last_discrepancy {
+                            Some(l) if n < l => rom.bit(n),
+                            Some(l) => n == l,
+                            None => false,
+                        };
+                        if !dir {
+                            discrepancy = Some(n);
+                        }
+                        dir
+                    }
+                };
+
+                rom.set_bit(n, dir);
+                self.write_bit(dir)?;
+            }
+
+            match rom.crc_valid() {
+                true => roms.push(rom),
+                false => log::debug!("Skipping 1-Wire ROM {} (CRC mismatch)", rom),
+            }
+
+            last_discrepancy = match discrepancy {
+                Some(d) => Some(d),
+                None => break,
+            };
+        }
+
+        Ok(roms)
+    }
+}
+
+/// 1-Wire master bit-banged on an open-drain GPIO
+pub struct GpioOneWire<P> {
+    pin: P,
+}
+
+impl<P> GpioOneWire<P> {
+    /// Create a 1-Wire master using an open-drain pin, which must read back the bus level
+    pub fn new(pin: P) -> Self {
+        Self { pin }
+    }
+
+    /// Release the underlying pin
+    pub fn release(self) -> P {
+        self.pin
+    }
+}
+
+/// Wait out slot timings, these are below the spin threshold so are busy-waited
+fn delay(us: u64) {
+    wait_until(
+        Instant::now() + Duration::from_micros(us),
+        DEFAULT_SPIN_THRESHOLD,
+    );
+}
+
+impl<P: InputPin<Error = Error> + OutputPin<Error = Error>> OneWireBus for GpioOneWire<P> {
+    fn reset(&mut self) -> Result<bool, Error> {
+        self.pin.set_low()?;
+        delay(480);
+        self.pin.set_high()?;
+        delay(70);
+
+        let presence = self.pin.is_low()?;
+        delay(410);
+
+        Ok(presence)
+    }
+
+    fn write_bit(&mut self, bit: bool) -> Result<(), Error> {
+        self.pin.set_low()?;
+        match bit {
+            true => {
+                delay(6);
+                self.pin.set_high()?;
+                delay(64);
+            }
+            false => {
+                delay(60);
+                self.pin.set_high()?;
+                delay(10);
+            }
+        }
+        Ok(())
+    }
+
+    fn read_bit(&mut self) -> Result<bool, Error> {
+        self.pin.set_low()?;
+        delay(6);
+        self.pin.set_high()?;
+        delay(9);
+
+        let bit = self.pin.is_high()?;
+        delay(55);
+
+        Ok(bit)
+    }
+}
+
+impl<T: UsbContext> Cp2130<T> {
+    /// Create a 1-Wire master on the provided pin, configured as an open-drain output
+    pub fn onewire(&self, pin: u8) -> Result<GpioOneWire<Cp2130OutputPin<T>>, Error> {
+        let pin = self.gpio_out(pin, GpioMode::OpenDrain, GpioLevel::High)?;
+        Ok(GpioOneWire::new(pin))
+    }
+}
@@ -0,0 +1,1006 @@
+//! CP2130 Driver One-Time-Programmable Configuration
+//!
+//! The CP2130 stores its USB configuration, strings and default pin configuration
+//! in a one-time-programmable PROM, loaded on reset.
+//!
+//! Copyright 2019 Ryan Kurte
+
+use byteorder::{ByteOrder, BE, LE};
+use log::debug;
+use rusb::UsbContext;
+
+use crate::device::{
+    Commands, EventCounterMode, UsbPower, GPIO_COUNT, NOT_SUSPEND_PIN, RTR_PIN, SUSPEND_PIN,
+};
+pub use crate::protocol::{MEMORY_KEY, PROM_BLOCKS, PROM_BLOCK_LEN, PROM_LEN};
+use crate::{Cp2130, Error, GpioLevel, GpioLevels, GpioMode};
+
+/// Length of the Get_USB_Config response
+const USB_CONFIG_LEN: usize = 9;
+
+/// Maximum bus current that may be requested (mA)
+pub const MAX_POWER_MA: u16 = 500;
+
+/// Length of each PROM string block
+const STRING_BLOCK_LEN: usize = 64;
+
+/// USB string descriptor type
+const STRING_DESCRIPTOR: u8 = 0x03;
+
+/// Maximum manufacturer and product string length (UTF-16 code units)
+pub const MAX_STRING_LEN: usize = 62;
+
+/// Maximum serial string length (UTF-16 code units)
+pub const MAX_SERIAL_LEN: usize = 30;
+
+/// Length of the Get/Set_Pin_Config block
+const PIN_CONFIG_LEN: usize = 20;
+
+/// Pin config bit for the power-on level of GPIO outputs
+const PIN_LEVEL_HIGH: u8 = 1 << 3;
+
+/// Token acknowledging that a PROM write is permanent
+///
+/// CP2130 configuration is one-time-programmable, fields may only be written once
+/// and a failed or incorrect write cannot be undone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OtpWrite(());
+
+impl OtpWrite {
+    /// Acknowledge that the following write permanently alters the device
+    pub fn irreversible() -> Self {
+        OtpWrite(())
+    }
+}
+
+/// Device power mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum PowerMode {
+    /// Bus powered, internal regulator enabled
+    BusPowered = 0x00,
+    /// Self powered, internal regulator enabled
+    SelfPowered = 0x01,
+    /// Self powered, internal regulator disabled
+    SelfPoweredRegulatorDisabled = 0x02,
+}
+
+impl TryFrom<u8> for PowerMode {
+    type Error = Error;
+
+    fn try_from(v: u8) -> Result<Self, Self::Error> {
+        match v {
+            0x00 => Ok(PowerMode::BusPowered),
+            0x01 => Ok(PowerMode::SelfPowered),
+            0x02 => Ok(PowerMode::SelfPoweredRegulatorDisabled),
+            _ => Err(Error::InvalidConfig {
+                field: "power_mode",
+                reason: "unrecognised power mode",
+            }),
+        }
+    }
+}
+
+/// Bulk endpoint transfer priority
+///
+/// Selects which endpoint the device services first when both have pending data.
+/// SPI reads and write-reads keep IN transfers pending while OUT data is submitted, so
+/// read priority suits read-heavy workloads (returned data is drained promptly), while
+/// write priority suits write-heavy workloads such as display updates, where IN
+/// responses may be delayed until buffered OUT data has been shifted out. Timeouts are
+/// scaled with the expected bus time so either setting is supported by the driver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum TransferPriority {
+    /// Prioritise the read (IN) endpoint
+    Read = 0x00,
+    /// Prioritise the write (OUT) endpoint
+    Write = 0x01,
+}
+
+impl TryFrom<u8> for TransferPriority {
+    type Error = Error;
+
+    fn try_from(v: u8) -> Result<Self, Self::Error> {
+        match v {
+            0x00 => Ok(TransferPriority::Read),
+            0x01 => Ok(TransferPriority::Write),
+            _ => Err(Error::InvalidConfig {
+                field: "transfer_priority",
+                reason: "unrecognised transfer priority",
+            }),
+        }
+    }
+}
+
+/// Programmed USB configuration
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UsbConfig {
+    pub vid: u16,
+    pub pid: u16,
+    /// Maximum bus current in mA (programmed in 2 mA units)
+    pub max_power_ma: u16,
+    pub power_mode: PowerMode,
+    pub release_major: u8,
+    pub release_minor: u8,
+    pub transfer_priority: TransferPriority,
+}
+
+impl UsbConfig {
+    /// Decode a Get_USB_Config response
+    pub fn decode(buff: &[u8]) -> Result<Self, Error> {
+        if buff.len() < USB_CONFIG_LEN {
+            return Err(Error::ShortTransfer {
+                expected: USB_CONFIG_LEN,
+                actual: buff.len(),
+            });
+        }
+
+        Ok(Self {
+            vid: LE::read_u16(&buff[0..2]),
+            pid: LE::read_u16(&buff[2..4]),
+            max_power_ma: buff[4] as u16 * 2,
+            power_mode: PowerMode::try_from(buff[5])?,
+            release_major: buff[6],
+            release_minor: buff[7],
+            transfer_priority: TransferPriority::try_from(buff[8])?,
+        })
+    }
+
+    /// Check whether the programmed power configuration matches the power attributes
+    /// reported to the host, see [`Info::power`](crate::device::Info::power)
+    pub fn matches_power(&self, power: &UsbPower) -> bool {
+        let self_powered = self.power_mode != PowerMode::BusPowered;
+        self_powered == power.self_powered && self.max_power_ma == power.max_power_ma
+    }
+}
+
+bitflags::bitflags!(
+    /// PROM fields, as used by the lock byte
+    ///
+    /// In the device lock byte a set bit indicates the field is unlocked,
+    /// [`Cp2130::locked_fields`] returns the inverse.
+    #[derive(Default)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    #[cfg_attr(feature = "serde", serde(into = "u16", from = "u16"))]
+    pub struct OtpFields: u16 {
+        const VID = 1 << 0;
+        const PID = 1 << 1;
+        const MAX_POWER = 1 << 2;
+        const POWER_MODE = 1 << 3;
+        const RELEASE = 1 << 4;
+        const MANUFACTURER_1 = 1 << 5;
+        const MANUFACTURER_2 = 1 << 6;
+        const TRANSFER_PRIORITY = 1 << 7;
+        const PRODUCT_1 = 1 << 8;
+        const PRODUCT_2 = 1 << 9;
+        const SERIAL = 1 << 10;
+        const PIN_CONFIG = 1 << 11;
+    }
+);
+
+impl From<OtpFields> for u16 {
+    fn from(f: OtpFields) -> Self {
+        f.bits()
+    }
+}
+
+impl From<u16> for OtpFields {
+    fn from(v: u16) -> Self {
+        OtpFields::from_bits_truncate(v)
+    }
+}
+
+/// Power-on pin function
+///
+/// Alternate functions are only available on specific pins.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum PinFunction {
+    /// General purpose IO, with the initial level for outputs
+    Gpio { mode: GpioMode, level: GpioLevel },
+    /// SPI chip select for the channel matching the pin index
+    ChipSelect,
+    /// Ready to read input (GPIO.3)
+    Rtr { active_high: bool },
+    /// Event counter input (GPIO.4)
+    EventCounter(EventCounterMode),
+    /// Clock output (GPIO.5)
+    ClockOut,
+    /// SPI activity indicator (GPIO.8)
+    SpiActive,
+    /// Suspend indicator, high while suspended (GPIO.9)
+    Suspend,
+    /// Inverted suspend indicator, low while suspended (GPIO.10)
+    NotSuspend,
+}
+
+impl PinFunction {
+    /// Encode the pin configuration byte for a given pin
+    fn encode(&self, pin: u8) -> Result<u8, Error> {
+        let v = match (self, pin) {
+            (PinFunction::Gpio { mode, level }, _) => match (mode, level) {
+                (GpioMode::Input, _) | (_, GpioLevel::Low) => *mode as u8,
+                (_, GpioLevel::High) => *mode as u8 | PIN_LEVEL_HIGH,
+            },
+            (PinFunction::ChipSelect, _) => 0x03,
+            (PinFunction::Rtr { active_high }, 3) => 0x04 | *active_high as u8,
+            (PinFunction::EventCounter(mode), 4) => *mode as u8,
+            (PinFunction::ClockOut, 5) | (PinFunction::SpiActive, 8) => 0x04,
+            (PinFunction::Suspend, 9) | (PinFunction::NotSuspend, 10) => 0x04,
+            _ => {
+                return Err(Error::InvalidConfig {
+                    field: "pin_config",
+                    reason: "function not available on pin",
+                })
+            }
+        };
+
+        Ok(v)
+    }
+
+    /// Decode the pin configuration byte for a given pin
+    fn decode(pin: u8, v: u8) -> Result<Self, Error> {
+        let level = match v & PIN_LEVEL_HIGH != 0 {
+            true => GpioLevel::High,
+            false => GpioLevel::Low,
+        };
+
+        let f = match (v & !PIN_LEVEL_HIGH, pin) {
+            (0x00, _) => PinFunction::Gpio {
+                mode: GpioMode::Input,
+                level: GpioLevel::Low,
+            },
+            (0x01, _) => PinFunction::Gpio {
+                mode: GpioMode::OpenDrain,
+                level,
+            },
+            (0x02, _) => PinFunction::Gpio {
+                mode: GpioMode::PushPull,
+                level,
+            },
+            (0x03, _) => PinFunction::ChipSelect,
+            (0x04, 3) => PinFunction::Rtr { active_high: false },
+            (0x05, 3) => PinFunction::Rtr { active_high: true },
+            (m, 4) => PinFunction::EventCounter(EventCounterMode::try_from(m)?),
+            (0x04, 5) => PinFunction::ClockOut,
+            (0x04, 8) => PinFunction::SpiActive,
+            (0x04, 9) => PinFunction::Suspend,
+            (0x04, 10) => PinFunction::NotSuspend,
+            _ => {
+                return Err(Error::InvalidConfig {
+                    field: "pin_config",
+                    reason: "unrecognised pin function",
+                })
+            }
+        };
+
+        Ok(f)
+    }
+}
+
+/// Power-on and suspend configuration for a single pin
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PinDefault {
+    pub function: PinFunction,
+    /// Level driven while the device is suspended
+    pub suspend_level: GpioLevel,
+    /// Output mode while the device is suspended (open-drain or push-pull)
+    pub suspend_mode: GpioMode,
+    /// Wake the device from suspend when the pin matches this level
+    pub wakeup: Option<GpioLevel>,
+}
+
+impl Default for PinDefault {
+    fn default() -> Self {
+        Self {
+            function: PinFunction::Gpio {
+                mode: GpioMode::Input,
+                level: GpioLevel::Low,
+            },
+            suspend_level: GpioLevel::Low,
+            suspend_mode: GpioMode::OpenDrain,
+            wakeup: None,
+        }
+    }
+}
+
+/// Programmed power-on pin configuration
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PinConfig {
+    /// Pin configuration in pin index order
+    pub pins: [PinDefault; GPIO_COUNT as usize],
+    /// Clock output divider, CLKOUT runs at 24 MHz / divider (0 divides by 256)
+    pub clock_divider: u8,
+}
+
+impl PinConfig {
+    /// Decode a Get_Pin_Config response
+    pub fn decode(buff: &[u8]) -> Result<Self, Error> {
+        if buff.len() < PIN_CONFIG_LEN {
+            return Err(Error::ShortTransfer {
+                expected: PIN_CONFIG_LEN,
+                actual: buff.len(),
+            });
+        }
+
+        // Suspend and wakeup fields are big-endian pin masks
+        let mask = |i: usize| GpioLevels::from_bits_truncate(BE::read_u16(&buff[i..i + 2]));
+        let (suspend_level, suspend_mode) = (mask(11), mask(13));
+        let (wakeup_mask, wakeup_match) = (mask(15), mask(17));
+
+        let level = |m: GpioLevels, pin| match m.pin(pin) {
+            true => GpioLevel::High,
+            false => GpioLevel::Low,
+        };
+
+        let mut config = PinConfig {
+            clock_divider: buff[19],
+            ..Default::default()
+        };
+
+        for (pin, p) in (0..GPIO_COUNT).zip(config.pins.iter_mut()) {
+            *p = PinDefault {
+                function: PinFunction::decode(pin, buff[pin as usize])?,
+                suspend_level: level(suspend_level, pin),
+                suspend_mode: match suspend_mode.pin(pin) {
+                    true => GpioMode::PushPull,
+                    false => GpioMode::OpenDrain,
+                },
+                wakeup: match wakeup_mask.pin(pin) {
+                    true => Some(level(wakeup_match, pin)),
+                    false => None,
+                },
+            };
+        }
+
+        Ok(config)
+    }
+
+    /// Check whether any pin is configured as a remote wakeup source
+    ///
+    /// The USB configuration has no separate remote wakeup field, the device may only
+    /// signal resume to a suspended host when pins are set in the wakeup mask. The
+    /// capability reported to the host is available via [`UsbPower::remote_wakeup`].
+    pub fn remote_wakeup(&self) -> bool {
+        self.pins.iter().any(|p| p.wakeup.is_some())
+    }
+
+    /// Check whether the SUSPEND (GPIO.9) and !SUSPEND (GPIO.10) outputs are enabled
+    pub fn suspend_outputs(&self) -> (bool, bool) {
+        (
+            self.pins[SUSPEND_PIN as usize].function == PinFunction::Suspend,
+            self.pins[NOT_SUSPEND_PIN as usize].function == PinFunction::NotSuspend,
+        )
+    }
+
+    /// Fetch the RTR (GPIO.3) polarity, `None` where the pin is not configured for RTR
+    pub fn rtr(&self) -> Option<bool> {
+        match self.pins[RTR_PIN as usize].function {
+            PinFunction::Rtr { active_high } => Some(active_high),
+            _ => None,
+        }
+    }
+
+    /// Configure GPIO.3 as the RTR (`Some(true)`) or !RTR (`Some(false)`) input,
+    /// `None` reverts an RTR pin to a GPIO input
+    pub fn set_rtr(&mut self, active_high: Option<bool>) {
+        let current = self.rtr();
+        let p = &mut self.pins[RTR_PIN as usize].function;
+        match (active_high, current) {
+            (Some(active_high), _) => *p = PinFunction::Rtr { active_high },
+            (None, Some(_)) => *p = PinDefault::default().function,
+            (None, None) => (),
+        }
+    }
+
+    /// Enable or disable the SUSPEND (GPIO.9) and !SUSPEND (GPIO.10) outputs
+    ///
+    /// These follow the USB suspend state so may be used to gate peripheral power rails,
+    /// SUSPEND is driven high and !SUSPEND low while suspended. The functions are only
+    /// selected by the programmed pin configuration, runtime GPIO commands on these pins
+    /// reconfigure them as GPIO until the device is reset.
+    /// Disabled outputs revert to GPIO inputs.
+    pub fn set_suspend_outputs(&mut self, suspend: bool, not_suspend: bool) {
+        for (pin, function, enabled) in [
+            (SUSPEND_PIN, PinFunction::Suspend, suspend),
+            (NOT_SUSPEND_PIN, PinFunction::NotSuspend, not_suspend),
+        ] {
+            let p = &mut self.pins[pin as usize].function;
+            match (enabled, *p == function) {
+                (true, _) => *p = function,
+                (false, true) => *p = PinDefault::default().function,
+                (false, false) => (),
+            }
+        }
+    }
+
+    /// Encode a Set_Pin_Config request
+    pub fn encode(&self) -> Result<[u8; PIN_CONFIG_LEN], Error> {
+        let mut buff = [0u8; PIN_CONFIG_LEN];
+
+        let mut suspend_level = GpioLevels::empty();
+        let mut suspend_mode = GpioLevels::empty();
+        let mut wakeup_mask = GpioLevels::empty();
+        let mut wakeup_match = GpioLevels::empty();
+
+        for (pin, p) in (0..GPIO_COUNT).zip(self.pins.iter()) {
+            buff[pin as usize] = p.function.encode(pin)?;
+
+            suspend_level.set_pin(pin, p.suspend_level);
+            if p.suspend_mode == GpioMode::PushPull {
+                suspend_mode.set_pin(pin, GpioLevel::High);
+            }
+            if let Some(l) = p.wakeup {
+                wakeup_mask.set_pin(pin, GpioLevel::High);
+                wakeup_match.set_pin(pin, l);
+            }
+        }
+
+        BE::write_u16(&mut buff[11..13], suspend_level.bits());
+        BE::write_u16(&mut buff[13..15], suspend_mode.bits());
+        BE::write_u16(&mut buff[15..17], wakeup_mask.bits());
+        BE::write_u16(&mut buff[17..19], wakeup_match.bits());
+        buff[19] = self.clock_divider;
+
+        Ok(buff)
+    }
+}
+
+/// Complete PROM image, for backup and cloning of device configuration
+///
+/// Images are formatted as text with one block of hex per line, lines starting
+/// with `#` are ignored when parsing.
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(into = "String", try_from = "String"))]
+pub struct PromImage {
+    data: Vec<u8>,
+}
+
+impl PromImage {
+    /// Create an image from raw PROM contents
+    pub fn from_bytes(data: &[u8]) -> Result<Self, Error> {
+        if data.len() != PROM_LEN {
+            return Err(Error::InvalidConfig {
+                field: "prom",
+                reason: "PROM image must be 512 bytes",
+            });
+        }
+
+        Ok(Self {
+            data: data.to_vec(),
+        })
+    }
+
+    /// Fetch the raw PROM contents
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Iterate over PROM blocks
+    pub fn blocks(&self) -> impl Iterator<Item = &[u8]> {
+        self.data.chunks(PROM_BLOCK_LEN)
+    }
+}
+
+impl std::fmt::Debug for PromImage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "PromImage({} bytes)", self.data.len())
+    }
+}
+
+impl std::fmt::Display for PromImage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "# CP2130 PROM image")?;
+        for b in self.blocks() {
+            for v in b {
+                write!(f, "{:02x}", v)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for PromImage {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidConfig {
+            field: "prom",
+            reason: "invalid hex in PROM image",
+        };
+
+        let mut data = Vec::with_capacity(PROM_LEN);
+
+        for line in s.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line.len() % 2 != 0 {
+                return Err(invalid());
+            }
+
+            for i in (0..line.len()).step_by(2) {
+                let b = line.get(i..i + 2).ok_or_else(invalid)?;
+                data.push(u8::from_str_radix(b, 16).map_err(|_| invalid())?);
+            }
+        }
+
+        Self::from_bytes(&data)
+    }
+}
+
+impl From<PromImage> for String {
+    fn from(p: PromImage) -> Self {
+        p.to_string()
+    }
+}
+
+impl TryFrom<String> for PromImage {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// PROM string fields
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PromString {
+    Manufacturer,
+    Product,
+    Serial,
+}
+
+impl PromString {
+    /// Get and set commands for each block of the string
+    fn blocks(&self) -> &'static [(Commands, Commands)] {
+        match self {
+            PromString::Manufacturer => &[
+                (
+                    Commands::GetManufacturingString1,
+                    Commands::SetManufacturingString1,
+                ),
+                (
+                    Commands::GetManufacturingString2,
+                    Commands::SetManufacturingString2,
+                ),
+            ],
+            PromString::Product => &[
+                (Commands::GetProductString1, Commands::SetProductString1),
+                (Commands::GetProductString2, Commands::SetProductString2),
+            ],
+            PromString::Serial => &[(Commands::GetSerialString, Commands::SetSerialString)],
+        }
+    }
+
+    /// Lock byte fields covering the string
+    fn fields(&self) -> OtpFields {
+        match self {
+            PromString::Manufacturer => OtpFields::MANUFACTURER_1 | OtpFields::MANUFACTURER_2,
+            PromString::Product => OtpFields::PRODUCT_1 | OtpFields::PRODUCT_2,
+            PromString::Serial => OtpFields::SERIAL,
+        }
+    }
+
+    fn max_len(&self) -> usize {
+        match self {
+            PromString::Serial => MAX_SERIAL_LEN,
+            _ => MAX_STRING_LEN,
+        }
+    }
+}
+
+/// Encode a string as a USB string descriptor split into PROM blocks
+///
+/// The first block starts with the descriptor length and type, followed by UTF-16LE
+/// characters continuing into subsequent blocks.
+pub fn encode_string(s: &str, blocks: usize) -> Result<Vec<u8>, Error> {
+    let chars: Vec<u16> = s.encode_utf16().collect();
+
+    let len = 2 + chars.len() * 2;
+    if len > blocks * STRING_BLOCK_LEN || len > u8::MAX as usize {
+        return Err(Error::InvalidConfig {
+            field: "string",
+            reason: "string too long for PROM",
+        });
+    }
+
+    let mut buff = vec![0u8; blocks * STRING_BLOCK_LEN];
+    buff[0] = len as u8;
+    buff[1] = STRING_DESCRIPTOR;
+    for (i, c) in chars.iter().enumerate() {
+        LE::write_u16(&mut buff[2 + i * 2..], *c);
+    }
+
+    Ok(buff)
+}
+
+/// Decode a USB string descriptor read from PROM blocks
+///
+/// Unprogrammed or invalid descriptors decode as an empty string.
+pub fn decode_string(buff: &[u8]) -> String {
+    if buff.len() < 2 || buff[1] != STRING_DESCRIPTOR {
+        return String::new();
+    }
+
+    let len = (buff[0] as usize).clamp(2, buff.len());
+    let chars: Vec<u16> = buff[2..len].chunks_exact(2).map(LE::read_u16).collect();
+
+    String::from_utf16_lossy(&chars)
+}
+
+bitflags::bitflags!(
+    /// Set_USB_Config field write mask
+    struct UsbConfigMask: u8 {
+        const VID = 1 << 0;
+        const PID = 1 << 1;
+        const MAX_POWER = 1 << 2;
+        const POWER_MODE = 1 << 3;
+        const RELEASE = 1 << 4;
+        const TRANSFER_PRIORITY = 1 << 7;
+    }
+);
+
+/// USB configuration update, only fields set to `Some` are programmed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct UsbConfigUpdate {
+    pub vid: Option<u16>,
+    pub pid: Option<u16>,
+    /// Maximum bus current in mA, rounded up to a 2 mA unit
+    pub max_power_ma: Option<u16>,
+    pub power_mode: Option<PowerMode>,
+    /// Release version (major, minor)
+    pub release: Option<(u8, u8)>,
+    pub transfer_priority: Option<TransferPriority>,
+}
+
+impl UsbConfigUpdate {
+    /// Check whether the update sets any fields
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Fetch the lock byte fields written by the update
+    ///
+    /// Set_USB_Config mask bits match the lock byte layout.
+    pub fn fields(&self) -> Result<OtpFields, Error> {
+        let mask = self.encode()?[USB_CONFIG_LEN];
+        Ok(OtpFields::from_bits_truncate(mask as u16))
+    }
+
+    /// Encode a Set_USB_Config request
+    pub fn encode(&self) -> Result<[u8; USB_CONFIG_LEN + 1], Error> {
+        let mut buff = [0u8; USB_CONFIG_LEN + 1];
+        let mut mask = UsbConfigMask::empty();
+
+        if let Some(vid) = self.vid {
+            LE::write_u16(&mut buff[0..2], vid);
+            mask |= UsbConfigMask::VID;
+        }
+        if let Some(pid) = self.pid {
+            LE::write_u16(&mut buff[2..4], pid);
+            mask |= UsbConfigMask::PID;
+        }
+        if let Some(ma) = self.max_power_ma {
+            if ma > MAX_POWER_MA {
+                return Err(Error::InvalidConfig {
+                    field: "max_power_ma",
+                    reason: "maximum power must not exceed 500 mA",
+                });
+            }
+            buff[4] = ma.div_ceil(2) as u8;
+            mask |= UsbConfigMask::MAX_POWER;
+        }
+        if let Some(mode) = self.power_mode {
+            buff[5] = mode as u8;
+            mask |= UsbConfigMask::POWER_MODE;
+        }
+        if let Some((major, minor)) = self.release {
+            buff[6] = major;
+            buff[7] = minor;
+            mask |= UsbConfigMask::RELEASE;
+        }
+        if let Some(p) = self.transfer_priority {
+            buff[8] = p as u8;
+            mask |= UsbConfigMask::TRANSFER_PRIORITY;
+        }
+
+        buff[9] = mask.bits();
+
+        Ok(buff)
+    }
+}
+
+impl<T: UsbContext> Cp2130<T> {
+    /// Read the USB configuration programmed in the device PROM
+    pub fn usb_config(&self) -> Result<UsbConfig, Error> {
+        let mut buff = [0u8; USB_CONFIG_LEN];
+
+        let n = self
+            .inner
+            .lock()
+            .unwrap()
+            .control_in(Commands::GetUsbConfig, 0, 0, &mut buff)?;
+
+        let config = UsbConfig::decode(&buff[..n])?;
+
+        debug!("USB config: {:?}", config);
+
+        Ok(config)
+    }
+
+    /// Permanently program the USB configuration fields set in `update`
+    ///
+    /// Changes take effect after the device is reset. Fields locked by the device
+    /// lock byte are not modified.
+    pub fn program_usb_config(&self, update: UsbConfigUpdate, _: OtpWrite) -> Result<(), Error> {
+        if update.is_empty() {
+            return Err(Error::InvalidConfig {
+                field: "usb_config",
+                reason: "no fields set for programming",
+            });
+        }
+
+        self.check_unlocked(update.fields()?)?;
+
+        let cmd = update.encode()?;
+
+        debug!("Programming USB config: {:?}", update);
+
+        self.inner
+            .lock()
+            .unwrap()
+            .control_out(Commands::SetUsbConfig, MEMORY_KEY, 0, &cmd)?;
+
+        Ok(())
+    }
+
+    /// Permanently program the power mode and maximum bus current (up to 500 mA, in 2 mA units)
+    ///
+    /// Changes take effect after the device is reset, the programmed values may be compared
+    /// with those reported to the host using [`UsbConfig::matches_power`].
+    pub fn program_power(
+        &self,
+        power_mode: PowerMode,
+        max_power_ma: u16,
+        otp: OtpWrite,
+    ) -> Result<(), Error> {
+        let update = UsbConfigUpdate {
+            max_power_ma: Some(max_power_ma),
+            power_mode: Some(power_mode),
+            ..Default::default()
+        };
+
+        self.program_usb_config(update, otp)
+    }
+
+    /// Read the programmed bulk endpoint transfer priority
+    pub fn transfer_priority(&self) -> Result<TransferPriority, Error> {
+        Ok(self.usb_config()?.transfer_priority)
+    }
+
+    /// Permanently program the bulk endpoint transfer priority
+    ///
+    /// Changes take effect after the device is reset.
+    pub fn program_transfer_priority(
+        &self,
+        priority: TransferPriority,
+        otp: OtpWrite,
+    ) -> Result<(), Error> {
+        let update = UsbConfigUpdate {
+            transfer_priority: Some(priority),
+            ..Default::default()
+        };
+
+        self.program_usb_config(update, otp)
+    }
+
+    /// Read the lock byte, returning the PROM fields that can no longer be programmed
+    pub fn locked_fields(&self) -> Result<OtpFields, Error> {
+        let mut buff = [0u8; 2];
+
+        let n = self
+            .inner
+            .lock()
+            .unwrap()
+            .control_in(Commands::GetLockByte, 0, 0, &mut buff)?;
+        if n != buff.len() {
+            return Err(Error::ShortTransfer {
+                expected: buff.len(),
+                actual: n,
+            });
+        }
+
+        let unlocked = OtpFields::from_bits_truncate(LE::read_u16(&buff));
+
+        Ok(OtpFields::all() - unlocked)
+    }
+
+    /// Permanently lock the provided PROM fields, preventing further programming
+    pub fn lock_fields(&self, fields: OtpFields, _: OtpWrite) -> Result<(), Error> {
+        // Lock bits may only be cleared, set bits leave the current state unchanged
+        let mut cmd = [0u8; 2];
+        LE::write_u16(&mut cmd, !fields.bits());
+
+        debug!("Locking PROM fields: {:?}", fields);
+
+        self.inner
+            .lock()
+            .unwrap()
+            .control_out(Commands::SetLockByte, MEMORY_KEY, 0, &cmd)?;
+
+        Ok(())
+    }
+
+    /// Check the provided fields are not locked prior to programming
+    fn check_unlocked(&self, fields: OtpFields) -> Result<(), Error> {
+        let locked = self.locked_fields()? & fields;
+
+        match locked.is_empty() {
+            true => Ok(()),
+            false => Err(Error::Locked(locked)),
+        }
+    }
+
+    /// Read the complete device PROM
+    pub fn read_prom(&self) -> Result<PromImage, Error> {
+        let mut data = vec![0u8; PROM_LEN];
+
+        let inner = self.inner.lock().unwrap();
+        for (i, b) in data.chunks_mut(PROM_BLOCK_LEN).enumerate() {
+            let n = inner.control_in(Commands::GetPromConfig, 0, i as u16, b)?;
+            if n != PROM_BLOCK_LEN {
+                return Err(Error::ShortTransfer {
+                    expected: PROM_BLOCK_LEN,
+                    actual: n,
+                });
+            }
+        }
+
+        Ok(PromImage { data })
+    }
+
+    /// Permanently program the complete device PROM from an image
+    ///
+    /// This requires that no PROM fields are locked, any locks set in the image
+    /// are applied as part of programming.
+    pub fn program_prom(&self, image: &PromImage, _: OtpWrite) -> Result<(), Error> {
+        self.check_unlocked(OtpFields::all())?;
+
+        debug!("Programming PROM image");
+
+        let inner = self.inner.lock().unwrap();
+        for (i, b) in image.blocks().enumerate() {
+            inner.control_out(Commands::SetPromConfig, MEMORY_KEY, i as u16, b)?;
+        }
+
+        Ok(())
+    }
+
+    /// Read the power-on pin configuration programmed in the device PROM
+    pub fn pin_config(&self) -> Result<PinConfig, Error> {
+        let mut buff = [0u8; PIN_CONFIG_LEN];
+
+        let n = self
+            .inner
+            .lock()
+            .unwrap()
+            .control_in(Commands::GetPinConfig, 0, 0, &mut buff)?;
+
+        PinConfig::decode(&buff[..n])
+    }
+
+    /// Permanently program the power-on pin configuration
+    ///
+    /// Changes take effect after the device is reset.
+    pub fn program_pin_config(&self, config: &PinConfig, _: OtpWrite) -> Result<(), Error> {
+        self.check_unlocked(OtpFields::PIN_CONFIG)?;
+
+        let cmd = config.encode()?;
+
+        debug!("Programming pin config: {:?}", config);
+
+        self.inner
+            .lock()
+            .unwrap()
+            .control_out(Commands::SetPinConfig, MEMORY_KEY, 0, &cmd)?;
+
+        Ok(())
+    }
+
+    /// Permanently configure GPIO.3 as the RTR input with the provided polarity
+    ///
+    /// RTR gates [`Cp2130::spi_read_rtr`] and RTR streams, the slave signals that data
+    /// is ready by asserting the pin. Changes take effect after the device is reset,
+    /// this fails with [`Error::GpioInUse`] while GPIO.3 is held as a plain GPIO.
+    pub fn program_rtr(&self, active_high: bool, otp: OtpWrite) -> Result<(), Error> {
+        self.inner.lock().unwrap().check_rtr()?;
+
+        let mut config = self.pin_config()?;
+        config.set_rtr(Some(active_high));
+
+        self.program_pin_config(&config, otp)
+    }
+
+    /// Read the manufacturer string programmed in the device PROM
+    pub fn manufacturer_string(&self) -> Result<String, Error> {
+        self.prom_string(PromString::Manufacturer)
+    }
+
+    /// Read the product string programmed in the device PROM
+    pub fn product_string(&self) -> Result<String, Error> {
+        self.prom_string(PromString::Product)
+    }
+
+    /// Read the serial string programmed in the device PROM
+    pub fn serial_string(&self) -> Result<String, Error> {
+        self.prom_string(PromString::Serial)
+    }
+
+    /// Permanently program the manufacturer string (up to 62 characters)
+    pub fn set_manufacturer_string(&self, s: &str, token: OtpWrite) -> Result<(), Error> {
+        self.set_prom_string(PromString::Manufacturer, s, token)
+    }
+
+    /// Permanently program the product string (up to 62 characters)
+    pub fn set_product_string(&self, s: &str, token: OtpWrite) -> Result<(), Error> {
+        self.set_prom_string(PromString::Product, s, token)
+    }
+
+    /// Permanently program the serial string (up to 30 characters)
+    pub fn set_serial_string(&self, s: &str, token: OtpWrite) -> Result<(), Error> {
+        self.set_prom_string(PromString::Serial, s, token)
+    }
+
+    fn prom_string(&self, field: PromString) -> Result<String, Error> {
+        let blocks = field.blocks();
+        let mut buff = vec![0u8; blocks.len() * STRING_BLOCK_LEN];
+
+        let inner = self.inner.lock().unwrap();
+        for ((get, _), b) in blocks.iter().zip(buff.chunks_mut(STRING_BLOCK_LEN)) {
+            let n = inner.control_in(*get, 0, 0, b)?;
+            if n != STRING_BLOCK_LEN {
+                return Err(Error::ShortTransfer {
+                    expected: STRING_BLOCK_LEN,
+                    actual: n,
+                });
+            }
+        }
+
+        Ok(decode_string(&buff))
+    }
+
+    fn set_prom_string(&self, field: PromString, s: &str, _: OtpWrite) -> Result<(), Error> {
+        if s.encode_utf16().count() > field.max_len() {
+            return Err(Error::InvalidConfig {
+                field: "string",
+                reason: "string exceeds maximum length for field",
+            });
+        }
+
+        self.check_unlocked(field.fields())?;
+
+        let blocks = field.blocks();
+        let buff = encode_string(s, blocks.len())?;
+
+        debug!("Programming {:?} string: {}", field, s);
+
+        let inner = self.inner.lock().unwrap();
+        for ((_, set), b) in blocks.iter().zip(buff.chunks(STRING_BLOCK_LEN)) {
+            inner.control_out(*set, MEMORY_KEY, 0, b)?;
+        }
+
+        Ok(())
+    }
+}
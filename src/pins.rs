@@ -0,0 +1,201 @@
+//! CP2130 Driver typed pin interface
+//!
+//! [`Pin`] encodes the GPIO index and mode in the type, so mode changes are
+//! type transitions and out-of-range indices fail at compile time.
+//!
+//! ```no_run
+//! # use driver_cp2130::prelude::*;
+//! # fn f(cp2130: &Cp2130) -> Result<(), Cp2130Error> {
+//! let reset = cp2130.pin::<6>()?.into_push_pull(GpioLevel::High)?;
+//! let cs = cp2130.pin::<0>()?;
+//!
+//! // The CS pin is consumed, so it cannot be reused as a data GPIO
+//! let spi = cp2130.spi_with_cs(0, SpiConfig::default(), cs)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Copyright 2019 Ryan Kurte
+
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use rusb::{GlobalContext, UsbContext};
+
+use crate::device::check_channel;
+use crate::{
+    Cp2130, Error, GpioLevel, GpioMode, InputPin, OutputPin, PinAllocation, Spi, SpiConfig,
+    DEFAULT_POLL_INTERVAL,
+};
+
+/// Input mode marker
+pub struct Input;
+
+/// Push-pull output mode marker
+pub struct PushPull;
+
+/// Open-drain output mode marker
+pub struct OpenDrain;
+
+/// Output modes for typed pins
+pub trait OutputMode: sealed::Sealed {
+    /// GPIO mode applied to the pin
+    const MODE: GpioMode;
+}
+
+impl OutputMode for PushPull {
+    const MODE: GpioMode = GpioMode::PushPull;
+}
+
+impl OutputMode for OpenDrain {
+    const MODE: GpioMode = GpioMode::OpenDrain;
+}
+
+mod sealed {
+    pub trait Sealed {}
+
+    impl Sealed for super::PushPull {}
+    impl Sealed for super::OpenDrain {}
+}
+
+/// Typed GPIO pin with index `N` in mode `MODE`
+pub struct Pin<const N: u8, MODE, T: UsbContext = GlobalContext> {
+    pin: PinAllocation<T>,
+    _mode: PhantomData<MODE>,
+}
+
+impl<const N: u8, MODE, T: UsbContext> Pin<N, MODE, T> {
+    /// Compile-time check that the pin index is valid
+    const VALID: () = assert!(N <= 10, "CP2130 GPIO index must be in the range 0..=10");
+
+    /// Fetch the pin index
+    pub const fn index(&self) -> u8 {
+        N
+    }
+
+    /// Reconfigure the pin as an input
+    pub fn into_input(self) -> Result<Pin<N, Input, T>, Error> {
+        self.into_mode(GpioMode::Input, GpioLevel::Low)
+    }
+
+    /// Reconfigure the pin as a push-pull output with the provided initial level
+    pub fn into_push_pull(self, level: GpioLevel) -> Result<Pin<N, PushPull, T>, Error> {
+        self.into_mode(PushPull::MODE, level)
+    }
+
+    /// Reconfigure the pin as an open-drain output with the provided initial level
+    pub fn into_open_drain(self, level: GpioLevel) -> Result<Pin<N, OpenDrain, T>, Error> {
+        self.into_mode(OpenDrain::MODE, level)
+    }
+
+    fn into_mode<M>(self, mode: GpioMode, level: GpioLevel) -> Result<Pin<N, M, T>, Error> {
+        self.pin.lock()?.set_gpio_mode_level(N, mode, level)?;
+
+        Ok(Pin {
+            pin: self.pin,
+            _mode: PhantomData,
+        })
+    }
+}
+
+impl<T: UsbContext> Cp2130<T> {
+    /// Allocate a typed GPIO pin, configured as an input
+    ///
+    /// Invalid pin indices are rejected at compile time:
+    ///
+    /// ```compile_fail
+    /// # use driver_cp2130::prelude::*;
+    /// # fn f(cp2130: &Cp2130) -> Result<(), Cp2130Error> {
+    /// let pin = cp2130.pin::<11>()?;
+    /// # Ok(())
+    /// # }
+    /// # let _: fn(&Cp2130) -> _ = f;
+    /// ```
+    pub fn pin<const N: u8>(&self) -> Result<Pin<N, Input, T>, Error> {
+        let () = Pin::<N, Input, T>::VALID;
+
+        let p = self.gpio_in(N)?;
+
+        Ok(Pin {
+            pin: p.pin,
+            _mode: PhantomData,
+        })
+    }
+
+    /// Create an SPI connector using a typed pin for CS
+    ///
+    /// The pin is consumed and allocated to the SPI channel until released
+    /// with [`Cp2130::spi_release`]. On failure the pin is released, pins allocated
+    /// from another device are rejected with [`Error::ForeignPin`].
+    pub fn spi_with_cs<const N: u8, MODE>(
+        &self,
+        channel: u8,
+        config: SpiConfig,
+        cs: Pin<N, MODE, T>,
+    ) -> Result<Spi<T>, Error> {
+        check_channel(channel)?;
+
+        // Pins can only be used with the device they were allocated from
+        if !Arc::ptr_eq(&cs.pin.inner, &self.inner) {
+            return Err(Error::ForeignPin);
+        }
+
+        let mut inner = cs.pin.lock()?;
+
+        // Hand the pin allocation over to the SPI channel
+        inner.gpio_release(N);
+
+        self.spi_locked(&mut inner, channel, config, Some(N))
+    }
+}
+
+/// Convert a typed input into a runtime-indexed [`InputPin`]
+impl<const N: u8, T: UsbContext> From<Pin<N, Input, T>> for InputPin<T> {
+    fn from(p: Pin<N, Input, T>) -> Self {
+        InputPin {
+            pin: p.pin,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+        }
+    }
+}
+
+/// Convert a typed output into a runtime-indexed [`OutputPin`]
+impl<const N: u8, MODE: OutputMode, T: UsbContext> From<Pin<N, MODE, T>> for OutputPin<T> {
+    fn from(p: Pin<N, MODE, T>) -> Self {
+        OutputPin {
+            pin: p.pin,
+            mode: MODE::MODE,
+        }
+    }
+}
+
+impl<const N: u8, T: UsbContext> embedded_hal::digital::InputPin for Pin<N, Input, T> {
+    fn is_high(&mut self) -> Result<bool, Self::Error> {
+        self.pin.lock()?.get_gpio_level(N)
+    }
+
+    fn is_low(&mut self) -> Result<bool, Self::Error> {
+        let v = self.is_high()?;
+        Ok(!v)
+    }
+}
+
+impl<const N: u8, MODE: OutputMode, T: UsbContext> embedded_hal::digital::OutputPin
+    for Pin<N, MODE, T>
+{
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.pin
+            .lock()?
+            .set_gpio_mode_level(N, MODE::MODE, GpioLevel::High)
+    }
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.pin
+            .lock()?
+            .set_gpio_mode_level(N, MODE::MODE, GpioLevel::Low)
+    }
+}
+
+impl<const N: u8, MODE, T: UsbContext> embedded_hal::digital::ErrorType for Pin<N, MODE, T> {
+    type Error = Error;
+}
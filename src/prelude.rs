@@ -1,7 +1,47 @@
 pub use embedded_hal::spi::Mode as SpiMode;
 
-pub use crate::{Cp2130, Device, Error as Cp2130Error, InputPin, OutputPin, Spi};
+pub use crate::{
+    Cp2130, Device, Error as Cp2130Error, GpioAccess, InputPin, OutputPin, Spi, SpiAccess,
+};
 
-pub use crate::device::{GpioLevel, GpioMode, SpiClock, SpiConfig, UsbOptions};
+pub use crate::batch::GpioBatch;
 
-pub use crate::manager::{Filter, Manager};
+pub use crate::debounce::DebouncedInput;
+
+pub use crate::delay::Delay;
+
+pub use crate::device::{
+    CsMode, Edge, EventCounter, EventCounterMode, GpioLevel, GpioLevels, GpioMode, SpiClock,
+    SpiConfig, SpiConfigBuilder, SpiDelays, UsbOptions, UsbPower,
+};
+
+pub use crate::eeprom::{EepromGeometry, SpiEeprom};
+
+pub use crate::flash::{FlashGeometry, JedecId, Sfdp, SpiFlash};
+
+pub use crate::health::{HealthConfig, HealthMonitor};
+
+pub use crate::manager::{
+    DeviceSummary, Filter, HotplugEvent, Manager, OpenAll, PortPath, Speed, Watch,
+};
+
+pub use crate::onewire::{GpioOneWire, OneWireBus, RomId};
+
+pub use crate::otp::{
+    OtpFields, OtpWrite, PinConfig, PinDefault, PinFunction, PowerMode, PromImage,
+    TransferPriority, UsbConfig, UsbConfigUpdate,
+};
+
+pub use crate::provision::{ProvisioningReport, ProvisioningSpec};
+
+pub use crate::pwm::Pwm;
+
+pub use crate::remote::RemoteCp2130;
+
+pub use crate::self_test::{SelfTestConfig, SelfTestReport};
+
+pub use crate::stats::Stats;
+
+pub use crate::stream::{SpiStream, StreamConfig};
+
+pub use crate::transport::Transport;
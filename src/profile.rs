@@ -0,0 +1,155 @@
+//! CP2130 Driver device profiles
+//!
+//! Profiles describe the SPI and GPIO configuration of a target board in TOML
+//! or JSON, and can be applied to a device to fetch ready-to-use handles.
+//!
+//! ```toml
+//! [spi.flash]
+//! channel = 0
+//! cs_pin = 0
+//! clock = 6000000
+//! spi_mode = 0
+//!
+//! [gpio.reset]
+//! pin = 6
+//! mode = "push-pull"
+//! level = "high"
+//!
+//! [gpio.irq]
+//! pin = 4
+//! mode = "input"
+//! ```
+//!
+//! Copyright 2019 Ryan Kurte
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use log::debug;
+use rusb::{GlobalContext, UsbContext};
+use serde::{Deserialize, Serialize};
+
+use crate::{Cp2130, Error, GpioLevel, GpioMode, InputPin, OutputPin, Spi, SpiConfig};
+
+/// Errors loading device profiles
+#[derive(Debug, thiserror::Error)]
+pub enum ProfileError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("TOML error: {0}")]
+    Toml(#[from] toml::de::Error),
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Unrecognised profile extension (expected .toml or .json)")]
+    UnknownFormat,
+}
+
+/// Board profile, describing named SPI devices and GPIOs
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    /// Named SPI devices
+    pub spi: BTreeMap<String, SpiProfile>,
+    /// Named GPIO pins
+    pub gpio: BTreeMap<String, GpioProfile>,
+}
+
+/// SPI device profile
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpiProfile {
+    /// SPI channel
+    pub channel: u8,
+    /// GPIO pin used for CS, if any
+    #[serde(default)]
+    pub cs_pin: Option<u8>,
+    /// SPI configuration
+    #[serde(flatten)]
+    pub config: SpiConfig,
+}
+
+/// GPIO pin profile
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GpioProfile {
+    /// GPIO pin index
+    pub pin: u8,
+    /// GPIO pin mode
+    pub mode: GpioMode,
+    /// Initial level for output pins
+    #[serde(default = "default_level")]
+    pub level: GpioLevel,
+}
+
+fn default_level() -> GpioLevel {
+    GpioLevel::Low
+}
+
+/// Handles created by applying a [`Profile`] to a device
+pub struct Board<T: UsbContext = GlobalContext> {
+    /// Named SPI devices
+    pub spi: BTreeMap<String, Spi<T>>,
+    /// Named input pins
+    pub inputs: BTreeMap<String, InputPin<T>>,
+    /// Named output pins
+    pub outputs: BTreeMap<String, OutputPin<T>>,
+}
+
+impl Profile {
+    /// Parse a profile from a TOML string
+    pub fn from_toml(s: &str) -> Result<Self, ProfileError> {
+        Ok(toml::from_str(s)?)
+    }
+
+    /// Parse a profile from a JSON string
+    pub fn from_json(s: &str) -> Result<Self, ProfileError> {
+        Ok(serde_json::from_str(s)?)
+    }
+
+    /// Load a profile from a `.toml` or `.json` file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ProfileError> {
+        let path = path.as_ref();
+        let s = std::fs::read_to_string(path)?;
+
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => Self::from_toml(&s),
+            Some("json") => Self::from_json(&s),
+            _ => Err(ProfileError::UnknownFormat),
+        }
+    }
+
+    /// Apply the profile to a device, returning handles for each named SPI device and pin
+    pub fn apply<T: UsbContext>(&self, cp2130: &Cp2130<T>) -> Result<Board<T>, Error> {
+        let mut board = Board {
+            spi: BTreeMap::new(),
+            inputs: BTreeMap::new(),
+            outputs: BTreeMap::new(),
+        };
+
+        // Configure GPIOs first so CS pins conflicting with named GPIOs are detected
+        for (name, p) in &self.gpio {
+            debug!(
+                "Configuring GPIO {} (pin: {} mode: {:?})",
+                name, p.pin, p.mode
+            );
+
+            match p.mode {
+                GpioMode::Input => {
+                    let pin = cp2130.gpio_in(p.pin)?;
+                    board.inputs.insert(name.clone(), pin);
+                }
+                _ => {
+                    let pin = cp2130.gpio_out(p.pin, p.mode, p.level)?;
+                    board.outputs.insert(name.clone(), pin);
+                }
+            }
+        }
+
+        for (name, p) in &self.spi {
+            debug!("Configuring SPI {} (channel: {})", name, p.channel);
+
+            let spi = cp2130.spi(p.channel, p.config.clone(), p.cs_pin)?;
+            board.spi.insert(name.clone(), spi);
+        }
+
+        Ok(board)
+    }
+}
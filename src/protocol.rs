@@ -0,0 +1,751 @@
+//! CP2130 Driver Protocol
+//!
+//! Command framing for the CP2130: control request codes, SPI word and delay encodings,
+//! bulk transfer headers, GPIO commands and the PROM block layout.
+//! This module uses only `core` (no allocation, no USB backend) so encodings may be shared
+//! with device emulators and firmware-side tooling, types are re-exported from
+//! [`crate::device`] for use with the driver.
+//!
+//! Copyright 2019 Ryan Kurte
+
+#![deny(
+    clippy::std_instead_of_core,
+    clippy::std_instead_of_alloc,
+    clippy::alloc_instead_of_core
+)]
+
+use core::fmt;
+use core::ops::Range;
+
+use bitflags::bitflags;
+use embedded_hal::spi::{Mode as SpiMode, Phase, Polarity};
+
+/// Protocol encoding / decoding errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtocolError {
+    /// Buffer is shorter than the command or response
+    ShortBuffer { expected: usize, actual: usize },
+    /// Field contains an unrecognised value
+    InvalidValue { field: &'static str, value: u8 },
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ShortBuffer { expected, actual } => {
+                write!(
+                    f,
+                    "short buffer (expected {} bytes, got {})",
+                    expected, actual
+                )
+            }
+            Self::InvalidValue { field, value } => {
+                write!(f, "invalid {} value: 0x{:02x}", field, value)
+            }
+        }
+    }
+}
+
+/// Check a buffer holds at least `expected` bytes
+fn check_len(buff: &[u8], expected: usize) -> Result<(), ProtocolError> {
+    match buff.len() >= expected {
+        true => Ok(()),
+        false => Err(ProtocolError::ShortBuffer {
+            expected,
+            actual: buff.len(),
+        }),
+    }
+}
+
+fn read_u16_be(buff: &[u8]) -> u16 {
+    u16::from_be_bytes([buff[0], buff[1]])
+}
+
+/// CP2130 command enumeration
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Commands {
+    GetClockDivider = 0x46,
+    GetEventCounter = 0x44,
+    GetFullThreshold = 0x34,
+    GetGpioChipSelect = 0x24,
+    GetGpioModeAndLevel = 0x22,
+    GetGpioValues = 0x20,
+    GetRtrState = 0x36,
+    GetSpiWord = 0x30,
+    GetSpiDelay = 0x32,
+    GetReadOnlyVersion = 0x11,
+    ResetDevice = 0x10,
+    SetClockDivider = 0x47,
+    SetEventCOunter = 0x45,
+    SetFullThreshold = 0x35,
+    SetGpioChipSelect = 0x25,
+    SetGpioModeAndLevel = 0x23,
+    SetGpioValues = 0x21,
+    SetRtrStop = 0x37,
+    SetSpiWord = 0x31,
+    SetSpiDelay = 0x33,
+    GetUsbConfig = 0x60,
+    SetUsbConfig = 0x61,
+    GetManufacturingString1 = 0x62,
+    SetManufacturingString1 = 0x63,
+    GetManufacturingString2 = 0x64,
+    SetManufacturingString2 = 0x65,
+    GetProductString1 = 0x66,
+    SetProductString1 = 0x67,
+    GetProductString2 = 0x68,
+    SetProductString2 = 0x69,
+    GetSerialString = 0x6A,
+    SetSerialString = 0x6B,
+    GetPinConfig = 0x6C,
+    SetPinConfig = 0x6D,
+    GetLockByte = 0x6E,
+    SetLockByte = 0x6F,
+    GetPromConfig = 0x70,
+    SetPromConfig = 0x71,
+}
+
+/// Default CP2130 VID
+pub const VID: u16 = 0x10c4;
+
+/// Default CP2130 PID
+pub const PID: u16 = 0x87a0;
+
+bitflags!(
+    /// USB request type flags
+    pub struct RequestType: u8 {
+        const HOST_TO_DEVICE = 0b0000_0000;
+        const DEVICE_TO_HOST = 0b1000_0000;
+
+        const TYPE_STANDARD = 0b0000_0000;
+        const TYPE_CLASS =    0b0010_0000;
+        const TYPE_VENDOR =   0b0100_0000;
+
+        const RECIPIENT_DEVICE =    0b0000_0000;
+        const RECIPIENT_INTERFACE = 0b0000_0001;
+        const RECIPIENT_ENDPOINT =  0b0000_0010;
+        const RECIPIENT_OTHER =     0b0000_0011;
+    }
+);
+
+bitflags!(
+    /// Gpio PIN masks for multiple pin operations
+    ///
+    /// Note the bit positions are not contiguous, use [`GpioLevels::pin`] and
+    /// [`GpioLevels::set_pin`] to access levels by pin index.
+    /// The endianness of this varies depending on where it is used
+    /// (GetGpioValues returns big-endian), values are always held in host order.
+    pub struct GpioLevels: u16 {
+        const GPIO_10 = (1 << 14);
+        const GPIO_9  = (1 << 13);
+        const GPIO_8  = (1 << 12);
+        const GPIO_7  = (1 << 11);
+        const GPIO_6  = (1 << 10);
+        const GPIO_5  = (1 << 8);
+
+        const GPIO_4  = (1 << 7);
+        const GPIO_3  = (1 << 6);
+        const GPIO_2  = (1 << 5);
+        const GPIO_1  = (1 << 4);
+        const GPIO_0  = (1 << 3);
+    }
+);
+
+impl GpioLevels {
+    /// Pin masks in pin index order
+    const PINS: [GpioLevels; 11] = [
+        GpioLevels::GPIO_0,
+        GpioLevels::GPIO_1,
+        GpioLevels::GPIO_2,
+        GpioLevels::GPIO_3,
+        GpioLevels::GPIO_4,
+        GpioLevels::GPIO_5,
+        GpioLevels::GPIO_6,
+        GpioLevels::GPIO_7,
+        GpioLevels::GPIO_8,
+        GpioLevels::GPIO_9,
+        GpioLevels::GPIO_10,
+    ];
+
+    /// Fetch the mask for a given pin index, `None` for an invalid index
+    pub fn mask(pin: u8) -> Option<GpioLevels> {
+        Self::PINS.get(pin as usize).copied()
+    }
+
+    /// Fetch whether a given pin is high (invalid pin indices read as low)
+    pub fn pin(&self, pin: u8) -> bool {
+        match Self::mask(pin) {
+            Some(m) => self.contains(m),
+            None => false,
+        }
+    }
+
+    /// Set the level for a given pin (invalid pin indices are ignored)
+    pub fn set_pin(&mut self, pin: u8, level: GpioLevel) {
+        if let Some(m) = Self::mask(pin) {
+            self.set(m, level == GpioLevel::High);
+        }
+    }
+
+    /// Iterate over `(pin, level)` pairs for all pins
+    pub fn iter_pins(&self) -> impl Iterator<Item = (u8, GpioLevel)> + '_ {
+        (0..Self::PINS.len() as u8).map(move |p| {
+            let level = match self.pin(p) {
+                true => GpioLevel::High,
+                false => GpioLevel::Low,
+            };
+            (p, level)
+        })
+    }
+
+    /// Encode levels as returned by Get_GPIO_Values (big-endian)
+    pub fn encode(&self) -> [u8; 2] {
+        self.bits().to_be_bytes()
+    }
+
+    /// Decode levels from a Get_GPIO_Values response, unknown bits are discarded
+    pub fn decode(buff: &[u8]) -> Result<Self, ProtocolError> {
+        check_len(buff, 2)?;
+        Ok(Self::from_bits_truncate(read_u16_be(buff)))
+    }
+}
+
+/// Render pin levels as `GPIO0: high, GPIO1: low, ...`
+impl fmt::Display for GpioLevels {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (pin, level)) in self.iter_pins().enumerate() {
+            if i != 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "GPIO{}: {}", pin, level)?;
+        }
+        Ok(())
+    }
+}
+
+/// Number of GPIO pins on the CP2130
+pub const GPIO_COUNT: u8 = 11;
+
+/// GPIO mode enumeration
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum GpioMode {
+    Input = 0x00,
+    OpenDrain = 0x01,
+    PushPull = 0x02,
+}
+
+impl TryFrom<u8> for GpioMode {
+    type Error = ProtocolError;
+
+    fn try_from(v: u8) -> Result<Self, Self::Error> {
+        match v {
+            0x00 => Ok(Self::Input),
+            0x01 => Ok(Self::OpenDrain),
+            0x02 => Ok(Self::PushPull),
+            _ => Err(ProtocolError::InvalidValue {
+                field: "gpio_mode",
+                value: v,
+            }),
+        }
+    }
+}
+
+/// GPIO level enumeration
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum GpioLevel {
+    Low = 0x00,
+    High = 0x01,
+}
+
+impl fmt::Display for GpioLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Low => write!(f, "low"),
+            Self::High => write!(f, "high"),
+        }
+    }
+}
+
+impl TryFrom<u8> for GpioLevel {
+    type Error = ProtocolError;
+
+    fn try_from(v: u8) -> Result<Self, Self::Error> {
+        match v {
+            0x00 => Ok(Self::Low),
+            0x01 => Ok(Self::High),
+            _ => Err(ProtocolError::InvalidValue {
+                field: "gpio_level",
+                value: v,
+            }),
+        }
+    }
+}
+
+/// Set_GPIO_Mode_And_Level command
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct GpioModeLevel {
+    pub pin: u8,
+    pub mode: GpioMode,
+    pub level: GpioLevel,
+}
+
+impl GpioModeLevel {
+    /// Encoded command length
+    pub const LEN: usize = 3;
+
+    pub fn encode(&self) -> [u8; Self::LEN] {
+        [self.pin, self.mode as u8, self.level as u8]
+    }
+
+    pub fn decode(buff: &[u8]) -> Result<Self, ProtocolError> {
+        check_len(buff, Self::LEN)?;
+        Ok(Self {
+            pin: buff[0],
+            mode: GpioMode::try_from(buff[1])?,
+            level: GpioLevel::try_from(buff[2])?,
+        })
+    }
+}
+
+/// Set_GPIO_Values command, applying `levels` to pins in `mask`
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct GpioValues {
+    pub levels: GpioLevels,
+    pub mask: GpioLevels,
+}
+
+impl GpioValues {
+    /// Encoded command length
+    pub const LEN: usize = 4;
+
+    pub fn encode(&self) -> [u8; Self::LEN] {
+        let [l0, l1] = self.levels.encode();
+        let [m0, m1] = self.mask.encode();
+        [l0, l1, m0, m1]
+    }
+
+    pub fn decode(buff: &[u8]) -> Result<Self, ProtocolError> {
+        check_len(buff, Self::LEN)?;
+        Ok(Self {
+            levels: GpioLevels::decode(&buff[0..2])?,
+            mask: GpioLevels::decode(&buff[2..4])?,
+        })
+    }
+}
+
+/// Event counter (GPIO.4) input mode
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum EventCounterMode {
+    RisingEdge = 0x04,
+    FallingEdge = 0x05,
+    NegativePulse = 0x06,
+    PositivePulse = 0x07,
+}
+
+impl EventCounterMode {
+    /// Fetch the mode for a mode field value, `None` where the counter is disabled
+    /// or the value is unrecognised
+    pub fn from_bits(v: u8) -> Option<Self> {
+        match v {
+            0x04 => Some(Self::RisingEdge),
+            0x05 => Some(Self::FallingEdge),
+            0x06 => Some(Self::NegativePulse),
+            0x07 => Some(Self::PositivePulse),
+            _ => None,
+        }
+    }
+}
+
+/// Event counter overflow flag in the mode byte
+pub const EVENT_COUNTER_OVERFLOW: u8 = 1 << 7;
+
+/// Event counter mode field in the mode byte
+const EVENT_COUNTER_MODE_MASK: u8 = 0b0111;
+
+/// Event counter state
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EventCounter {
+    /// Counter mode, `None` where GPIO.4 is not configured as an event counter
+    pub mode: Option<EventCounterMode>,
+    /// Set when the count has wrapped
+    pub overflow: bool,
+    pub count: u16,
+}
+
+impl EventCounter {
+    /// Encoded Get/Set_Event_Counter length
+    pub const LEN: usize = 3;
+
+    pub fn encode(&self) -> [u8; Self::LEN] {
+        let mut mode = self.mode.map(|m| m as u8).unwrap_or(0);
+        if self.overflow {
+            mode |= EVENT_COUNTER_OVERFLOW;
+        }
+        let [c0, c1] = self.count.to_be_bytes();
+        [mode, c0, c1]
+    }
+
+    pub fn decode(buff: &[u8]) -> Result<Self, ProtocolError> {
+        check_len(buff, Self::LEN)?;
+        Ok(Self {
+            mode: EventCounterMode::from_bits(buff[0] & EVENT_COUNTER_MODE_MASK),
+            overflow: buff[0] & EVENT_COUNTER_OVERFLOW != 0,
+            count: read_u16_be(&buff[1..3]),
+        })
+    }
+}
+
+/// Transfer command enumeration
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TransferCommand {
+    Read = 0x00,
+    Write = 0x01,
+    WriteRead = 0x02,
+    ReadWithRTR = 0x04,
+}
+
+impl TryFrom<u8> for TransferCommand {
+    type Error = ProtocolError;
+
+    fn try_from(v: u8) -> Result<Self, Self::Error> {
+        match v {
+            0x00 => Ok(Self::Read),
+            0x01 => Ok(Self::Write),
+            0x02 => Ok(Self::WriteRead),
+            0x04 => Ok(Self::ReadWithRTR),
+            _ => Err(ProtocolError::InvalidValue {
+                field: "transfer_command",
+                value: v,
+            }),
+        }
+    }
+}
+
+/// Maximum length issued in a single transfer command
+///
+/// The header length field is 32 bits, however the device is only reliable for transfers
+/// up to 64 KiB, the driver splits longer buffers across multiple commands.
+pub const MAX_TRANSFER_LEN: usize = 64 * 1024;
+
+/// Bulk transfer header, preceding any data written to the bulk OUT endpoint
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct TransferHeader {
+    pub command: TransferCommand,
+    /// Transfer length in bytes
+    pub len: u32,
+}
+
+impl TransferHeader {
+    /// Encoded header length
+    pub const LEN: usize = 8;
+
+    pub fn encode(&self) -> [u8; Self::LEN] {
+        let mut buff = [0u8; Self::LEN];
+        buff[2] = self.command as u8;
+        buff[4..8].copy_from_slice(&self.len.to_le_bytes());
+        buff
+    }
+
+    pub fn decode(buff: &[u8]) -> Result<Self, ProtocolError> {
+        check_len(buff, Self::LEN)?;
+        Ok(Self {
+            command: TransferCommand::try_from(buff[2])?,
+            len: u32::from_le_bytes([buff[4], buff[5], buff[6], buff[7]]),
+        })
+    }
+}
+
+/// SPI clock configuration
+///
+/// Discriminants match the SPI word clock divider field
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(into = "u64", try_from = "u64"))]
+pub enum SpiClock {
+    Clock12Mhz = 0,
+    Clock6MHz = 1,
+    Clock3MHz = 2,
+    Clock1_5MHz = 3,
+    Clock750KHz = 4,
+    Clock375KHz = 5,
+    Clock187_5KHz = 6,
+    Clock93_75KHz = 7,
+}
+
+impl SpiClock {
+    /// All supported clock rates, fastest first
+    pub const ALL: [SpiClock; 8] = [
+        SpiClock::Clock12Mhz,
+        SpiClock::Clock6MHz,
+        SpiClock::Clock3MHz,
+        SpiClock::Clock1_5MHz,
+        SpiClock::Clock750KHz,
+        SpiClock::Clock375KHz,
+        SpiClock::Clock187_5KHz,
+        SpiClock::Clock93_75KHz,
+    ];
+
+    /// Previous (misnamed) 375 kHz clock
+    #[deprecated(note = "use SpiClock::Clock375KHz")]
+    #[allow(non_upper_case_globals)]
+    pub const Clock375MHz: SpiClock = SpiClock::Clock375KHz;
+
+    pub fn freq(&self) -> u64 {
+        match self {
+            SpiClock::Clock12Mhz => 12_000_000,
+            SpiClock::Clock6MHz => 6_000_000,
+            SpiClock::Clock3MHz => 3_000_000,
+            SpiClock::Clock1_5MHz => 1_500_000,
+            SpiClock::Clock750KHz => 750_000,
+            SpiClock::Clock375KHz => 375_000,
+            SpiClock::Clock187_5KHz => 187_500,
+            SpiClock::Clock93_75KHz => 93_750,
+        }
+    }
+
+    /// Fetch the supported clock rate closest to the provided frequency in Hz
+    pub fn from_frequency(hz: u64) -> SpiClock {
+        let mut closest = SpiClock::Clock12Mhz;
+
+        for c in Self::ALL {
+            if c.freq().abs_diff(hz) < closest.freq().abs_diff(hz) {
+                closest = c;
+            }
+        }
+
+        closest
+    }
+}
+
+/// SPI word clock phase flag (capture on second transition)
+pub const SPI_WORD_CPHA: u8 = 1 << 5;
+
+/// SPI word clock polarity flag (idle high)
+pub const SPI_WORD_CPOL: u8 = 1 << 4;
+
+/// SPI word chip select push-pull flag (open drain where cleared)
+pub const SPI_WORD_CS_PUSH_PULL: u8 = 1 << 3;
+
+/// SPI word clock divider field
+const SPI_WORD_CLOCK_MASK: u8 = 0b0111;
+
+/// Per-channel SPI control word, as used by Get/Set_SPI_Word
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct SpiWord {
+    pub clock: SpiClock,
+    pub spi_mode: SpiMode,
+    /// Chip select pin mode, either [`GpioMode::OpenDrain`] or [`GpioMode::PushPull`]
+    pub cs_pin_mode: GpioMode,
+}
+
+impl SpiWord {
+    pub fn encode(&self) -> u8 {
+        let mut flags = 0;
+
+        if let Phase::CaptureOnSecondTransition = self.spi_mode.phase {
+            flags |= SPI_WORD_CPHA;
+        }
+
+        if let Polarity::IdleHigh = self.spi_mode.polarity {
+            flags |= SPI_WORD_CPOL;
+        };
+
+        if let GpioMode::PushPull = self.cs_pin_mode {
+            flags |= SPI_WORD_CS_PUSH_PULL;
+        }
+
+        flags | ((self.clock as u8) & SPI_WORD_CLOCK_MASK)
+    }
+
+    /// Decode an SPI word, all values are valid
+    pub fn decode(word: u8) -> Self {
+        let spi_mode = SpiMode {
+            polarity: match word & SPI_WORD_CPOL {
+                0 => Polarity::IdleLow,
+                _ => Polarity::IdleHigh,
+            },
+            phase: match word & SPI_WORD_CPHA {
+                0 => Phase::CaptureOnFirstTransition,
+                _ => Phase::CaptureOnSecondTransition,
+            },
+        };
+        let cs_pin_mode = match word & SPI_WORD_CS_PUSH_PULL {
+            0 => GpioMode::OpenDrain,
+            _ => GpioMode::PushPull,
+        };
+
+        Self {
+            clock: SpiClock::ALL[(word & SPI_WORD_CLOCK_MASK) as usize],
+            spi_mode,
+            cs_pin_mode,
+        }
+    }
+}
+
+/// Chip select mode
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum CsMode {
+    /// Auto chip select is disabled for the specified channel
+    Disabled = 0x00,
+    /// Auto chip select is enabled for the specified channel
+    Enabled = 0x01,
+    /// Auto chip select is enabled for the specified channel,
+    /// all other chip selects are disabled
+    Exclusive = 0x02,
+}
+
+impl TryFrom<u8> for CsMode {
+    type Error = ProtocolError;
+
+    fn try_from(v: u8) -> Result<Self, Self::Error> {
+        match v {
+            0x00 => Ok(Self::Disabled),
+            0x01 => Ok(Self::Enabled),
+            0x02 => Ok(Self::Exclusive),
+            _ => Err(ProtocolError::InvalidValue {
+                field: "cs_mode",
+                value: v,
+            }),
+        }
+    }
+}
+
+/// Set_GPIO_Chip_Select command
+#[derive(Debug, PartialEq, Clone)]
+pub struct ChipSelect {
+    pub channel: u8,
+    pub mode: CsMode,
+}
+
+impl ChipSelect {
+    /// Encoded command length
+    pub const LEN: usize = 2;
+
+    pub fn encode(&self) -> [u8; Self::LEN] {
+        [self.channel, self.mode.clone() as u8]
+    }
+
+    pub fn decode(buff: &[u8]) -> Result<Self, ProtocolError> {
+        check_len(buff, Self::LEN)?;
+        Ok(Self {
+            channel: buff[0],
+            mode: CsMode::try_from(buff[1])?,
+        })
+    }
+}
+
+/// Get_GPIO_Chip_Select response, with a bit per channel
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct ChipSelectEnables {
+    /// Channels with chip select enabled
+    pub channels: u16,
+    /// Pins currently acting as chip selects
+    pub pins: u16,
+}
+
+impl ChipSelectEnables {
+    /// Encoded response length
+    pub const LEN: usize = 4;
+
+    pub fn encode(&self) -> [u8; Self::LEN] {
+        let [c0, c1] = self.channels.to_be_bytes();
+        let [p0, p1] = self.pins.to_be_bytes();
+        [c0, c1, p0, p1]
+    }
+
+    pub fn decode(buff: &[u8]) -> Result<Self, ProtocolError> {
+        check_len(buff, Self::LEN)?;
+        Ok(Self {
+            channels: read_u16_be(&buff[0..2]),
+            pins: read_u16_be(&buff[2..4]),
+        })
+    }
+}
+
+bitflags!(
+    /// Mask for delay configuration
+    #[derive(Default)]
+    pub struct DelayMask: u8 {
+        const CS_TOGGLE      = 1 << 3;
+        const PRE_DEASSERT   = 1 << 2;
+        const POST_ASSERT    = 1 << 1;
+        const INTER_BYE      = 1 << 0;
+    }
+);
+
+/// Get/Set_SPI_Delay command, delays are in 10 us device units
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct SpiDelayWord {
+    pub channel: u8,
+    pub mask: DelayMask,
+    pub inter_byte: u16,
+    pub post_assert: u16,
+    pub pre_deassert: u16,
+}
+
+impl SpiDelayWord {
+    /// Encoded command length
+    pub const LEN: usize = 8;
+
+    pub fn encode(&self) -> [u8; Self::LEN] {
+        let mut buff = [0u8; Self::LEN];
+        buff[0] = self.channel;
+        buff[1] = self.mask.bits();
+        buff[2..4].copy_from_slice(&self.inter_byte.to_be_bytes());
+        buff[4..6].copy_from_slice(&self.post_assert.to_be_bytes());
+        buff[6..8].copy_from_slice(&self.pre_deassert.to_be_bytes());
+        buff
+    }
+
+    /// Decode a delay word, unknown mask bits are discarded
+    pub fn decode(buff: &[u8]) -> Result<Self, ProtocolError> {
+        check_len(buff, Self::LEN)?;
+        Ok(Self {
+            channel: buff[0],
+            mask: DelayMask::from_bits_truncate(buff[1]),
+            inter_byte: read_u16_be(&buff[2..4]),
+            post_assert: read_u16_be(&buff[4..6]),
+            pre_deassert: read_u16_be(&buff[6..8]),
+        })
+    }
+}
+
+/// Encode a Get_ReadOnlyVersion response
+pub fn encode_version(version: u16) -> [u8; 2] {
+    version.to_le_bytes()
+}
+
+/// Decode a Get_ReadOnlyVersion response (little-endian, unlike most other fields)
+pub fn decode_version(buff: &[u8]) -> Result<u16, ProtocolError> {
+    check_len(buff, 2)?;
+    Ok(u16::from_le_bytes([buff[0], buff[1]]))
+}
+
+/// Key required in `wValue` for PROM write commands
+pub const MEMORY_KEY: u16 = 0xA5F1;
+
+/// Length of each Get/Set_PROM_Config block
+pub const PROM_BLOCK_LEN: usize = 64;
+
+/// Number of PROM blocks
+pub const PROM_BLOCKS: usize = 8;
+
+/// Total PROM length
+pub const PROM_LEN: usize = PROM_BLOCK_LEN * PROM_BLOCKS;
+
+/// Fetch the byte range of a PROM block (the Get/Set_PROM_Config index),
+/// `None` for an invalid block index
+pub fn prom_block(index: usize) -> Option<Range<usize>> {
+    match index < PROM_BLOCKS {
+        true => Some(index * PROM_BLOCK_LEN..(index + 1) * PROM_BLOCK_LEN),
+        false => None,
+    }
+}
@@ -0,0 +1,270 @@
+//! CP2130 Driver Provisioning
+//!
+//! Declarative PROM provisioning for production, comparing a [`ProvisioningSpec`]
+//! against the device, programming only the fields that differ, then reading the
+//! configuration back to verify before applying any requested locks.
+//!
+//! Copyright 2019 Ryan Kurte
+
+use log::{debug, error};
+use rusb::UsbContext;
+
+use crate::otp::{OtpFields, OtpWrite, PinConfig, UsbConfig, UsbConfigUpdate};
+use crate::{Cp2130, Error};
+
+/// Desired device configuration, unset fields are left unchanged
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct ProvisioningSpec {
+    /// USB configuration (VID/PID, power, release and priority)
+    pub usb: UsbConfigUpdate,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+    pub serial: Option<String>,
+    /// Power-on pin configuration
+    pub pins: Option<PinConfig>,
+    /// Fields to lock once programming is verified
+    pub lock: OtpFields,
+}
+
+/// Changes required to bring a device in line with a [`ProvisioningSpec`]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProvisioningPlan {
+    pub usb: UsbConfigUpdate,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+    pub serial: Option<String>,
+    pub pins: Option<PinConfig>,
+    /// Fields to lock that are not already locked
+    pub lock: OtpFields,
+}
+
+impl ProvisioningPlan {
+    /// Check whether the plan requires any writes
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Fetch the PROM fields written by the plan (excluding locks)
+    pub fn fields(&self) -> Result<OtpFields, Error> {
+        let mut fields = self.usb.fields()?;
+
+        if self.manufacturer.is_some() {
+            fields |= OtpFields::MANUFACTURER_1 | OtpFields::MANUFACTURER_2;
+        }
+        if self.product.is_some() {
+            fields |= OtpFields::PRODUCT_1 | OtpFields::PRODUCT_2;
+        }
+        if self.serial.is_some() {
+            fields |= OtpFields::SERIAL;
+        }
+        if self.pins.is_some() {
+            fields |= OtpFields::PIN_CONFIG;
+        }
+
+        Ok(fields)
+    }
+}
+
+/// Provisioned configuration items
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum ProvisionItem {
+    UsbConfig,
+    Manufacturer,
+    Product,
+    Serial,
+    PinConfig,
+    Lock,
+}
+
+/// Verification result for a single provisioned item
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VerifyResult {
+    pub item: ProvisionItem,
+    pub passed: bool,
+    /// Mismatch description
+    pub detail: Option<String>,
+}
+
+/// Provisioning report, containing the executed plan and verification results
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProvisioningReport {
+    pub plan: ProvisioningPlan,
+    pub results: Vec<VerifyResult>,
+}
+
+impl ProvisioningReport {
+    /// Check whether all provisioned items verified
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(|r| r.passed)
+    }
+
+    /// Fetch the failed items
+    pub fn failures(&self) -> impl Iterator<Item = &VerifyResult> {
+        self.results.iter().filter(|r| !r.passed)
+    }
+
+    fn record(&mut self, item: ProvisionItem, detail: Option<String>) {
+        if let Some(d) = &detail {
+            error!("Provisioning {:?} failed verification: {}", item, d);
+        }
+
+        self.results.push(VerifyResult {
+            item,
+            passed: detail.is_none(),
+            detail,
+        });
+    }
+}
+
+/// Maximum power as stored by the device (2 mA units)
+fn round_power(ma: u16) -> u16 {
+    ma.div_ceil(2) * 2
+}
+
+/// Compute the USB configuration fields that differ from the current configuration
+fn usb_changes(spec: &UsbConfigUpdate, current: &UsbConfig) -> UsbConfigUpdate {
+    let differs = |a, b| match a {
+        Some(a) if a != b => Some(a),
+        _ => None,
+    };
+
+    UsbConfigUpdate {
+        vid: differs(spec.vid, current.vid),
+        pid: differs(spec.pid, current.pid),
+        max_power_ma: spec
+            .max_power_ma
+            .filter(|ma| round_power(*ma) != current.max_power_ma),
+        power_mode: spec.power_mode.filter(|m| *m != current.power_mode),
+        release: spec
+            .release
+            .filter(|r| *r != (current.release_major, current.release_minor)),
+        transfer_priority: spec
+            .transfer_priority
+            .filter(|p| *p != current.transfer_priority),
+    }
+}
+
+/// Compare expected and read back values, returning a mismatch description
+fn compare<T: PartialEq + std::fmt::Debug>(expected: &T, actual: &T) -> Option<String> {
+    match expected == actual {
+        true => None,
+        false => Some(format!("expected {:?}, read {:?}", expected, actual)),
+    }
+}
+
+impl<T: UsbContext> Cp2130<T> {
+    /// Compute the writes required to provision the device to the provided spec
+    pub fn provision_plan(&self, spec: &ProvisioningSpec) -> Result<ProvisioningPlan, Error> {
+        let changed = |s: &Option<String>, current: String| match s {
+            Some(s) if *s != current => Some(s.clone()),
+            _ => None,
+        };
+
+        let plan = ProvisioningPlan {
+            usb: usb_changes(&spec.usb, &self.usb_config()?),
+            manufacturer: changed(&spec.manufacturer, self.manufacturer_string()?),
+            product: changed(&spec.product, self.product_string()?),
+            serial: changed(&spec.serial, self.serial_string()?),
+            pins: match &spec.pins {
+                Some(p) if *p != self.pin_config()? => Some(p.clone()),
+                _ => None,
+            },
+            lock: spec.lock - self.locked_fields()?,
+        };
+
+        debug!("Provisioning plan: {:?}", plan);
+
+        Ok(plan)
+    }
+
+    /// Provision the device PROM to the provided spec
+    ///
+    /// Fields that differ from the spec are programmed then read back for verification,
+    /// locks are only applied where all items verify. Writes fail prior to programming
+    /// if any required field is locked.
+    pub fn provision(
+        &self,
+        spec: &ProvisioningSpec,
+        token: OtpWrite,
+    ) -> Result<ProvisioningReport, Error> {
+        let plan = self.provision_plan(spec)?;
+
+        // Check all required fields are writable before programming anything
+        let locked = self.locked_fields()? & plan.fields()?;
+        if !locked.is_empty() {
+            return Err(Error::Locked(locked));
+        }
+
+        if !plan.usb.is_empty() {
+            self.program_usb_config(plan.usb.clone(), token)?;
+        }
+        if let Some(s) = &plan.manufacturer {
+            self.set_manufacturer_string(s, token)?;
+        }
+        if let Some(s) = &plan.product {
+            self.set_product_string(s, token)?;
+        }
+        if let Some(s) = &plan.serial {
+            self.set_serial_string(s, token)?;
+        }
+        if let Some(p) = &plan.pins {
+            self.program_pin_config(p, token)?;
+        }
+
+        // Read back and verify against the spec
+        let mut report = ProvisioningReport {
+            plan,
+            results: vec![],
+        };
+
+        let usb = self.usb_config()?;
+        let remaining = usb_changes(&spec.usb, &usb);
+        report.record(
+            ProvisionItem::UsbConfig,
+            compare(&UsbConfigUpdate::default(), &remaining),
+        );
+
+        let strings = [
+            (ProvisionItem::Manufacturer, &spec.manufacturer),
+            (ProvisionItem::Product, &spec.product),
+            (ProvisionItem::Serial, &spec.serial),
+        ];
+        for (item, expected) in strings {
+            if let Some(s) = expected {
+                let actual = match item {
+                    ProvisionItem::Manufacturer => self.manufacturer_string()?,
+                    ProvisionItem::Product => self.product_string()?,
+                    _ => self.serial_string()?,
+                };
+                report.record(item, compare(s, &actual));
+            }
+        }
+
+        if let Some(p) = &spec.pins {
+            report.record(ProvisionItem::PinConfig, compare(p, &self.pin_config()?));
+        }
+
+        if !report.passed() {
+            debug!("Verification failed, skipping locks");
+            return Ok(report);
+        }
+
+        // Apply locks once verified
+        if !report.plan.lock.is_empty() {
+            self.lock_fields(report.plan.lock, token)?;
+
+            let locked = self.locked_fields()?;
+            report.record(
+                ProvisionItem::Lock,
+                compare(&spec.lock, &(locked & spec.lock)),
+            );
+        }
+
+        Ok(report)
+    }
+}
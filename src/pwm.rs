@@ -0,0 +1,251 @@
+//! CP2130 Driver Software PWM
+//!
+//! [`Pwm`] toggles a GPIO from a background thread. Each edge is a USB control
+//! transfer, taking in the order of a millisecond depending on the host and bus load,
+//! so frequencies are limited to [`MAX_PWM_FREQUENCY`] and edge timing jitters by
+//! about a millisecond. This is suitable for LED dimming or slow actuators on test
+//! jigs, not for precise waveforms.
+//!
+//! Edges that fall more than a period behind schedule are counted as late and the
+//! schedule is restarted, rather than issuing a burst of edges to catch up.
+//!
+//! Copyright 2019 Ryan Kurte
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use embedded_hal::digital::OutputPin as _;
+use log::{debug, error};
+use rusb::{GlobalContext, UsbContext};
+
+use crate::{Cp2130, Error, GpioLevel, GpioMode, OutputPin};
+
+/// Maximum supported PWM frequency in Hz
+pub const MAX_PWM_FREQUENCY: f32 = 250.0;
+
+/// PWM period and duty cycle
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct PwmSettings {
+    frequency: f32,
+    duty: f32,
+}
+
+impl PwmSettings {
+    fn new(frequency: f32, duty: f32) -> Result<Self, Error> {
+        if !(frequency > 0.0 && frequency <= MAX_PWM_FREQUENCY) {
+            return Err(Error::InvalidConfig {
+                field: "frequency",
+                reason: "PWM frequency must be greater than 0 and at most 250 Hz",
+            });
+        }
+
+        if !(0.0..=1.0).contains(&duty) {
+            return Err(Error::InvalidConfig {
+                field: "duty",
+                reason: "PWM duty cycle must be between 0.0 and 1.0",
+            });
+        }
+
+        Ok(Self { frequency, duty })
+    }
+
+    /// Time to hold the provided level for
+    fn hold(&self, high: bool) -> Duration {
+        let period = Duration::from_secs_f32(1.0 / self.frequency);
+        match high {
+            true => period.mul_f32(self.duty),
+            false => period.mul_f32(1.0 - self.duty),
+        }
+    }
+}
+
+/// State shared with the PWM thread
+#[derive(Default)]
+struct Shared {
+    late: AtomicU64,
+    error: Mutex<Option<Error>>,
+}
+
+/// Software PWM output, stopped (and the pin released) when dropped
+pub struct Pwm<T: UsbContext = GlobalContext> {
+    settings: PwmSettings,
+    tx: Option<Sender<PwmSettings>>,
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<OutputPin<T>>>,
+}
+
+impl<T: UsbContext + 'static> Cp2130<T> {
+    /// Start a software PWM output on the provided pin
+    ///
+    /// `frequency` is in Hz (up to [`MAX_PWM_FREQUENCY`]) and `duty` is the fraction
+    /// of each period the pin is held high, duty cycles of 0.0 and 1.0 hold the pin
+    /// at a constant level.
+    pub fn pwm_out(&self, pin: u8, frequency: f32, duty: f32) -> Result<Pwm<T>, Error> {
+        let settings = PwmSettings::new(frequency, duty)?;
+
+        let pin = self.gpio_out(pin, GpioMode::PushPull, GpioLevel::Low)?;
+
+        let shared = Arc::new(Shared::default());
+        let (tx, rx) = mpsc::channel();
+
+        let s = shared.clone();
+        let thread = std::thread::Builder::new()
+            .name("cp2130-pwm".to_string())
+            .spawn(move || pwm(pin, settings, rx, &s))
+            .expect("failed to spawn PWM thread");
+
+        Ok(Pwm {
+            settings,
+            tx: Some(tx),
+            shared,
+            thread: Some(thread),
+        })
+    }
+}
+
+/// PWM thread, toggling the pin until the sender is dropped or an error occurs
+fn pwm<T: UsbContext>(
+    mut pin: OutputPin<T>,
+    mut settings: PwmSettings,
+    rx: Receiver<PwmSettings>,
+    shared: &Shared,
+) -> OutputPin<T> {
+    let mut high = false;
+    let mut deadline = Instant::now();
+
+    loop {
+        // Constant levels need no toggling, wait for new settings
+        let res = match settings.duty {
+            d if d <= 0.0 => pin.set_low().map(|_| None),
+            d if d >= 1.0 => pin.set_high().map(|_| None),
+            _ => {
+                high = !high;
+                match high {
+                    true => pin.set_high(),
+                    false => pin.set_low(),
+                }
+                .map(|_| Some(settings.hold(high)))
+            }
+        };
+
+        let next = match res {
+            Ok(Some(hold)) => {
+                deadline += hold;
+
+                let now = Instant::now();
+                if now > deadline + settings.hold(!high) {
+                    shared.late.fetch_add(1, Ordering::Relaxed);
+                    deadline = now;
+                }
+
+                rx.recv_timeout(deadline.saturating_duration_since(now))
+            }
+            Ok(None) => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            Err(e) => {
+                error!("PWM output: {}", e);
+                *shared.error.lock().unwrap() = Some(e);
+                break;
+            }
+        };
+
+        match next {
+            // Restart the period with the new settings
+            Ok(s) => {
+                settings = s;
+                high = false;
+                deadline = Instant::now();
+            }
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+
+    debug!("PWM thread exiting");
+
+    pin
+}
+
+impl<T: UsbContext> Pwm<T> {
+    /// Fetch the current PWM frequency in Hz
+    pub fn frequency(&self) -> f32 {
+        self.settings.frequency
+    }
+
+    /// Fetch the current PWM duty cycle
+    pub fn duty(&self) -> f32 {
+        self.settings.duty
+    }
+
+    /// Update the PWM frequency, restarting the current period
+    pub fn set_frequency(&mut self, frequency: f32) -> Result<(), Error> {
+        self.update(PwmSettings::new(frequency, self.settings.duty)?)
+    }
+
+    /// Update the PWM duty cycle, restarting the current period
+    pub fn set_duty(&mut self, duty: f32) -> Result<(), Error> {
+        self.update(PwmSettings::new(self.settings.frequency, duty)?)
+    }
+
+    /// Fetch the number of edges that fell more than a period behind schedule
+    pub fn late_edges(&self) -> u64 {
+        self.shared.late.load(Ordering::Relaxed)
+    }
+
+    /// Stop the PWM output, returning the pin (driven low)
+    pub fn stop(mut self) -> Result<OutputPin<T>, Error> {
+        let pin = self.shutdown();
+
+        if let Some(e) = self.shared.error.lock().unwrap().take() {
+            return Err(e);
+        }
+
+        let mut pin = pin.ok_or(Error::PwmStopped)?;
+        pin.set_low()?;
+
+        Ok(pin)
+    }
+
+    fn update(&mut self, settings: PwmSettings) -> Result<(), Error> {
+        let sent = match &self.tx {
+            Some(tx) => tx.send(settings).is_ok(),
+            None => false,
+        };
+
+        if !sent {
+            return Err(self
+                .shared
+                .error
+                .lock()
+                .unwrap()
+                .take()
+                .unwrap_or(Error::PwmStopped));
+        }
+
+        self.settings = settings;
+
+        Ok(())
+    }
+
+    fn shutdown(&mut self) -> Option<OutputPin<T>> {
+        // Dropping the sender wakes and stops the thread
+        self.tx.take();
+
+        match self.thread.take().map(|t| t.join()) {
+            Some(Ok(pin)) => Some(pin),
+            Some(Err(_)) => {
+                error!("PWM thread panicked");
+                None
+            }
+            None => None,
+        }
+    }
+}
+
+impl<T: UsbContext> Drop for Pwm<T> {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
@@ -0,0 +1,229 @@
+//! CP2130 Driver Python Bindings
+//!
+//! `cp2130` Python module, enabled with the `python` feature and built with
+//! [maturin](https://www.maturin.rs) (see `pyproject.toml`):
+//!
+//! ```text
+//! maturin develop --release
+//! ```
+//!
+//! ```python
+//! import cp2130
+//!
+//! dev = cp2130.open(serial="0001")
+//! spi = dev.spi(channel=0, clock=1_500_000, mode=0, cs_pin=0)
+//! id = spi.transfer(b"\x9f\x00\x00\x00")
+//!
+//! led = dev.gpio_out(3)
+//! led.set(True)
+//! ```
+//!
+//! USB operations release the GIL, so devices may be used from multiple Python threads.
+//!
+//! Copyright 2019 Ryan Kurte
+
+use embedded_hal::digital::{InputPin as _, OutputPin as _};
+use embedded_hal::spi::SpiDevice;
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyTimeoutError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use crate::manager::{Filter, Manager};
+use crate::{
+    Cp2130, Device as _, Error, GpioLevel, GpioMode, InputPin, OutputPin, Spi, SpiClock, SpiConfig,
+    UsbOptions,
+};
+
+create_exception!(cp2130, Cp2130Error, PyException, "CP2130 driver error");
+
+impl From<Error> for PyErr {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Usb(rusb::Error::Timeout) => PyTimeoutError::new_err(e.to_string()),
+            Error::InvalidPin(_)
+            | Error::ForeignPin
+            | Error::InvalidBaud
+            | Error::InvalidConfig { .. } => PyValueError::new_err(e.to_string()),
+            _ => Cp2130Error::new_err(e.to_string()),
+        }
+    }
+}
+
+/// Open a CP2130, by serial number where provided, otherwise by index
+#[pyfunction]
+#[pyo3(signature = (serial=None, index=0))]
+fn open(py: Python<'_>, serial: Option<String>, index: usize) -> PyResult<PyDevice> {
+    let filter = match serial {
+        Some(s) => Filter::with_serial(&s),
+        None => Filter::default(),
+    };
+
+    let cp2130 = py.detach(|| {
+        let (device, descriptor) = Manager::device(filter, index)?;
+        Cp2130::new(device, descriptor, UsbOptions::default())
+    })?;
+
+    Ok(PyDevice { cp2130 })
+}
+
+/// CP2130 device
+#[pyclass(name = "Device")]
+struct PyDevice {
+    cp2130: Cp2130,
+}
+
+#[pymethods]
+impl PyDevice {
+    /// Device serial number
+    #[getter]
+    fn serial(&self) -> String {
+        self.cp2130.info().serial().to_string()
+    }
+
+    /// Fetch the CP2130 chip version
+    fn version(&self, py: Python<'_>) -> PyResult<u16> {
+        Ok(py.detach(|| self.cp2130.version())?)
+    }
+
+    /// Create an SPI channel, `clock` must be a supported CP2130 rate in Hz
+    #[pyo3(signature = (channel=0, clock=3_000_000, mode=0, cs_pin=None))]
+    fn spi(
+        &self,
+        py: Python<'_>,
+        channel: u8,
+        clock: u64,
+        mode: u8,
+        cs_pin: Option<u8>,
+    ) -> PyResult<PySpi> {
+        let spi_mode = match mode {
+            0 => embedded_hal::spi::MODE_0,
+            1 => embedded_hal::spi::MODE_1,
+            2 => embedded_hal::spi::MODE_2,
+            3 => embedded_hal::spi::MODE_3,
+            _ => return Err(PyValueError::new_err("SPI mode must be 0 to 3")),
+        };
+
+        let config = SpiConfig::builder()
+            .clock(SpiClock::from_frequency_exact(clock)?)
+            .spi_mode(spi_mode)
+            .build()?;
+
+        let spi = py.detach(|| self.cp2130.spi(channel, config, cs_pin))?;
+
+        Ok(PySpi { spi })
+    }
+
+    /// Create a GPIO output pin
+    #[pyo3(signature = (pin, high=false, open_drain=false))]
+    fn gpio_out(
+        &self,
+        py: Python<'_>,
+        pin: u8,
+        high: bool,
+        open_drain: bool,
+    ) -> PyResult<PyOutputPin> {
+        let mode = match open_drain {
+            true => GpioMode::OpenDrain,
+            false => GpioMode::PushPull,
+        };
+        let level = match high {
+            true => GpioLevel::High,
+            false => GpioLevel::Low,
+        };
+
+        let pin = py.detach(|| self.cp2130.gpio_out(pin, mode, level))?;
+
+        Ok(PyOutputPin { pin })
+    }
+
+    /// Create a GPIO input pin
+    fn gpio_in(&self, py: Python<'_>, pin: u8) -> PyResult<PyInputPin> {
+        let pin = py.detach(|| self.cp2130.gpio_in(pin))?;
+
+        Ok(PyInputPin { pin })
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Device(serial={:?})", self.cp2130.info().serial())
+    }
+}
+
+/// SPI channel, asserting the configured chip select for each operation
+#[pyclass(name = "Spi")]
+struct PySpi {
+    spi: Spi,
+}
+
+#[pymethods]
+impl PySpi {
+    /// Write data while reading, returning the bytes read
+    fn transfer<'py>(&mut self, py: Python<'py>, data: &[u8]) -> PyResult<Bound<'py, PyBytes>> {
+        let mut buff = vec![0u8; data.len()];
+        py.detach(|| self.spi.transfer(&mut buff, data))?;
+
+        Ok(PyBytes::new(py, &buff))
+    }
+
+    /// Write data, discarding bytes read
+    fn write(&mut self, py: Python<'_>, data: &[u8]) -> PyResult<()> {
+        Ok(py.detach(|| self.spi.write(data))?)
+    }
+
+    /// Read `len` bytes
+    fn read<'py>(&mut self, py: Python<'py>, len: usize) -> PyResult<Bound<'py, PyBytes>> {
+        let mut buff = vec![0u8; len];
+        py.detach(|| self.spi.read(&mut buff))?;
+
+        Ok(PyBytes::new(py, &buff))
+    }
+}
+
+/// GPIO output pin
+#[pyclass(name = "OutputPin")]
+struct PyOutputPin {
+    pin: OutputPin,
+}
+
+#[pymethods]
+impl PyOutputPin {
+    /// Set the pin level
+    fn set(&mut self, py: Python<'_>, high: bool) -> PyResult<()> {
+        Ok(py.detach(|| match high {
+            true => self.pin.set_high(),
+            false => self.pin.set_low(),
+        })?)
+    }
+
+    /// Read back the pin level
+    fn value(&mut self, py: Python<'_>) -> PyResult<bool> {
+        Ok(py.detach(|| self.pin.is_high())?)
+    }
+}
+
+/// GPIO input pin
+#[pyclass(name = "InputPin")]
+struct PyInputPin {
+    pin: InputPin,
+}
+
+#[pymethods]
+impl PyInputPin {
+    /// Read the pin level
+    fn value(&mut self, py: Python<'_>) -> PyResult<bool> {
+        Ok(py.detach(|| self.pin.is_high())?)
+    }
+}
+
+/// CP2130 USB-SPI bridge driver
+#[pymodule]
+fn cp2130(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(open, m)?)?;
+    m.add_class::<PyDevice>()?;
+    m.add_class::<PySpi>()?;
+    m.add_class::<PyOutputPin>()?;
+    m.add_class::<PyInputPin>()?;
+    m.add("Cp2130Error", m.py().get_type::<Cp2130Error>())?;
+
+    Ok(())
+}
@@ -0,0 +1,73 @@
+//! CP2130 Driver Raw Bulk Transfers
+//!
+//! [`Cp2130::raw_bulk`] exposes the bulk endpoints without transfer command framing,
+//! for experimenting with custom transfer headers (such as ReadWithRTR behaviour) while
+//! reusing device discovery and endpoint handling. Raw transfers bypass the SPI
+//! statistics and timeout scaling, and the caller is responsible for leaving the device
+//! in a consistent state (ie. reading any response data requested by a header).
+//!
+//! Copyright 2019 Ryan Kurte
+
+use std::time::Duration;
+
+use rusb::UsbContext;
+
+use crate::device::SpiBus;
+use crate::{Cp2130, Error};
+
+/// Raw bulk endpoint access, see [`Cp2130::raw_bulk`]
+pub struct RawBulk<'a> {
+    bus: SpiBus<'a>,
+}
+
+impl RawBulk<'_> {
+    /// Write `buff` to the bulk OUT endpoint, returning the number of bytes written
+    pub fn bulk_write_raw(&mut self, buff: &[u8], timeout: Duration) -> Result<usize, Error> {
+        self.bus.bulk_out_raw(buff, timeout)
+    }
+
+    /// Read from the bulk IN endpoint into `buff`, returning the number of bytes read
+    pub fn bulk_read_raw(&mut self, buff: &mut [u8], timeout: Duration) -> Result<usize, Error> {
+        self.bus.bulk_in_raw(buff, timeout)
+    }
+
+    /// Fetch the bulk endpoint packet size
+    pub fn max_packet_size(&self) -> usize {
+        self.bus.max_packet_size()
+    }
+}
+
+impl<T: UsbContext> Cp2130<T> {
+    /// Run raw bulk transfers while holding the SPI bus lock
+    ///
+    /// SPI transfers from other handles are held off until `f` returns, so a custom
+    /// header and its response are not interleaved with other traffic.
+    ///
+    /// ```no_run
+    /// # use std::time::Duration;
+    /// # use driver_cp2130::prelude::*;
+    /// # use driver_cp2130::protocol::{TransferCommand, TransferHeader};
+    /// # fn f(cp2130: &Cp2130) -> Result<(), Cp2130Error> {
+    /// let header = TransferHeader {
+    ///     command: TransferCommand::ReadWithRTR,
+    ///     len: 4,
+    /// };
+    /// let mut buff = [0u8; 4];
+    ///
+    /// cp2130.raw_bulk(|raw| {
+    ///     raw.bulk_write_raw(&header.encode(), Duration::from_millis(200))?;
+    ///     raw.bulk_read_raw(&mut buff, Duration::from_secs(5))
+    /// })?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn raw_bulk<R>(
+        &self,
+        f: impl FnOnce(&mut RawBulk) -> Result<R, Error>,
+    ) -> Result<R, Error> {
+        let spi = self.inner.lock().unwrap().spi_transfers();
+        let mut raw = RawBulk { bus: spi.lock() };
+
+        f(&mut raw)
+    }
+}
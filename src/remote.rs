@@ -0,0 +1,375 @@
+//! CP2130 Driver Remote Access
+//!
+//! [`Server`] exposes a [`Device`] over TCP and [`RemoteCp2130`] implements [`Device`]
+//! over a connection to a server, so a bridge attached to a headless host (such as a
+//! Raspberry Pi in the lab) can be driven from a workstation.
+//!
+//! Each message is a big-endian `u32` length followed by the message body. Requests
+//! start with an operation code followed by its arguments, responses start with a
+//! status byte (0 for success followed by any result, otherwise followed by a UTF-8
+//! error message). Each operation is a single round trip.
+//!
+//! There is no authentication or encryption, servers should only be exposed on
+//! trusted networks.
+//!
+//! Copyright 2019 Ryan Kurte
+
+use std::io::{Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use byteorder::{ReadBytesExt, WriteBytesExt, BE};
+use log::{debug, info, warn};
+
+use crate::{Device, Error, GpioAccess, GpioLevel, GpioLevels, GpioMode, SpiAccess};
+
+/// Default port for remote CP2130 servers
+pub const DEFAULT_PORT: u16 = 2130;
+
+/// Maximum message length, bounding allocations for malformed or hostile peers
+pub const MAX_MESSAGE_LEN: usize = 16 * 1024 * 1024;
+
+/// Default client response timeout
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+const STATUS_OK: u8 = 0x00;
+const STATUS_ERROR: u8 = 0x01;
+
+/// Remote operations, mapping to [`Device`] methods
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Op {
+    Version = 0x01,
+    SpiRead = 0x02,
+    SpiWrite = 0x03,
+    SpiWriteRead = 0x04,
+    SetGpioModeLevel = 0x05,
+    GetGpioValues = 0x06,
+    GetGpioLevel = 0x07,
+}
+
+impl TryFrom<u8> for Op {
+    type Error = Error;
+
+    fn try_from(v: u8) -> Result<Self, Self::Error> {
+        match v {
+            0x01 => Ok(Self::Version),
+            0x02 => Ok(Self::SpiRead),
+            0x03 => Ok(Self::SpiWrite),
+            0x04 => Ok(Self::SpiWriteRead),
+            0x05 => Ok(Self::SetGpioModeLevel),
+            0x06 => Ok(Self::GetGpioValues),
+            0x07 => Ok(Self::GetGpioLevel),
+            _ => Err(Error::Remote(format!("unrecognised operation 0x{:02x}", v))),
+        }
+    }
+}
+
+fn malformed(op: Op) -> Error {
+    Error::Remote(format!("malformed {:?} request", op))
+}
+
+fn closed() -> Error {
+    Error::Remote("connection closed following a previous error".to_string())
+}
+
+/// Write a length-prefixed message
+fn write_message(w: &mut impl Write, body: &[u8]) -> Result<(), Error> {
+    w.write_u32::<BE>(body.len() as u32)?;
+    w.write_all(body)?;
+    w.flush()?;
+    Ok(())
+}
+
+/// Read a length-prefixed message
+fn read_message(r: &mut impl Read) -> Result<Vec<u8>, Error> {
+    let len = r.read_u32::<BE>()? as usize;
+    if len > MAX_MESSAGE_LEN {
+        return Err(Error::Remote(format!(
+            "message length {} exceeds limit",
+            len
+        )));
+    }
+
+    let mut body = vec![0u8; len];
+    r.read_exact(&mut body)?;
+
+    Ok(body)
+}
+
+/// Check a message body of `len` bytes is within [`MAX_MESSAGE_LEN`]
+fn check_len(len: usize) -> Result<(), Error> {
+    match len <= MAX_MESSAGE_LEN {
+        true => Ok(()),
+        false => Err(Error::Remote(format!(
+            "message length {} exceeds limit",
+            len
+        ))),
+    }
+}
+
+/// Read a big-endian `u32` length argument
+fn length_arg(op: Op, args: &[u8]) -> Result<(usize, &[u8]), Error> {
+    if args.len() < 4 {
+        return Err(malformed(op));
+    }
+
+    let (len, rest) = args.split_at(4);
+    let len = u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize;
+    if len > MAX_MESSAGE_LEN {
+        return Err(malformed(op));
+    }
+
+    Ok((len, rest))
+}
+
+/// TCP server exposing a device to remote clients
+pub struct Server<D: ?Sized> {
+    device: Arc<D>,
+    listener: TcpListener,
+}
+
+impl<D: Device + ?Sized + 'static> Server<D> {
+    /// Bind a server for the provided device to a local address
+    pub fn bind(device: Arc<D>, addr: impl ToSocketAddrs) -> Result<Self, Error> {
+        let listener = TcpListener::bind(addr)?;
+
+        Ok(Self { device, listener })
+    }
+
+    /// Fetch the address the server is listening on
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Accept and serve clients, blocking indefinitely
+    ///
+    /// Each client is served on a separate thread, so operations from multiple clients
+    /// are interleaved at operation granularity.
+    pub fn run(&self) -> Result<(), Error> {
+        for stream in self.listener.incoming() {
+            let stream = match stream {
+                Ok(s) => s,
+                Err(e) => {
+                    warn!("Remote accept failed: {}", e);
+                    continue;
+                }
+            };
+
+            let device = self.device.clone();
+            std::thread::Builder::new()
+                .name("cp2130-remote".to_string())
+                .spawn(move || {
+                    let peer = stream.peer_addr().ok();
+                    info!("Remote client connected: {:?}", peer);
+
+                    if let Err(e) = serve(device.as_ref(), stream) {
+                        debug!("Remote client {:?} error: {}", peer, e);
+                    }
+
+                    info!("Remote client disconnected: {:?}", peer);
+                })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Serve requests from a single client until the connection closes
+fn serve<D: Device + ?Sized>(device: &D, mut stream: TcpStream) -> Result<(), Error> {
+    stream.set_nodelay(true)?;
+
+    loop {
+        let request = match read_message(&mut stream) {
+            Ok(r) => r,
+            Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        let response = match handle(device, &request) {
+            Ok(data) => [&[STATUS_OK], data.as_slice()].concat(),
+            Err(e) => [&[STATUS_ERROR], e.to_string().as_bytes()].concat(),
+        };
+
+        write_message(&mut stream, &response)?;
+    }
+}
+
+/// Execute a single request against the device
+fn handle<D: Device + ?Sized>(device: &D, request: &[u8]) -> Result<Vec<u8>, Error> {
+    let (op, args) = match request.split_first() {
+        Some((op, args)) => (Op::try_from(*op)?, args),
+        None => return Err(Error::Remote("empty request".to_string())),
+    };
+
+    match op {
+        Op::Version => Ok(device.version()?.to_be_bytes().to_vec()),
+        Op::SpiRead => {
+            let (len, _) = length_arg(op, args)?;
+            let mut buff = vec![0u8; len];
+            let n = device.spi_read(&mut buff)?;
+            buff.truncate(n);
+            Ok(buff)
+        }
+        Op::SpiWrite => {
+            device.spi_write(args)?;
+            Ok(vec![])
+        }
+        Op::SpiWriteRead => {
+            let (len, data) = length_arg(op, args)?;
+            let mut buff = vec![0u8; len];
+            let n = device.spi_write_read(data, &mut buff)?;
+            buff.truncate(n);
+            Ok(buff)
+        }
+        Op::SetGpioModeLevel => {
+            let (pin, mode, level) = match args {
+                [pin, mode, level] => (*pin, *mode, *level),
+                _ => return Err(malformed(op)),
+            };
+
+            let mode = GpioMode::try_from(mode).map_err(|_| malformed(op))?;
+            let level = match level {
+                0x00 => GpioLevel::Low,
+                _ => GpioLevel::High,
+            };
+
+            device.set_gpio_mode_level(pin, mode, level)?;
+            Ok(vec![])
+        }
+        Op::GetGpioValues => Ok(device.get_gpio_values()?.bits().to_be_bytes().to_vec()),
+        Op::GetGpioLevel => match args {
+            [pin] => Ok(vec![device.get_gpio_level(*pin)? as u8]),
+            _ => Err(malformed(op)),
+        },
+    }
+}
+
+/// Client for a remote CP2130 server
+///
+/// Errors from the remote device are returned as [`Error::Remote`]. The connection is
+/// closed following any I/O error (including response timeouts), as a late response
+/// would otherwise be read in place of the next, and later calls fail.
+pub struct RemoteCp2130 {
+    stream: Mutex<Option<TcpStream>>,
+}
+
+impl RemoteCp2130 {
+    /// Connect to a remote server
+    pub fn connect(addr: impl ToSocketAddrs) -> Result<Self, Error> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(DEFAULT_TIMEOUT))?;
+
+        Ok(Self {
+            stream: Mutex::new(Some(stream)),
+        })
+    }
+
+    /// Set the timeout for responses from the server, `None` waits indefinitely
+    ///
+    /// This should allow for the duration of the longest SPI transfer in use.
+    pub fn set_timeout(&self, timeout: Option<Duration>) -> Result<(), Error> {
+        match self.stream.lock().unwrap().as_ref() {
+            Some(s) => Ok(s.set_read_timeout(timeout)?),
+            None => Err(closed()),
+        }
+    }
+
+    /// Issue a request and wait for the response
+    fn call(&self, op: Op, args: &[&[u8]]) -> Result<Vec<u8>, Error> {
+        let request = [&[op as u8][..], &args.concat()].concat();
+        check_len(request.len())?;
+
+        let mut guard = self.stream.lock().unwrap();
+        let stream = guard.as_mut().ok_or_else(closed)?;
+
+        let response = match write_message(stream, &request).and_then(|_| read_message(stream)) {
+            Ok(r) => r,
+            Err(e) => {
+                // The stream is no longer in step with the server, drop the connection
+                warn!("Remote connection closed: {}", e);
+                let _ = stream.shutdown(Shutdown::Both);
+                *guard = None;
+                return Err(e);
+            }
+        };
+
+        match response.split_first() {
+            Some((&STATUS_OK, data)) => Ok(data.to_vec()),
+            Some((_, message)) => Err(Error::Remote(String::from_utf8_lossy(message).into_owned())),
+            None => Err(Error::Remote("empty response".to_string())),
+        }
+    }
+}
+
+impl SpiAccess for RemoteCp2130 {
+    fn spi_read(&self, buff: &mut [u8]) -> Result<usize, Error> {
+        // Responses include a status byte
+        check_len(buff.len() + 1)?;
+
+        let data = self.call(Op::SpiRead, &[&(buff.len() as u32).to_be_bytes()])?;
+
+        let n = data.len().min(buff.len());
+        buff[..n].copy_from_slice(&data[..n]);
+
+        Ok(n)
+    }
+
+    fn spi_write(&self, buff: &[u8]) -> Result<(), Error> {
+        self.call(Op::SpiWrite, &[buff])?;
+        Ok(())
+    }
+
+    /// Remote streams are buffered in memory and sent as a single write
+    fn spi_write_stream(&self, reader: &mut dyn Read, len: usize) -> Result<(), Error> {
+        let mut buff = vec![0u8; len];
+        reader.read_exact(&mut buff)?;
+
+        self.spi_write(&buff)
+    }
+
+    fn spi_write_read(&self, buff_out: &[u8], buff_in: &mut [u8]) -> Result<usize, Error> {
+        check_len(buff_in.len() + 1)?;
+
+        let data = self.call(
+            Op::SpiWriteRead,
+            &[&(buff_in.len() as u32).to_be_bytes(), buff_out],
+        )?;
+
+        let n = data.len().min(buff_in.len());
+        buff_in[..n].copy_from_slice(&data[..n]);
+
+        Ok(n)
+    }
+}
+
+impl GpioAccess for RemoteCp2130 {
+    fn set_gpio_mode_level(&self, pin: u8, mode: GpioMode, level: GpioLevel) -> Result<(), Error> {
+        self.call(Op::SetGpioModeLevel, &[&[pin, mode as u8, level as u8]])?;
+        Ok(())
+    }
+
+    fn get_gpio_values(&self) -> Result<GpioLevels, Error> {
+        match self.call(Op::GetGpioValues, &[])?.as_slice() {
+            [a, b] => Ok(GpioLevels::from_bits_truncate(u16::from_be_bytes([*a, *b]))),
+            _ => Err(Error::Remote("malformed GPIO values response".to_string())),
+        }
+    }
+
+    fn get_gpio_level(&self, pin: u8) -> Result<bool, Error> {
+        match self.call(Op::GetGpioLevel, &[&[pin]])?.as_slice() {
+            [v] => Ok(*v != 0),
+            _ => Err(Error::Remote("malformed GPIO level response".to_string())),
+        }
+    }
+}
+
+impl Device for RemoteCp2130 {
+    fn version(&self) -> Result<u16, Error> {
+        match self.call(Op::Version, &[])?.as_slice() {
+            [a, b] => Ok(u16::from_be_bytes([*a, *b])),
+            _ => Err(Error::Remote("malformed version response".to_string())),
+        }
+    }
+}
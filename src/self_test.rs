@@ -0,0 +1,176 @@
+//! CP2130 Driver Self Test
+//!
+//! Loopback tests for production / bring-up, requiring MOSI to be connected to MISO
+//! and the configured GPIO write pin to be connected to the read pin.
+//!
+//! Copyright 2019 Ryan Kurte
+
+use log::{debug, error};
+use rusb::UsbContext;
+
+use crate::{Cp2130, Error, GpioAccess, GpioLevel, GpioMode, SpiAccess};
+
+/// Self test configuration
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "clap", derive(clap::Parser))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct SelfTestConfig {
+    #[cfg_attr(feature = "clap", clap(long, default_value = "0"))]
+    /// Pin for GPIO write
+    pub write_pin: u8,
+
+    #[cfg_attr(feature = "clap", clap(long, default_value = "1"))]
+    /// Pin for GPIO read
+    pub read_pin: u8,
+
+    #[cfg_attr(feature = "clap", clap(long, default_value = "34"))]
+    /// Length for short SPI tests
+    pub short_len: usize,
+
+    #[cfg_attr(feature = "clap", clap(long, default_value = "300"))]
+    /// Length for long SPI tests
+    pub long_len: usize,
+}
+
+impl Default for SelfTestConfig {
+    fn default() -> Self {
+        Self {
+            write_pin: 0,
+            read_pin: 1,
+            short_len: 34,
+            long_len: 300,
+        }
+    }
+}
+
+/// Individual self test checks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum SelfTestCheck {
+    /// GPIO read pin follows write pin when driven low
+    GpioLow,
+    /// GPIO read pin follows write pin when driven high
+    GpioHigh,
+    SpiWriteShort,
+    SpiWriteLong,
+    /// SPI transfer data is looped back from MOSI to MISO
+    SpiTransferShort,
+    SpiTransferLong,
+}
+
+/// Result of a single self test check
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SelfTestResult {
+    pub check: SelfTestCheck,
+    pub passed: bool,
+    /// Failure description
+    pub detail: Option<String>,
+}
+
+/// Self test report, containing results for each check in the order executed
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SelfTestReport {
+    pub results: Vec<SelfTestResult>,
+}
+
+impl SelfTestReport {
+    /// Check whether all self test checks passed
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(|r| r.passed)
+    }
+
+    /// Fetch the failed checks
+    pub fn failures(&self) -> impl Iterator<Item = &SelfTestResult> {
+        self.results.iter().filter(|r| !r.passed)
+    }
+
+    fn record(&mut self, check: SelfTestCheck, res: Result<Result<(), String>, Error>) {
+        let detail = match res {
+            Ok(Ok(())) => None,
+            Ok(Err(e)) => Some(e),
+            Err(e) => Some(e.to_string()),
+        };
+
+        match &detail {
+            None => debug!("Self test {:?} okay", check),
+            Some(d) => error!("Self test {:?} failed: {}", check, d),
+        }
+
+        self.results.push(SelfTestResult {
+            check,
+            passed: detail.is_none(),
+            detail,
+        });
+    }
+}
+
+/// Generate a repeatable test pattern (xorshift)
+fn pattern(len: usize, mut seed: u32) -> Vec<u8> {
+    (0..len)
+        .map(|_| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed as u8
+        })
+        .collect()
+}
+
+impl<T: UsbContext> Cp2130<T> {
+    /// Run loopback self tests, returning the result of each check
+    pub fn self_test(&self, config: SelfTestConfig) -> SelfTestReport {
+        let mut report = SelfTestReport { results: vec![] };
+
+        // GPIO cross-wire checks
+        let gpio = |level: GpioLevel| -> Result<Result<(), String>, Error> {
+            self.set_gpio_mode_level(config.read_pin, GpioMode::Input, GpioLevel::Low)?;
+            self.set_gpio_mode_level(config.write_pin, GpioMode::PushPull, level)?;
+
+            let v = self.get_gpio_level(config.read_pin)?;
+            match v == (level == GpioLevel::High) {
+                true => Ok(Ok(())),
+                false => Ok(Err(format!(
+                    "read pin {} did not follow {}",
+                    config.read_pin, level
+                ))),
+            }
+        };
+
+        report.record(SelfTestCheck::GpioLow, gpio(GpioLevel::Low));
+        report.record(SelfTestCheck::GpioHigh, gpio(GpioLevel::High));
+
+        // SPI writes
+        let write = |len: usize| -> Result<Result<(), String>, Error> {
+            self.spi_write(&pattern(len, 0x1234_5678))?;
+            Ok(Ok(()))
+        };
+
+        report.record(SelfTestCheck::SpiWriteShort, write(config.short_len));
+        report.record(SelfTestCheck::SpiWriteLong, write(config.long_len));
+
+        // SPI loopback transfers
+        let transfer = |len: usize| -> Result<Result<(), String>, Error> {
+            let data = pattern(len, 0x8765_4321);
+            let mut buff = vec![0u8; len];
+
+            self.spi_write_read(&data, &mut buff)?;
+
+            match data.iter().zip(buff.iter()).position(|(a, b)| a != b) {
+                None => Ok(Ok(())),
+                Some(i) => Ok(Err(format!(
+                    "loopback mismatch at byte {} (wrote 0x{:02x}, read 0x{:02x})",
+                    i, data[i], buff[i]
+                ))),
+            }
+        };
+
+        report.record(SelfTestCheck::SpiTransferShort, transfer(config.short_len));
+        report.record(SelfTestCheck::SpiTransferLong, transfer(config.long_len));
+
+        report
+    }
+}
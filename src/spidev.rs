@@ -0,0 +1,642 @@
+//! CP2130 Driver spidev Bridge
+//!
+//! [`SpidevBridge`] implements the Linux spidev interface (read / write and the
+//! `SPI_IOC_*` ioctls) over a CP2130 SPI channel. On Linux the bridge can be exposed
+//! as a character device via CUSE (character devices in userspace), so existing spidev
+//! tools (such as flashrom or spi-tools) can use the CP2130 unmodified:
+//!
+//! ```text
+//! sudo cp2130-util spidev --name spidev9.0 --channel 0 --cs-pin 0
+//! flashrom -p linux_spi:dev=/dev/spidev9.0
+//! ```
+//!
+//! Each `SPI_IOC_MESSAGE` is executed as a single CP2130 transaction with chip select
+//! asserted throughout, per-transfer speed and `cs_change` are not supported and per
+//! transfer delays are applied on the host.
+//!
+//! Copyright 2019 Ryan Kurte
+
+use embedded_hal::spi::{Operation, SpiDevice, MODE_0, MODE_1, MODE_2, MODE_3};
+use log::{debug, trace, warn};
+use rusb::{GlobalContext, UsbContext};
+
+use crate::{Cp2130, Error, Spi, SpiClock, SpiConfig};
+
+/// spidev ioctl type
+const SPI_IOC_MAGIC: u8 = b'k';
+
+/// spidev ioctl numbers
+const SPI_IOC_NR_MESSAGE: u8 = 0;
+const SPI_IOC_NR_MODE: u8 = 1;
+const SPI_IOC_NR_LSB_FIRST: u8 = 2;
+const SPI_IOC_NR_BITS_PER_WORD: u8 = 3;
+const SPI_IOC_NR_MAX_SPEED_HZ: u8 = 4;
+const SPI_IOC_NR_MODE32: u8 = 5;
+
+/// ioctl direction bits (asm-generic encoding)
+const IOC_WRITE: u32 = 1;
+const IOC_READ: u32 = 2;
+
+/// Argument size for fixed-size spidev ioctls, `None` for messages and unknown commands
+fn arg_size(nr: u8) -> Option<usize> {
+    match nr {
+        SPI_IOC_NR_MODE | SPI_IOC_NR_LSB_FIRST | SPI_IOC_NR_BITS_PER_WORD => Some(1),
+        SPI_IOC_NR_MAX_SPEED_HZ | SPI_IOC_NR_MODE32 => Some(4),
+        _ => None,
+    }
+}
+
+/// spidev clock phase and polarity mode bits
+const SPI_MODE_MASK: u32 = 0x03;
+/// spidev mode bit disabling chip select
+const SPI_NO_CS: u32 = 0x40;
+
+/// Size of `struct spi_ioc_transfer`
+pub const SPI_IOC_TRANSFER_LEN: usize = 32;
+
+/// Maximum read / write and message size, bounding SPI transfer lengths
+pub const MAX_TRANSFER: usize = 64 * 1024;
+
+const EINVAL: i32 = 22;
+const EIO: i32 = 5;
+const ENOTTY: i32 = 25;
+
+/// Single transfer from an `SPI_IOC_MESSAGE` ioctl (`struct spi_ioc_transfer`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SpiIocTransfer {
+    /// User address of the transmit buffer, 0 to transmit zeros
+    pub tx_buf: u64,
+    /// User address of the receive buffer, 0 to discard received data
+    pub rx_buf: u64,
+    pub len: u32,
+    pub speed_hz: u32,
+    pub delay_usecs: u16,
+    pub bits_per_word: u8,
+    pub cs_change: u8,
+}
+
+impl SpiIocTransfer {
+    /// Decode a transfer from its (native endian) kernel representation
+    pub fn decode(b: &[u8]) -> Self {
+        let u64_at = |i: usize| u64::from_ne_bytes(b[i..i + 8].try_into().unwrap());
+        let u32_at = |i: usize| u32::from_ne_bytes(b[i..i + 4].try_into().unwrap());
+
+        Self {
+            tx_buf: u64_at(0),
+            rx_buf: u64_at(8),
+            len: u32_at(16),
+            speed_hz: u32_at(20),
+            delay_usecs: u16::from_ne_bytes([b[24], b[25]]),
+            bits_per_word: b[26],
+            cs_change: b[27],
+        }
+    }
+
+    /// Encode a transfer to its (native endian) kernel representation
+    pub fn encode(&self) -> [u8; SPI_IOC_TRANSFER_LEN] {
+        let mut b = [0u8; SPI_IOC_TRANSFER_LEN];
+        b[0..8].copy_from_slice(&self.tx_buf.to_ne_bytes());
+        b[8..16].copy_from_slice(&self.rx_buf.to_ne_bytes());
+        b[16..20].copy_from_slice(&self.len.to_ne_bytes());
+        b[20..24].copy_from_slice(&self.speed_hz.to_ne_bytes());
+        b[24..26].copy_from_slice(&self.delay_usecs.to_ne_bytes());
+        b[26] = self.bits_per_word;
+        b[27] = self.cs_change;
+        b
+    }
+}
+
+/// Encode a spidev ioctl request number
+pub fn spi_ioc(dir: u32, nr: u8, size: usize) -> u32 {
+    (dir << 30) | ((size as u32 & 0x3FFF) << 16) | ((SPI_IOC_MAGIC as u32) << 8) | nr as u32
+}
+
+/// `SPI_IOC_MESSAGE(n)` request number
+pub fn spi_ioc_message(n: usize) -> u32 {
+    spi_ioc(IOC_WRITE, SPI_IOC_NR_MESSAGE, n * SPI_IOC_TRANSFER_LEN)
+}
+
+/// Result of handling an ioctl
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IoctlResult {
+    /// Completed with the provided return value and output data
+    Done { result: i32, out: Vec<u8> },
+    /// User memory is required, retry with the provided (address, length) input and
+    /// output regions
+    Retry {
+        input: Vec<(u64, u64)>,
+        output: Vec<(u64, u64)>,
+    },
+    /// Failed with the provided errno
+    Error(i32),
+}
+
+/// spidev interface over a CP2130 SPI channel
+pub struct SpidevBridge<'a, T: UsbContext = GlobalContext> {
+    cp2130: &'a Cp2130<T>,
+    channel: u8,
+    cs_pin: Option<u8>,
+    config: SpiConfig,
+    mode: u32,
+    spi: Spi<T>,
+}
+
+impl<'a, T: UsbContext> SpidevBridge<'a, T> {
+    /// Create a bridge for an SPI channel, using `cs_pin` as chip select where provided
+    pub fn new(
+        cp2130: &'a Cp2130<T>,
+        channel: u8,
+        cs_pin: Option<u8>,
+        config: SpiConfig,
+    ) -> Result<Self, Error> {
+        let spi = cp2130.spi(channel, config.clone(), cs_pin)?;
+
+        let mode = match config.spi_mode {
+            MODE_0 => 0,
+            MODE_1 => 1,
+            MODE_2 => 2,
+            _ => 3,
+        };
+
+        Ok(Self {
+            cp2130,
+            channel,
+            cs_pin,
+            config,
+            mode,
+            spi,
+        })
+    }
+
+    /// Fetch the spidev mode flags
+    pub fn mode(&self) -> u32 {
+        self.mode
+    }
+
+    /// Set the spidev mode flags, only clock phase / polarity and `SPI_NO_CS` are supported
+    pub fn set_mode(&mut self, mode: u32) -> Result<(), Error> {
+        if mode & !(SPI_MODE_MASK | SPI_NO_CS) != 0 {
+            return Err(Error::InvalidConfig {
+                field: "mode",
+                reason: "unsupported spidev mode flags",
+            });
+        }
+
+        if mode & SPI_NO_CS != 0 && self.cs_pin.is_some() {
+            return Err(Error::InvalidConfig {
+                field: "mode",
+                reason: "SPI_NO_CS is unavailable where a CS pin is configured",
+            });
+        }
+
+        self.config.spi_mode = match mode & SPI_MODE_MASK {
+            0 => MODE_0,
+            1 => MODE_1,
+            2 => MODE_2,
+            _ => MODE_3,
+        };
+        self.mode = mode;
+
+        self.reconfigure()
+    }
+
+    /// Fetch the maximum SPI clock in Hz
+    pub fn max_speed_hz(&self) -> u32 {
+        self.config.clock.freq() as u32
+    }
+
+    /// Set the maximum SPI clock, using the fastest CP2130 rate not exceeding `hz`
+    pub fn set_max_speed_hz(&mut self, hz: u32) -> Result<(), Error> {
+        self.config.clock = SpiClock::ALL
+            .into_iter()
+            .find(|c| c.freq() <= hz as u64)
+            .unwrap_or(SpiClock::Clock93_75KHz);
+
+        debug!("spidev max speed {} Hz ({:?})", hz, self.config.clock);
+
+        self.reconfigure()
+    }
+
+    fn reconfigure(&mut self) -> Result<(), Error> {
+        self.spi = self
+            .cp2130
+            .spi(self.channel, self.config.clone(), self.cs_pin)?;
+        Ok(())
+    }
+
+    /// Half-duplex read, as for `read(2)` on a spidev device
+    pub fn read(&mut self, buff: &mut [u8]) -> Result<(), Error> {
+        self.spi.read(buff)
+    }
+
+    /// Half-duplex write, as for `write(2)` on a spidev device
+    pub fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.spi.write(data)
+    }
+
+    /// Execute the transfers of an `SPI_IOC_MESSAGE` as a single transaction
+    ///
+    /// `tx` holds the transmit data for each transfer (empty to transmit zeros),
+    /// returning the received data for each transfer.
+    pub fn message(
+        &mut self,
+        transfers: &[SpiIocTransfer],
+        tx: &[&[u8]],
+    ) -> Result<Vec<Vec<u8>>, Error> {
+        // Bound the buffers allocated from caller supplied lengths
+        if message_len(transfers) > MAX_TRANSFER {
+            return Err(Error::InvalidConfig {
+                field: "len",
+                reason: "message exceeds the maximum transfer size",
+            });
+        }
+
+        let mut rx: Vec<Vec<u8>> = transfers
+            .iter()
+            .map(|t| vec![0u8; t.len as usize])
+            .collect();
+        let zeros: Vec<Vec<u8>> = transfers
+            .iter()
+            .map(|t| vec![0u8; t.len as usize])
+            .collect();
+
+        for t in transfers {
+            if t.cs_change != 0 {
+                trace!("spidev cs_change unsupported, ignoring");
+            }
+            if t.bits_per_word != 0 && t.bits_per_word != 8 {
+                return Err(Error::InvalidConfig {
+                    field: "bits_per_word",
+                    reason: "only 8 bit words are supported",
+                });
+            }
+        }
+
+        let mut ops = vec![];
+        for (i, r) in rx.iter_mut().enumerate() {
+            let w = match tx.get(i) {
+                Some(w) if !w.is_empty() => *w,
+                _ => zeros[i].as_slice(),
+            };
+            ops.push(Operation::Transfer(r, w));
+
+            if transfers[i].delay_usecs > 0 {
+                ops.push(Operation::DelayNs(transfers[i].delay_usecs as u32 * 1000));
+            }
+        }
+
+        self.spi.transaction(&mut ops)?;
+
+        Ok(rx)
+    }
+
+    /// Handle a spidev ioctl
+    ///
+    /// `data` holds the input regions requested by the last [`IoctlResult::Retry`]
+    /// (concatenated in order), and `out_size` the total length of the output regions.
+    /// Initial calls have no data, and will request the ioctl argument (and for messages,
+    /// the transmit and receive buffers) via retry.
+    pub fn ioctl(&mut self, cmd: u32, arg: u64, data: &[u8], out_size: usize) -> IoctlResult {
+        let dir = cmd >> 30;
+        let size = ((cmd >> 16) & 0x3FFF) as usize;
+        let nr = cmd as u8;
+
+        if (cmd >> 8) as u8 != SPI_IOC_MAGIC {
+            return IoctlResult::Error(ENOTTY);
+        }
+
+        // Reject malformed commands before the argument is fetched or decoded
+        match arg_size(nr) {
+            Some(n) if n != size => return IoctlResult::Error(EINVAL),
+            None if nr != SPI_IOC_NR_MESSAGE => return IoctlResult::Error(ENOTTY),
+            _ => (),
+        }
+
+        // Fetch the ioctl argument
+        if dir == IOC_WRITE && data.len() < size {
+            return IoctlResult::Retry {
+                input: vec![(arg, size as u64)],
+                output: vec![],
+            };
+        }
+        if dir == IOC_READ && out_size < size {
+            return IoctlResult::Retry {
+                input: vec![],
+                output: vec![(arg, size as u64)],
+            };
+        }
+
+        let done = |v: &[u8]| IoctlResult::Done {
+            result: 0,
+            out: v.to_vec(),
+        };
+
+        let res = match (dir, nr) {
+            (IOC_WRITE, SPI_IOC_NR_MESSAGE) => {
+                return self.ioctl_message(arg, size, data, out_size)
+            }
+            (IOC_READ, SPI_IOC_NR_MODE) => Ok(done(&[self.mode as u8])),
+            (IOC_READ, SPI_IOC_NR_MODE32) => Ok(done(&self.mode.to_ne_bytes())),
+            (IOC_READ, SPI_IOC_NR_LSB_FIRST) => Ok(done(&[0])),
+            (IOC_READ, SPI_IOC_NR_BITS_PER_WORD) => Ok(done(&[8])),
+            (IOC_READ, SPI_IOC_NR_MAX_SPEED_HZ) => Ok(done(&self.max_speed_hz().to_ne_bytes())),
+            (IOC_WRITE, SPI_IOC_NR_MODE) => self.set_mode(data[0] as u32).map(|_| done(&[])),
+            (IOC_WRITE, SPI_IOC_NR_MODE32) => self
+                .set_mode(u32::from_ne_bytes(data[..4].try_into().unwrap()))
+                .map(|_| done(&[])),
+            (IOC_WRITE, SPI_IOC_NR_LSB_FIRST) if data[0] == 0 => Ok(done(&[])),
+            (IOC_WRITE, SPI_IOC_NR_BITS_PER_WORD) if data[0] == 0 || data[0] == 8 => Ok(done(&[])),
+            (IOC_WRITE, SPI_IOC_NR_MAX_SPEED_HZ) => self
+                .set_max_speed_hz(u32::from_ne_bytes(data[..4].try_into().unwrap()))
+                .map(|_| done(&[])),
+            (IOC_WRITE, SPI_IOC_NR_LSB_FIRST) | (IOC_WRITE, SPI_IOC_NR_BITS_PER_WORD) => {
+                return IoctlResult::Error(EINVAL)
+            }
+            _ => return IoctlResult::Error(ENOTTY),
+        };
+
+        res.unwrap_or_else(|e| IoctlResult::Error(errno(&e)))
+    }
+
+    /// Handle `SPI_IOC_MESSAGE`, where `data` begins with the transfer array
+    fn ioctl_message(
+        &mut self,
+        arg: u64,
+        size: usize,
+        data: &[u8],
+        out_size: usize,
+    ) -> IoctlResult {
+        if !size.is_multiple_of(SPI_IOC_TRANSFER_LEN) {
+            return IoctlResult::Error(EINVAL);
+        }
+
+        let transfers: Vec<_> = data[..size]
+            .chunks(SPI_IOC_TRANSFER_LEN)
+            .map(SpiIocTransfer::decode)
+            .collect();
+
+        // Reject oversized messages before requesting their buffers
+        if message_len(&transfers) > MAX_TRANSFER {
+            return IoctlResult::Error(EINVAL);
+        }
+
+        let tx_len: usize = transfers
+            .iter()
+            .filter(|t| t.tx_buf != 0)
+            .map(|t| t.len as usize)
+            .sum();
+        let rx_len: usize = transfers
+            .iter()
+            .filter(|t| t.rx_buf != 0)
+            .map(|t| t.len as usize)
+            .sum();
+
+        // Fetch transmit buffers and map receive buffers
+        if data.len() < size + tx_len || out_size < rx_len {
+            let mut input = vec![(arg, size as u64)];
+            input.extend(
+                transfers
+                    .iter()
+                    .filter(|t| t.tx_buf != 0 && t.len > 0)
+                    .map(|t| (t.tx_buf, t.len as u64)),
+            );
+            let output = transfers
+                .iter()
+                .filter(|t| t.rx_buf != 0 && t.len > 0)
+                .map(|t| (t.rx_buf, t.len as u64))
+                .collect();
+
+            return IoctlResult::Retry { input, output };
+        }
+
+        let mut offset = size;
+        let tx: Vec<&[u8]> = transfers
+            .iter()
+            .map(|t| match t.tx_buf {
+                0 => &[][..],
+                _ => {
+                    let w = &data[offset..offset + t.len as usize];
+                    offset += t.len as usize;
+                    w
+                }
+            })
+            .collect();
+
+        match self.message(&transfers, &tx) {
+            Ok(rx) => {
+                let out = transfers
+                    .iter()
+                    .zip(rx)
+                    .filter(|(t, _)| t.rx_buf != 0)
+                    .flat_map(|(_, r)| r)
+                    .collect();
+                let result = transfers.iter().map(|t| t.len as i32).sum();
+
+                IoctlResult::Done { result, out }
+            }
+            Err(e) => {
+                warn!("spidev message failed: {}", e);
+                IoctlResult::Error(errno(&e))
+            }
+        }
+    }
+}
+
+/// Map driver errors to errno values
+fn errno(e: &Error) -> i32 {
+    match e {
+        Error::InvalidConfig { .. } | Error::InvalidBaud | Error::InvalidPin(_) => EINVAL,
+        _ => EIO,
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub use cuse::serve_cuse;
+
+/// Total data length of an `SPI_IOC_MESSAGE`
+fn message_len(transfers: &[SpiIocTransfer]) -> usize {
+    transfers.iter().map(|t| t.len as usize).sum()
+}
+
+/// Minimal CUSE (character device in userspace) server for the spidev bridge
+#[cfg(target_os = "linux")]
+mod cuse {
+    use std::fs::{File, OpenOptions};
+    use std::io::{Read, Write};
+
+    use byteorder::{ByteOrder, NativeEndian as NE};
+    use log::{debug, info, trace};
+    use rusb::UsbContext;
+
+    use super::{IoctlResult, SpidevBridge, MAX_TRANSFER};
+    use crate::Error;
+
+    const FUSE_OPEN: u32 = 14;
+    const FUSE_READ: u32 = 15;
+    const FUSE_WRITE: u32 = 16;
+    const FUSE_RELEASE: u32 = 18;
+    const FUSE_FLUSH: u32 = 25;
+    const FUSE_INTERRUPT: u32 = 36;
+    const FUSE_IOCTL: u32 = 39;
+    const CUSE_INIT: u32 = 4096;
+
+    const FUSE_KERNEL_VERSION: u32 = 7;
+    const FUSE_KERNEL_MINOR_VERSION: u32 = 31;
+
+    const CUSE_UNRESTRICTED_IOCTL: u32 = 1 << 0;
+    const FUSE_IOCTL_RETRY: u32 = 1 << 2;
+
+    /// Size of `struct fuse_in_header`
+    const IN_HEADER_LEN: usize = 40;
+
+    const EIO: i32 = 5;
+    const ENOSYS: i32 = 38;
+
+    /// Expose the bridge as `/dev/<name>` via CUSE, serving requests until the device
+    /// is removed
+    ///
+    /// This requires the `cuse` kernel module and permission to open `/dev/cuse`.
+    pub fn serve_cuse<T: UsbContext>(
+        bridge: &mut SpidevBridge<T>,
+        name: &str,
+    ) -> Result<(), Error> {
+        let mut dev = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/cuse")?;
+
+        let mut buff = vec![0u8; MAX_TRANSFER + 4096];
+
+        loop {
+            let n = match dev.read(&mut buff) {
+                Ok(n) => n,
+                Err(e) if e.raw_os_error() == Some(libc::ENODEV) => return Ok(()),
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) if e.raw_os_error() == Some(libc::ENOENT) => continue,
+                Err(e) => return Err(e.into()),
+            };
+
+            if n < IN_HEADER_LEN {
+                continue;
+            }
+
+            let opcode = NE::read_u32(&buff[4..8]);
+            let unique = NE::read_u64(&buff[8..16]);
+            let body = &buff[IN_HEADER_LEN..n];
+
+            trace!("CUSE request {} (unique: {})", opcode, unique);
+
+            let reply = match opcode {
+                CUSE_INIT => {
+                    let r = init(body, name);
+                    info!("Created /dev/{}", name);
+                    Ok(r)
+                }
+                // Stateless, a single handle is shared by all opens
+                FUSE_OPEN => Ok(vec![0u8; 16]),
+                FUSE_RELEASE | FUSE_FLUSH => Ok(vec![]),
+                FUSE_INTERRUPT => continue,
+                FUSE_READ => {
+                    let size = (NE::read_u32(&body[16..20]) as usize).min(MAX_TRANSFER);
+                    let mut data = vec![0u8; size];
+                    bridge.read(&mut data).map(|_| data).map_err(|e| {
+                        debug!("spidev read: {}", e);
+                        EIO
+                    })
+                }
+                FUSE_WRITE => {
+                    let size = NE::read_u32(&body[16..20]) as usize;
+                    let data = &body[40..40 + size];
+                    bridge
+                        .write(data)
+                        .map(|_| {
+                            let mut r = vec![0u8; 8];
+                            NE::write_u32(&mut r[0..4], size as u32);
+                            r
+                        })
+                        .map_err(|e| {
+                            debug!("spidev write: {}", e);
+                            EIO
+                        })
+                }
+                FUSE_IOCTL => ioctl(bridge, body),
+                _ => Err(ENOSYS),
+            };
+
+            respond(&mut dev, unique, reply)?;
+        }
+    }
+
+    /// Build the CUSE_INIT reply, registering the device name
+    fn init(body: &[u8], name: &str) -> Vec<u8> {
+        let minor = NE::read_u32(&body[4..8]).min(FUSE_KERNEL_MINOR_VERSION);
+
+        // struct cuse_init_out
+        let mut r = vec![0u8; 72];
+        NE::write_u32(&mut r[0..4], FUSE_KERNEL_VERSION);
+        NE::write_u32(&mut r[4..8], minor);
+        NE::write_u32(&mut r[12..16], CUSE_UNRESTRICTED_IOCTL);
+        NE::write_u32(&mut r[16..20], MAX_TRANSFER as u32);
+        NE::write_u32(&mut r[20..24], MAX_TRANSFER as u32);
+        // Zero device major requests dynamic allocation
+
+        r.extend_from_slice(format!("DEVNAME={}\0", name).as_bytes());
+        r
+    }
+
+    /// Handle a FUSE_IOCTL request, building the `fuse_ioctl_out` reply
+    fn ioctl<T: UsbContext>(bridge: &mut SpidevBridge<T>, body: &[u8]) -> Result<Vec<u8>, i32> {
+        let cmd = NE::read_u32(&body[12..16]);
+        let arg = NE::read_u64(&body[16..24]);
+        let out_size = NE::read_u32(&body[28..32]) as usize;
+        let data = &body[32..];
+
+        let mut r = vec![0u8; 16];
+
+        match bridge.ioctl(cmd, arg, data, out_size) {
+            IoctlResult::Done { result, out } => {
+                NE::write_i32(&mut r[0..4], result);
+                r.extend_from_slice(&out);
+            }
+            IoctlResult::Retry { input, output } => {
+                NE::write_u32(&mut r[4..8], FUSE_IOCTL_RETRY);
+                NE::write_u32(&mut r[8..12], input.len() as u32);
+                NE::write_u32(&mut r[12..16], output.len() as u32);
+
+                // struct fuse_ioctl_iovec
+                for (base, len) in input.iter().chain(output.iter()) {
+                    r.extend_from_slice(&base.to_ne_bytes());
+                    r.extend_from_slice(&len.to_ne_bytes());
+                }
+            }
+            IoctlResult::Error(e) => return Err(e),
+        }
+
+        Ok(r)
+    }
+
+    /// Write a reply (`struct fuse_out_header` and body, or an error)
+    fn respond(dev: &mut File, unique: u64, reply: Result<Vec<u8>, i32>) -> Result<(), Error> {
+        let (error, body) = match reply {
+            Ok(b) => (0, b),
+            Err(e) => (-e, vec![]),
+        };
+
+        let mut r = vec![0u8; 16];
+        NE::write_u32(&mut r[0..4], (16 + body.len()) as u32);
+        NE::write_i32(&mut r[4..8], error);
+        NE::write_u64(&mut r[8..16], unique);
+        r.extend_from_slice(&body);
+
+        // Replies must be written in a single call
+        let n = dev.write(&r)?;
+        if n != r.len() {
+            return Err(Error::ShortTransfer {
+                expected: r.len(),
+                actual: n,
+            });
+        }
+
+        Ok(())
+    }
+}
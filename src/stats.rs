@@ -0,0 +1,25 @@
+//! CP2130 Driver Statistics
+//!
+//! Per-device transfer counters, fetched with [`Cp2130::stats`](crate::Cp2130::stats).
+//!
+//! Copyright 2019 Ryan Kurte
+
+use std::time::Duration;
+
+/// Transfer statistics for a device
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Stats {
+    /// SPI bytes written to the device
+    pub bytes_written: u64,
+    /// SPI bytes read from the device
+    pub bytes_read: u64,
+    /// Completed SPI transfers
+    pub transfers: u64,
+    /// Failed USB operations (control or SPI)
+    pub usb_errors: u64,
+    /// Timed out USB reads that were retried (RTR gated reads)
+    pub retries: u64,
+    /// Estimated SPI bus time for completed transfers
+    pub bus_time: Duration,
+}
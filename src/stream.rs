@@ -0,0 +1,222 @@
+//! CP2130 Driver SPI Streaming
+//!
+//! [`SpiStream`] runs repeated SPI reads on a background thread, buffering blocks
+//! in a bounded queue so fixed-rate sources (such as ADCs) can be sampled without gaps.
+//! Blocks arriving while the queue is full are dropped and counted as overflows.
+//!
+//! Copyright 2019 Ryan Kurte
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TryRecvError, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use log::{debug, error};
+use rusb::UsbContext;
+
+use crate::device::SpiTransfers;
+use crate::{Cp2130, Error};
+
+/// SPI stream configuration
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "clap", derive(clap::Parser))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct StreamConfig {
+    #[cfg_attr(feature = "clap", clap(long, default_value = "64"))]
+    /// Length of each block read from the device
+    pub block_len: usize,
+
+    #[cfg_attr(feature = "clap", clap(long, default_value = "64"))]
+    /// Number of blocks buffered before overflow
+    pub capacity: usize,
+
+    #[cfg_attr(feature = "clap", clap(long))]
+    /// Gate reads on the RTR (ready to read) pin
+    pub rtr: bool,
+}
+
+impl Default for StreamConfig {
+    fn default() -> Self {
+        Self {
+            block_len: 64,
+            capacity: 64,
+            rtr: false,
+        }
+    }
+}
+
+/// State shared with the stream thread
+#[derive(Default)]
+struct Shared {
+    running: Arc<AtomicBool>,
+    blocks: AtomicU64,
+    overflows: AtomicU64,
+    error: Mutex<Option<Error>>,
+}
+
+/// Continuous SPI read stream, stopped when dropped
+pub struct SpiStream {
+    rx: Receiver<Vec<u8>>,
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl<T: UsbContext> Cp2130<T> {
+    /// Start streaming SPI reads into a bounded queue
+    ///
+    /// Reads are issued in blocks of `config.block_len` bytes, other device operations
+    /// may be interleaved between blocks.
+    pub fn spi_stream(&self, config: StreamConfig) -> Result<SpiStream, Error> {
+        if config.block_len == 0 || config.capacity == 0 {
+            return Err(Error::InvalidConfig {
+                field: "stream",
+                reason: "block length and capacity must be non-zero",
+            });
+        }
+
+        let spi = {
+            let inner = self.inner.lock().unwrap();
+            if config.rtr {
+                inner.check_rtr()?;
+            }
+            inner.spi_transfers()
+        };
+
+        let shared = Arc::new(Shared::default());
+        shared.running.store(true, Ordering::SeqCst);
+
+        let (tx, rx) = mpsc::sync_channel(config.capacity);
+
+        let s = shared.clone();
+        let thread = std::thread::Builder::new()
+            .name("cp2130-stream".to_string())
+            .spawn(move || stream(spi, config, tx, &s))
+            .expect("failed to spawn stream thread");
+
+        Ok(SpiStream {
+            rx,
+            shared,
+            thread: Some(thread),
+        })
+    }
+}
+
+/// Stream thread, reading blocks until stopped or an error occurs
+fn stream(spi: SpiTransfers, config: StreamConfig, tx: SyncSender<Vec<u8>>, shared: &Shared) {
+    while shared.running.load(Ordering::SeqCst) {
+        let mut block = vec![0u8; config.block_len];
+
+        let res = match config.rtr {
+            true => spi.lock().read_rtr(&mut block, shared.running.clone()),
+            false => spi.lock().read(&mut block),
+        };
+
+        match res {
+            Ok(_) => (),
+            // Stopped while waiting on an RTR gated read, the read has been aborted
+            Err(_) if config.rtr && !shared.running.load(Ordering::SeqCst) => break,
+            Err(e) => {
+                error!("SPI stream read: {}", e);
+                *shared.error.lock().unwrap() = Some(e);
+                break;
+            }
+        }
+
+        shared.blocks.fetch_add(1, Ordering::Relaxed);
+
+        match tx.try_send(block) {
+            Ok(_) => (),
+            Err(TrySendError::Full(_)) => {
+                shared.overflows.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(TrySendError::Disconnected(_)) => break,
+        }
+    }
+
+    shared.running.store(false, Ordering::SeqCst);
+
+    debug!("SPI stream exiting");
+}
+
+impl SpiStream {
+    /// Receive the next block, blocking until available
+    ///
+    /// Once the stream has stopped and buffered blocks are consumed this returns the
+    /// error that stopped the stream, or [`Error::StreamStopped`].
+    pub fn recv(&self) -> Result<Vec<u8>, Error> {
+        match self.rx.recv() {
+            Ok(b) => Ok(b),
+            Err(_) => Err(self.stopped()),
+        }
+    }
+
+    /// Receive the next block, returning `None` if none is available within the timeout
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Option<Vec<u8>>, Error> {
+        match self.rx.recv_timeout(timeout) {
+            Ok(b) => Ok(Some(b)),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(self.stopped()),
+        }
+    }
+
+    /// Receive the next block if one is available
+    pub fn try_recv(&self) -> Result<Option<Vec<u8>>, Error> {
+        match self.rx.try_recv() {
+            Ok(b) => Ok(Some(b)),
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Disconnected) => Err(self.stopped()),
+        }
+    }
+
+    /// Check whether the stream is still reading
+    pub fn is_running(&self) -> bool {
+        self.shared.running.load(Ordering::SeqCst)
+    }
+
+    /// Fetch the number of blocks read from the device
+    pub fn blocks(&self) -> u64 {
+        self.shared.blocks.load(Ordering::Relaxed)
+    }
+
+    /// Fetch the number of blocks dropped as the queue was full
+    pub fn overflows(&self) -> u64 {
+        self.shared.overflows.load(Ordering::Relaxed)
+    }
+
+    /// Stop the stream, returning any error that occurred while streaming
+    pub fn stop(mut self) -> Result<(), Error> {
+        self.shutdown();
+
+        match self.shared.error.lock().unwrap().take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    fn stopped(&self) -> Error {
+        self.shared
+            .error
+            .lock()
+            .unwrap()
+            .take()
+            .unwrap_or(Error::StreamStopped)
+    }
+
+    fn shutdown(&mut self) {
+        self.shared.running.store(false, Ordering::SeqCst);
+
+        if let Some(t) = self.thread.take() {
+            if t.join().is_err() {
+                error!("SPI stream thread panicked");
+            }
+        }
+    }
+}
+
+impl Drop for SpiStream {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
@@ -0,0 +1,280 @@
+//! CP2130 Driver libusb (rusb) Transport
+//!
+//!
+//! Copyright 2019 Ryan Kurte
+
+use std::time::Duration;
+
+use log::{debug, error, trace};
+use rusb::{DeviceDescriptor, DeviceHandle, Direction, TransferType, UsbContext};
+
+use super::Transport;
+use crate::device::{Info, UsbOptions, USB_TIMEOUT};
+use crate::Error;
+
+/// Device specific endpoints
+/// TODO: given it's one device this could all be hard-coded
+#[derive(Debug)]
+struct Endpoints {
+    control: Endpoint,
+    read: Endpoint,
+    write: Endpoint,
+}
+
+/// Internal endpoint representations
+#[derive(Debug, PartialEq, Clone)]
+struct Endpoint {
+    config: u8,
+    iface: u8,
+    setting: u8,
+    address: u8,
+    max_packet_size: u16,
+}
+
+/// Transport using a libusb device handle
+pub struct RusbTransport<T: UsbContext> {
+    handle: DeviceHandle<T>,
+    endpoints: Endpoints,
+    kernel_driver_detached: bool,
+    interface_claimed: bool,
+}
+
+impl<T: UsbContext> RusbTransport<T> {
+    /// Connect to a CP2130 using an already opened libusb device handle
+    pub fn new(
+        handle: DeviceHandle<T>,
+        descriptor: DeviceDescriptor,
+        opts: UsbOptions,
+    ) -> Result<(Self, Info), Error> {
+        let timeout = USB_TIMEOUT;
+        let device = handle.device();
+
+        // Reset device
+        if opts.reset_on_open {
+            handle.reset()?;
+        } else {
+            debug!("Skipping device reset");
+        }
+
+        // Fetch base configuration
+        let languages = handle.read_languages(timeout)?;
+        let active_config = handle.active_configuration()?;
+
+        trace!("Active configuration: {}", active_config);
+        trace!("Languages: {:?}", languages);
+
+        // Check a language is available
+        if languages.is_empty() {
+            return Err(Error::NoLanguages);
+        }
+
+        // Fetch information
+        let language = languages[0];
+        let manufacturer = handle.read_manufacturer_string(language, &descriptor, timeout)?;
+        let product = handle.read_product_string(language, &descriptor, timeout)?;
+        let serial = handle.read_serial_number_string(language, &descriptor, timeout)?;
+        let info = Info::new(manufacturer, product, serial).with_usb_device(&device, &descriptor);
+
+        // Check at least one configuration exists
+        if descriptor.num_configurations() != 1 {
+            error!("Unexpected number of configurations");
+            return Err(Error::Configurations);
+        }
+
+        // Connect to endpoints
+        let config_desc = device.config_descriptor(0)?;
+
+        let (mut write, mut read) = (None, None);
+
+        for interface in config_desc.interfaces() {
+            for interface_desc in interface.descriptors() {
+                for endpoint_desc in interface_desc.endpoint_descriptors() {
+                    // Create an endpoint container
+                    let e = Endpoint {
+                        config: config_desc.number(),
+                        iface: interface_desc.interface_number(),
+                        setting: interface_desc.setting_number(),
+                        address: endpoint_desc.address(),
+                        max_packet_size: endpoint_desc.max_packet_size(),
+                    };
+
+                    trace!("Endpoint: {:?}", e);
+
+                    // Find the relevant endpoints
+                    match (endpoint_desc.transfer_type(), endpoint_desc.direction()) {
+                        (TransferType::Bulk, Direction::In) => read = Some(e),
+                        (TransferType::Bulk, Direction::Out) => write = Some(e),
+                        (_, _) => continue,
+                    }
+                }
+            }
+        }
+
+        // Configure endpoints
+        let control = Endpoint {
+            config: 1,
+            iface: 0,
+            setting: 0,
+            address: 0,
+            max_packet_size: 0,
+        };
+        //control.configure(&mut handle)?;
+
+        // Map endpoints before touching the kernel driver or interface
+        let write = match write {
+            Some(c) => c,
+            None => {
+                error!("No write endpoint found");
+                return Err(Error::Endpoint);
+            }
+        };
+
+        let read = match read {
+            Some(c) => c,
+            None => {
+                error!("No read endpoint found");
+                return Err(Error::Endpoint);
+            }
+        };
+
+        // Build endpoints
+        let endpoints = Endpoints {
+            control,
+            write,
+            read,
+        };
+
+        let mut transport = Self {
+            handle,
+            endpoints,
+            kernel_driver_detached: false,
+            interface_claimed: false,
+        };
+
+        // Undo any detach / claim if the remaining setup fails
+        if let Err(e) = transport.attach(&opts) {
+            let _ = transport.close();
+            return Err(e);
+        }
+
+        if opts.allow_suspend {
+            super::allow_suspend(&info);
+        }
+
+        Ok((transport, info))
+    }
+
+    /// Detach the kernel driver, claim the interface and set the active configuration
+    ///
+    /// Detach and claim are recorded as they happen so they can be undone by `close`.
+    fn attach(&mut self, opts: &UsbOptions) -> Result<(), Error> {
+        let iface = self.endpoints.control.iface;
+
+        // Detach kernel driver if required (re-attached on close)
+        if opts.detach_kernel_driver {
+            debug!("Checking for active kernel driver");
+            match self.handle.kernel_driver_active(iface)? {
+                true => {
+                    debug!("Detaching kernel driver");
+                    self.handle.detach_kernel_driver(iface)?;
+                    self.kernel_driver_detached = true;
+                }
+                false => {
+                    debug!("Kernel driver inactive");
+                }
+            }
+        } else {
+            debug!("Skipping kernel driver attach check");
+        }
+
+        // Claim interface (released on close)
+        if opts.claim_interface {
+            debug!("Claiming device interface");
+            self.handle.claim_interface(iface)?;
+            self.interface_claimed = true;
+        } else {
+            debug!("Skipping claim device interface");
+        }
+
+        self.handle
+            .set_active_configuration(self.endpoints.write.config)?;
+        self.handle
+            .set_active_configuration(self.endpoints.read.config)?;
+
+        Ok(())
+    }
+}
+
+impl<T: UsbContext> Transport for RusbTransport<T> {
+    fn control_in(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buff: &mut [u8],
+        timeout: Duration,
+    ) -> Result<usize, Error> {
+        let n = self
+            .handle
+            .read_control(request_type, request, value, index, buff, timeout)?;
+        Ok(n)
+    }
+
+    fn control_out(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buff: &[u8],
+        timeout: Duration,
+    ) -> Result<usize, Error> {
+        let n = self
+            .handle
+            .write_control(request_type, request, value, index, buff, timeout)?;
+        Ok(n)
+    }
+
+    fn bulk_in(&self, buff: &mut [u8], timeout: Duration) -> Result<usize, Error> {
+        let n = self
+            .handle
+            .read_bulk(self.endpoints.read.address, buff, timeout)?;
+        Ok(n)
+    }
+
+    fn bulk_out(&self, buff: &[u8], timeout: Duration) -> Result<usize, Error> {
+        let n = self
+            .handle
+            .write_bulk(self.endpoints.write.address, buff, timeout)?;
+        Ok(n)
+    }
+
+    fn max_packet_size(&self) -> usize {
+        let (read, write) = (&self.endpoints.read, &self.endpoints.write);
+        read.max_packet_size.min(write.max_packet_size) as usize
+    }
+
+    /// Release the interface and re-attach the kernel driver if these were
+    /// claimed / detached on connection
+    fn close(&mut self) -> Result<(), Error> {
+        let iface = self.endpoints.control.iface;
+        let mut res = Ok(());
+
+        // Attempt both steps, reporting the first failure
+        if self.interface_claimed {
+            debug!("Releasing device interface");
+            res = self.handle.release_interface(iface).map_err(Error::from);
+            self.interface_claimed = false;
+        }
+
+        if self.kernel_driver_detached {
+            debug!("Re-attaching kernel driver");
+            let r = self.handle.attach_kernel_driver(iface).map_err(Error::from);
+            res = res.and(r);
+            self.kernel_driver_detached = false;
+        }
+
+        res
+    }
+}
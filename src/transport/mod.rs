@@ -0,0 +1,94 @@
+//! CP2130 Driver USB Transports
+//!
+//! The protocol implementation in [`crate::device`] issues control and bulk transfers
+//! via the [`Transport`] trait, allowing the underlying USB library to be swapped.
+//!
+//! Copyright 2019 Ryan Kurte
+
+use std::time::Duration;
+
+use log::{debug, warn};
+
+use crate::device::Info;
+use crate::Error;
+
+mod libusb;
+pub use libusb::RusbTransport;
+
+mod record;
+pub use record::{Exchange, ExchangeKind, Recorder, Replayer};
+
+#[cfg(feature = "nusb")]
+mod nusb;
+#[cfg(feature = "nusb")]
+pub use self::nusb::NusbTransport;
+
+/// Bulk endpoint packet size used where a transport does not report one
+pub const DEFAULT_MAX_PACKET_SIZE: usize = 64;
+
+/// USB transport used to communicate with a CP2130
+///
+/// Implementations are responsible for locating the CP2130 bulk endpoints,
+/// and may be used with [`Cp2130::from_transport`](crate::Cp2130::from_transport)
+/// to provide alternate backends or mocks.
+pub trait Transport: Send + Sync {
+    /// Issue a control transfer from the device to the host, returning the number of bytes read
+    fn control_in(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buff: &mut [u8],
+        timeout: Duration,
+    ) -> Result<usize, Error>;
+
+    /// Issue a control transfer from the host to the device, returning the number of bytes written
+    fn control_out(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buff: &[u8],
+        timeout: Duration,
+    ) -> Result<usize, Error>;
+
+    /// Read from the bulk IN endpoint, returning the number of bytes read
+    fn bulk_in(&self, buff: &mut [u8], timeout: Duration) -> Result<usize, Error>;
+
+    /// Write to the bulk OUT endpoint, returning the number of bytes written
+    fn bulk_out(&self, buff: &[u8], timeout: Duration) -> Result<usize, Error>;
+
+    /// Maximum packet size (wMaxPacketSize) of the bulk endpoints, used to size transfers
+    fn max_packet_size(&self) -> usize {
+        DEFAULT_MAX_PACKET_SIZE
+    }
+
+    /// Release any resources claimed on connection
+    fn close(&mut self) -> Result<(), Error>;
+}
+
+/// Allow the host to suspend an idle device, with remote wakeup enabled
+///
+/// Uses the linux sysfs runtime power management controls for the device port,
+/// which typically requires write access to `/sys/bus/usb/devices`. Failures are
+/// logged rather than returned as the device remains usable without suspend.
+pub(crate) fn allow_suspend(info: &Info) {
+    let port = match info.port() {
+        Some(p) if cfg!(target_os = "linux") => p,
+        _ => {
+            warn!("Host suspend control unavailable for this device");
+            return;
+        }
+    };
+
+    let power = format!("/sys/bus/usb/devices/{}/power", port);
+    debug!("Enabling host suspend and remote wakeup via {}", power);
+
+    for (attr, value) in [("wakeup", "enabled"), ("control", "auto")] {
+        if let Err(e) = std::fs::write(format!("{}/{}", power, attr), value) {
+            warn!("Failed to set {}/{}: {}", power, attr, e);
+        }
+    }
+}
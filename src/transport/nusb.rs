@@ -0,0 +1,319 @@
+//! CP2130 Driver nusb Transport
+//!
+//!
+//! Copyright 2019 Ryan Kurte
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use log::{debug, error, trace};
+use nusb::descriptors::TransferType;
+use nusb::transfer::{
+    Buffer, Bulk, ControlIn, ControlOut, ControlType, Direction, In, Out, Recipient, TransferError,
+};
+use nusb::{Device, Endpoint, Interface, MaybeFuture};
+
+use super::Transport;
+use crate::device::{Info, RequestType, UsbOptions, UsbPower, USB_TIMEOUT};
+use crate::Error;
+
+/// CP2130 interface number
+const IFACE: u8 = 0;
+
+/// Self powered bit in the configuration descriptor bmAttributes
+const SELF_POWERED: u8 = 1 << 6;
+
+/// Remote wakeup bit in the configuration descriptor bmAttributes
+const REMOTE_WAKEUP: u8 = 1 << 5;
+
+/// Claimed interface and bulk endpoints, released on close
+struct Claimed {
+    interface: Interface,
+    read: Mutex<BulkIn>,
+    write: Mutex<Endpoint<Bulk, Out>>,
+}
+
+/// Bulk IN endpoint, holding data received beyond the caller's buffer
+///
+/// IN transfers are requested in whole packets, so a short read may receive more data
+/// than requested. This is returned by the following reads rather than discarded.
+struct BulkIn {
+    endpoint: Endpoint<Bulk, In>,
+    residue: VecDeque<u8>,
+}
+
+/// Transport using the pure-rust nusb library
+pub struct NusbTransport {
+    claimed: Option<Claimed>,
+    max_packet_size: usize,
+}
+
+impl NusbTransport {
+    /// Connect to a CP2130 using an opened nusb device
+    ///
+    /// nusb always claims the interface, `opts.claim_interface` is ignored
+    pub fn new(device: Device, opts: UsbOptions) -> Result<(Self, Info), Error> {
+        let timeout = USB_TIMEOUT;
+        let descriptor = device.device_descriptor();
+
+        // Reset device
+        if opts.reset_on_open {
+            device.reset().wait()?;
+        } else {
+            debug!("Skipping device reset");
+        }
+
+        // Check a language is available
+        let language = match device
+            .get_string_descriptor_supported_languages(timeout)
+            .wait()?
+            .next()
+        {
+            Some(l) => l,
+            None => return Err(Error::NoLanguages),
+        };
+
+        // Fetch information
+        let read_string = |index| match index {
+            Some(i) => device.get_string_descriptor(i, language, timeout).wait(),
+            None => Ok(String::new()),
+        };
+        let manufacturer = read_string(descriptor.manufacturer_string_index())?;
+        let product = read_string(descriptor.product_string_index())?;
+        let serial = read_string(descriptor.serial_number_string_index())?;
+        let mut info =
+            Info::new(manufacturer, product, serial).with_release(descriptor.device_version());
+        if let Some(s) = device.speed() {
+            info = info.with_speed(s.into());
+        }
+        if let Ok(c) = device.active_configuration() {
+            info = info.with_power(UsbPower {
+                self_powered: c.attributes() & SELF_POWERED != 0,
+                max_power_ma: c.max_power() as u16 * 2,
+                remote_wakeup: c.attributes() & REMOTE_WAKEUP != 0,
+            });
+        }
+
+        // Check at least one configuration exists
+        if descriptor.num_configurations() != 1 {
+            error!("Unexpected number of configurations");
+            return Err(Error::Configurations);
+        }
+
+        // Claim interface, detaching the kernel driver if required (re-attached on close)
+        let interface = if opts.detach_kernel_driver {
+            debug!("Detaching kernel driver and claiming device interface");
+            device.detach_and_claim_interface(IFACE).wait()?
+        } else {
+            debug!("Claiming device interface");
+            device.claim_interface(IFACE).wait()?
+        };
+
+        // Find the relevant endpoints
+        let (mut write, mut read) = (None, None);
+
+        if let Some(interface_desc) = interface.descriptor() {
+            for endpoint_desc in interface_desc.endpoints() {
+                trace!(
+                    "Endpoint: {:02x} ({:?})",
+                    endpoint_desc.address(),
+                    endpoint_desc.transfer_type()
+                );
+
+                match (endpoint_desc.transfer_type(), endpoint_desc.direction()) {
+                    (TransferType::Bulk, Direction::In) => read = Some(endpoint_desc.address()),
+                    (TransferType::Bulk, Direction::Out) => write = Some(endpoint_desc.address()),
+                    (_, _) => continue,
+                }
+            }
+        }
+
+        // Map endpoints
+        let write = match write {
+            Some(a) => interface.endpoint::<Bulk, Out>(a)?,
+            None => {
+                error!("No write endpoint found");
+                return Err(Error::Endpoint);
+            }
+        };
+
+        let read = match read {
+            Some(a) => interface.endpoint::<Bulk, In>(a)?,
+            None => {
+                error!("No read endpoint found");
+                return Err(Error::Endpoint);
+            }
+        };
+
+        let max_packet_size = read.max_packet_size().min(write.max_packet_size());
+
+        let claimed = Claimed {
+            interface,
+            read: Mutex::new(BulkIn {
+                endpoint: read,
+                residue: VecDeque::new(),
+            }),
+            write: Mutex::new(write),
+        };
+
+        if opts.allow_suspend {
+            super::allow_suspend(&info);
+        }
+
+        Ok((
+            Self {
+                claimed: Some(claimed),
+                max_packet_size,
+            },
+            info,
+        ))
+    }
+
+    fn claimed(&self) -> Result<&Claimed, Error> {
+        self.claimed
+            .as_ref()
+            .ok_or(Error::NusbTransfer(TransferError::Disconnected))
+    }
+}
+
+/// Split a USB request type into nusb control type and recipient
+fn request_type(request_type: u8) -> (ControlType, Recipient) {
+    let t = RequestType::from_bits_truncate(request_type);
+
+    let control_type = if t.contains(RequestType::TYPE_VENDOR) {
+        ControlType::Vendor
+    } else if t.contains(RequestType::TYPE_CLASS) {
+        ControlType::Class
+    } else {
+        ControlType::Standard
+    };
+
+    let recipient = match request_type & RequestType::RECIPIENT_OTHER.bits() {
+        0b00 => Recipient::Device,
+        0b01 => Recipient::Interface,
+        0b10 => Recipient::Endpoint,
+        _ => Recipient::Other,
+    };
+
+    (control_type, recipient)
+}
+
+impl Transport for NusbTransport {
+    fn control_in(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buff: &mut [u8],
+        timeout: Duration,
+    ) -> Result<usize, Error> {
+        let (control_type, recipient) = self::request_type(request_type);
+
+        let data = self
+            .claimed()?
+            .interface
+            .control_in(
+                ControlIn {
+                    control_type,
+                    recipient,
+                    request,
+                    value,
+                    index,
+                    length: buff.len() as u16,
+                },
+                timeout,
+            )
+            .wait()?;
+
+        let n = data.len().min(buff.len());
+        buff[..n].copy_from_slice(&data[..n]);
+
+        Ok(n)
+    }
+
+    fn control_out(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buff: &[u8],
+        timeout: Duration,
+    ) -> Result<usize, Error> {
+        let (control_type, recipient) = self::request_type(request_type);
+
+        self.claimed()?
+            .interface
+            .control_out(
+                ControlOut {
+                    control_type,
+                    recipient,
+                    request,
+                    value,
+                    index,
+                    data: buff,
+                },
+                timeout,
+            )
+            .wait()?;
+
+        Ok(buff.len())
+    }
+
+    fn bulk_in(&self, buff: &mut [u8], timeout: Duration) -> Result<usize, Error> {
+        let mut read = self.claimed()?.read.lock().unwrap();
+
+        // Return data held from a previous transfer prior to requesting more
+        if !read.residue.is_empty() {
+            let n = read.residue.len().min(buff.len());
+            for (b, v) in buff.iter_mut().zip(read.residue.drain(..n)) {
+                *b = v;
+            }
+            return Ok(n);
+        }
+
+        // IN transfers must be requested in multiples of the packet size
+        let packet_size = read.endpoint.max_packet_size();
+        let len = buff.len().div_ceil(packet_size).max(1) * packet_size;
+
+        let c = read.endpoint.transfer_blocking(Buffer::new(len), timeout);
+
+        // Data received prior to a timeout is returned, so progress is reported by
+        // the following read rather than lost
+        match c.status {
+            Err(e) if c.actual_len == 0 => return Err(e.into()),
+            Err(e) => debug!("Bulk IN ended early ({} bytes): {}", c.actual_len, e),
+            Ok(_) => (),
+        }
+
+        let n = c.actual_len.min(buff.len());
+        buff[..n].copy_from_slice(&c.buffer[..n]);
+        read.residue.extend(&c.buffer[n..c.actual_len]);
+
+        Ok(n)
+    }
+
+    fn bulk_out(&self, buff: &[u8], timeout: Duration) -> Result<usize, Error> {
+        let mut ep = self.claimed()?.write.lock().unwrap();
+
+        let c = ep.transfer_blocking(Buffer::from(buff), timeout);
+        c.status?;
+
+        Ok(c.actual_len)
+    }
+
+    fn max_packet_size(&self) -> usize {
+        self.max_packet_size
+    }
+
+    /// Release the interface, re-attaching the kernel driver if detached on connection
+    fn close(&mut self) -> Result<(), Error> {
+        if self.claimed.take().is_some() {
+            debug!("Releasing device interface");
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,439 @@
+//! CP2130 Driver Record / Replay Transports
+//!
+//! [`Recorder`] wraps a transport and logs each exchange to a transcript,
+//! one exchange per line in the form:
+//!
+//! `<elapsed us> <kind> <request type> <request> <value> <index> <data out> <data in> [error]`
+//!
+//! with fields in hex and `-` for empty data. [`Replayer`] serves the responses
+//! from a transcript without hardware attached.
+//!
+//! Copyright 2019 Ryan Kurte
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader, LineWriter, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use log::{error, trace};
+
+use super::Transport;
+use crate::Error;
+
+/// Kind of USB exchange
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExchangeKind {
+    ControlIn,
+    ControlOut,
+    BulkIn,
+    BulkOut,
+}
+
+impl fmt::Display for ExchangeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::ControlIn => "control-in",
+            Self::ControlOut => "control-out",
+            Self::BulkIn => "bulk-in",
+            Self::BulkOut => "bulk-out",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl FromStr for ExchangeKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "control-in" => Ok(Self::ControlIn),
+            "control-out" => Ok(Self::ControlOut),
+            "bulk-in" => Ok(Self::BulkIn),
+            "bulk-out" => Ok(Self::BulkOut),
+            _ => Err(format!("Unrecognised exchange kind: {}", s)),
+        }
+    }
+}
+
+/// A single recorded USB exchange
+#[derive(Debug, Clone, PartialEq)]
+pub struct Exchange {
+    /// Time since the start of the recording
+    pub elapsed: Duration,
+    pub kind: ExchangeKind,
+    /// Control request fields (zero for bulk exchanges)
+    pub request_type: u8,
+    pub request: u8,
+    pub value: u16,
+    pub index: u16,
+    /// Data sent to the device
+    pub data_out: Vec<u8>,
+    /// Data received from the device
+    pub data_in: Vec<u8>,
+    /// Error returned by the transport, if any
+    pub error: Option<String>,
+}
+
+fn write_hex(f: &mut fmt::Formatter<'_>, data: &[u8]) -> fmt::Result {
+    if data.is_empty() {
+        return write!(f, "-");
+    }
+    for b in data {
+        write!(f, "{:02x}", b)?;
+    }
+    Ok(())
+}
+
+fn parse_hex(s: &str) -> Result<Vec<u8>, String> {
+    if s == "-" {
+        return Ok(vec![]);
+    }
+    if !s.len().is_multiple_of(2) {
+        return Err(format!("Odd length hex data: {}", s));
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(|e| e.to_string()))
+        .collect()
+}
+
+impl fmt::Display for Exchange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {:02x} {:02x} {:04x} {:04x} ",
+            self.elapsed.as_micros(),
+            self.kind,
+            self.request_type,
+            self.request,
+            self.value,
+            self.index
+        )?;
+        write_hex(f, &self.data_out)?;
+        write!(f, " ")?;
+        write_hex(f, &self.data_in)?;
+        if let Some(e) = &self.error {
+            write!(f, " {}", e)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Exchange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.trim().splitn(9, ' ');
+        let mut next = |name| parts.next().ok_or(format!("Missing field: {}", name));
+
+        let elapsed = next("elapsed")?
+            .parse()
+            .map_err(|_| "Invalid elapsed time")?;
+        let kind = next("kind")?.parse()?;
+        let request_type =
+            u8::from_str_radix(next("request_type")?, 16).map_err(|e| e.to_string())?;
+        let request = u8::from_str_radix(next("request")?, 16).map_err(|e| e.to_string())?;
+        let value = u16::from_str_radix(next("value")?, 16).map_err(|e| e.to_string())?;
+        let index = u16::from_str_radix(next("index")?, 16).map_err(|e| e.to_string())?;
+        let data_out = parse_hex(next("data_out")?)?;
+        let data_in = parse_hex(next("data_in")?)?;
+        let error = next("error").ok().map(|e| e.to_string());
+
+        Ok(Self {
+            elapsed: Duration::from_micros(elapsed),
+            kind,
+            request_type,
+            request,
+            value,
+            index,
+            data_out,
+            data_in,
+            error,
+        })
+    }
+}
+
+/// Transport wrapper recording every exchange to a transcript
+pub struct Recorder<T: Transport, W: Write + Send> {
+    transport: T,
+    start: Instant,
+    writer: Mutex<W>,
+}
+
+impl<T: Transport> Recorder<T, LineWriter<File>> {
+    /// Record exchanges on the provided transport to a transcript file
+    pub fn create<P: AsRef<Path>>(transport: T, path: P) -> Result<Self, std::io::Error> {
+        let f = File::create(path)?;
+        Ok(Self::new(transport, LineWriter::new(f)))
+    }
+}
+
+impl<T: Transport, W: Write + Send> Recorder<T, W> {
+    /// Record exchanges on the provided transport to a writer
+    pub fn new(transport: T, writer: W) -> Self {
+        Self {
+            transport,
+            start: Instant::now(),
+            writer: Mutex::new(writer),
+        }
+    }
+
+    fn record<R>(
+        &self,
+        kind: ExchangeKind,
+        setup: (u8, u8, u16, u16),
+        data_out: &[u8],
+        data_in: impl FnOnce(&R) -> Vec<u8>,
+        res: Result<R, Error>,
+    ) -> Result<R, Error> {
+        let (request_type, request, value, index) = setup;
+
+        let (data_in, error) = match &res {
+            Ok(r) => (data_in(r), None),
+            Err(e) => (vec![], Some(e.to_string())),
+        };
+
+        let e = Exchange {
+            elapsed: self.start.elapsed(),
+            kind,
+            request_type,
+            request,
+            value,
+            index,
+            data_out: data_out.to_vec(),
+            data_in,
+            error,
+        };
+
+        trace!("Record: {}", e);
+
+        // Recording failures are logged rather than interrupting the transfer
+        if let Err(err) = writeln!(self.writer.lock().unwrap(), "{}", e) {
+            error!("Writing transcript: {}", err);
+        }
+
+        res
+    }
+}
+
+impl<T: Transport, W: Write + Send> Transport for Recorder<T, W> {
+    fn control_in(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buff: &mut [u8],
+        timeout: Duration,
+    ) -> Result<usize, Error> {
+        let res = self
+            .transport
+            .control_in(request_type, request, value, index, buff, timeout);
+        self.record(
+            ExchangeKind::ControlIn,
+            (request_type, request, value, index),
+            &[],
+            |n: &usize| buff[..*n].to_vec(),
+            res,
+        )
+    }
+
+    fn control_out(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buff: &[u8],
+        timeout: Duration,
+    ) -> Result<usize, Error> {
+        let res = self
+            .transport
+            .control_out(request_type, request, value, index, buff, timeout);
+        self.record(
+            ExchangeKind::ControlOut,
+            (request_type, request, value, index),
+            buff,
+            |_| vec![],
+            res,
+        )
+    }
+
+    fn bulk_in(&self, buff: &mut [u8], timeout: Duration) -> Result<usize, Error> {
+        let res = self.transport.bulk_in(buff, timeout);
+        self.record(
+            ExchangeKind::BulkIn,
+            (0, 0, 0, 0),
+            &[],
+            |n: &usize| buff[..*n].to_vec(),
+            res,
+        )
+    }
+
+    fn bulk_out(&self, buff: &[u8], timeout: Duration) -> Result<usize, Error> {
+        let res = self.transport.bulk_out(buff, timeout);
+        self.record(ExchangeKind::BulkOut, (0, 0, 0, 0), buff, |_| vec![], res)
+    }
+
+    fn max_packet_size(&self) -> usize {
+        self.transport.max_packet_size()
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        self.writer
+            .lock()
+            .unwrap()
+            .flush()
+            .map_err(|e| Error::Replay(e.to_string()))?;
+        self.transport.close()
+    }
+}
+
+/// Transport serving responses from a recorded transcript
+///
+/// Each call must match the next recorded exchange of the same kind (request and data out),
+/// recorded errors are returned as [`Error::Replay`].
+pub struct Replayer {
+    exchanges: Mutex<VecDeque<Exchange>>,
+}
+
+impl Replayer {
+    /// Create a replayer from a list of exchanges
+    pub fn new(exchanges: impl IntoIterator<Item = Exchange>) -> Self {
+        Self {
+            exchanges: Mutex::new(exchanges.into_iter().collect()),
+        }
+    }
+
+    /// Load a transcript from a reader
+    pub fn from_reader<R: Read>(r: R) -> Result<Self, Error> {
+        let mut exchanges = vec![];
+
+        for (i, line) in BufReader::new(r).lines().enumerate() {
+            let line = line.map_err(|e| Error::Replay(e.to_string()))?;
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let e = line
+                .parse()
+                .map_err(|e| Error::Replay(format!("line {}: {}", i + 1, e)))?;
+            exchanges.push(e);
+        }
+
+        Ok(Self::new(exchanges))
+    }
+
+    /// Load a transcript file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let f = File::open(path).map_err(|e| Error::Replay(e.to_string()))?;
+        Self::from_reader(f)
+    }
+
+    /// Number of exchanges remaining in the transcript
+    pub fn remaining(&self) -> usize {
+        self.exchanges.lock().unwrap().len()
+    }
+
+    /// Fetch the next exchange, checking it matches the request
+    fn next(
+        &self,
+        kind: ExchangeKind,
+        setup: (u8, u8, u16, u16),
+        data_out: &[u8],
+    ) -> Result<Exchange, Error> {
+        // Bulk IN and OUT may be issued concurrently, so match the next exchange of the same kind
+        let mut exchanges = self.exchanges.lock().unwrap();
+        let e = match exchanges.iter().position(|e| e.kind == kind) {
+            Some(i) => exchanges.remove(i).unwrap(),
+            None => {
+                return Err(Error::Replay(format!(
+                    "Unexpected {}, transcript ended",
+                    kind
+                )))
+            }
+        };
+        drop(exchanges);
+
+        if (e.request_type, e.request, e.value, e.index) != setup || e.data_out != data_out {
+            return Err(Error::Replay(format!(
+                "Unexpected {} (request: {:02x?}), expected: {}",
+                kind, setup, e
+            )));
+        }
+
+        if let Some(err) = &e.error {
+            return Err(Error::Replay(format!("Recorded error: {}", err)));
+        }
+
+        Ok(e)
+    }
+
+    fn read(&self, e: Exchange, buff: &mut [u8]) -> Result<usize, Error> {
+        if e.data_in.len() > buff.len() {
+            return Err(Error::Replay(format!(
+                "Recorded {} bytes for {} byte buffer",
+                e.data_in.len(),
+                buff.len()
+            )));
+        }
+
+        buff[..e.data_in.len()].copy_from_slice(&e.data_in);
+
+        Ok(e.data_in.len())
+    }
+}
+
+impl Transport for Replayer {
+    fn control_in(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buff: &mut [u8],
+        _timeout: Duration,
+    ) -> Result<usize, Error> {
+        let e = self.next(
+            ExchangeKind::ControlIn,
+            (request_type, request, value, index),
+            &[],
+        )?;
+        self.read(e, buff)
+    }
+
+    fn control_out(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buff: &[u8],
+        _timeout: Duration,
+    ) -> Result<usize, Error> {
+        self.next(
+            ExchangeKind::ControlOut,
+            (request_type, request, value, index),
+            buff,
+        )?;
+        Ok(buff.len())
+    }
+
+    fn bulk_in(&self, buff: &mut [u8], _timeout: Duration) -> Result<usize, Error> {
+        let e = self.next(ExchangeKind::BulkIn, (0, 0, 0, 0), &[])?;
+        self.read(e, buff)
+    }
+
+    fn bulk_out(&self, buff: &[u8], _timeout: Duration) -> Result<usize, Error> {
+        self.next(ExchangeKind::BulkOut, (0, 0, 0, 0), buff)?;
+        Ok(buff.len())
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
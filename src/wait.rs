@@ -0,0 +1,166 @@
+//! CP2130 Driver async GPIO wait support
+//!
+//! The CP2130 has no interrupt endpoint for GPIO changes, so waiting on a pin
+//! is implemented by polling the pin level at the pin's configured interval.
+//! Each wait runs its polling on a helper thread, which wakes the task once the
+//! awaited level or edge is seen so no blocking USB transfers occur in `poll`.
+//!
+//! Copyright 2019 Ryan Kurte
+
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    thread,
+    time::Duration,
+};
+
+use rusb::UsbContext;
+
+use crate::{lock_pin, Edge, Error, InputPin};
+
+impl<T: UsbContext + 'static> embedded_hal_async::digital::Wait for InputPin<T> {
+    async fn wait_for_high(&mut self) -> Result<(), Self::Error> {
+        self.wait(Target::Level(true)).await
+    }
+
+    async fn wait_for_low(&mut self) -> Result<(), Self::Error> {
+        self.wait(Target::Level(false)).await
+    }
+
+    async fn wait_for_rising_edge(&mut self) -> Result<(), Self::Error> {
+        self.wait(Target::Edge(Edge::Rising)).await
+    }
+
+    async fn wait_for_falling_edge(&mut self) -> Result<(), Self::Error> {
+        self.wait(Target::Edge(Edge::Falling)).await
+    }
+
+    async fn wait_for_any_edge(&mut self) -> Result<(), Self::Error> {
+        self.wait(Target::Edge(Edge::Any)).await
+    }
+}
+
+impl<T: UsbContext + 'static> InputPin<T> {
+    /// Create a future resolving once the pin reaches the provided target
+    fn wait(&mut self, target: Target) -> PinWait {
+        let inner = self.pin.inner.clone();
+        let (index, generation) = (self.pin.index, self.pin.generation);
+
+        PinWait::new(target, self.poll_interval, move || {
+            lock_pin(&inner, index, generation)?.get_gpio_level(index)
+        })
+    }
+}
+
+/// Pin state awaited by a [`PinWait`]
+#[derive(Debug, Clone, Copy)]
+enum Target {
+    Level(bool),
+    Edge(Edge),
+}
+
+/// State shared between a [`PinWait`] and its helper thread
+#[derive(Default)]
+struct Shared {
+    result: Option<Result<(), Error>>,
+    waker: Option<Waker>,
+    cancelled: bool,
+}
+
+/// Type-erased pin level read, run on the helper thread
+type ReadLevel = Box<dyn FnMut() -> Result<bool, Error> + Send>;
+
+/// Runtime-agnostic pin wait future, polled by a helper thread started on first poll
+struct PinWait {
+    start: Option<(Target, Duration, ReadLevel)>,
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl PinWait {
+    fn new(
+        target: Target,
+        interval: Duration,
+        read: impl FnMut() -> Result<bool, Error> + Send + 'static,
+    ) -> Self {
+        Self {
+            start: Some((target, interval, Box::new(read))),
+            shared: Arc::new(Mutex::new(Shared::default())),
+        }
+    }
+}
+
+/// Poll the pin level until the target is reached, the wait is cancelled or a read fails
+fn poll_level(
+    target: Target,
+    interval: Duration,
+    read: &mut ReadLevel,
+    shared: &Mutex<Shared>,
+) -> Result<(), Error> {
+    let mut last = read()?;
+    if let Target::Level(high) = target {
+        if last == high {
+            return Ok(());
+        }
+    }
+
+    while !shared.lock().unwrap().cancelled {
+        thread::sleep(interval);
+
+        let level = read()?;
+        let done = match target {
+            Target::Level(high) => level == high,
+            Target::Edge(edge) => edge.matches(last, level),
+        };
+        if done {
+            break;
+        }
+
+        last = level;
+    }
+
+    Ok(())
+}
+
+impl Future for PinWait {
+    type Output = Result<(), Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut shared = self.shared.lock().unwrap();
+
+        if let Some(res) = shared.result.take() {
+            return Poll::Ready(res);
+        }
+
+        // Update the waker in case the task has moved
+        match &shared.waker {
+            Some(w) if w.will_wake(cx.waker()) => (),
+            _ => shared.waker = Some(cx.waker().clone()),
+        }
+        drop(shared);
+
+        // Start the helper thread on first poll
+        if let Some((target, interval, mut read)) = self.start.take() {
+            let shared = self.shared.clone();
+
+            thread::spawn(move || {
+                let res = poll_level(target, interval, &mut read, &shared);
+
+                let mut s = shared.lock().unwrap();
+                s.result = Some(res);
+                if let Some(w) = s.waker.take() {
+                    w.wake();
+                }
+            });
+        }
+
+        Poll::Pending
+    }
+}
+
+impl Drop for PinWait {
+    fn drop(&mut self) {
+        self.shared.lock().unwrap().cancelled = true;
+    }
+}
@@ -0,0 +1,200 @@
+//! CP2130 Driver Transport Worker
+//!
+//! A dedicated worker thread runs bulk (SPI) jobs, callers submit jobs over a channel
+//! and block on a oneshot reply. Jobs run to completion in submission order, so
+//! multi-transfer operations (such as SPI transfers) are not interleaved.
+//!
+//! Control transfers (GPIO, configuration) bypass the queue and are issued directly
+//! from the calling thread, so they can proceed while bulk traffic is in flight.
+//!
+//! Copyright 2019 Ryan Kurte
+
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use log::{debug, error};
+
+use crate::stats::Stats;
+use crate::transport::Transport;
+use crate::Error;
+
+/// Job executed against the transport on the worker thread
+type Job = Box<dyn FnOnce(&dyn Transport) + Send>;
+
+/// Transport shared between the worker thread and control callers, removed on close
+type Shared = Arc<RwLock<Option<Box<dyn Transport>>>>;
+
+/// Worker thread for bulk transfers over a shared transport
+pub(crate) struct Worker {
+    transport: Shared,
+    tx: Mutex<Option<Sender<Job>>>,
+    thread: Mutex<Option<JoinHandle<()>>>,
+    stats: Mutex<Stats>,
+}
+
+impl Worker {
+    /// Spawn a worker thread for the provided transport
+    pub fn spawn(transport: Box<dyn Transport>) -> Self {
+        let transport: Shared = Arc::new(RwLock::new(Some(transport)));
+        let (tx, rx) = mpsc::channel::<Job>();
+
+        let shared = transport.clone();
+        let thread = std::thread::Builder::new()
+            .name("cp2130-worker".to_string())
+            .spawn(move || {
+                for job in rx.iter() {
+                    match shared.read().unwrap().as_deref() {
+                        Some(t) => job(t),
+                        None => break,
+                    }
+                }
+
+                debug!("Worker exiting");
+            })
+            .expect("failed to spawn worker thread");
+
+        Self {
+            transport,
+            tx: Mutex::new(Some(tx)),
+            thread: Mutex::new(Some(thread)),
+            stats: Mutex::new(Stats::default()),
+        }
+    }
+
+    /// Run a job on the worker thread, blocking until it completes
+    pub fn call<R: Send + 'static>(
+        &self,
+        f: impl FnOnce(&dyn Transport) -> R + Send + 'static,
+    ) -> Result<R, Error> {
+        let (tx, rx) = mpsc::sync_channel(1);
+
+        let job: Job = Box::new(move |t| {
+            let _ = tx.send(f(t));
+        });
+
+        match self.tx.lock().unwrap().as_ref() {
+            Some(s) => s.send(job).map_err(|_| Error::WorkerStopped)?,
+            None => return Err(Error::WorkerStopped),
+        }
+
+        rx.recv().map_err(|_| Error::WorkerStopped)
+    }
+
+    /// Run a function against the transport on the calling thread
+    fn direct<R>(&self, f: impl FnOnce(&dyn Transport) -> R) -> Result<R, Error> {
+        match self.transport.read().unwrap().as_deref() {
+            Some(t) => Ok(f(t)),
+            None => Err(Error::WorkerStopped),
+        }
+    }
+
+    /// Fetch transfer statistics
+    pub fn stats(&self) -> Stats {
+        self.stats.lock().unwrap().clone()
+    }
+
+    /// Reset transfer statistics, returning the previous values
+    pub fn reset_stats(&self) -> Stats {
+        std::mem::take(&mut *self.stats.lock().unwrap())
+    }
+
+    /// Update transfer statistics
+    pub fn record(&self, f: impl FnOnce(&mut Stats)) {
+        f(&mut self.stats.lock().unwrap())
+    }
+
+    /// Count failed operations
+    fn check<R>(&self, res: Result<R, Error>) -> Result<R, Error> {
+        if res.is_err() {
+            self.record(|s| s.usb_errors += 1);
+        }
+        res
+    }
+
+    /// Stop the worker thread once queued jobs complete, then close the transport
+    pub fn close(&self) -> Result<(), Error> {
+        // Dropping the sender ends the worker loop
+        drop(self.tx.lock().unwrap().take());
+
+        if let Some(thread) = self.thread.lock().unwrap().take() {
+            if thread.join().is_err() {
+                error!("Worker thread panicked");
+            }
+        }
+
+        match self.transport.write().unwrap().take() {
+            Some(mut t) => t.close(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        if let Err(e) = self.close() {
+            error!("Closing worker: {}", e);
+        }
+    }
+}
+
+/// Bulk transport operations are forwarded to the worker thread, control operations
+/// are issued directly
+impl Transport for Worker {
+    fn control_in(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buff: &mut [u8],
+        timeout: Duration,
+    ) -> Result<usize, Error> {
+        let res =
+            self.direct(|t| t.control_in(request_type, request, value, index, buff, timeout))?;
+        self.check(res)
+    }
+
+    fn control_out(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buff: &[u8],
+        timeout: Duration,
+    ) -> Result<usize, Error> {
+        let res =
+            self.direct(|t| t.control_out(request_type, request, value, index, buff, timeout))?;
+        self.check(res)
+    }
+
+    fn bulk_in(&self, buff: &mut [u8], timeout: Duration) -> Result<usize, Error> {
+        let len = buff.len();
+
+        let (n, data) = self.call(move |t| {
+            let mut data = vec![0u8; len];
+            t.bulk_in(&mut data, timeout).map(|n| (n, data))
+        })??;
+
+        buff[..n].copy_from_slice(&data[..n]);
+
+        Ok(n)
+    }
+
+    fn bulk_out(&self, buff: &[u8], timeout: Duration) -> Result<usize, Error> {
+        let data = buff.to_vec();
+
+        self.call(move |t| t.bulk_out(&data, timeout))?
+    }
+
+    fn max_packet_size(&self) -> usize {
+        self.direct(|t| t.max_packet_size())
+            .unwrap_or(crate::transport::DEFAULT_MAX_PACKET_SIZE)
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        Worker::close(self)
+    }
+}
@@ -0,0 +1,69 @@
+#![cfg(feature = "mock")]
+
+use driver_cp2130::mock::MockCp2130;
+use driver_cp2130::prelude::*;
+
+#[test]
+fn gpio_batch_sequence() {
+    let mock = MockCp2130::new();
+    let _dc = mock
+        .gpio_out(2, GpioMode::PushPull, GpioLevel::High)
+        .unwrap();
+    let _cs = mock
+        .gpio_out(0, GpioMode::PushPull, GpioLevel::High)
+        .unwrap();
+    mock.set_input(5, GpioLevel::High);
+
+    // dc low + cs low, then cs high + dc high, then a single read for both levels
+    mock.inject_error_after(3, Cp2130Error::Usb(rusb::Error::Io));
+
+    let reads = mock
+        .gpio_batch(|b| {
+            b.set_low(2)
+                .set_low(0)
+                .set_high(0)
+                .set_high(2)
+                .get(2)
+                .get(5);
+        })
+        .unwrap();
+    assert_eq!(reads, vec![true, true]);
+    assert_eq!(mock.gpio_level(0), GpioLevel::High);
+    assert_eq!(mock.gpio_level(2), GpioLevel::High);
+
+    // The injected error is consumed by the following operation
+    assert!(mock.version().is_err());
+}
+
+#[test]
+fn gpio_batch_mode_changes() {
+    let mock = MockCp2130::new();
+
+    let reads = mock
+        .gpio_batch(|b| {
+            b.set_mode_level(3, GpioMode::PushPull, GpioLevel::Low)
+                .get(3)
+                .set_high(3)
+                .get(3);
+        })
+        .unwrap();
+    assert_eq!(reads, vec![false, true]);
+    assert_eq!(mock.gpio_mode(3), GpioMode::PushPull);
+}
+
+#[test]
+fn gpio_batch_invalid_pin() {
+    let mock = MockCp2130::new();
+    let _pin = mock
+        .gpio_out(1, GpioMode::PushPull, GpioLevel::Low)
+        .unwrap();
+
+    // Nothing is executed where any pin is invalid
+    assert!(matches!(
+        mock.gpio_batch(|b| {
+            b.set_high(1).set_high(11);
+        }),
+        Err(Cp2130Error::InvalidPin(11))
+    ));
+    assert_eq!(mock.gpio_level(1), GpioLevel::Low);
+}
@@ -0,0 +1,67 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use embedded_hal::digital::{ErrorType, InputPin};
+
+use driver_cp2130::prelude::*;
+
+/// Input returning a scripted sequence of levels, holding the last level once exhausted
+struct FakeInput {
+    levels: VecDeque<bool>,
+}
+
+impl ErrorType for FakeInput {
+    type Error = Cp2130Error;
+}
+
+impl InputPin for FakeInput {
+    fn is_high(&mut self) -> Result<bool, Cp2130Error> {
+        match self.levels.len() {
+            1 => Ok(self.levels[0]),
+            _ => Ok(self.levels.pop_front().unwrap()),
+        }
+    }
+
+    fn is_low(&mut self) -> Result<bool, Cp2130Error> {
+        self.is_high().map(|v| !v)
+    }
+}
+
+#[test]
+fn debounce_bounce() {
+    // Initial low, bounce, then settle high
+    let levels = [
+        false, true, false, true, true, false, true, true, true, true,
+    ];
+    let pin = FakeInput {
+        levels: levels.into_iter().collect(),
+    };
+
+    let mut input = DebouncedInput::new(pin, Duration::ZERO, 3).unwrap();
+    assert!(!input.level());
+
+    let changes: Vec<_> = (0..9).map(|_| input.update().unwrap()).collect();
+    assert_eq!(changes.iter().flatten().collect::<Vec<_>>(), vec![&true]);
+    assert_eq!(changes[7], Some(true));
+    assert!(input.is_high().unwrap());
+
+    let pin = FakeInput {
+        levels: [true, false].into_iter().collect(),
+    };
+    let mut input = DebouncedInput::new(pin, Duration::from_millis(1), 4).unwrap();
+    assert_eq!(
+        input.wait_for_change(Some(Duration::from_secs(1))).unwrap(),
+        Some(false)
+    );
+    assert_eq!(
+        input
+            .wait_for_change(Some(Duration::from_millis(10)))
+            .unwrap(),
+        None
+    );
+
+    let pin = FakeInput {
+        levels: [true].into_iter().collect(),
+    };
+    assert!(DebouncedInput::new(pin, Duration::ZERO, 0).is_err());
+}
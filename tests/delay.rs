@@ -0,0 +1,19 @@
+use std::time::{Duration, Instant};
+
+use embedded_hal::delay::DelayNs;
+
+use driver_cp2130::Delay;
+
+#[test]
+fn delay_minimum() {
+    let mut delay = Delay::new();
+
+    let start = Instant::now();
+    delay.delay_ms(5);
+    assert!(start.elapsed() >= Duration::from_millis(5));
+
+    let start = Instant::now();
+    delay.delay_us(500);
+    delay.delay_ns(500_000);
+    assert!(start.elapsed() >= Duration::from_millis(1));
+}
@@ -0,0 +1,81 @@
+use embedded_hal::spi::{ErrorType, Operation, SpiDevice};
+
+use driver_cp2130::eeprom::EepromCommand;
+use driver_cp2130::prelude::*;
+
+/// In-memory 25xx040 model (512 bytes, 16 byte pages, A8 in the instruction)
+struct FakeEeprom {
+    memory: [u8; 512],
+    wel: bool,
+}
+
+impl ErrorType for FakeEeprom {
+    type Error = Cp2130Error;
+}
+
+impl SpiDevice<u8> for FakeEeprom {
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Cp2130Error> {
+        let (cmd, rest) = match operations.split_first_mut() {
+            Some((Operation::Write(cmd), rest)) => (cmd.to_vec(), rest),
+            _ => panic!("expected command write"),
+        };
+
+        let address = || ((cmd[0] as usize & 0x08) << 5) | cmd[1] as usize;
+
+        match cmd[0] & !0x08 {
+            c if c == EepromCommand::WriteEnable as u8 => self.wel = true,
+            c if c == EepromCommand::ReadStatus as u8 => match &mut rest[0] {
+                Operation::Read(r) => r[0] = 0,
+                _ => panic!("expected read"),
+            },
+            c if c == EepromCommand::Read as u8 => match &mut rest[0] {
+                Operation::Read(r) => {
+                    let a = address();
+                    r.copy_from_slice(&self.memory[a..a + r.len()]);
+                }
+                _ => panic!("expected read"),
+            },
+            c if c == EepromCommand::Write as u8 => {
+                assert!(self.wel, "write without write enable");
+                let data = match &rest[0] {
+                    Operation::Write(w) => w.to_vec(),
+                    _ => panic!("expected write"),
+                };
+
+                // Writes wrap within a page
+                let a = address();
+                for (i, b) in data.iter().enumerate() {
+                    self.memory[(a & !0x0F) | ((a + i) & 0x0F)] = *b;
+                }
+                self.wel = false;
+            }
+            c => panic!("unexpected command 0x{:02x}", c),
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn eeprom_write_read() {
+    let fake = FakeEeprom {
+        memory: [0xFF; 512],
+        wel: false,
+    };
+    let geometry = EepromGeometry::new(512, 16).unwrap();
+    assert_eq!(geometry.address_bytes(), 1);
+
+    let mut eeprom = SpiEeprom::new(fake, geometry);
+
+    // Unaligned write crossing pages and the A8 boundary
+    let data: Vec<u8> = (0..40u8).collect();
+    eeprom.write(250, &data).unwrap();
+    assert_eq!(eeprom.verify(250, &data).unwrap(), None);
+
+    let mut buff = [0u8; 2];
+    eeprom.read(256, &mut buff).unwrap();
+    assert_eq!(buff, [6, 7]);
+
+    assert!(eeprom.write(500, &data).is_err());
+    assert_eq!(EepromGeometry::new(32768, 64).unwrap().address_bytes(), 2);
+}
@@ -0,0 +1,28 @@
+#![cfg(feature = "ffi")]
+
+use std::ffi::c_char;
+
+use driver_cp2130::ffi::*;
+
+#[test]
+fn ffi_errors() {
+    let mut level = 0u8;
+
+    let status = unsafe { cp2130_gpio_get(std::ptr::null_mut(), 0, &mut level) };
+    assert_eq!(status, Cp2130Status::InvalidArgument);
+
+    let mut buff = [0 as c_char; 8];
+    let len = unsafe { cp2130_last_error(buff.as_mut_ptr(), buff.len()) };
+    assert!(len > buff.len());
+    assert_eq!(buff[7], 0);
+
+    let mut full = vec![0 as c_char; len + 1];
+    unsafe { cp2130_last_error(full.as_mut_ptr(), full.len()) };
+    let message = unsafe { std::ffi::CStr::from_ptr(full.as_ptr()) };
+    assert!(message.to_str().unwrap().contains("null pointer"));
+
+    assert_eq!(
+        unsafe { cp2130_close(std::ptr::null_mut()) },
+        Cp2130Status::Ok
+    );
+}
@@ -0,0 +1,196 @@
+use embedded_hal::spi::{ErrorType, Operation, SpiDevice};
+
+use driver_cp2130::flash::{EraseType, FlashCommand, FlashGeometry, Sfdp, SpiFlash};
+use driver_cp2130::prelude::*;
+
+/// In-memory SPI NOR flash model
+struct FakeFlash {
+    memory: Vec<u8>,
+    wel: bool,
+}
+
+impl FakeFlash {
+    fn new(len: usize) -> Self {
+        Self {
+            memory: vec![0xFF; len],
+            wel: false,
+        }
+    }
+}
+
+impl ErrorType for FakeFlash {
+    type Error = Cp2130Error;
+}
+
+impl SpiDevice<u8> for FakeFlash {
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Cp2130Error> {
+        let (cmd, rest) = match operations.split_first_mut() {
+            Some((Operation::Write(cmd), rest)) => (cmd.to_vec(), rest),
+            _ => panic!("expected command write"),
+        };
+
+        let address = || u32::from_be_bytes([0, cmd[1], cmd[2], cmd[3]]) as usize;
+
+        match cmd[0] {
+            c if c == FlashCommand::WriteEnable as u8 => self.wel = true,
+            c if c == FlashCommand::ReadJedecId as u8 => match &mut rest[0] {
+                Operation::Read(r) => r.copy_from_slice(&[0xEF, 0x40, 0x10]),
+                _ => panic!("expected read"),
+            },
+            c if c == FlashCommand::ReadStatus as u8 => match &mut rest[0] {
+                Operation::Read(r) => r[0] = 0,
+                _ => panic!("expected read"),
+            },
+            c if c == FlashCommand::Read as u8 => match &mut rest[0] {
+                Operation::Read(r) => {
+                    let a = address();
+                    r.copy_from_slice(&self.memory[a..a + r.len()]);
+                }
+                _ => panic!("expected read"),
+            },
+            c if c == FlashCommand::PageProgram as u8 => {
+                assert!(self.wel, "program without write enable");
+                let data = match &rest[0] {
+                    Operation::Write(w) => w.to_vec(),
+                    _ => panic!("expected write"),
+                };
+
+                // Programs must not cross a page boundary
+                let a = address();
+                assert_eq!(a / 256, (a + data.len() - 1) / 256);
+
+                for (i, b) in data.iter().enumerate() {
+                    self.memory[a + i] &= b;
+                }
+                self.wel = false;
+            }
+            c if c == FlashCommand::SectorErase as u8 => {
+                assert!(self.wel, "erase without write enable");
+                let a = address();
+                self.memory[a..a + 4096].fill(0xFF);
+                self.wel = false;
+            }
+            c => panic!("unexpected command 0x{:02x}", c),
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn flash_program_verify() {
+    let geometry = FlashGeometry {
+        capacity: 64 * 1024,
+        ..Default::default()
+    };
+    let mut flash = SpiFlash::new(FakeFlash::new(geometry.capacity), geometry);
+
+    let id = flash.read_id().unwrap();
+    assert!(id.is_valid());
+    assert_eq!(id.capacity_bytes(), Some(64 * 1024));
+
+    // Unaligned write spanning multiple pages
+    let data: Vec<u8> = (0..1000u32).map(|v| v as u8).collect();
+    flash.erase(0, 4096).unwrap();
+    flash.write(100, &data).unwrap();
+    assert_eq!(flash.verify(100, &data).unwrap(), None);
+
+    let mut buff = [0u8; 4];
+    flash.read(96, &mut buff).unwrap();
+    assert_eq!(buff, [0xFF, 0xFF, 0xFF, 0xFF]);
+
+    // Programming without erase only clears bits
+    flash.write(100 + 255, &[0x0F]).unwrap();
+    assert_eq!(flash.verify(100, &data).unwrap(), Some(255));
+
+    assert!(flash.erase(100, 4096).is_err());
+    assert!(flash.read(64 * 1024 - 2, &mut buff).is_err());
+}
+
+#[test]
+fn flash_sfdp_decode() {
+    let header = [
+        0x53, 0x46, 0x44, 0x50, 0x06, 0x01, 0x00, 0xFF, // SFDP v1.6, one parameter header
+        0x00, 0x06, 0x01, 0x10, 0x80, 0x00, 0x00, 0xFF, // BFPT v1.6, 16 dwords at 0x80
+    ];
+    let (revision, addr, len) = Sfdp::decode_header(&header).unwrap();
+    assert_eq!(revision, (1, 6));
+    assert_eq!((addr, len), (0x80, 64));
+
+    let mut bfpt = [0u8; 64];
+    let dwords = [
+        (1, 0xFFF9_20E5u32),
+        (2, 0x01FF_FFFF),
+        (8, 0x520F_200C),
+        (9, 0x0000_D810),
+        (11, 0x0000_0080),
+    ];
+    for (n, v) in dwords {
+        bfpt[(n - 1) * 4..n * 4].copy_from_slice(&v.to_le_bytes());
+    }
+
+    let sfdp = Sfdp::decode(revision, &bfpt).unwrap();
+    assert_eq!(sfdp.capacity, 4 * 1024 * 1024);
+    assert_eq!(sfdp.page_size, Some(256));
+    assert_eq!(
+        sfdp.erase_types,
+        vec![
+            EraseType {
+                size: 4096,
+                opcode: 0x20
+            },
+            EraseType {
+                size: 32 * 1024,
+                opcode: 0x52
+            },
+            EraseType {
+                size: 64 * 1024,
+                opcode: 0xD8
+            },
+        ]
+    );
+
+    let g = sfdp.geometry();
+    assert_eq!((g.page_size, g.sector_size), (256, 4096));
+
+    assert!(Sfdp::decode_header(&[0xFF; 16]).is_none());
+}
+
+#[test]
+fn flash_sfdp_invalid_erase() {
+    let mut bfpt = [0u8; 36];
+    let dwords = [(2, 0x01FF_FFFFu32), (8, 0x20FF_200C), (9, 0xD840_D8FF)];
+    for (n, v) in dwords {
+        bfpt[(n - 1) * 4..n * 4].copy_from_slice(&v.to_le_bytes());
+    }
+
+    // Out of range erase sizes are dropped rather than overflowing
+    let sfdp = Sfdp::decode((1, 0), &bfpt).unwrap();
+    assert_eq!(
+        sfdp.erase_types,
+        vec![EraseType {
+            size: 4096,
+            opcode: 0x20
+        }]
+    );
+}
+
+#[test]
+fn flash_invalid_geometry() {
+    let geometry = FlashGeometry {
+        page_size: 0,
+        ..Default::default()
+    };
+    let mut flash = SpiFlash::new(FakeFlash::new(64 * 1024), geometry.clone());
+
+    // Zero sizes are rejected rather than dividing by zero
+    assert!(flash.write(0, &[0x55]).is_err());
+    assert!(flash.set_geometry(geometry).is_err());
+
+    let geometry = FlashGeometry {
+        sector_size: 0,
+        ..Default::default()
+    };
+    assert!(flash.set_geometry(geometry).is_err());
+    assert!(flash.erase(0, 4096).is_err());
+}
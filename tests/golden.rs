@@ -0,0 +1,244 @@
+#![cfg(feature = "mock")]
+
+//! Golden transcript tests
+//!
+//! Each scenario is recorded against the mock device and compared byte-for-byte with
+//! the transcript in `tests/golden`, then replayed from that transcript in place of the
+//! device. Set `CP2130_UPDATE_GOLDEN=1` to rewrite transcripts after an intended change.
+//! Transcripts of concurrent transfers are checked for ordering instead.
+
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use driver_cp2130::device::Info;
+use driver_cp2130::mock::MockCp2130;
+use driver_cp2130::prelude::*;
+use driver_cp2130::transport::{Exchange, ExchangeKind, Replayer};
+use embedded_hal::spi::MODE_3;
+
+/// Transcript buffer shared with the recorder
+#[derive(Clone, Default)]
+struct Transcript(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Transcript {
+    fn write(&mut self, buff: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buff);
+        Ok(buff.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Transport serving a shared replayer, so the remaining exchanges can be checked
+struct Shared(Arc<Replayer>);
+
+impl Transport for Shared {
+    fn control_in(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buff: &mut [u8],
+        timeout: Duration,
+    ) -> Result<usize, Cp2130Error> {
+        self.0
+            .control_in(request_type, request, value, index, buff, timeout)
+    }
+
+    fn control_out(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buff: &[u8],
+        timeout: Duration,
+    ) -> Result<usize, Cp2130Error> {
+        self.0
+            .control_out(request_type, request, value, index, buff, timeout)
+    }
+
+    fn bulk_in(&self, buff: &mut [u8], timeout: Duration) -> Result<usize, Cp2130Error> {
+        self.0.bulk_in(buff, timeout)
+    }
+
+    fn bulk_out(&self, buff: &[u8], timeout: Duration) -> Result<usize, Cp2130Error> {
+        self.0.bulk_out(buff, timeout)
+    }
+
+    fn close(&mut self) -> Result<(), Cp2130Error> {
+        Ok(())
+    }
+}
+
+/// Parse a transcript, dropping timestamps
+///
+/// Bulk IN and OUT are issued concurrently so exchanges are compared in order per kind.
+fn exchanges(transcript: &str, kind: ExchangeKind) -> Vec<Exchange> {
+    transcript
+        .lines()
+        .filter(|l| !l.trim().is_empty() && !l.starts_with('#'))
+        .map(|l| l.parse::<Exchange>().unwrap())
+        .filter(|e| e.kind == kind)
+        .map(|e| Exchange {
+            elapsed: Duration::ZERO,
+            ..e
+        })
+        .collect()
+}
+
+/// Record `scenario` against `mock`, compare with the golden transcript, then replay it
+fn check(name: &str, mock: MockCp2130, transcript: Transcript, scenario: impl Fn(&Cp2130)) {
+    scenario(&mock);
+    drop(mock);
+
+    let recorded = String::from_utf8(transcript.0.lock().unwrap().clone()).unwrap();
+    let path = format!("{}/tests/golden/{}.txt", env!("CARGO_MANIFEST_DIR"), name);
+
+    if std::env::var_os("CP2130_UPDATE_GOLDEN").is_some() {
+        std::fs::write(&path, &recorded).unwrap();
+    }
+    let golden = std::fs::read_to_string(&path).unwrap();
+
+    for kind in [
+        ExchangeKind::ControlIn,
+        ExchangeKind::ControlOut,
+        ExchangeKind::BulkIn,
+        ExchangeKind::BulkOut,
+    ] {
+        assert_eq!(
+            exchanges(&recorded, kind),
+            exchanges(&golden, kind),
+            "{} {} exchanges differ from golden transcript",
+            name,
+            kind
+        );
+    }
+
+    // The driver must issue exactly the golden requests with no device attached
+    let replayer = Arc::new(Replayer::from_reader(golden.as_bytes()).unwrap());
+    let info = Info::new("Mock".into(), "CP2130".into(), "0".into());
+    let cp2130 = Cp2130::from_transport(Shared(replayer.clone()), info);
+
+    scenario(&cp2130);
+    assert_eq!(replayer.remaining(), 0);
+}
+
+#[test]
+fn golden_gpio() {
+    let transcript = Transcript::default();
+    let mock = MockCp2130::recorded(transcript.clone());
+    mock.set_version(0x0107);
+    mock.set_input(5, GpioLevel::High);
+
+    check("gpio", mock, transcript, |cp2130| {
+        assert_eq!(cp2130.version().unwrap(), 0x0107);
+
+        cp2130
+            .set_gpio_mode_level(10, GpioMode::PushPull, GpioLevel::High)
+            .unwrap();
+        cp2130
+            .set_gpio_mode_level(0, GpioMode::OpenDrain, GpioLevel::Low)
+            .unwrap();
+        cp2130
+            .set_gpio_values(GpioLevels::GPIO_0, GpioLevels::GPIO_0 | GpioLevels::GPIO_10)
+            .unwrap();
+
+        let levels = cp2130.get_gpio_values().unwrap();
+        assert_eq!(levels, GpioLevels::GPIO_0 | GpioLevels::GPIO_5);
+    });
+}
+
+#[test]
+fn golden_event_counter() {
+    let transcript = Transcript::default();
+    let mock = MockCp2130::recorded(transcript.clone());
+
+    check("event_counter", mock, transcript, |cp2130| {
+        cp2130
+            .set_event_counter(EventCounterMode::FallingEdge, 0xfffe)
+            .unwrap();
+
+        let counter = cp2130.event_counter().unwrap();
+        assert_eq!(counter.mode, Some(EventCounterMode::FallingEdge));
+        assert_eq!(counter.count, 0xfffe);
+    });
+}
+
+#[test]
+fn golden_spi() {
+    let transcript = Transcript::default();
+    let mock = MockCp2130::recorded(transcript.clone());
+    mock.push_spi_response(&[0x11, 0x22, 0x33]);
+    mock.push_spi_response(&[0x44, 0x55]);
+
+    check("spi", mock, transcript, |cp2130| {
+        let config = SpiConfig::builder()
+            .clock(SpiClock::Clock1_5MHz)
+            .spi_mode(MODE_3)
+            .cs_mode(CsMode::Exclusive)
+            .post_assert_delay(Duration::from_micros(50))
+            .pre_deassert_delay(Duration::from_micros(2560))
+            .build()
+            .unwrap();
+        let _spi = cp2130.spi(2, config, None).unwrap();
+
+        cp2130.spi_write(&[0xde, 0xad, 0xbe, 0xef]).unwrap();
+
+        let mut buff = [0u8; 3];
+        assert_eq!(cp2130.spi_write_read(&[0x9f, 0, 0], &mut buff).unwrap(), 3);
+        assert_eq!(buff, [0x11, 0x22, 0x33]);
+
+        let mut buff = [0u8; 2];
+        assert_eq!(cp2130.spi_read(&mut buff).unwrap(), 2);
+        assert_eq!(buff, [0x44, 0x55]);
+    });
+}
+
+#[test]
+fn transcript_write_read_pipelined() {
+    use driver_cp2130::device::MAX_TRANSFER_LEN;
+
+    let transcript = Transcript::default();
+    let mock = MockCp2130::recorded(transcript.clone());
+    mock.set_stalled(true);
+
+    let data: Vec<u8> = (0..MAX_TRANSFER_LEN + 4).map(|i| i as u8).collect();
+    mock.push_spi_response(&data[..MAX_TRANSFER_LEN]);
+    mock.push_spi_response(&data[MAX_TRANSFER_LEN..]);
+
+    // Complete lines recorded so far, the recorder may be part way through a line
+    let recorded = || {
+        let t = String::from_utf8(transcript.0.lock().unwrap().clone()).unwrap();
+        t[..t.rfind('\n').map_or(0, |i| i + 1)].to_string()
+    };
+    let mut buff = vec![0u8; data.len()];
+
+    std::thread::scope(|s| {
+        let transfer = s.spawn(|| mock.spi_write_read(&data, &mut buff));
+
+        // The second command is written while the response to the first is outstanding
+        let start = std::time::Instant::now();
+        while !exchanges(&recorded(), ExchangeKind::BulkOut)
+            .iter()
+            .any(|e| e.data_out.len() == 8 + 4)
+        {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "second command not written before the first response"
+            );
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert!(exchanges(&recorded(), ExchangeKind::BulkIn).is_empty());
+
+        mock.set_stalled(false);
+        assert_eq!(transfer.join().unwrap().unwrap(), data.len());
+    });
+
+    assert_eq!(buff, data);
+    assert_eq!(mock.take_spi_writes().concat(), data);
+}
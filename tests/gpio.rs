@@ -0,0 +1,39 @@
+use driver_cp2130::prelude::*;
+
+#[test]
+fn gpio_levels_pin_access() {
+    let mut levels = GpioLevels::empty();
+
+    levels.set_pin(0, GpioLevel::High);
+    levels.set_pin(5, GpioLevel::High);
+    levels.set_pin(10, GpioLevel::High);
+    levels.set_pin(11, GpioLevel::High);
+
+    assert_eq!(
+        levels,
+        GpioLevels::GPIO_0 | GpioLevels::GPIO_5 | GpioLevels::GPIO_10
+    );
+    assert!(levels.pin(5));
+    assert!(!levels.pin(4));
+    assert!(!levels.pin(11));
+
+    levels.set_pin(5, GpioLevel::Low);
+    assert!(!levels.pin(5));
+}
+
+#[test]
+fn gpio_levels_iter_display() {
+    let levels = GpioLevels::GPIO_1 | GpioLevels::GPIO_9;
+
+    let high: Vec<u8> = levels
+        .iter_pins()
+        .filter(|(_, l)| *l == GpioLevel::High)
+        .map(|(p, _)| p)
+        .collect();
+    assert_eq!(high, vec![1, 9]);
+    assert_eq!(levels.iter_pins().count(), 11);
+
+    let s = levels.to_string();
+    assert!(s.starts_with("GPIO0: low, GPIO1: high, GPIO2: low"));
+    assert!(s.ends_with("GPIO9: high, GPIO10: low"));
+}
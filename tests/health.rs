@@ -0,0 +1,65 @@
+#![cfg(feature = "mock")]
+
+use std::sync::mpsc;
+use std::time::Duration;
+
+use driver_cp2130::mock::MockCp2130;
+use driver_cp2130::prelude::*;
+
+#[test]
+fn ping() {
+    let mock = MockCp2130::new();
+    assert!(mock.ping(Duration::from_millis(100)).is_ok());
+
+    mock.inject_error(Cp2130Error::Usb(rusb::Error::Timeout));
+    assert!(matches!(
+        mock.ping(Duration::from_millis(100)),
+        Err(Cp2130Error::Usb(rusb::Error::Timeout))
+    ));
+}
+
+#[test]
+fn health_monitor_transitions() {
+    let mock = MockCp2130::new();
+    let (tx, rx) = mpsc::channel();
+
+    // Two consecutive failures are required, so fail the second and third pings
+    mock.inject_error_after(1, Cp2130Error::Usb(rusb::Error::Timeout));
+    mock.inject_error(Cp2130Error::Usb(rusb::Error::Timeout));
+
+    let config = HealthConfig {
+        interval: Duration::from_millis(10),
+        failures: 2,
+        ..Default::default()
+    };
+    let monitor = mock
+        .health_monitor(config, move |healthy| tx.send(healthy).unwrap())
+        .unwrap();
+
+    assert_eq!(rx.recv_timeout(Duration::from_secs(1)), Ok(false));
+    assert!(matches!(
+        monitor.take_error(),
+        Some(Cp2130Error::Usb(rusb::Error::Timeout))
+    ));
+
+    assert_eq!(rx.recv_timeout(Duration::from_secs(1)), Ok(true));
+    assert!(monitor.is_healthy());
+    assert_eq!(monitor.failures(), 0);
+    assert!(monitor.latency().is_some());
+
+    monitor.stop();
+}
+
+#[test]
+fn health_monitor_config() {
+    let mock = MockCp2130::new();
+
+    let config = HealthConfig {
+        failures: 0,
+        ..Default::default()
+    };
+    assert!(matches!(
+        mock.health_monitor(config, |_| ()),
+        Err(Cp2130Error::InvalidConfig { .. })
+    ));
+}
@@ -0,0 +1,75 @@
+use driver_cp2130::device::Info;
+use driver_cp2130::prelude::*;
+
+#[test]
+fn filter_strings() {
+    let info = Info::new("Silicon Labs".into(), "CP2130".into(), "0001".into());
+
+    let f = Filter::default();
+    assert!(!f.requires_strings());
+    assert!(f.matches_info(&info));
+
+    let f = Filter::with_serial("0001");
+    assert!(f.requires_strings());
+    assert!(f.matches_info(&info));
+    assert!(!Filter::with_serial("0002").matches_info(&info));
+
+    let f = Filter {
+        product: Some("Other".into()),
+        ..Filter::with_serial("0001")
+    };
+    assert!(!f.matches_info(&info));
+}
+
+#[test]
+fn filter_location() {
+    let port: PortPath = "1-2.3".parse().unwrap();
+    assert_eq!(port.bus, 1);
+    assert_eq!(port.ports, vec![2, 3]);
+    assert_eq!(port.to_string(), "1-2.3");
+    assert!("1".parse::<PortPath>().is_err());
+    assert!("1-a".parse::<PortPath>().is_err());
+
+    let f = Filter::with_port(port);
+    assert!(f.matches_location(1, 7, &[2, 3]));
+    assert!(!f.matches_location(1, 7, &[2]));
+
+    let f = Filter::with_location(1, 7);
+    assert!(f.matches_location(1, 7, &[4]));
+    assert!(!f.matches_location(2, 7, &[4]));
+}
+
+#[test]
+fn info_display() {
+    let info = Info::new("Silicon Labs".into(), "CP2130".into(), "0001".into());
+    assert_eq!(info.to_string(), "Silicon Labs CP2130 (serial: 0001)");
+    assert_eq!(info.bus(), None);
+
+    let info = info
+        .with_release(0x0100)
+        .with_location(1, 4, vec![2, 3])
+        .with_speed(Speed::Full);
+    assert_eq!(info.port().map(|p| p.to_string()), Some("1-2.3".into()));
+    assert_eq!(
+        info.to_string(),
+        "Silicon Labs CP2130 (serial: 0001) release 1.00 bus 001 address 004 port 1-2.3 full speed"
+    );
+}
+
+#[test]
+fn filter_matches() {
+    let info = Info::new("Silicon Labs".into(), "CP2130".into(), "0001".into());
+    let f = Filter {
+        port: Some("1-2.3".parse().unwrap()),
+        ..Filter::with_serial("0001")
+    };
+
+    assert!(f.matches(0x10c4, 0x87a0, 1, 7, &[2, 3], Some(&info)));
+    assert!(!f.matches(0x10c4, 0x87a1, 1, 7, &[2, 3], Some(&info)));
+    assert!(!f.matches(0x10c4, 0x87a0, 1, 7, &[4], Some(&info)));
+
+    // Strings are only checked where provided
+    let other = Info::new("Silicon Labs".into(), "CP2130".into(), "0002".into());
+    assert!(!f.matches(0x10c4, 0x87a0, 1, 7, &[2, 3], Some(&other)));
+    assert!(f.matches(0x10c4, 0x87a0, 1, 7, &[2, 3], None));
+}
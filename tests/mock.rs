@@ -0,0 +1,1041 @@
+#![cfg(feature = "mock")]
+
+use std::time::Duration;
+
+use embedded_hal::digital::{InputPin as _, OutputPin as _};
+use embedded_hal::spi::SpiDevice;
+
+use driver_cp2130::mock::MockCp2130;
+use driver_cp2130::prelude::*;
+use driver_cp2130::self_test::SelfTestCheck;
+
+#[test]
+fn mock_gpio() {
+    let mock = MockCp2130::new();
+
+    let mut output = mock
+        .gpio_out(3, GpioMode::PushPull, GpioLevel::Low)
+        .unwrap();
+    assert_eq!(mock.gpio_mode(3), GpioMode::PushPull);
+    assert_eq!(mock.gpio_level(3), GpioLevel::Low);
+
+    output.set_high().unwrap();
+    assert_eq!(mock.gpio_level(3), GpioLevel::High);
+
+    let mut input = mock.gpio_in(5).unwrap();
+    assert!(input.is_low().unwrap());
+
+    mock.set_input(5, GpioLevel::High);
+    assert!(input.is_high().unwrap());
+    assert!(mock.get_gpio_level(5).unwrap());
+}
+
+#[test]
+fn mock_spi() {
+    let mock = MockCp2130::new();
+    let mut spi = mock.spi(0, SpiConfig::default(), Some(2)).unwrap();
+
+    mock.push_spi_response(&[0x12, 0x34]);
+
+    let mut buff = [0u8; 2];
+    spi.transfer(&mut buff, &[0xaa, 0xbb]).unwrap();
+    assert_eq!(buff, [0x12, 0x34]);
+
+    spi.write(&[0x01, 0x02, 0x03]).unwrap();
+
+    assert_eq!(
+        mock.take_spi_writes(),
+        vec![vec![0xaa, 0xbb], vec![0x01, 0x02, 0x03]]
+    );
+
+    // CS is deasserted after each transaction
+    assert_eq!(mock.gpio_level(2), GpioLevel::High);
+}
+
+#[test]
+fn mock_spi_cs_on_error() {
+    let mock = MockCp2130::new();
+    let mut spi = mock.spi(0, SpiConfig::default(), Some(2)).unwrap();
+
+    // Fail the SPI write following CS assertion
+    mock.inject_error_after(1, Cp2130Error::Usb(rusb::Error::Pipe));
+    assert!(spi.write(&[0x01, 0x02]).is_err());
+
+    assert_eq!(mock.gpio_level(2), GpioLevel::High);
+}
+
+#[test]
+fn mock_spi_long() {
+    let mock = MockCp2130::new();
+
+    // Longer than a single bulk chunk, so split across multiple OUT transfers
+    let data: Vec<u8> = (0..3000).map(|i| i as u8).collect();
+    mock.spi_write(&data).unwrap();
+
+    mock.push_spi_response(&data);
+    let mut buff = vec![0u8; data.len()];
+    mock.spi_write_read(&data, &mut buff).unwrap();
+    assert_eq!(buff, data);
+
+    assert_eq!(mock.take_spi_writes(), vec![data.clone(), data]);
+}
+
+#[test]
+fn mock_spi_write_stream() {
+    let mock = MockCp2130::new();
+
+    let data: Vec<u8> = (0..5000).map(|i| (i * 7) as u8).collect();
+    mock.spi_write_stream(&mut data.as_slice(), data.len())
+        .unwrap();
+    assert_eq!(mock.take_spi_writes(), vec![data.clone()]);
+
+    // Short readers are padded with zeros to complete the transfer
+    let res = mock.spi_write_stream(&mut &data[..100], 200);
+    assert!(matches!(res, Err(Cp2130Error::Io(_))));
+    assert_eq!(mock.take_spi_writes()[0].len(), 200);
+}
+
+#[test]
+fn mock_spi_stream() {
+    let mock = MockCp2130::new();
+    mock.push_spi_response(&[1, 2, 3, 4]);
+    mock.push_spi_response(&[5, 6, 7, 8]);
+
+    let config = StreamConfig {
+        block_len: 4,
+        capacity: 2,
+        ..Default::default()
+    };
+    let stream = mock.spi_stream(config).unwrap();
+
+    assert_eq!(stream.recv().unwrap(), vec![1, 2, 3, 4]);
+    assert_eq!(stream.recv().unwrap(), vec![5, 6, 7, 8]);
+
+    // Unconsumed blocks overflow the queue
+    std::thread::sleep(Duration::from_millis(50));
+    assert!(stream.overflows() > 0);
+
+    // The stream error is reported once buffered blocks are consumed
+    mock.inject_error(Cp2130Error::Usb(rusb::Error::Pipe));
+    let err = loop {
+        if let Err(e) = stream.recv() {
+            break e;
+        }
+    };
+    assert!(matches!(err, Cp2130Error::Usb(rusb::Error::Pipe)));
+    assert!(!stream.is_running());
+    assert!(stream.stop().is_ok());
+}
+
+#[test]
+fn mock_stats() {
+    let mock = MockCp2130::new();
+
+    mock.spi_write(&[0u8; 10]).unwrap();
+    mock.spi_write_read(&[0u8; 4], &mut [0u8; 4]).unwrap();
+
+    mock.inject_error(Cp2130Error::Usb(rusb::Error::Pipe));
+    assert!(mock.spi_read(&mut [0u8; 4]).is_err());
+
+    let stats = mock.reset_stats();
+    assert_eq!(stats.transfers, 2);
+    assert_eq!(stats.bytes_written, 14);
+    assert_eq!(stats.bytes_read, 4);
+    assert_eq!(stats.usb_errors, 1);
+    assert!(stats.bus_time > Duration::ZERO);
+
+    assert_eq!(mock.stats(), Stats::default());
+}
+
+#[test]
+fn mock_partial_read() {
+    let mock = MockCp2130::new();
+    let data: Vec<u8> = (0..100).collect();
+    mock.push_spi_response(&data);
+
+    // Fail after the read command and first packet
+    mock.inject_error_after(2, Cp2130Error::Usb(rusb::Error::Timeout));
+
+    let mut buff = [0u8; 100];
+    match mock.spi_read(&mut buff) {
+        Err(Cp2130Error::Partial {
+            completed,
+            data: d,
+            source,
+        }) => {
+            assert_eq!(completed, 64);
+            assert_eq!(d, &data[..64]);
+            assert!(matches!(*source, Cp2130Error::Usb(rusb::Error::Timeout)));
+        }
+        r => panic!("unexpected result: {:?}", r),
+    }
+}
+
+#[test]
+fn mock_reset_and_reopen() {
+    let mock = MockCp2130::new();
+
+    // Re-enumeration is only supported for libusb devices
+    assert!(matches!(
+        mock.reset_and_reopen(Duration::from_millis(10)),
+        Err(Cp2130Error::Unsupported(_))
+    ));
+}
+
+#[test]
+fn mock_errors() {
+    let mock = MockCp2130::new();
+    mock.set_version(0x0107);
+
+    mock.inject_error(Cp2130Error::Usb(rusb::Error::Timeout));
+    assert!(matches!(
+        mock.version(),
+        Err(Cp2130Error::Usb(rusb::Error::Timeout))
+    ));
+
+    assert_eq!(mock.version().unwrap(), 0x0107);
+}
+
+#[test]
+fn mock_self_test() {
+    let mock = MockCp2130::new();
+
+    // No loopback wiring on the mock, GPIO high and SPI transfer checks fail
+    let report = mock.self_test(SelfTestConfig::default());
+
+    let failed: Vec<_> = report.failures().map(|r| r.check).collect();
+    assert_eq!(
+        failed,
+        vec![
+            SelfTestCheck::GpioHigh,
+            SelfTestCheck::SpiTransferShort,
+            SelfTestCheck::SpiTransferLong
+        ]
+    );
+    assert!(!report.passed());
+}
+
+#[test]
+fn mock_usb_config() {
+    let mock = MockCp2130::new();
+
+    let config = mock.usb_config().unwrap();
+    assert_eq!(config.vid, 0x10c4);
+    assert_eq!(config.pid, 0x87a0);
+    assert_eq!(config.max_power_ma, 100);
+    assert_eq!(config.power_mode, PowerMode::BusPowered);
+    assert_eq!((config.release_major, config.release_minor), (1, 0));
+    assert_eq!(config.transfer_priority, TransferPriority::Write);
+}
+
+#[test]
+fn mock_program_usb_config() {
+    let mock = MockCp2130::new();
+
+    let update = UsbConfigUpdate {
+        pid: Some(0x1234),
+        max_power_ma: Some(251),
+        ..Default::default()
+    };
+    mock.program_usb_config(update, OtpWrite::irreversible())
+        .unwrap();
+
+    let config = mock.usb_config().unwrap();
+    assert_eq!(config.vid, 0x10c4);
+    assert_eq!(config.pid, 0x1234);
+    assert_eq!(config.max_power_ma, 252);
+
+    let update = UsbConfigUpdate {
+        max_power_ma: Some(600),
+        ..Default::default()
+    };
+    assert!(mock
+        .program_usb_config(update, OtpWrite::irreversible())
+        .is_err());
+    assert!(mock
+        .program_usb_config(UsbConfigUpdate::default(), OtpWrite::irreversible())
+        .is_err());
+}
+
+#[test]
+fn mock_prom_strings() {
+    let mock = MockCp2130::new();
+    assert_eq!(mock.manufacturer_string().unwrap(), "Mock");
+    assert_eq!(mock.serial_string().unwrap(), "0");
+
+    // Product strings span both blocks
+    let product = "A product string long enough to continue into the second block";
+    mock.set_product_string(product, OtpWrite::irreversible())
+        .unwrap();
+    assert_eq!(mock.product_string().unwrap(), product);
+
+    mock.set_serial_string("SN-0042", OtpWrite::irreversible())
+        .unwrap();
+    assert_eq!(mock.serial_string().unwrap(), "SN-0042");
+
+    let long = "0123456789012345678901234567890";
+    assert!(mock
+        .set_serial_string(long, OtpWrite::irreversible())
+        .is_err());
+}
+
+#[test]
+fn mock_pin_config() {
+    let mock = MockCp2130::new();
+    assert_eq!(mock.pin_config().unwrap(), PinConfig::default());
+
+    let mut config = PinConfig {
+        clock_divider: 24,
+        ..Default::default()
+    };
+    config.pins[0] = PinDefault {
+        function: PinFunction::Gpio {
+            mode: GpioMode::PushPull,
+            level: GpioLevel::High,
+        },
+        suspend_level: GpioLevel::High,
+        suspend_mode: GpioMode::PushPull,
+        wakeup: None,
+    };
+    config.pins[1].function = PinFunction::ChipSelect;
+    config.pins[3].function = PinFunction::Rtr { active_high: true };
+    config.pins[4].function = PinFunction::EventCounter(EventCounterMode::FallingEdge);
+    config.pins[6].wakeup = Some(GpioLevel::Low);
+    config.pins[7].wakeup = Some(GpioLevel::High);
+
+    mock.program_pin_config(&config, OtpWrite::irreversible())
+        .unwrap();
+    assert_eq!(mock.pin_config().unwrap(), config);
+
+    // Alternate functions are only available on specific pins
+    config.pins[0].function = PinFunction::ClockOut;
+    assert!(mock
+        .program_pin_config(&config, OtpWrite::irreversible())
+        .is_err());
+}
+
+#[test]
+fn mock_power_config() {
+    let mock = MockCp2130::new();
+
+    mock.program_power(PowerMode::SelfPowered, 99, OtpWrite::irreversible())
+        .unwrap();
+
+    // Maximum power is programmed in 2 mA units
+    let usb = mock.usb_config().unwrap();
+    assert_eq!(usb.power_mode, PowerMode::SelfPowered);
+    assert_eq!(usb.max_power_ma, 100);
+
+    let mut power = UsbPower {
+        self_powered: true,
+        max_power_ma: 100,
+        remote_wakeup: false,
+    };
+    assert!(usb.matches_power(&power));
+    power.self_powered = false;
+    assert!(!usb.matches_power(&power));
+
+    assert!(mock
+        .program_power(PowerMode::BusPowered, 502, OtpWrite::irreversible())
+        .is_err());
+}
+
+#[test]
+fn mock_suspend_outputs() {
+    let mock = MockCp2130::new();
+
+    let mut config = PinConfig::default();
+    config.pins[10].function = PinFunction::Gpio {
+        mode: GpioMode::PushPull,
+        level: GpioLevel::High,
+    };
+    config.set_suspend_outputs(true, false);
+    assert_eq!(config.suspend_outputs(), (true, false));
+
+    // Disabling an output leaves other functions on the pin unchanged
+    assert_eq!(
+        config.pins[10].function,
+        PinFunction::Gpio {
+            mode: GpioMode::PushPull,
+            level: GpioLevel::High
+        }
+    );
+
+    mock.program_pin_config(&config, OtpWrite::irreversible())
+        .unwrap();
+    assert_eq!(mock.pin_config().unwrap().suspend_outputs(), (true, false));
+
+    config.set_suspend_outputs(false, true);
+    assert_eq!(config.pins[9].function, PinDefault::default().function);
+    assert_eq!(config.suspend_outputs(), (false, true));
+}
+
+#[test]
+fn mock_lock_fields() {
+    let mock = MockCp2130::new();
+    assert!(mock.locked_fields().unwrap().is_empty());
+
+    mock.lock_fields(OtpFields::SERIAL | OtpFields::PID, OtpWrite::irreversible())
+        .unwrap();
+    assert_eq!(
+        mock.locked_fields().unwrap(),
+        OtpFields::SERIAL | OtpFields::PID
+    );
+
+    // Writes to locked fields fail prior to programming
+    assert!(matches!(
+        mock.set_serial_string("1234", OtpWrite::irreversible()),
+        Err(Cp2130Error::Locked(OtpFields::SERIAL))
+    ));
+    let update = UsbConfigUpdate {
+        vid: Some(0x1234),
+        pid: Some(0x5678),
+        ..Default::default()
+    };
+    assert!(matches!(
+        mock.program_usb_config(update, OtpWrite::irreversible()),
+        Err(Cp2130Error::Locked(OtpFields::PID))
+    ));
+    assert_eq!(mock.usb_config().unwrap().vid, 0x10c4);
+
+    mock.set_product_string("Fixture", OtpWrite::irreversible())
+        .unwrap();
+}
+
+#[test]
+fn mock_prom_image() {
+    let mock = MockCp2130::new();
+
+    let data: Vec<u8> = (0..512).map(|i| i as u8).collect();
+    let image = PromImage::from_bytes(&data).unwrap();
+
+    // Images round-trip through the text format
+    let text = image.to_string();
+    assert_eq!(text.parse::<PromImage>().unwrap(), image);
+    assert!("# empty".parse::<PromImage>().is_err());
+
+    mock.program_prom(&image, OtpWrite::irreversible()).unwrap();
+    assert_eq!(mock.read_prom().unwrap(), image);
+
+    mock.lock_fields(OtpFields::VID, OtpWrite::irreversible())
+        .unwrap();
+    assert!(mock.program_prom(&image, OtpWrite::irreversible()).is_err());
+}
+
+#[test]
+fn mock_provision() {
+    let mock = MockCp2130::new();
+
+    let mut pins = PinConfig::default();
+    pins.pins[3].function = PinFunction::Rtr { active_high: false };
+
+    let spec = ProvisioningSpec {
+        usb: UsbConfigUpdate {
+            vid: Some(0x10c4),
+            pid: Some(0x1234),
+            ..Default::default()
+        },
+        product: Some("Fixture".into()),
+        serial: Some("F-0001".into()),
+        pins: Some(pins),
+        lock: OtpFields::SERIAL,
+        ..Default::default()
+    };
+
+    // Only differing fields are planned
+    let plan = mock.provision_plan(&spec).unwrap();
+    assert_eq!(plan.usb.vid, None);
+    assert_eq!(plan.usb.pid, Some(0x1234));
+    assert_eq!(plan.manufacturer, None);
+
+    let report = mock.provision(&spec, OtpWrite::irreversible()).unwrap();
+    assert!(report.passed(), "{:?}", report);
+    assert_eq!(mock.serial_string().unwrap(), "F-0001");
+    assert_eq!(mock.locked_fields().unwrap(), OtpFields::SERIAL);
+
+    // Re-provisioning is a no-op
+    assert!(mock.provision_plan(&spec).unwrap().is_empty());
+
+    // Changing a locked field fails before writing
+    let spec = ProvisioningSpec {
+        product: Some("Other".into()),
+        serial: Some("F-0002".into()),
+        ..Default::default()
+    };
+    assert!(matches!(
+        mock.provision(&spec, OtpWrite::irreversible()),
+        Err(Cp2130Error::Locked(OtpFields::SERIAL))
+    ));
+    assert_eq!(mock.product_string().unwrap(), "Fixture");
+}
+
+#[test]
+fn mock_gpio_values() {
+    let mock = MockCp2130::new();
+    mock.set_gpio_mode_level(0, GpioMode::PushPull, GpioLevel::Low)
+        .unwrap();
+    mock.set_gpio_mode_level(3, GpioMode::PushPull, GpioLevel::High)
+        .unwrap();
+
+    // Only masked output pins are modified
+    let levels = GpioLevels::GPIO_0 | GpioLevels::GPIO_5;
+    let mask = GpioLevels::GPIO_0 | GpioLevels::GPIO_3 | GpioLevels::GPIO_5;
+    mock.set_gpio_values(levels, mask).unwrap();
+
+    assert_eq!(mock.gpio_level(0), GpioLevel::High);
+    assert_eq!(mock.gpio_level(3), GpioLevel::Low);
+    assert_eq!(mock.gpio_level(5), GpioLevel::Low);
+}
+
+#[test]
+fn mock_event_counter() {
+    let mock = MockCp2130::new();
+
+    mock.set_event_counter(EventCounterMode::FallingEdge, 0xfffe)
+        .unwrap();
+    mock.push_events(3);
+
+    let c = mock.event_counter().unwrap();
+    assert_eq!(c.mode, Some(EventCounterMode::FallingEdge));
+    assert_eq!(c.count, 1);
+    assert!(c.overflow);
+}
+
+#[test]
+fn mock_clock_divider() {
+    use driver_cp2130::device::{clock_out_divider, clock_out_frequency};
+
+    let mock = MockCp2130::new();
+
+    let d = clock_out_divider(1_500_000).unwrap();
+    assert_eq!(d, 16);
+    assert_eq!(clock_out_frequency(0), 93_750);
+    assert!(clock_out_divider(48_000_000).is_err());
+
+    mock.set_clock_divider(d).unwrap();
+    assert_eq!(mock.clock_divider().unwrap(), 16);
+}
+
+#[test]
+fn mock_spi_config_readback() {
+    let mock = MockCp2130::new();
+
+    let config = SpiConfig::builder()
+        .clock(SpiClock::Clock750KHz)
+        .spi_mode(embedded_hal::spi::MODE_3)
+        .cs_mode(CsMode::Enabled)
+        .post_assert_delay(Duration::from_micros(50))
+        .build()
+        .unwrap();
+    let _spi = mock.spi(1, config.clone(), None).unwrap();
+
+    assert_eq!(mock.read_spi_config(1).unwrap(), config);
+    assert_eq!(mock.read_spi_config(0).unwrap().cs_mode, CsMode::Disabled);
+}
+
+#[test]
+fn mock_pwm() {
+    let mock = MockCp2130::new();
+
+    assert!(mock.pwm_out(2, 1000.0, 0.5).is_err());
+    assert!(mock.pwm_out(2, 50.0, 1.5).is_err());
+
+    let mut pwm = mock.pwm_out(2, 100.0, 1.0).unwrap();
+    std::thread::sleep(Duration::from_millis(20));
+    assert_eq!(mock.gpio_mode(2), GpioMode::PushPull);
+    assert_eq!(mock.gpio_level(2), GpioLevel::High);
+
+    pwm.set_duty(0.5).unwrap();
+    std::thread::sleep(Duration::from_millis(50));
+    assert_eq!(pwm.duty(), 0.5);
+
+    let mut pin = pwm.stop().unwrap();
+    assert_eq!(mock.gpio_level(2), GpioLevel::Low);
+    pin.set_high().unwrap();
+    assert_eq!(mock.gpio_level(2), GpioLevel::High);
+}
+
+#[test]
+fn mock_pulse() {
+    let mock = MockCp2130::new();
+
+    let mut pin = mock
+        .gpio_out(6, GpioMode::PushPull, GpioLevel::High)
+        .unwrap();
+
+    let start = std::time::Instant::now();
+    pin.pulse(GpioLevel::Low, Duration::from_millis(5)).unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(5));
+    assert_eq!(mock.gpio_level(6), GpioLevel::High);
+
+    let pattern = [
+        (GpioLevel::Low, Duration::from_millis(2)),
+        (GpioLevel::High, Duration::from_millis(2)),
+        (GpioLevel::Low, Duration::from_millis(2)),
+    ];
+    let start = std::time::Instant::now();
+    pin.pulse_pattern(&pattern).unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(6));
+    assert_eq!(mock.gpio_level(6), GpioLevel::High);
+
+    pin.set_low().unwrap();
+    pin.pulse(GpioLevel::High, Duration::from_millis(1))
+        .unwrap();
+    assert_eq!(mock.gpio_level(6), GpioLevel::Low);
+}
+
+#[test]
+fn mock_wait_for_edge() {
+    let mock = MockCp2130::new();
+
+    // GPIO.4 uses the event counter, catching pulses between reads
+    let mut pin = mock.gpio_in(4).unwrap();
+    pin.set_poll_interval(Duration::from_millis(5));
+    std::thread::scope(|s| {
+        s.spawn(|| {
+            std::thread::sleep(Duration::from_millis(10));
+            mock.push_events(1);
+        });
+        pin.wait_for_edge(Edge::Rising, Duration::from_secs(1))
+            .unwrap();
+    });
+    assert!(mock.event_counter().unwrap().mode.is_none());
+
+    assert!(pin
+        .wait_for_edge(Edge::Falling, Duration::from_millis(10))
+        .is_err());
+
+    // Other pins poll the level
+    let mut pin = mock.gpio_in(3).unwrap();
+    std::thread::scope(|s| {
+        s.spawn(|| {
+            std::thread::sleep(Duration::from_millis(10));
+            mock.set_input(3, GpioLevel::High);
+        });
+        pin.wait_for_edge(Edge::Rising, Duration::from_secs(1))
+            .unwrap();
+    });
+    assert!(pin
+        .wait_for_edge(Edge::Any, Duration::from_millis(10))
+        .is_err());
+}
+
+/// Narrow bounds accept devices, references and trait objects
+fn toggle(gpio: impl GpioAccess, pin: u8) -> Result<bool, Cp2130Error> {
+    gpio.set_gpio_mode_level(pin, GpioMode::PushPull, GpioLevel::High)?;
+    gpio.get_gpio_level(pin)
+}
+
+#[test]
+fn mock_dyn_device() {
+    let mock = std::sync::Arc::new(MockCp2130::new());
+    mock.set_version(0x0011);
+
+    let device: std::sync::Arc<dyn Device> = mock.clone();
+    assert_eq!(device.version().unwrap(), 0x0011);
+
+    assert!(toggle(&*device, 2).unwrap());
+    assert!(toggle(device.clone(), 3).unwrap());
+    assert_eq!(mock.gpio_mode(3), GpioMode::PushPull);
+
+    // Trait objects may be shared between threads
+    let d = device.clone();
+    std::thread::spawn(move || d.spi_write(&[0x01]).unwrap())
+        .join()
+        .unwrap();
+    assert_eq!(mock.take_spi_writes(), vec![vec![0x01]]);
+}
+
+#[test]
+fn mock_clone() {
+    let mock = MockCp2130::new();
+    let cp2130 = (*mock).clone();
+
+    // Clones share pin allocations
+    let pin = cp2130
+        .gpio_out(4, GpioMode::PushPull, GpioLevel::High)
+        .unwrap();
+    assert!(matches!(mock.gpio_in(4), Err(Cp2130Error::GpioInUse)));
+    assert_eq!(mock.gpio_level(4), GpioLevel::High);
+
+    let t = std::thread::spawn(move || cp2130.get_gpio_level(4).unwrap());
+    assert!(t.join().unwrap());
+
+    drop(pin);
+    assert!(mock.gpio_in(4).is_ok());
+}
+
+#[test]
+fn mock_spi_clone() {
+    let mock = MockCp2130::new();
+    let mut spi = mock.spi(0, SpiConfig::default(), Some(2)).unwrap();
+    let mut other = spi.clone();
+
+    let t = std::thread::spawn(move || other.write(&[0x01]).unwrap());
+    t.join().unwrap();
+    spi.write(&[0x02]).unwrap();
+
+    assert_eq!(mock.take_spi_writes(), vec![vec![0x01], vec![0x02]]);
+    assert_eq!(mock.gpio_level(2), GpioLevel::High);
+
+    // Releasing the channel applies to all clones
+    let mut other = spi.clone();
+    mock.spi_release(0, false).unwrap();
+    assert!(matches!(
+        other.write(&[0x03]),
+        Err(Cp2130Error::SpiReleased)
+    ));
+    assert!(matches!(spi.write(&[0x03]), Err(Cp2130Error::SpiReleased)));
+}
+
+#[test]
+fn mock_spi_delay() {
+    use embedded_hal::spi::Operation;
+
+    let mock = MockCp2130::new();
+    let mut spi = mock.spi(0, SpiConfig::default(), None).unwrap();
+
+    for threshold in [Duration::ZERO, Duration::from_millis(1)] {
+        spi.set_spin_threshold(threshold);
+
+        let start = std::time::Instant::now();
+        spi.transaction(&mut [
+            Operation::Write(&[0x01]),
+            Operation::DelayNs(5_000_000),
+            Operation::Write(&[0x02]),
+        ])
+        .unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(5));
+    }
+}
+
+#[test]
+fn mock_remote_wakeup() {
+    let mock = MockCp2130::new();
+
+    let mut config = mock.pin_config().unwrap();
+    assert!(!config.remote_wakeup());
+
+    config.pins[6].wakeup = Some(GpioLevel::Low);
+    mock.program_pin_config(&config, OtpWrite::irreversible())
+        .unwrap();
+
+    let config = mock.pin_config().unwrap();
+    assert!(config.remote_wakeup());
+    assert_eq!(config.pins[6].wakeup, Some(GpioLevel::Low));
+
+    // Host suspend is opt-in
+    assert!(!UsbOptions::default().allow_suspend);
+}
+
+#[test]
+fn mock_raw_bulk() {
+    use driver_cp2130::protocol::{TransferCommand, TransferHeader};
+
+    let mock = MockCp2130::new();
+    mock.push_spi_response(&[0x12, 0x34]);
+    let timeout = Duration::from_millis(200);
+
+    let write = TransferHeader {
+        command: TransferCommand::Write,
+        len: 3,
+    };
+    let read = TransferHeader {
+        command: TransferCommand::ReadWithRTR,
+        len: 2,
+    };
+
+    let mut buff = [0u8; 2];
+    let n = mock
+        .raw_bulk(|raw| {
+            let mut cmd = write.encode().to_vec();
+            cmd.extend_from_slice(&[1, 2, 3]);
+            assert_eq!(raw.bulk_write_raw(&cmd, timeout)?, 11);
+
+            raw.bulk_write_raw(&read.encode(), timeout)?;
+            raw.bulk_read_raw(&mut buff, timeout)
+        })
+        .unwrap();
+
+    assert_eq!(n, 2);
+    assert_eq!(buff, [0x12, 0x34]);
+    assert_eq!(mock.take_spi_writes(), vec![vec![1, 2, 3]]);
+
+    // Raw transfers are not included in SPI statistics
+    assert_eq!(mock.stats().transfers, 0);
+}
+
+#[test]
+fn mock_timeout_overrides() {
+    let mock = MockCp2130::new();
+    let timeout = Duration::from_secs(5);
+
+    mock.set_input(3, GpioLevel::High);
+    assert!(mock.get_gpio_level_with_timeout(3, timeout).unwrap());
+    assert_eq!(
+        mock.get_gpio_values_with_timeout(timeout).unwrap(),
+        GpioLevels::GPIO_3
+    );
+
+    mock.spi_write_with_timeout(&[1, 2], timeout).unwrap();
+    assert_eq!(mock.take_spi_writes(), vec![vec![1, 2]]);
+
+    mock.push_spi_response(&[3, 4]);
+    let mut buff = [0u8; 2];
+    assert_eq!(
+        mock.spi_write_read_with_timeout(&[5, 6], &mut buff, timeout)
+            .unwrap(),
+        2
+    );
+    assert_eq!(buff, [3, 4]);
+
+    mock.push_spi_response(&[7, 8]);
+    let mut buff = [0u8; 2];
+    assert_eq!(mock.spi_read_with_timeout(&mut buff, timeout).unwrap(), 2);
+    assert_eq!(buff, [7, 8]);
+}
+
+#[test]
+fn mock_spi_write_completion() {
+    let mock = MockCp2130::new();
+    let config = SpiConfig::builder()
+        .clock(SpiClock::Clock93_75KHz)
+        .build()
+        .unwrap();
+    let _spi = mock.spi(0, config, None).unwrap();
+
+    // Writes wait only for the final buffered packet (~5.5 ms), not the full transfer (~350 ms)
+    let start = std::time::Instant::now();
+    mock.spi_write(&[0x5a; 4096]).unwrap();
+    let elapsed = start.elapsed();
+
+    assert!(elapsed >= Duration::from_millis(5), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_millis(100), "{:?}", elapsed);
+    assert_eq!(mock.take_spi_writes().concat(), vec![0x5a; 4096]);
+}
+
+#[test]
+fn mock_spi_channel_timing() {
+    let mock = MockCp2130::new();
+    let slow = SpiConfig::builder()
+        .clock(SpiClock::Clock93_75KHz)
+        .build()
+        .unwrap();
+    let mut spi = mock.spi(0, slow, None).unwrap();
+    let _fast = mock.spi(1, SpiConfig::default(), None).unwrap();
+
+    // Transfers use the timing of their own channel rather than the last configured
+    let start = std::time::Instant::now();
+    spi.write(&[0xa5; 64]).unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(5));
+}
+
+#[test]
+fn mock_spi_oversized_transfers() {
+    use driver_cp2130::device::MAX_TRANSFER_LEN;
+
+    let mock = MockCp2130::new();
+
+    // Oversized writes are split across transfer commands
+    let data: Vec<u8> = (0..2 * MAX_TRANSFER_LEN + 10).map(|i| i as u8).collect();
+    mock.spi_write(&data).unwrap();
+
+    let writes = mock.take_spi_writes();
+    assert_eq!(
+        writes.iter().map(|w| w.len()).collect::<Vec<_>>(),
+        vec![MAX_TRANSFER_LEN, MAX_TRANSFER_LEN, 10]
+    );
+    assert_eq!(writes.concat(), data);
+
+    // Responses are collected from each command
+    let data = vec![0xa5; MAX_TRANSFER_LEN + 4];
+    mock.push_spi_response(&data[..MAX_TRANSFER_LEN]);
+    mock.push_spi_response(&data[MAX_TRANSFER_LEN..]);
+
+    let mut buff = vec![0u8; data.len()];
+    assert_eq!(mock.spi_write_read(&data, &mut buff).unwrap(), data.len());
+    assert_eq!(buff, data);
+    assert_eq!(mock.take_spi_writes().len(), 2);
+
+    mock.push_spi_response(&[1; MAX_TRANSFER_LEN]);
+    mock.push_spi_response(&[2; 4]);
+    assert_eq!(mock.spi_read(&mut buff).unwrap(), data.len());
+    assert_eq!(&buff[MAX_TRANSFER_LEN - 1..], &[1, 2, 2, 2, 2]);
+}
+
+#[test]
+fn mock_spi_write_read_lengths() {
+    let mock = MockCp2130::new();
+
+    // Response bytes beyond the read buffer are drained rather than left pending
+    let data: Vec<u8> = (0..300).map(|i| i as u8).collect();
+    mock.push_spi_response(&data);
+    let mut buff = [0u8; 100];
+    assert_eq!(mock.spi_write_read(&data, &mut buff).unwrap(), 100);
+    assert_eq!(&buff[..], &data[..100]);
+
+    mock.push_spi_response(&[0x12, 0x34]);
+    let mut buff = [0u8; 2];
+    mock.spi_read(&mut buff).unwrap();
+    assert_eq!(buff, [0x12, 0x34]);
+
+    // Reads longer than the write clock out zeros
+    mock.push_spi_response(&data);
+    let mut buff = vec![0u8; 300];
+    assert_eq!(mock.spi_write_read(&[0xaa, 0xbb], &mut buff).unwrap(), 300);
+    assert_eq!(buff, data);
+
+    let writes = mock.take_spi_writes();
+    assert_eq!(writes[1].len(), 300);
+    assert_eq!(&writes[1][..3], &[0xaa, 0xbb, 0x00]);
+}
+
+#[test]
+fn mock_transfer_priority() {
+    let mock = MockCp2130::new();
+    assert_eq!(mock.transfer_priority().unwrap(), TransferPriority::Write);
+
+    mock.program_transfer_priority(TransferPriority::Read, OtpWrite::irreversible())
+        .unwrap();
+    assert_eq!(mock.transfer_priority().unwrap(), TransferPriority::Read);
+
+    mock.lock_fields(OtpFields::TRANSFER_PRIORITY, OtpWrite::irreversible())
+        .unwrap();
+    assert!(matches!(
+        mock.program_transfer_priority(TransferPriority::Write, OtpWrite::irreversible()),
+        Err(Cp2130Error::Locked(OtpFields::TRANSFER_PRIORITY))
+    ));
+}
+
+#[test]
+fn mock_rtr() {
+    let mock = MockCp2130::new();
+    let timeout = Duration::from_millis(50);
+
+    // RTR is unavailable while GPIO.3 is held as a plain GPIO
+    let pin = mock.gpio_in(3).unwrap();
+    assert!(matches!(
+        mock.program_rtr(false, OtpWrite::irreversible()),
+        Err(Cp2130Error::GpioInUse)
+    ));
+    assert!(matches!(
+        mock.spi_read_rtr(&mut [0u8; 2], timeout),
+        Err(Cp2130Error::GpioInUse)
+    ));
+    drop(pin);
+
+    mock.program_rtr(false, OtpWrite::irreversible()).unwrap();
+    let mut config = mock.pin_config().unwrap();
+    assert_eq!(config.rtr(), Some(false));
+    assert_eq!(
+        config.pins[3].function,
+        PinFunction::Rtr { active_high: false }
+    );
+
+    config.set_rtr(None);
+    assert_eq!(config.rtr(), None);
+
+    mock.push_spi_response(&[0x12, 0x34]);
+    let mut buff = [0u8; 2];
+    assert_eq!(mock.spi_read_rtr(&mut buff, timeout).unwrap(), 2);
+    assert_eq!(buff, [0x12, 0x34]);
+}
+
+#[test]
+fn mock_rtr_timeout() {
+    let mock = MockCp2130::new();
+    let timeout = Duration::from_millis(20);
+
+    // RTR is never asserted, the read times out and the command is aborted
+    mock.set_rtr_ready(false);
+    mock.push_spi_response(&[0xaa; 4]);
+    let mut buff = [0u8; 4];
+    assert!(matches!(
+        mock.spi_read_rtr(&mut buff, timeout),
+        Err(Cp2130Error::Usb(rusb::Error::Timeout))
+    ));
+
+    // Asserting RTR later does not return stale data to following transfers
+    mock.set_rtr_ready(true);
+    mock.push_spi_response(&[0x12, 0x34]);
+    let mut buff = [0u8; 2];
+    assert_eq!(mock.spi_read(&mut buff).unwrap(), 2);
+    assert_eq!(buff, [0x12, 0x34]);
+}
+
+#[test]
+fn mock_rtr_stream_stop() {
+    let mock = MockCp2130::new();
+    mock.set_rtr_ready(false);
+
+    let config = StreamConfig {
+        block_len: 4,
+        rtr: true,
+        ..Default::default()
+    };
+    let stream = mock.spi_stream(config).unwrap();
+    std::thread::sleep(Duration::from_millis(50));
+
+    // Stopping while a gated read is pending aborts the read without error
+    assert!(stream.stop().is_ok());
+
+    mock.set_rtr_ready(true);
+    mock.push_spi_response(&[0x56, 0x78]);
+    let mut buff = [0u8; 2];
+    assert_eq!(mock.spi_read(&mut buff).unwrap(), 2);
+    assert_eq!(buff, [0x56, 0x78]);
+}
+
+#[test]
+fn mock_typed_pins() {
+    let mock = MockCp2130::new();
+
+    let mut led = mock
+        .pin::<6>()
+        .unwrap()
+        .into_push_pull(GpioLevel::Low)
+        .unwrap();
+    assert_eq!(mock.gpio_mode(6), GpioMode::PushPull);
+    led.set_high().unwrap();
+    assert_eq!(mock.gpio_level(6), GpioLevel::High);
+    assert!(matches!(mock.pin::<6>(), Err(Cp2130Error::GpioInUse)));
+
+    // Pins are released when dropped
+    drop(led);
+    let cs = mock.pin::<6>().unwrap();
+
+    // The CS pin is allocated to the SPI channel until the channel is released
+    let _spi = mock.spi_with_cs(0, SpiConfig::default(), cs).unwrap();
+    assert!(matches!(mock.pin::<6>(), Err(Cp2130Error::GpioInUse)));
+    mock.spi_release(0, false).unwrap();
+    mock.pin::<6>().unwrap();
+
+    // On failure the pin is released
+    let config = SpiConfig {
+        cs_pin_mode: GpioMode::Input,
+        ..SpiConfig::default()
+    };
+    let cs = mock.pin::<7>().unwrap();
+    assert!(matches!(
+        mock.spi_with_cs(1, config, cs),
+        Err(Cp2130Error::InvalidConfig { .. })
+    ));
+    let cs = mock.pin::<7>().unwrap();
+    assert!(matches!(
+        mock.spi_with_cs(11, SpiConfig::default(), cs),
+        Err(Cp2130Error::InvalidConfig {
+            field: "channel",
+            ..
+        })
+    ));
+
+    // Pins from another device are rejected
+    let other = MockCp2130::new();
+    let cs = other.pin::<7>().unwrap();
+    assert!(matches!(
+        mock.spi_with_cs(1, SpiConfig::default(), cs),
+        Err(Cp2130Error::ForeignPin)
+    ));
+    mock.pin::<7>().unwrap();
+    other.pin::<7>().unwrap();
+}
@@ -0,0 +1,127 @@
+use driver_cp2130::onewire::crc8;
+use driver_cp2130::prelude::*;
+
+/// Bit-level model of devices on a 1-Wire bus, supporting ROM search and read
+struct FakeBus {
+    roms: Vec<RomId>,
+    command: Vec<bool>,
+    /// Devices still participating in the current search
+    active: Vec<bool>,
+    /// Search position (bit index, step within the bit)
+    search: Option<(usize, usize)>,
+    read: Vec<bool>,
+}
+
+impl FakeBus {
+    fn new(roms: &[RomId]) -> Self {
+        Self {
+            roms: roms.to_vec(),
+            command: vec![],
+            active: vec![],
+            search: None,
+            read: vec![],
+        }
+    }
+
+    fn rom_bit(rom: &RomId, n: usize) -> bool {
+        rom.0[n / 8] & (1 << (n % 8)) != 0
+    }
+}
+
+impl OneWireBus for FakeBus {
+    fn reset(&mut self) -> Result<bool, Cp2130Error> {
+        self.command.clear();
+        self.active = vec![true; self.roms.len()];
+        self.search = None;
+        self.read.clear();
+        Ok(!self.roms.is_empty())
+    }
+
+    fn write_bit(&mut self, bit: bool) -> Result<(), Cp2130Error> {
+        if let Some((n, _)) = self.search {
+            for (i, r) in self.roms.iter().enumerate() {
+                if Self::rom_bit(r, n) != bit {
+                    self.active[i] = false;
+                }
+            }
+            self.search = Some((n + 1, 0));
+            return Ok(());
+        }
+
+        self.command.push(bit);
+        if self.command.len() == 8 {
+            let cmd = self
+                .command
+                .iter()
+                .rev()
+                .fold(0u8, |a, b| a << 1 | *b as u8);
+            match cmd {
+                0xF0 => self.search = Some((0, 0)),
+                0x33 => {
+                    self.read = (0..64)
+                        .rev()
+                        .map(|n| Self::rom_bit(&self.roms[0], n))
+                        .collect()
+                }
+                c => panic!("unexpected command 0x{:02x}", c),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read_bit(&mut self) -> Result<bool, Cp2130Error> {
+        if let Some((n, step)) = self.search {
+            // Wired-AND of participating device bits, then their complements
+            let v = self
+                .roms
+                .iter()
+                .zip(self.active.iter())
+                .filter(|(_, a)| **a)
+                .all(|(r, _)| Self::rom_bit(r, n) != (step == 1));
+            self.search = Some((n, step + 1));
+            return Ok(v);
+        }
+
+        Ok(self.read.pop().unwrap_or(true))
+    }
+}
+
+fn rom(family: u8, serial: u8) -> RomId {
+    let mut r = [family, serial, 0, 0, 0x12, 0x34, 0, 0];
+    r[7] = crc8(&r[..7]);
+    RomId(r)
+}
+
+#[test]
+fn onewire_crc() {
+    // Example from Maxim application note 27
+    let r = RomId([0x02, 0x1C, 0xB8, 0x01, 0x00, 0x00, 0x00, 0xA2]);
+    assert!(r.crc_valid());
+    assert_eq!(r.family(), 0x02);
+    assert_eq!(r.to_string(), "021cb801000000a2");
+}
+
+#[test]
+fn onewire_search() {
+    let roms = [
+        rom(0x28, 0x01),
+        rom(0x28, 0x81),
+        rom(0x10, 0x01),
+        rom(0x28, 0x80),
+    ];
+    let mut bus = FakeBus::new(&roms);
+
+    let mut found = bus.search().unwrap();
+    let mut expected = roms.to_vec();
+    found.sort_by_key(|r| r.0);
+    expected.sort_by_key(|r| r.0);
+    assert_eq!(found, expected);
+
+    let mut bus = FakeBus::new(&roms[..1]);
+    assert_eq!(bus.read_rom().unwrap(), Some(roms[0]));
+
+    let mut bus = FakeBus::new(&[]);
+    assert!(bus.search().unwrap().is_empty());
+    assert_eq!(bus.read_rom().unwrap(), None);
+}
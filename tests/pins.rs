@@ -0,0 +1,140 @@
+#![cfg(feature = "mock")]
+
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+use embedded_hal::digital::OutputPin as _;
+use embedded_hal::spi::SpiDevice;
+
+use driver_cp2130::mock::MockCp2130;
+use driver_cp2130::prelude::*;
+use driver_cp2130::transport::{Exchange, ExchangeKind};
+
+/// Transcript buffer shared with the recorder
+#[derive(Clone, Default)]
+struct Transcript(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Transcript {
+    fn write(&mut self, buff: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buff);
+        Ok(buff.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Payloads of the Set_GPIO_Mode_And_Level requests (pin, mode, level) recorded so far
+fn mode_level_requests(transcript: &Transcript) -> Vec<Vec<u8>> {
+    String::from_utf8(transcript.0.lock().unwrap().clone())
+        .unwrap()
+        .lines()
+        .filter(|l| !l.trim().is_empty() && !l.starts_with('#'))
+        .map(|l| l.parse::<Exchange>().unwrap())
+        .filter(|e| e.kind == ExchangeKind::ControlOut && e.request == 0x23)
+        .map(|e| e.data_out)
+        .collect()
+}
+
+#[test]
+fn pins_convert() {
+    let transcript = Transcript::default();
+    let mock = MockCp2130::recorded(transcript.clone());
+
+    let input = mock.gpio_in(2).unwrap();
+    let output = input
+        .into_output(GpioMode::PushPull, GpioLevel::High)
+        .unwrap();
+    assert_eq!(mock.gpio_mode(2), GpioMode::PushPull);
+    assert_eq!(mock.gpio_level(2), GpioLevel::High);
+
+    // The allocation is carried over to the converted pin
+    assert!(matches!(mock.gpio_in(2), Err(Cp2130Error::GpioInUse)));
+
+    let input = output.into_input().unwrap();
+    assert_eq!(mock.gpio_mode(2), GpioMode::Input);
+    assert!(matches!(
+        mock.gpio_out(2, GpioMode::PushPull, GpioLevel::Low),
+        Err(Cp2130Error::GpioInUse)
+    ));
+
+    // One request on allocation, then one per conversion
+    assert_eq!(
+        mode_level_requests(&transcript),
+        vec![vec![2, 0x00, 0], vec![2, 0x02, 1], vec![2, 0x00, 0]]
+    );
+
+    // Dropping the converted pin releases the allocation
+    drop(input);
+    mock.gpio_in(2).unwrap();
+}
+
+#[test]
+fn pins_drop_release() {
+    let mock = MockCp2130::new();
+
+    let output = mock
+        .gpio_out(2, GpioMode::PushPull, GpioLevel::High)
+        .unwrap();
+    let input = mock.gpio_in(3).unwrap();
+    assert!(matches!(mock.gpio_in(2), Err(Cp2130Error::GpioInUse)));
+    assert!(matches!(mock.gpio_in(3), Err(Cp2130Error::GpioInUse)));
+
+    // Dropping pins frees the index for re-allocation, leaving other pins allocated
+    drop(output);
+    drop(input);
+    let _pin2 = mock.gpio_in(2).unwrap();
+    let _pin3 = mock
+        .gpio_out(3, GpioMode::OpenDrain, GpioLevel::Low)
+        .unwrap();
+    assert_eq!(mock.gpio_mode(3), GpioMode::OpenDrain);
+    assert!(matches!(mock.gpio_in(2), Err(Cp2130Error::GpioInUse)));
+}
+
+#[test]
+fn pins_release() {
+    let mock = MockCp2130::new();
+
+    // Released pin handles fail, and the index may be re-allocated
+    let mut stale = mock
+        .gpio_out(4, GpioMode::PushPull, GpioLevel::High)
+        .unwrap();
+    mock.gpio_release(4, true).unwrap();
+    assert_eq!(mock.gpio_mode(4), GpioMode::Input);
+    assert!(matches!(stale.set_low(), Err(Cp2130Error::GpioReleased)));
+
+    let mut pin = mock
+        .gpio_out(4, GpioMode::PushPull, GpioLevel::Low)
+        .unwrap();
+
+    // Dropping the stale handle leaves the new allocation in place
+    drop(stale);
+    assert!(matches!(mock.gpio_in(4), Err(Cp2130Error::GpioInUse)));
+    pin.set_high().unwrap();
+    assert_eq!(mock.gpio_level(4), GpioLevel::High);
+
+    // Released SPI handles fail, with the channel and CS pin available for re-use
+    let mut stale = mock.spi(1, SpiConfig::default(), Some(5)).unwrap();
+    assert!(matches!(mock.gpio_in(5), Err(Cp2130Error::GpioInUse)));
+    mock.spi_release(1, true).unwrap();
+    assert_eq!(mock.gpio_mode(5), GpioMode::Input);
+    assert!(matches!(
+        stale.write(&[0x01]),
+        Err(Cp2130Error::SpiReleased)
+    ));
+
+    drop(mock.gpio_in(5).unwrap());
+    let mut spi = mock.spi(1, SpiConfig::default(), Some(5)).unwrap();
+    spi.write(&[0x02]).unwrap();
+    assert!(matches!(
+        stale.write(&[0x03]),
+        Err(Cp2130Error::SpiReleased)
+    ));
+    assert_eq!(mock.take_spi_writes(), vec![vec![0x02]]);
+
+    assert!(matches!(
+        mock.gpio_release(11, false),
+        Err(Cp2130Error::InvalidPin(11))
+    ));
+}
@@ -0,0 +1,132 @@
+use driver_cp2130::otp::{decode_string, encode_string};
+use driver_cp2130::protocol::*;
+use embedded_hal::spi::{MODE_0, MODE_3};
+
+#[test]
+fn spi_word_layout() {
+    let word = SpiWord {
+        clock: SpiClock::Clock750KHz,
+        spi_mode: MODE_3,
+        cs_pin_mode: GpioMode::PushPull,
+    };
+    assert_eq!(word.encode(), 0b0011_1100);
+    assert_eq!(SpiWord::decode(word.encode()), word);
+
+    let word = SpiWord {
+        clock: SpiClock::Clock12Mhz,
+        spi_mode: MODE_0,
+        cs_pin_mode: GpioMode::OpenDrain,
+    };
+    assert_eq!(word.encode(), 0);
+    assert_eq!(SpiWord::decode(0), word);
+}
+
+#[test]
+fn transfer_header_layout() {
+    let header = TransferHeader {
+        command: TransferCommand::WriteRead,
+        len: 0x0102_0304,
+    };
+    assert_eq!(header.encode(), [0, 0, 0x02, 0, 0x04, 0x03, 0x02, 0x01]);
+    assert_eq!(TransferHeader::decode(&header.encode()), Ok(header));
+
+    assert_eq!(
+        TransferHeader::decode(&[0, 0, 0x03, 0, 0, 0, 0, 0]),
+        Err(ProtocolError::InvalidValue {
+            field: "transfer_command",
+            value: 0x03
+        })
+    );
+    assert_eq!(
+        TransferHeader::decode(&[0; 4]),
+        Err(ProtocolError::ShortBuffer {
+            expected: 8,
+            actual: 4
+        })
+    );
+}
+
+#[test]
+fn gpio_commands() {
+    let cmd = GpioModeLevel {
+        pin: 7,
+        mode: GpioMode::OpenDrain,
+        level: GpioLevel::High,
+    };
+    assert_eq!(cmd.encode(), [7, 0x01, 0x01]);
+    assert_eq!(GpioModeLevel::decode(&cmd.encode()), Ok(cmd));
+    assert!(GpioModeLevel::decode(&[7, 0x03, 0x00]).is_err());
+
+    let cmd = GpioValues {
+        levels: GpioLevels::GPIO_10 | GpioLevels::GPIO_0,
+        mask: GpioLevels::GPIO_10,
+    };
+    assert_eq!(cmd.encode(), [0x40, 0x08, 0x40, 0x00]);
+    assert_eq!(GpioValues::decode(&cmd.encode()), Ok(cmd));
+
+    // Unknown bits are discarded
+    assert_eq!(GpioLevels::decode(&[0xff, 0xff]), Ok(GpioLevels::all()));
+}
+
+#[test]
+fn spi_delay_layout() {
+    let delay = SpiDelayWord {
+        channel: 3,
+        mask: DelayMask::POST_ASSERT | DelayMask::PRE_DEASSERT,
+        inter_byte: 0,
+        post_assert: 5,
+        pre_deassert: 0x0102,
+    };
+    assert_eq!(delay.encode(), [3, 0x06, 0, 0, 0, 5, 0x01, 0x02]);
+    assert_eq!(SpiDelayWord::decode(&delay.encode()), Ok(delay));
+}
+
+#[test]
+fn event_counter_layout() {
+    let counter = EventCounter {
+        mode: Some(EventCounterMode::FallingEdge),
+        overflow: true,
+        count: 0x1234,
+    };
+    assert_eq!(counter.encode(), [0x85, 0x12, 0x34]);
+    assert_eq!(EventCounter::decode(&counter.encode()), Ok(counter));
+
+    // Disabled counters report no mode
+    let counter = EventCounter::decode(&[0x00, 0, 1]).unwrap();
+    assert_eq!(counter.mode, None);
+}
+
+#[test]
+fn chip_select_and_version() {
+    let cs = ChipSelect {
+        channel: 2,
+        mode: CsMode::Exclusive,
+    };
+    assert_eq!(ChipSelect::decode(&cs.encode()), Ok(cs));
+
+    let enables = ChipSelectEnables::decode(&[0x00, 0x05, 0x00, 0x05]).unwrap();
+    assert_eq!(enables.channels, 0b101);
+
+    assert_eq!(encode_version(0x0107), [0x07, 0x01]);
+    assert_eq!(decode_version(&[0x07, 0x01]), Ok(0x0107));
+}
+
+#[test]
+fn prom_blocks() {
+    assert_eq!(prom_block(0), Some(0..PROM_BLOCK_LEN));
+    assert_eq!(
+        prom_block(PROM_BLOCKS - 1),
+        Some(PROM_LEN - PROM_BLOCK_LEN..PROM_LEN)
+    );
+    assert_eq!(prom_block(PROM_BLOCKS), None);
+}
+
+#[test]
+fn prom_strings() {
+    let buff = encode_string("CP2130", 1).unwrap();
+    assert_eq!(buff.len(), PROM_BLOCK_LEN);
+    assert_eq!(decode_string(&buff), "CP2130");
+
+    // Descriptor lengths shorter than the header decode as empty
+    assert_eq!(decode_string(&[0x01, 0x03, 0x00]), "");
+}
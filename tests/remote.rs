@@ -0,0 +1,100 @@
+#![cfg(feature = "mock")]
+
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::time::Duration;
+
+use driver_cp2130::mock::MockCp2130;
+use driver_cp2130::prelude::*;
+use driver_cp2130::remote::{Server, MAX_MESSAGE_LEN};
+
+#[test]
+fn remote_loopback() {
+    let mock = Arc::new(MockCp2130::new());
+    mock.set_version(0x0011);
+
+    let server = Server::bind(mock.clone(), "127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    std::thread::spawn(move || server.run());
+
+    let remote = RemoteCp2130::connect(addr).unwrap();
+    assert_eq!(remote.version().unwrap(), 0x0011);
+
+    remote
+        .set_gpio_mode_level(3, GpioMode::PushPull, GpioLevel::High)
+        .unwrap();
+    assert_eq!(mock.gpio_mode(3), GpioMode::PushPull);
+    assert!(remote.get_gpio_level(3).unwrap());
+    assert!(remote.get_gpio_values().unwrap().pin(3));
+
+    mock.push_spi_response(&[0x12, 0x34]);
+    let mut buff = [0u8; 2];
+    assert_eq!(remote.spi_write_read(&[0xaa, 0xbb], &mut buff).unwrap(), 2);
+    assert_eq!(buff, [0x12, 0x34]);
+
+    remote.spi_write(&[0x01, 0x02]).unwrap();
+    assert_eq!(
+        mock.take_spi_writes(),
+        vec![vec![0xaa, 0xbb], vec![0x01, 0x02]]
+    );
+
+    // Device errors are reported by the client
+    match remote.set_gpio_mode_level(11, GpioMode::Input, GpioLevel::Low) {
+        Err(Cp2130Error::Remote(e)) => assert!(e.contains("11")),
+        r => panic!("unexpected result: {:?}", r),
+    }
+}
+
+#[test]
+fn remote_timeout_closes_connection() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    // Server replying to the first request only after the client has timed out
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut len = [0u8; 4];
+        stream.read_exact(&mut len).unwrap();
+        let mut request = vec![0u8; u32::from_be_bytes(len) as usize];
+        stream.read_exact(&mut request).unwrap();
+
+        std::thread::sleep(Duration::from_millis(200));
+        let _ = stream.write_all(&[0, 0, 0, 3, 0, 0x00, 0x11]);
+    });
+
+    let remote = RemoteCp2130::connect(addr).unwrap();
+    remote.set_timeout(Some(Duration::from_millis(50))).unwrap();
+
+    assert!(matches!(remote.version(), Err(Cp2130Error::Io(_))));
+
+    // The late response is never returned to a following call
+    std::thread::sleep(Duration::from_millis(300));
+    assert!(matches!(remote.version(), Err(Cp2130Error::Remote(_))));
+
+    server.join().unwrap();
+}
+
+#[test]
+fn remote_message_limit() {
+    let mock = Arc::new(MockCp2130::new());
+    mock.set_version(0x0011);
+
+    let server = Server::bind(mock.clone(), "127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    std::thread::spawn(move || server.run());
+
+    let remote = RemoteCp2130::connect(addr).unwrap();
+
+    // Oversized requests are rejected by the client, leaving the connection usable
+    assert!(matches!(
+        remote.spi_write(&vec![0u8; MAX_MESSAGE_LEN]),
+        Err(Cp2130Error::Remote(_))
+    ));
+    assert!(matches!(
+        remote.spi_read(&mut vec![0u8; MAX_MESSAGE_LEN]),
+        Err(Cp2130Error::Remote(_))
+    ));
+    assert_eq!(remote.version().unwrap(), 0x0011);
+    assert!(mock.take_spi_writes().is_empty());
+}
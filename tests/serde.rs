@@ -0,0 +1,85 @@
+#![cfg(feature = "serde")]
+
+use std::time::Duration;
+
+use driver_cp2130::prelude::*;
+
+#[test]
+fn spi_config_round_trip() {
+    let c = SpiConfig::builder()
+        .clock(SpiClock::Clock187_5KHz)
+        .spi_mode(embedded_hal::spi::MODE_2)
+        .cs_mode(CsMode::Exclusive)
+        .inter_byte_delay(Duration::from_micros(30))
+        .build()
+        .unwrap();
+
+    let s = serde_json::to_string(&c).unwrap();
+    let d: SpiConfig = serde_json::from_str(&s).unwrap();
+
+    assert!(c == d);
+}
+
+#[test]
+fn spi_config_partial() {
+    let c: SpiConfig = serde_json::from_str(
+        r#"{ "clock": 750000, "spi_mode": 3, "delays": { "post_assert_us": 100 } }"#,
+    )
+    .unwrap();
+
+    assert_eq!(c.clock, SpiClock::Clock750KHz);
+    assert_eq!(c.spi_mode, embedded_hal::spi::MODE_3);
+    assert_eq!(c.cs_pin_mode, GpioMode::PushPull);
+    assert_eq!(c.delays.post_assert_delay(), Duration::from_micros(100));
+
+    assert!(serde_json::from_str::<SpiConfig>(r#"{ "clock": 1000 }"#).is_err());
+}
+
+#[cfg(feature = "profile")]
+#[test]
+fn profile_from_toml() {
+    use driver_cp2130::profile::Profile;
+
+    let p = Profile::from_toml(
+        r#"
+[spi.flash]
+channel = 0
+cs_pin = 0
+clock = 6000000
+spi_mode = 3
+
+[gpio.reset]
+pin = 6
+mode = "push-pull"
+level = "high"
+
+[gpio.irq]
+pin = 4
+mode = "input"
+"#,
+    )
+    .unwrap();
+
+    let flash = &p.spi["flash"];
+    assert_eq!(flash.cs_pin, Some(0));
+    assert_eq!(flash.config.clock, SpiClock::Clock6MHz);
+    assert_eq!(flash.config.spi_mode, embedded_hal::spi::MODE_3);
+
+    assert_eq!(p.gpio["reset"].level, GpioLevel::High);
+    assert_eq!(p.gpio["irq"].mode, GpioMode::Input);
+    assert_eq!(p.gpio["irq"].level, GpioLevel::Low);
+}
+
+#[test]
+fn provisioning_spec_partial() {
+    let s: ProvisioningSpec = serde_json::from_str(
+        r#"{ "usb": { "pid": 4660, "release": [1, 2] }, "serial": "F-0001", "lock": 1024 }"#,
+    )
+    .unwrap();
+
+    assert_eq!(s.usb.pid, Some(0x1234));
+    assert_eq!(s.usb.release, Some((1, 2)));
+    assert_eq!(s.serial.as_deref(), Some("F-0001"));
+    assert_eq!(s.lock, OtpFields::SERIAL);
+    assert!(s.pins.is_none());
+}
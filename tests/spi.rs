@@ -0,0 +1,88 @@
+use std::time::Duration;
+
+use driver_cp2130::prelude::*;
+
+#[test]
+fn spi_clock_from_frequency() {
+    assert_eq!(SpiClock::from_frequency(12_000_000), SpiClock::Clock12Mhz);
+    assert_eq!(SpiClock::from_frequency(20_000_000), SpiClock::Clock12Mhz);
+    assert_eq!(SpiClock::from_frequency(1_000_000), SpiClock::Clock750KHz);
+    assert_eq!(SpiClock::from_frequency(200_000), SpiClock::Clock187_5KHz);
+    assert_eq!(SpiClock::from_frequency(0), SpiClock::Clock93_75KHz);
+
+    assert_eq!(
+        SpiClock::from_frequency_exact(93_750).unwrap(),
+        SpiClock::Clock93_75KHz
+    );
+    assert!(SpiClock::from_frequency_exact(100_000).is_err());
+}
+
+#[test]
+fn spi_config_builder() {
+    let c = SpiConfig::builder()
+        .clock(SpiClock::Clock1_5MHz)
+        .spi_mode(embedded_hal::spi::MODE_3)
+        .cs_mode(CsMode::Enabled)
+        .post_assert_delay(Duration::from_micros(50))
+        .build()
+        .unwrap();
+
+    assert_eq!(c.clock, SpiClock::Clock1_5MHz);
+    assert_eq!(c.spi_mode, embedded_hal::spi::MODE_3);
+    assert_eq!(c.cs_mode, CsMode::Enabled);
+    assert!(c.delays.validate().is_ok());
+
+    let e = SpiConfig::builder().cs_pin_mode(GpioMode::Input).build();
+    assert!(matches!(
+        e,
+        Err(Cp2130Error::InvalidConfig {
+            field: "cs_pin_mode",
+            ..
+        })
+    ));
+}
+
+#[test]
+fn spi_delays_from_duration() {
+    let d = SpiDelays::new()
+        .inter_byte(Duration::from_micros(15))
+        .unwrap()
+        .pre_deassert(Duration::from_millis(655))
+        .unwrap();
+
+    // Rounded up to the 10 us resolution
+    assert_eq!(d.inter_byte_delay(), Duration::from_micros(20));
+    assert_eq!(d.pre_deassert_delay(), Duration::from_millis(655));
+    assert_eq!(d.post_assert_delay(), Duration::ZERO);
+    assert!(d.validate().is_ok());
+
+    assert!(SpiDelays::new()
+        .post_assert(Duration::from_millis(700))
+        .is_err());
+
+    let e = SpiConfig::builder()
+        .post_assert_delay(Duration::from_secs(1))
+        .build();
+    assert!(matches!(
+        e,
+        Err(Cp2130Error::InvalidConfig {
+            field: "post_assert",
+            ..
+        })
+    ));
+}
+
+#[test]
+fn spi_transfer_time() {
+    // 4 KiB at 93.75 kHz exceeds the base USB timeout
+    let t = SpiClock::Clock93_75KHz.transfer_time(4096);
+    assert!(t > Duration::from_millis(300));
+
+    let d = SpiDelays::new()
+        .inter_byte(Duration::from_micros(10))
+        .unwrap()
+        .post_assert(Duration::from_micros(50))
+        .unwrap();
+    assert_eq!(d.transfer_time(11), Duration::from_micros(150));
+    assert_eq!(SpiDelays::new().transfer_time(100), Duration::ZERO);
+}
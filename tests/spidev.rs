@@ -0,0 +1,172 @@
+#![cfg(feature = "mock")]
+
+use driver_cp2130::mock::MockCp2130;
+use driver_cp2130::spidev::{
+    spi_ioc_message, IoctlResult, SpiIocTransfer, SpidevBridge, MAX_TRANSFER,
+};
+use driver_cp2130::SpiConfig;
+
+/// SPI_IOC_RD_MODE32 / SPI_IOC_WR_MODE32
+const RD_MODE32: u32 = 0x8004_6b05;
+const WR_MODE32: u32 = 0x4004_6b05;
+/// SPI_IOC_RD_MAX_SPEED_HZ / SPI_IOC_WR_MAX_SPEED_HZ
+const RD_MAX_SPEED_HZ: u32 = 0x8004_6b04;
+const WR_MAX_SPEED_HZ: u32 = 0x4004_6b04;
+/// SPI_IOC_WR_LSB_FIRST
+const WR_LSB_FIRST: u32 = 0x4001_6b02;
+
+#[test]
+fn spidev_mode_and_speed() {
+    let mock = MockCp2130::new();
+    let mut bridge = SpidevBridge::new(&mock, 0, Some(0), SpiConfig::default()).unwrap();
+
+    // Reads first request the output buffer
+    assert_eq!(
+        bridge.ioctl(RD_MODE32, 0x1000, &[], 0),
+        IoctlResult::Retry {
+            input: vec![],
+            output: vec![(0x1000, 4)]
+        }
+    );
+    assert_eq!(
+        bridge.ioctl(RD_MODE32, 0x1000, &[], 4),
+        IoctlResult::Done {
+            result: 0,
+            out: 0u32.to_ne_bytes().to_vec()
+        }
+    );
+
+    // Writes first request the input buffer
+    assert_eq!(
+        bridge.ioctl(WR_MODE32, 0x1000, &[], 0),
+        IoctlResult::Retry {
+            input: vec![(0x1000, 4)],
+            output: vec![]
+        }
+    );
+    assert!(matches!(
+        bridge.ioctl(WR_MODE32, 0x1000, &3u32.to_ne_bytes(), 0),
+        IoctlResult::Done { result: 0, .. }
+    ));
+    assert_eq!(bridge.mode(), 3);
+
+    // Speeds round down to a supported clock
+    assert!(matches!(
+        bridge.ioctl(WR_MAX_SPEED_HZ, 0x1000, &1_000_000u32.to_ne_bytes(), 0),
+        IoctlResult::Done { result: 0, .. }
+    ));
+    assert_eq!(
+        bridge.ioctl(RD_MAX_SPEED_HZ, 0x1000, &[], 4),
+        IoctlResult::Done {
+            result: 0,
+            out: 750_000u32.to_ne_bytes().to_vec()
+        }
+    );
+
+    // Unsupported options are rejected
+    assert_eq!(
+        bridge.ioctl(WR_LSB_FIRST, 0x1000, &[1], 0),
+        IoctlResult::Error(22)
+    );
+    assert_eq!(bridge.ioctl(0x5401, 0, &[], 0), IoctlResult::Error(25));
+}
+
+#[test]
+fn spidev_message() {
+    let mock = MockCp2130::new();
+    let mut bridge = SpidevBridge::new(&mock, 0, Some(0), SpiConfig::default()).unwrap();
+
+    let transfer = SpiIocTransfer {
+        tx_buf: 0x2000,
+        rx_buf: 0x3000,
+        len: 2,
+        ..Default::default()
+    };
+    let cmd = spi_ioc_message(1);
+
+    // Fetch the transfer array
+    assert_eq!(
+        bridge.ioctl(cmd, 0x1000, &[], 0),
+        IoctlResult::Retry {
+            input: vec![(0x1000, 32)],
+            output: vec![]
+        }
+    );
+
+    // Fetch transmit and map receive buffers
+    let mut data = transfer.encode().to_vec();
+    assert_eq!(
+        bridge.ioctl(cmd, 0x1000, &data, 0),
+        IoctlResult::Retry {
+            input: vec![(0x1000, 32), (0x2000, 2)],
+            output: vec![(0x3000, 2)]
+        }
+    );
+
+    mock.push_spi_response(&[0x12, 0x34]);
+    data.extend_from_slice(&[0xaa, 0xbb]);
+    assert_eq!(
+        bridge.ioctl(cmd, 0x1000, &data, 2),
+        IoctlResult::Done {
+            result: 2,
+            out: vec![0x12, 0x34]
+        }
+    );
+    assert_eq!(mock.take_spi_writes(), vec![vec![0xaa, 0xbb]]);
+}
+
+#[test]
+fn spidev_malformed_ioctl() {
+    let mock = MockCp2130::new();
+    let mut bridge = SpidevBridge::new(&mock, 0, Some(0), SpiConfig::default()).unwrap();
+
+    // Argument sizes not matching the command are rejected rather than decoded
+    for cmd in [
+        0x4000_6b01,
+        0x4002_6b05,
+        0x4000_6b04,
+        0x8000_6b04,
+        0x8002_6b05,
+    ] {
+        assert_eq!(bridge.ioctl(cmd, 0x1000, &[], 0), IoctlResult::Error(22));
+        assert_eq!(bridge.ioctl(cmd, 0x1000, &[3], 1), IoctlResult::Error(22));
+    }
+
+    // The bridge remains usable
+    assert!(matches!(
+        bridge.ioctl(WR_MODE32, 0x1000, &3u32.to_ne_bytes(), 0),
+        IoctlResult::Done { result: 0, .. }
+    ));
+}
+
+#[test]
+fn spidev_oversized_message() {
+    let mock = MockCp2130::new();
+    let mut bridge = SpidevBridge::new(&mock, 0, Some(0), SpiConfig::default()).unwrap();
+
+    let transfer = SpiIocTransfer {
+        tx_buf: 0x2000,
+        rx_buf: 0x3000,
+        len: u32::MAX,
+        ..Default::default()
+    };
+
+    // Rejected before buffers are requested or allocated
+    let cmd = spi_ioc_message(1);
+    assert_eq!(
+        bridge.ioctl(cmd, 0x1000, &transfer.encode(), 0),
+        IoctlResult::Error(22)
+    );
+
+    let transfers = [
+        SpiIocTransfer {
+            len: MAX_TRANSFER as u32,
+            ..Default::default()
+        },
+        SpiIocTransfer {
+            len: 1,
+            ..Default::default()
+        },
+    ];
+    assert!(bridge.message(&transfers, &[]).is_err());
+}
@@ -0,0 +1,240 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use driver_cp2130::device::Info;
+use driver_cp2130::prelude::*;
+use driver_cp2130::transport::{Exchange, ExchangeKind, Recorder, Replayer};
+
+/// Logged control request (request type, request, payload)
+type Request = (u8, u8, Vec<u8>);
+
+/// Transport logging control requests and answering reads with a fixed payload
+#[derive(Clone, Default)]
+struct FakeTransport {
+    requests: Arc<Mutex<Vec<Request>>>,
+    response: Vec<u8>,
+}
+
+impl Transport for FakeTransport {
+    fn control_in(
+        &self,
+        request_type: u8,
+        request: u8,
+        _value: u16,
+        _index: u16,
+        buff: &mut [u8],
+        _timeout: Duration,
+    ) -> Result<usize, Cp2130Error> {
+        self.requests
+            .lock()
+            .unwrap()
+            .push((request_type, request, vec![]));
+
+        let n = buff.len().min(self.response.len());
+        buff[..n].copy_from_slice(&self.response[..n]);
+        Ok(n)
+    }
+
+    fn control_out(
+        &self,
+        request_type: u8,
+        request: u8,
+        _value: u16,
+        _index: u16,
+        buff: &[u8],
+        _timeout: Duration,
+    ) -> Result<usize, Cp2130Error> {
+        self.requests
+            .lock()
+            .unwrap()
+            .push((request_type, request, buff.to_vec()));
+        Ok(buff.len())
+    }
+
+    fn bulk_in(&self, buff: &mut [u8], _timeout: Duration) -> Result<usize, Cp2130Error> {
+        Ok(buff.len())
+    }
+
+    fn bulk_out(&self, buff: &[u8], _timeout: Duration) -> Result<usize, Cp2130Error> {
+        Ok(buff.len())
+    }
+
+    fn close(&mut self) -> Result<(), Cp2130Error> {
+        Ok(())
+    }
+}
+
+#[test]
+fn transport_version_and_gpio() {
+    let transport = FakeTransport {
+        response: vec![0x34, 0x12],
+        ..Default::default()
+    };
+    let requests = transport.requests.clone();
+
+    let info = Info::new("Silicon Labs".into(), "CP2130".into(), "0001".into());
+    let cp2130 = Cp2130::from_transport(transport, info.clone());
+    assert_eq!(cp2130.info(), info);
+
+    assert_eq!(cp2130.version().unwrap(), 0x1234);
+
+    cp2130
+        .set_gpio_mode_level(3, GpioMode::PushPull, GpioLevel::High)
+        .unwrap();
+
+    let requests = requests.lock().unwrap();
+    assert_eq!(requests[0], (0xc0, 0x11, vec![]));
+    assert_eq!(requests[1], (0x40, 0x23, vec![3, 0x02, 0x01]));
+}
+
+/// Transport where bulk reads stall until a control transfer is issued
+#[derive(Clone, Default)]
+struct StallingTransport {
+    inner: FakeTransport,
+    control: Arc<AtomicBool>,
+}
+
+impl Transport for StallingTransport {
+    fn control_in(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buff: &mut [u8],
+        timeout: Duration,
+    ) -> Result<usize, Cp2130Error> {
+        self.control.store(true, Ordering::SeqCst);
+        self.inner
+            .control_in(request_type, request, value, index, buff, timeout)
+    }
+
+    fn control_out(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buff: &[u8],
+        timeout: Duration,
+    ) -> Result<usize, Cp2130Error> {
+        self.inner
+            .control_out(request_type, request, value, index, buff, timeout)
+    }
+
+    fn bulk_in(&self, buff: &mut [u8], timeout: Duration) -> Result<usize, Cp2130Error> {
+        let start = Instant::now();
+        while !self.control.load(Ordering::SeqCst) {
+            if start.elapsed() > timeout {
+                return Err(Cp2130Error::Usb(rusb::Error::Timeout));
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        self.inner.bulk_in(buff, timeout)
+    }
+
+    fn bulk_out(&self, buff: &[u8], timeout: Duration) -> Result<usize, Cp2130Error> {
+        self.inner.bulk_out(buff, timeout)
+    }
+
+    fn close(&mut self) -> Result<(), Cp2130Error> {
+        Ok(())
+    }
+}
+
+#[test]
+fn transport_gpio_during_spi() {
+    let transport = StallingTransport::default();
+    let info = Info::new("Silicon Labs".into(), "CP2130".into(), "0001".into());
+    let cp2130 = Cp2130::from_transport(transport, info);
+
+    // GPIO reads must complete while the SPI read is waiting on bulk data
+    std::thread::scope(|s| {
+        let spi = s.spawn(|| cp2130.spi_read(&mut [0u8; 16]));
+
+        std::thread::sleep(Duration::from_millis(20));
+        cp2130.get_gpio_values().unwrap();
+
+        assert_eq!(spi.join().unwrap().unwrap(), 16);
+    });
+}
+
+#[test]
+fn transport_record_replay() {
+    let path = std::env::temp_dir().join(format!("cp2130-transcript-{}.txt", std::process::id()));
+
+    let transport = FakeTransport {
+        response: vec![0x34, 0x12],
+        ..Default::default()
+    };
+    let info = Info::new("Silicon Labs".into(), "CP2130".into(), "0001".into());
+
+    // Record exchanges with the fake device
+    let recorder = Recorder::create(transport, &path).unwrap();
+    let cp2130 = Cp2130::from_transport(recorder, info.clone());
+    assert_eq!(cp2130.version().unwrap(), 0x1234);
+    cp2130.spi_write(&[0xaa, 0xbb]).unwrap();
+    cp2130.close().unwrap();
+
+    // Replay them without the device
+    let replayer = Replayer::load(&path).unwrap();
+    assert_eq!(replayer.remaining(), 2);
+
+    let cp2130 = Cp2130::from_transport(replayer, info);
+    assert_eq!(cp2130.version().unwrap(), 0x1234);
+    assert!(matches!(
+        cp2130.spi_write(&[0xaa, 0xcc]),
+        Err(Cp2130Error::Replay(_))
+    ));
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn transport_exchange_format() {
+    let e: Exchange = "1500 control-in c0 11 0000 0000 - 3412".parse().unwrap();
+    assert_eq!(e.kind, ExchangeKind::ControlIn);
+    assert_eq!(e.elapsed, Duration::from_micros(1500));
+    assert_eq!(e.data_in, vec![0x34, 0x12]);
+    assert_eq!(e.error, None);
+    assert_eq!(e.to_string(), "1500 control-in c0 11 0000 0000 - 3412");
+
+    let e: Exchange = "10 bulk-in 00 00 0000 0000 - - Operation timed out"
+        .parse()
+        .unwrap();
+    assert_eq!(e.error.as_deref(), Some("Operation timed out"));
+}
+
+#[test]
+fn transport_open_all() {
+    let open = |serial: &str| {
+        let info = Info::new("Silicon Labs".into(), "CP2130".into(), serial.into());
+        Ok(Cp2130::from_transport(FakeTransport::default(), info))
+    };
+    let port = |p: &str| p.parse::<PortPath>().unwrap();
+
+    // Failures are collected by port without preventing other devices opening
+    let all: OpenAll = vec![
+        (port("1-1"), open("0001")),
+        (port("1-2"), Err(Cp2130Error::Usb(rusb::Error::Access))),
+        (port("2-1.4"), open("0003")),
+    ]
+    .into_iter()
+    .collect();
+
+    let serials: Vec<_> = all
+        .devices
+        .iter()
+        .map(|d| d.info().serial().to_string())
+        .collect();
+    assert_eq!(serials, vec!["0001", "0003"]);
+
+    assert_eq!(all.failures.len(), 1);
+    assert_eq!(all.failures[0].0, port("1-2"));
+    assert!(matches!(
+        all.failures[0].1,
+        Cp2130Error::Usb(rusb::Error::Access)
+    ));
+}
@@ -0,0 +1,78 @@
+#![cfg(feature = "mock")]
+
+use std::time::Duration;
+
+use driver_cp2130::mock::MockCp2130;
+use driver_cp2130::prelude::*;
+
+#[test]
+fn validation_invalid_pins() {
+    let mock = MockCp2130::new();
+
+    let invalid = |r: Result<_, Cp2130Error>| matches!(r, Err(Cp2130Error::InvalidPin(11)));
+
+    assert!(invalid(mock.set_gpio_mode_level(
+        11,
+        GpioMode::PushPull,
+        GpioLevel::High
+    )));
+    assert!(invalid(mock.get_gpio_level(11).map(drop)));
+    assert!(invalid(mock.gpio_in(11).map(drop)));
+    assert!(invalid(
+        mock.gpio_out(11, GpioMode::PushPull, GpioLevel::Low)
+            .map(drop)
+    ));
+    assert!(invalid(
+        mock.spi(0, SpiConfig::default(), Some(11)).map(drop)
+    ));
+
+    // The channel is left unconfigured
+    assert_eq!(mock.read_spi_config(0).unwrap().cs_mode, CsMode::Disabled);
+}
+
+#[test]
+fn validation_invalid_spi_config() {
+    let mock = MockCp2130::new();
+    let initial = mock.read_spi_config(1).unwrap();
+
+    assert!(matches!(
+        mock.spi(11, SpiConfig::default(), None),
+        Err(Cp2130Error::InvalidConfig {
+            field: "channel",
+            ..
+        })
+    ));
+    assert!(matches!(
+        mock.read_spi_config(11),
+        Err(Cp2130Error::InvalidConfig {
+            field: "channel",
+            ..
+        })
+    ));
+
+    let config = SpiConfig {
+        clock: SpiClock::Clock93_75KHz,
+        cs_pin_mode: GpioMode::Input,
+        ..SpiConfig::default()
+    };
+    assert!(matches!(
+        mock.spi(1, config, None),
+        Err(Cp2130Error::InvalidConfig {
+            field: "cs_pin_mode",
+            ..
+        })
+    ));
+
+    assert!(matches!(
+        SpiConfig::builder()
+            .post_assert_delay(Duration::from_secs(1))
+            .build(),
+        Err(Cp2130Error::InvalidConfig {
+            field: "post_assert",
+            ..
+        })
+    ));
+
+    // Rejected configurations are not applied to the device
+    assert_eq!(mock.read_spi_config(1).unwrap(), initial);
+}
@@ -0,0 +1,89 @@
+#![cfg(all(feature = "mock", feature = "async"))]
+
+use std::future::Future;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake};
+use std::thread::{self, Thread};
+use std::time::{Duration, Instant};
+
+use embedded_hal_async::digital::Wait;
+
+use driver_cp2130::mock::MockCp2130;
+use driver_cp2130::prelude::*;
+
+/// Waker unparking the thread running [`block_on`]
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Minimal executor, running a future to completion on the current thread
+fn block_on<F: Future>(f: F) -> F::Output {
+    let mut f = std::pin::pin!(f);
+    let waker = Arc::new(ThreadWaker(thread::current())).into();
+    let mut cx = Context::from_waker(&waker);
+
+    loop {
+        match f.as_mut().poll(&mut cx) {
+            Poll::Ready(v) => return v,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+#[test]
+fn wait_for_high() {
+    let mock = MockCp2130::new();
+    let mut pin = mock.gpio_in(3).unwrap();
+
+    let start = Instant::now();
+    thread::scope(|s| {
+        s.spawn(|| {
+            thread::sleep(Duration::from_millis(20));
+            mock.set_input(3, GpioLevel::High);
+        });
+
+        block_on(pin.wait_for_high()).unwrap();
+    });
+    assert!(start.elapsed() >= Duration::from_millis(20));
+
+    // Resolves immediately where the pin is already at the level
+    block_on(pin.wait_for_high()).unwrap();
+}
+
+#[test]
+fn wait_for_rising_edge() {
+    let mock = MockCp2130::new();
+    let mut pin = mock.gpio_in(3).unwrap();
+    mock.set_input(3, GpioLevel::High);
+
+    // Starting high, the pin must fall before the rising edge is seen
+    let start = Instant::now();
+    thread::scope(|s| {
+        s.spawn(|| {
+            thread::sleep(Duration::from_millis(20));
+            mock.set_input(3, GpioLevel::Low);
+            thread::sleep(Duration::from_millis(20));
+            mock.set_input(3, GpioLevel::High);
+        });
+
+        block_on(pin.wait_for_rising_edge()).unwrap();
+    });
+    assert!(start.elapsed() >= Duration::from_millis(40));
+}
+
+#[test]
+fn wait_released() {
+    let mock = MockCp2130::new();
+    let mut pin = mock.gpio_in(3).unwrap();
+    mock.gpio_release(3, false).unwrap();
+
+    // Reads from the helper thread report errors through the future
+    assert!(matches!(
+        block_on(pin.wait_for_low()),
+        Err(Cp2130Error::GpioReleased)
+    ));
+}